@@ -0,0 +1,10 @@
+use derive_more::derive::{Display, Error, From};
+use sam_client::ClientError;
+
+#[derive(Debug, Display, Error, From)]
+pub enum CliError {
+    Client(ClientError),
+    Io(std::io::Error),
+    #[display("The database already belongs to a registered device")]
+    AlreadyRegistered,
+}
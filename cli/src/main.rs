@@ -0,0 +1,516 @@
+use std::{env, fs, path::PathBuf, str::FromStr as _};
+
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use clap::{Args, Parser, Subcommand};
+use libsignal_protocol::{IdentityKeyPair, PublicKey};
+use rand::rngs::OsRng;
+use sam_client::{
+    provisioning::{ProvisioningSession, ProvisioningUrl},
+    storage::{
+        sqlite::{SqliteStoreConfig, SqliteStoreType},
+        AccountStore, ContactStore, Store, StoreConfig,
+    },
+    transport::HttpClient,
+    Client, ClientError,
+};
+use sam_common::{
+    address::{AccountId, RegistrationId},
+    sam_message::NotificationType,
+};
+
+use tokio::time::{self, MissedTickBehavior};
+
+use error::CliError;
+
+mod error;
+
+/// Terminal client for a sam server
+#[derive(Debug, Parser)]
+struct Cli {
+    /// Base url of the server
+    #[arg(long, env = "SAM_SERVER", default_value = "http://127.0.0.1:8080")]
+    server: String,
+    /// SQLite file holding the keys and sessions of this device
+    #[arg(long, env = "SAM_DATABASE", default_value = "sam-cli.db")]
+    database: String,
+    /// Base64 encoded trust root of the server, printed by
+    /// `sam-server --print-trust-root`. Sealed messages are only opened with it.
+    #[arg(long, env = "SAM_TRUST_ROOT", value_parser = parse_trust_root)]
+    trust_root: Option<PublicKey>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Register a new account with this device as its primary device
+    Register {
+        username: String,
+        #[command(flatten)]
+        password: PasswordInput,
+        #[arg(long, default_value = "sam-cli")]
+        device_name: String,
+    },
+    /// Send the account keys to the new device that printed `url`
+    Provision { url: String },
+    /// Link this device to an account, prints a url to pass to `provision` on
+    /// the primary device and waits for it
+    Link {
+        #[command(flatten)]
+        password: PasswordInput,
+        #[arg(long, default_value = "sam-cli")]
+        device_name: String,
+    },
+    /// Print the address of this device
+    Whoami,
+    /// Change the username of this account
+    Username { username: String },
+    /// Change the password of this device
+    Password {
+        #[command(flatten)]
+        password: PasswordInput,
+        /// Only store the password, after the primary device reset it
+        #[arg(long)]
+        local: bool,
+    },
+    /// Manage the contacts of this device
+    #[command(subcommand)]
+    Contacts(ContactsCommand),
+    /// Manage the devices linked to this account
+    #[command(subcommand)]
+    Devices(DevicesCommand),
+    /// Send a text message to a contact
+    Send { contact: String, message: String },
+    /// Print incoming messages as they arrive
+    Listen {
+        /// Stop after this many messages
+        #[arg(long)]
+        count: Option<usize>,
+    },
+}
+
+/// Passwords are not taken as arguments, which would leave them in the shell
+/// history and show them to everyone running `ps`
+#[derive(Debug, Args)]
+struct PasswordInput {
+    /// Read the password from the first line of this file instead of
+    /// `SAM_PASSWORD` or a prompt
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+}
+
+const PASSWORD_ENV: &str = "SAM_PASSWORD";
+
+impl PasswordInput {
+    fn read(&self, prompt: &str) -> Result<String, CliError> {
+        if let Some(path) = &self.password_file {
+            let contents = fs::read_to_string(path)?;
+            return Ok(contents.lines().next().unwrap_or_default().to_string());
+        }
+        if let Ok(password) = env::var(PASSWORD_ENV) {
+            return Ok(password);
+        }
+        Ok(rpassword::prompt_password(prompt)?)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ContactsCommand {
+    /// Store an account under a name, without an account id the name is
+    /// looked up as a username
+    Add {
+        name: String,
+        account_id: Option<String>,
+    },
+    /// List all contacts
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum DevicesCommand {
+    /// List all devices linked to this account
+    List,
+    /// Give a device a new name
+    Rename { device_id: u32, name: String },
+    /// Revoke a link token printed by `provision` that has not been used yet
+    Revoke { token_id: String },
+    /// Make another device the primary device of this account
+    Primary { device_id: u32 },
+    /// Give another device a new password and disconnect it
+    ResetPassword {
+        device_id: u32,
+        #[command(flatten)]
+        password: PasswordInput,
+    },
+}
+
+type CliClient = Client<SqliteStoreType>;
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    let http = HttpClient::new(cli.server);
+    let config = SqliteStoreConfig::file(&cli.database).await;
+
+    match cli.command {
+        Command::Register {
+            username,
+            password,
+            device_name,
+        } => {
+            let password = password.read("Password: ")?;
+            let mut client = new_client(new_store(config, None).await?, http, cli.trust_root);
+            let account_id = client
+                .register(&username, &password, &device_name, &mut OsRng)
+                .await?;
+            println!("{account_id}");
+        }
+        Command::Link {
+            password,
+            device_name,
+        } => {
+            // fail before the primary device sends anything
+            ensure_unregistered(&config).await?;
+            let password = password.read("Password: ")?;
+            let session = ProvisioningSession::start(&http, &mut OsRng).await?;
+            println!("{}", session.url());
+            let account = session.receive().await?;
+
+            let store = new_store(config, Some(account.identity_key_pair)).await?;
+            let mut client = new_client(store, http, cli.trust_root);
+            let address = client
+                .link_device(
+                    &account.username,
+                    &password,
+                    &device_name,
+                    account.token,
+                    account.unidentified_access_key,
+                    &mut OsRng,
+                )
+                .await?;
+            println!("{}.{}", address.account_id(), address.device_id());
+        }
+        command => {
+            let client = new_client(config.load_store().await?, http, cli.trust_root);
+            run_registered(client, command).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_registered(mut client: CliClient, command: Command) -> Result<(), CliError> {
+    match command {
+        Command::Provision { url } => {
+            let url = ProvisioningUrl::from_str(&url)?;
+            let token = client.provision_new_device(&url, &mut OsRng).await?;
+            println!("{}", token.id());
+        }
+        Command::Whoami => {
+            let account_store = &client.store().account_store;
+            println!(
+                "{} {}.{}",
+                account_store.get_username().await?,
+                account_store.get_account_id().await?,
+                account_store.get_device_id().await?
+            );
+        }
+        Command::Username { username } => {
+            client.change_username(&username).await?;
+        }
+        Command::Password { password, local } => {
+            let password = password.read("New password: ")?;
+            if local {
+                client
+                    .store_mut()
+                    .account_store
+                    .set_password(password)
+                    .await?;
+            } else {
+                client.change_password(&password).await?;
+            }
+        }
+        Command::Contacts(ContactsCommand::Add { name, account_id }) => {
+            let account_id = match account_id {
+                Some(account_id) => AccountId::from_str(&account_id)
+                    .map_err(|_| ClientError::InvalidServiceId(account_id))?,
+                None => client.lookup_username(&name).await?,
+            };
+            client
+                .store_mut()
+                .contact_store
+                .add_contact(&name, account_id)
+                .await?;
+        }
+        Command::Contacts(ContactsCommand::List) => {
+            for (name, account_id) in client.store().contact_store.get_contacts().await? {
+                println!("{name} {account_id}");
+            }
+        }
+        Command::Devices(DevicesCommand::List) => {
+            for device in client.devices().await? {
+                println!(
+                    "{} {} ({}, registration id {}, created {})",
+                    device.id, device.name, device.role, device.registration_id, device.created
+                );
+            }
+        }
+        Command::Devices(DevicesCommand::Rename { device_id, name }) => {
+            client.rename_device(device_id.into(), &name).await?;
+        }
+        Command::Devices(DevicesCommand::Revoke { token_id }) => {
+            client.revoke_device_token(&token_id).await?;
+        }
+        Command::Devices(DevicesCommand::Primary { device_id }) => {
+            client.transfer_primary(device_id.into()).await?;
+        }
+        Command::Devices(DevicesCommand::ResetPassword {
+            device_id,
+            password,
+        }) => {
+            let password = password.read("New password of the device: ")?;
+            client
+                .reset_device_password(device_id.into(), &password)
+                .await?;
+        }
+        Command::Send { contact, message } => {
+            let recipient = client.store().contact_store.get_contact(&contact).await?;
+            let websocket = client.connect_websocket().await?;
+            client
+                .send_message(&websocket, recipient, message.as_bytes(), &mut OsRng)
+                .await?;
+        }
+        Command::Listen { count } => {
+            let mut websocket = client.connect_websocket().await?;
+            let mut notifications = websocket
+                .take_notifications()
+                .expect("Notifications are taken once");
+            // the first tick completes right away, so keys are checked on start
+            let mut maintenance = time::interval(client.key_maintenance().check_interval);
+            maintenance.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            let mut received = 0;
+            while count.is_none_or(|count| received < count) {
+                tokio::select! {
+                    ready = websocket.wait_for_envelope() => ready?,
+                    Some(notification) = notifications.recv() => {
+                        match notification.r#type() {
+                            NotificationType::PreKeysLow => maintain_keys(&mut client).await,
+                            NotificationType::DeviceUnlinked => {
+                                if let Some(device_id) = notification.device_id {
+                                    println!("device {device_id} was unlinked");
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    _ = maintenance.tick() => {
+                        maintain_keys(&mut client).await;
+                        continue;
+                    }
+                }
+
+                let (source, plaintext) =
+                    match client.receive_message(&mut websocket, &mut OsRng).await {
+                        Ok(message) => message,
+                        Err(err @ ClientError::WebSocketClosed) => return Err(err.into()),
+                        Err(err) => {
+                            eprintln!("error: could not receive message: {err}");
+                            continue;
+                        }
+                    };
+                let contacts = client.store().contact_store.get_contacts().await?;
+                let sender = contacts
+                    .into_iter()
+                    .find(|(_, account_id)| *account_id == source.account_id())
+                    .map(|(name, _)| name)
+                    .unwrap_or_else(|| source.account_id().to_string());
+                println!(
+                    "{sender}.{}: {}",
+                    source.device_id(),
+                    String::from_utf8_lossy(&plaintext)
+                );
+                received += 1;
+            }
+        }
+        Command::Register { .. } | Command::Link { .. } => {
+            unreachable!("Handled before loading the store")
+        }
+    }
+
+    Ok(())
+}
+
+/// Tops up the one-time keys of this device on the server and rotates its
+/// signed keys when they are due. Failures are reported but do not stop the
+/// caller, the next attempt may succeed.
+async fn maintain_keys(client: &mut CliClient) {
+    if let Err(err) = client.maintain_keys(&mut OsRng).await {
+        eprintln!("error: could not maintain keys: {err}");
+    }
+}
+
+fn new_client(
+    store: Store<SqliteStoreType>,
+    http: HttpClient,
+    trust_root: Option<PublicKey>,
+) -> CliClient {
+    let client = Client::new(store, http);
+    match trust_root {
+        Some(trust_root) => client.with_trust_root(trust_root),
+        None => client,
+    }
+}
+
+fn parse_trust_root(value: &str) -> Result<PublicKey, String> {
+    let bytes = BASE64_STANDARD
+        .decode(value.trim())
+        .map_err(|err| err.to_string())?;
+    PublicKey::deserialize(&bytes).map_err(|err| err.to_string())
+}
+
+/// Creates the store of a new device, a linked device reuses the identity of
+/// its account
+async fn new_store(
+    config: SqliteStoreConfig,
+    identity: Option<IdentityKeyPair>,
+) -> Result<Store<SqliteStoreType>, CliError> {
+    ensure_unregistered(&config).await?;
+
+    let mut csprng = OsRng;
+    let identity = identity.unwrap_or_else(|| IdentityKeyPair::generate(&mut csprng));
+    Ok(config
+        .create_store(identity, RegistrationId::generate(&mut csprng))
+        .await?)
+}
+
+async fn ensure_unregistered(config: &SqliteStoreConfig) -> Result<(), CliError> {
+    let existing = config.clone().load_store().await?;
+    if existing.account_store.get_account_id().await.is_ok() {
+        return Err(CliError::AlreadyRegistered);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use base64::{prelude::BASE64_STANDARD, Engine as _};
+    use clap::{CommandFactory as _, Parser as _};
+    use libsignal_protocol::{IdentityKeyPair, IdentityKeyStore as _, KeyPair};
+    use rand::rngs::OsRng;
+    use sam_client::storage::{sqlite::SqliteStoreConfig, AccountStore, StoreConfig};
+    use sam_common::address::AccountId;
+
+    use crate::{
+        ensure_unregistered, error::CliError, new_store, Cli, Command, DevicesCommand,
+        PasswordInput,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sam-cli-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_passwords_are_not_arguments() {
+        let cli = Cli::try_parse_from(["sam-cli", "register", "alice"]).expect("Can parse");
+        assert!(matches!(
+            cli.command,
+            Command::Register { username, password: PasswordInput { password_file: None }, .. }
+                if username == "alice"
+        ));
+        assert!(Cli::try_parse_from(["sam-cli", "register", "alice", "hunter2"]).is_err());
+        assert!(Cli::try_parse_from(["sam-cli", "link", "hunter2"]).is_err());
+        assert!(Cli::try_parse_from(["sam-cli", "password", "hunter2"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "sam-cli",
+            "devices",
+            "reset-password",
+            "2",
+            "--password-file",
+            "password.txt",
+        ])
+        .expect("Can parse");
+        assert!(matches!(
+            cli.command,
+            Command::Devices(DevicesCommand::ResetPassword {
+                device_id: 2,
+                password: PasswordInput { password_file: Some(path) },
+            }) if path == PathBuf::from("password.txt")
+        ));
+    }
+
+    #[test]
+    fn test_password_is_read_from_file() {
+        let path = temp_path("password");
+        std::fs::write(&path, "hunter2\nignored\n").expect("Can write password file");
+        let password = PasswordInput {
+            password_file: Some(path.clone()),
+        }
+        .read("Password: ");
+        std::fs::remove_file(&path).expect("Can remove password file");
+
+        assert!(password.is_ok_and(|password| password == "hunter2"));
+        assert!(matches!(
+            PasswordInput {
+                password_file: Some(path)
+            }
+            .read("Password: "),
+            Err(CliError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_trust_root_is_parsed() {
+        let trust_root = KeyPair::generate(&mut OsRng).public_key;
+        let encoded = BASE64_STANDARD.encode(trust_root.serialize());
+
+        let cli = Cli::try_parse_from(["sam-cli", "--trust-root", &encoded, "whoami"])
+            .expect("Can parse");
+        assert!(cli.trust_root == Some(trust_root));
+        assert!(Cli::try_parse_from(["sam-cli", "--trust-root", "not a key", "whoami"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_store_survives_a_restart() {
+        let path = temp_path("store.db");
+        let database = path.to_str().expect("Temp path is UTF-8");
+        let identity = IdentityKeyPair::generate(&mut OsRng);
+        let account_id = AccountId::generate();
+
+        let mut store = new_store(SqliteStoreConfig::file(database).await, Some(identity))
+            .await
+            .expect("Can create store");
+        store
+            .account_store
+            .set_account_id(account_id)
+            .await
+            .expect("Can store account id");
+        drop(store);
+
+        let config = SqliteStoreConfig::file(database).await;
+        let store = config.clone().load_store().await.expect("Can load store");
+        let stored_identity = store.identity_key_store.get_identity_key_pair().await;
+        let stored_account_id = store.account_store.get_account_id().await;
+        let unregistered = ensure_unregistered(&config).await;
+        std::fs::remove_file(&path).expect("Can remove store");
+
+        assert!(stored_identity.is_ok_and(|pair| pair.public_key() == identity.public_key()));
+        assert!(stored_account_id.is_ok_and(|id| id == account_id));
+        assert!(matches!(unregistered, Err(CliError::AlreadyRegistered)));
+    }
+}
@@ -0,0 +1,3 @@
+CREATE TABLE DeviceId (
+  device_id  INTEGER NOT NULL
+);
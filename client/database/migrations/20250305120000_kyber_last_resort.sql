@@ -0,0 +1 @@
+ALTER TABLE DeviceKyberPreKeyStore ADD COLUMN last_resort BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+CREATE TABLE UnidentifiedAccessKey (
+  access_key  BLOB NOT NULL
+);
@@ -0,0 +1,725 @@
+use std::{collections::HashMap, str::FromStr as _, time::SystemTime};
+
+use libsignal_protocol::{
+    kem, message_decrypt_prekey, message_decrypt_signal, message_encrypt, process_prekey_bundle,
+    CiphertextMessageType, IdentityKey, IdentityKeyStore, KyberPreKeyId, PreKeyId,
+    PreKeySignalMessage, ProtocolAddress, PublicKey, SenderCertificate, SessionStore,
+    SignalMessage, SignalProtocolError, SignedPreKeyId,
+};
+use rand::{CryptoRng, Rng};
+use sam_common::{
+    address::{AccountId, DeviceAddress, DeviceId},
+    api::{
+        device::{DeviceActivationInfo, DeviceInfo},
+        keys::{EcPreKey, PqPreKey, PublishPreKeys},
+        LinkDeviceRequest, LinkDeviceToken, PreKeyBundle, ProvisionMessage, RegistrationRequest,
+    },
+    sam_message::{
+        ClientEnvelope, EnvelopeType, ErrorType, MessageType, ServerEnvelope, ServerMessage,
+    },
+};
+use tokio::sync::watch;
+
+use crate::{
+    keygen::{KeyMaintenanceConfig, KeyManager, PRE_KEY_BATCH_SIZE},
+    provisioning::{encrypt_provision_message, ProvisioningUrl},
+    sealed_sender::{generate_access_key, seal, unseal},
+    signal_time_now,
+    storage::{AccountStore, Store, StoreType},
+    transport::{Credentials, HttpClient, WebSocketClient},
+    ClientError,
+};
+
+/// A sender certificate is fetched again this long before it expires
+const SENDER_CERTIFICATE_RENEW_MILLIS: u64 = 60 * 60 * 1000;
+
+/// Messaging on top of a [`Store`], sessions are set up from the key bundles
+/// published on the server and kept in the store.
+pub struct Client<T: StoreType> {
+    store: Store<T>,
+    http: HttpClient,
+    devices: HashMap<AccountId, Vec<DeviceId>>,
+    key_maintenance: KeyMaintenanceConfig,
+    /// Sealed senders are only trusted if their certificate chains up to it
+    trust_root: Option<PublicKey>,
+    sender_certificate: Option<SenderCertificate>,
+    /// Credentials the open websockets reconnect with
+    websocket_credentials: Option<watch::Sender<Credentials>>,
+}
+
+impl<T: StoreType> Client<T> {
+    pub fn new(store: Store<T>, http: HttpClient) -> Self {
+        Self {
+            store,
+            http,
+            devices: HashMap::new(),
+            key_maintenance: KeyMaintenanceConfig::default(),
+            trust_root: None,
+            sender_certificate: None,
+            websocket_credentials: None,
+        }
+    }
+
+    /// Sets the trust root of the server, which has to be obtained out of
+    /// band. Sealed messages cannot be opened without it.
+    pub fn with_trust_root(mut self, trust_root: PublicKey) -> Self {
+        self.trust_root = Some(trust_root);
+        self
+    }
+
+    pub fn with_key_maintenance(mut self, key_maintenance: KeyMaintenanceConfig) -> Self {
+        self.key_maintenance = key_maintenance;
+        self
+    }
+
+    pub fn key_maintenance(&self) -> &KeyMaintenanceConfig {
+        &self.key_maintenance
+    }
+
+    pub fn store(&self) -> &Store<T> {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut Store<T> {
+        &mut self.store
+    }
+
+    /// Registers a new account with this store as its primary device and
+    /// publishes a fresh key bundle
+    pub async fn register<R: Rng + CryptoRng>(
+        &mut self,
+        username: &str,
+        password: &str,
+        device_name: &str,
+        csprng: &mut R,
+    ) -> Result<AccountId, ClientError> {
+        let identity_key = *self
+            .store
+            .identity_key_store
+            .get_identity_key_pair()
+            .await?
+            .identity_key();
+        let registration_id = self
+            .store
+            .identity_key_store
+            .get_local_registration_id()
+            .await?;
+        let key_bundle = self.store.generate_key_bundle(csprng).await?;
+        let unidentified_access_key = generate_access_key(csprng);
+
+        let registration = RegistrationRequest {
+            identity_key,
+            device_activation: DeviceActivationInfo {
+                name: device_name.to_string(),
+                registration_id: registration_id.into(),
+                key_bundle: key_bundle.into(),
+            },
+            unidentified_access_key: Some(Box::from(unidentified_access_key.as_slice())),
+        };
+        let response = self
+            .http
+            .register(username, password, &registration)
+            .await?;
+
+        let account_store = &mut self.store.account_store;
+        account_store.set_account_id(response.account_id).await?;
+        account_store.set_device_id(1.into()).await?;
+        account_store.set_username(username.to_string()).await?;
+        account_store.set_password(password.to_string()).await?;
+        account_store
+            .set_unidentified_access_key(unidentified_access_key.to_vec())
+            .await?;
+
+        Ok(response.account_id)
+    }
+
+    /// Key that lets contacts send sealed messages to this account, it is
+    /// registered with the server and has to be handed to them
+    pub async fn unidentified_access_key(&self) -> Result<Vec<u8>, ClientError> {
+        self.store.account_store.get_unidentified_access_key().await
+    }
+
+    /// Finds the account that uses `username`, e.g. to add it as a contact
+    pub async fn lookup_username(&self, username: &str) -> Result<AccountId, ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.lookup_username(&credentials, username).await
+    }
+
+    /// Changes the username of the account, only the primary device may do
+    /// so. Other devices keep the old name in their store until they are
+    /// linked again.
+    pub async fn change_username(&mut self, username: &str) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.change_username(&credentials, username).await?;
+        self.store
+            .account_store
+            .set_username(username.to_string())
+            .await
+    }
+
+    /// Creates a token that lets a new device join this account, only the
+    /// primary device may do so
+    pub async fn provision_device(&self) -> Result<LinkDeviceToken, ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.provision_device(&credentials).await
+    }
+
+    /// Provisions the new device behind `url`. A link token is sent to it
+    /// together with the identity key pair of the account, encrypted so only
+    /// the new device can read them. The token is returned so it can be
+    /// revoked if the new device never links.
+    pub async fn provision_new_device<R: Rng + CryptoRng>(
+        &self,
+        url: &ProvisioningUrl,
+        csprng: &mut R,
+    ) -> Result<LinkDeviceToken, ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        let token = self.http.provision_device(&credentials).await?;
+        let identity_key_pair = self
+            .store
+            .identity_key_store
+            .get_identity_key_pair()
+            .await?;
+
+        let message = ProvisionMessage {
+            account_id: credentials.account_id,
+            username: self.store.account_store.get_username().await?,
+            identity_key_pair: identity_key_pair.serialize().to_vec(),
+            unidentified_access_key: self.unidentified_access_key().await?,
+            token: token.clone(),
+        };
+        let envelope = encrypt_provision_message(&url.public_key, &message, csprng)?;
+        self.http
+            .send_provisioning_message(&credentials, url.address, &envelope)
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Revokes a token from [`Client::provision_device`] that was handed out
+    /// but should no longer link a device
+    pub async fn revoke_device_token(&self, token_id: &str) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.revoke_device_token(&credentials, token_id).await
+    }
+
+    /// Devices linked to the account, to spot devices that should be unlinked
+    pub async fn devices(&self) -> Result<Vec<DeviceInfo>, ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        Ok(self.http.devices(&credentials).await?.devices)
+    }
+
+    /// Renames a device of the account, only the primary device may rename
+    /// devices other than itself
+    pub async fn rename_device(&self, device_id: DeviceId, name: &str) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.rename_device(&credentials, device_id, name).await
+    }
+
+    /// Makes another device the primary device of the account, this device
+    /// keeps working as a linked device
+    pub async fn transfer_primary(&self, device_id: DeviceId) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.transfer_primary(&credentials, device_id).await
+    }
+
+    /// Changes the password of this device and stores the new one, the
+    /// websocket stays connected and reconnects with the new password
+    pub async fn change_password(&mut self, password: &str) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http.change_password(&credentials, password).await?;
+        self.store
+            .account_store
+            .set_password(password.to_string())
+            .await?;
+        if let Some(websocket_credentials) = &self.websocket_credentials {
+            websocket_credentials.send_modify(|credentials| {
+                credentials.password = password.to_string();
+            });
+        }
+        Ok(())
+    }
+
+    /// Resets the password of a linked device, e.g. one that was lost, and
+    /// disconnects it. Only the primary device may do so, the reset device
+    /// needs the new password stored before it can connect again.
+    pub async fn reset_device_password(
+        &self,
+        device_id: DeviceId,
+        password: &str,
+    ) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        self.http
+            .reset_device_password(&credentials, device_id, password)
+            .await
+    }
+
+    /// Links this store as a new device of the account that created `token`.
+    /// The store must hold the identity key pair of that account,
+    /// `unidentified_access_key` is the one the account registered.
+    pub async fn link_device<R: Rng + CryptoRng>(
+        &mut self,
+        username: &str,
+        password: &str,
+        device_name: &str,
+        token: LinkDeviceToken,
+        unidentified_access_key: Vec<u8>,
+        csprng: &mut R,
+    ) -> Result<DeviceAddress, ClientError> {
+        let registration_id = self
+            .store
+            .identity_key_store
+            .get_local_registration_id()
+            .await?;
+        let key_bundle = self.store.generate_key_bundle(csprng).await?;
+
+        let request = LinkDeviceRequest {
+            token,
+            device_activation: DeviceActivationInfo {
+                name: device_name.to_string(),
+                registration_id: registration_id.into(),
+                key_bundle: key_bundle.into(),
+            },
+        };
+        let response = self.http.link_device(password, &request).await?;
+
+        let account_store = &mut self.store.account_store;
+        account_store.set_account_id(response.account_id).await?;
+        account_store.set_device_id(response.device_id).await?;
+        account_store.set_username(username.to_string()).await?;
+        account_store.set_password(password.to_string()).await?;
+        account_store
+            .set_unidentified_access_key(unidentified_access_key)
+            .await?;
+
+        Ok(DeviceAddress::new(response.account_id, response.device_id))
+    }
+
+    /// Replenishes and rotates the keys this device has published, see
+    /// [`Client::refill_pre_keys`] and [`Client::rotate_keys`]. Rotation only
+    /// happens when this is called, so a long running client should call it
+    /// every [`KeyMaintenanceConfig::check_interval`] and not only when a
+    /// message arrives.
+    pub async fn maintain_keys<R: Rng + CryptoRng>(
+        &mut self,
+        csprng: &mut R,
+    ) -> Result<(), ClientError> {
+        self.refill_pre_keys(csprng).await?;
+        self.rotate_keys(csprng).await
+    }
+
+    /// Uploads a new batch of one-time EC and Kyber keys for each kind the
+    /// server has fewer of than the configured threshold
+    pub async fn refill_pre_keys<R: Rng + CryptoRng>(
+        &mut self,
+        csprng: &mut R,
+    ) -> Result<(), ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        let count = self.http.pre_key_count(&credentials).await?;
+        let threshold = self.key_maintenance.pre_key_threshold;
+
+        let pre_keys = if count.pre_keys < threshold {
+            let keys = self
+                .store
+                .generate_pre_keys(PRE_KEY_BATCH_SIZE, csprng)
+                .await?;
+            Some(keys.into_iter().map(EcPreKey::from).collect())
+        } else {
+            None
+        };
+        let pq_pre_keys = if count.pq_pre_keys < threshold {
+            let keys = self
+                .store
+                .generate_kyber_pre_keys(PRE_KEY_BATCH_SIZE)
+                .await?;
+            Some(keys.into_iter().map(PqPreKey::from).collect())
+        } else {
+            None
+        };
+        if pre_keys.is_none() && pq_pre_keys.is_none() {
+            return Ok(());
+        }
+
+        let keys = PublishPreKeys {
+            pre_keys,
+            signed_pre_key: None,
+            pq_pre_keys,
+            pq_last_resort_pre_key: None,
+        };
+        self.http.publish_pre_keys(&credentials, &keys).await
+    }
+
+    /// Publishes a new signed pre key and last resort Kyber key once the
+    /// current ones are older than the rotation interval, and prunes keys that
+    /// were replaced more than the grace period ago
+    pub async fn rotate_keys<R: Rng + CryptoRng>(
+        &mut self,
+        csprng: &mut R,
+    ) -> Result<(), ClientError> {
+        let rotation_interval = self.key_maintenance.rotation_interval.as_millis() as u64;
+        let due = match self.store.signed_pre_key_timestamp().await? {
+            Some(timestamp) => {
+                timestamp.epoch_millis().saturating_add(rotation_interval)
+                    <= signal_time_now().epoch_millis()
+            }
+            None => true,
+        };
+
+        if due {
+            let credentials = Credentials::from_store(&self.store.account_store).await?;
+            let signed_pre_key = self.store.generate_signed_pre_key(csprng).await?;
+            let pq_last_resort_pre_key = self.store.generate_last_resort_kyber_pre_key().await?;
+
+            let keys = PublishPreKeys {
+                pre_keys: None,
+                signed_pre_key: Some(signed_pre_key.into()),
+                pq_pre_keys: None,
+                pq_last_resort_pre_key: Some(pq_last_resort_pre_key.into()),
+            };
+            self.http.publish_pre_keys(&credentials, &keys).await?;
+        }
+
+        self.store
+            .prune_rotating_keys(self.key_maintenance.grace_period)
+            .await
+    }
+
+    /// Fetches the key bundles of all devices of `account_id` and starts a new
+    /// session with each of them, returning the devices of the account
+    pub async fn process_pre_key_bundles<R: Rng + CryptoRng>(
+        &mut self,
+        account_id: AccountId,
+        csprng: &mut R,
+    ) -> Result<Vec<DeviceId>, ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        let bundles = self.http.pre_key_bundles(&credentials, account_id).await?;
+
+        let mut devices = Vec::new();
+        for bundle in bundles.bundles {
+            let device_id = DeviceId::from(bundle.device_id);
+            // a device never sends a message to itself
+            if account_id == credentials.account_id && device_id == credentials.device_id {
+                continue;
+            }
+
+            process_prekey_bundle(
+                &ProtocolAddress::new(account_id.to_string(), bundle.device_id.into()),
+                &mut self.store.session_store,
+                &mut self.store.identity_key_store,
+                &signal_pre_key_bundle(bundles.identity_key, bundle)?,
+                SystemTime::now(),
+                csprng,
+            )
+            .await?;
+            devices.push(device_id);
+        }
+
+        self.devices.insert(account_id, devices.clone());
+        Ok(devices)
+    }
+
+    /// Encrypts `plaintext` for every device of `recipient`. Sessions are set up
+    /// the first time a recipient is seen, call [`Client::process_pre_key_bundles`]
+    /// again when the server reports mismatched or stale devices.
+    pub async fn encrypt<R: Rng + CryptoRng>(
+        &mut self,
+        recipient: AccountId,
+        plaintext: &[u8],
+        csprng: &mut R,
+    ) -> Result<ClientEnvelope, ClientError> {
+        let devices = match self.devices.get(&recipient) {
+            Some(devices) => devices.clone(),
+            None => self.process_pre_key_bundles(recipient, csprng).await?,
+        };
+
+        let mut content = HashMap::new();
+        let mut registration_ids = HashMap::new();
+        for device_id in devices {
+            let address = ProtocolAddress::new(recipient.to_string(), (*device_id).into());
+            let message = message_encrypt(
+                plaintext,
+                &address,
+                &mut self.store.session_store,
+                &mut self.store.identity_key_store,
+                SystemTime::now(),
+            )
+            .await?;
+            let r#type = match message.message_type() {
+                CiphertextMessageType::PreKey => EnvelopeType::PreKeySignalMessage,
+                CiphertextMessageType::Whisper => EnvelopeType::SignalMessage,
+                _ => return Err(ClientError::UnsupportedEnvelopeType),
+            };
+
+            let session = self
+                .store
+                .session_store
+                .load_session(&address)
+                .await?
+                .ok_or_else(|| SignalProtocolError::SessionNotFound(address.clone()))?;
+            registration_ids.insert(device_id, session.remote_registration_id()?.into());
+            content.insert(device_id, (r#type, message.serialize().to_vec()));
+        }
+
+        let source = Credentials::from_store(&self.store.account_store).await?;
+        Ok(ClientEnvelope::new(
+            recipient,
+            DeviceAddress::new(source.account_id, source.device_id),
+            content,
+            registration_ids,
+        ))
+    }
+
+    /// Encrypts `plaintext` for every device of `recipient` as a sealed sender
+    /// envelope, the server only learns who it is for. `access_key` is the
+    /// [`Client::unidentified_access_key`] of the recipient.
+    pub async fn encrypt_sealed<R: Rng + CryptoRng>(
+        &mut self,
+        recipient: AccountId,
+        access_key: &[u8],
+        plaintext: &[u8],
+        csprng: &mut R,
+    ) -> Result<ClientEnvelope, ClientError> {
+        let devices = match self.devices.get(&recipient) {
+            Some(devices) => devices.clone(),
+            None => self.process_pre_key_bundles(recipient, csprng).await?,
+        };
+        let sender_certificate = self.sender_certificate().await?;
+
+        seal(
+            &mut self.store,
+            recipient,
+            &devices,
+            &sender_certificate,
+            access_key,
+            plaintext,
+            csprng,
+        )
+        .await
+    }
+
+    /// The cached sender certificate, a new one is fetched shortly before it expires
+    async fn sender_certificate(&mut self) -> Result<SenderCertificate, ClientError> {
+        let renew_at = signal_time_now()
+            .epoch_millis()
+            .saturating_add(SENDER_CERTIFICATE_RENEW_MILLIS);
+        if let Some(certificate) = &self.sender_certificate {
+            if certificate.expiration()?.epoch_millis() > renew_at {
+                return Ok(certificate.clone());
+            }
+        }
+
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        let response = self.http.sender_certificate(&credentials).await?;
+        let certificate = SenderCertificate::deserialize(&response.certificate)?;
+        self.sender_certificate = Some(certificate.clone());
+        Ok(certificate)
+    }
+
+    /// Opens the message websocket with the credentials kept in the store
+    pub async fn connect_websocket(&mut self) -> Result<WebSocketClient, ClientError> {
+        let credentials = Credentials::from_store(&self.store.account_store).await?;
+        let receiver = match &self.websocket_credentials {
+            Some(sender) => {
+                sender.send_replace(credentials);
+                sender.subscribe()
+            }
+            None => {
+                let (sender, receiver) = watch::channel(credentials);
+                self.websocket_credentials = Some(sender);
+                receiver
+            }
+        };
+        WebSocketClient::connect(self.http.websocket_url(), receiver).await
+    }
+
+    /// Encrypts and sends `plaintext` to every device of `recipient`. If the
+    /// server reports that our view of the recipient's devices is outdated the
+    /// key bundles are fetched again and the message is sent once more.
+    pub async fn send_message<R: Rng + CryptoRng>(
+        &mut self,
+        websocket: &WebSocketClient,
+        recipient: AccountId,
+        plaintext: &[u8],
+        csprng: &mut R,
+    ) -> Result<(), ClientError> {
+        let envelope = self.encrypt(recipient, plaintext, csprng).await?;
+        match delivery_error(websocket.send(envelope).await?) {
+            Some(ErrorType::MismatchedDevices | ErrorType::StaleDevices) => {}
+            Some(error) => return Err(ClientError::MessageRejected(error)),
+            None => return Ok(()),
+        }
+
+        self.process_pre_key_bundles(recipient, csprng).await?;
+        let envelope = self.encrypt(recipient, plaintext, csprng).await?;
+        match delivery_error(websocket.send(envelope).await?) {
+            Some(error) => Err(ClientError::MessageRejected(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends `plaintext` to every device of `recipient` like
+    /// [`Client::send_message`], but as a sealed sender envelope authorized by
+    /// the recipient's `access_key`
+    pub async fn send_sealed_message<R: Rng + CryptoRng>(
+        &mut self,
+        websocket: &WebSocketClient,
+        recipient: AccountId,
+        access_key: &[u8],
+        plaintext: &[u8],
+        csprng: &mut R,
+    ) -> Result<(), ClientError> {
+        let envelope = self
+            .encrypt_sealed(recipient, access_key, plaintext, csprng)
+            .await?;
+        match delivery_error(websocket.send(envelope).await?) {
+            Some(ErrorType::MismatchedDevices | ErrorType::StaleDevices) => {}
+            Some(error) => return Err(ClientError::MessageRejected(error)),
+            None => return Ok(()),
+        }
+
+        self.process_pre_key_bundles(recipient, csprng).await?;
+        let envelope = self
+            .encrypt_sealed(recipient, access_key, plaintext, csprng)
+            .await?;
+        match delivery_error(websocket.send(envelope).await?) {
+            Some(error) => Err(ClientError::MessageRejected(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits for the next message, it is acknowledged once it has been
+    /// decrypted and the session state has been stored
+    pub async fn receive_message<R: Rng + CryptoRng>(
+        &mut self,
+        websocket: &mut WebSocketClient,
+        csprng: &mut R,
+    ) -> Result<(DeviceAddress, Vec<u8>), ClientError> {
+        websocket
+            .receive(|envelope| async move { self.decrypt(&envelope, csprng).await })
+            .await
+    }
+
+    /// Decrypts an envelope and returns the device that sent it with the
+    /// plaintext. The sender of a sealed envelope is taken from its sender
+    /// certificate, which must chain up to the configured trust root.
+    pub async fn decrypt<R: Rng + CryptoRng>(
+        &mut self,
+        envelope: &ServerEnvelope,
+        csprng: &mut R,
+    ) -> Result<(DeviceAddress, Vec<u8>), ClientError> {
+        if envelope.r#type() == EnvelopeType::UnidentifiedSender {
+            return self.decrypt_sealed(envelope).await;
+        }
+
+        let source = envelope_source(envelope)?;
+        let address = ProtocolAddress::new(
+            source.account_id().to_string(),
+            (*source.device_id()).into(),
+        );
+
+        let plaintext = match envelope.r#type() {
+            EnvelopeType::PreKeySignalMessage => message_decrypt_prekey(
+                &PreKeySignalMessage::try_from(envelope.content.as_slice())?,
+                &address,
+                &mut self.store.session_store,
+                &mut self.store.identity_key_store,
+                &mut self.store.pre_key_store,
+                &self.store.signed_pre_key_store,
+                &mut self.store.kyber_pre_key_store,
+                csprng,
+            )
+            .await
+            .map_err(ClientError::from),
+            EnvelopeType::SignalMessage => {
+                self.decrypt_signal(&address, &envelope.content, csprng)
+                    .await
+            }
+            _ => Err(ClientError::UnsupportedEnvelopeType),
+        }?;
+        Ok((source, plaintext))
+    }
+
+    async fn decrypt_sealed(
+        &mut self,
+        envelope: &ServerEnvelope,
+    ) -> Result<(DeviceAddress, Vec<u8>), ClientError> {
+        let trust_root = self.trust_root.ok_or(ClientError::NoTrustRoot)?;
+        let local_device_id = self.store.account_store.get_device_id().await?;
+        let opened = unseal(
+            &mut self.store,
+            &envelope.content,
+            &trust_root,
+            local_device_id,
+        )
+        .await?;
+
+        let sender = opened.sender_uuid()?;
+        let account_id = AccountId::from_str(sender)
+            .map_err(|_| ClientError::InvalidServiceId(sender.to_string()))?;
+        let device_id = DeviceId::from(u32::from(opened.device_id()?));
+        Ok((DeviceAddress::new(account_id, device_id), opened.message))
+    }
+
+    async fn decrypt_signal<R: Rng + CryptoRng>(
+        &mut self,
+        address: &ProtocolAddress,
+        content: &[u8],
+        csprng: &mut R,
+    ) -> Result<Vec<u8>, ClientError> {
+        Ok(message_decrypt_signal(
+            &SignalMessage::try_from(content)?,
+            address,
+            &mut self.store.session_store,
+            &mut self.store.identity_key_store,
+            csprng,
+        )
+        .await?)
+    }
+}
+
+fn envelope_source(envelope: &ServerEnvelope) -> Result<DeviceAddress, ClientError> {
+    let (account_id, device_id) = envelope
+        .source_account_id
+        .clone()
+        .zip(envelope.source_device_id)
+        .ok_or(ClientError::NoEnvelopeSource)?;
+    let account_id = AccountId::try_from(account_id.clone())
+        .map_err(|_| ClientError::InvalidServiceId(format!("{:?}", account_id)))?;
+    Ok(DeviceAddress::new(account_id, device_id.into()))
+}
+
+/// The reason the server gave for rejecting a message, `None` if it was accepted
+fn delivery_error(response: ServerMessage) -> Option<ErrorType> {
+    match response.r#type() {
+        MessageType::Error => Some(response.error()),
+        _ => None,
+    }
+}
+
+fn signal_pre_key_bundle(
+    identity_key: IdentityKey,
+    bundle: PreKeyBundle,
+) -> Result<libsignal_protocol::PreKeyBundle, ClientError> {
+    let pre_key = match bundle.pre_key {
+        Some(key) => Some((
+            PreKeyId::from(key.key_id),
+            PublicKey::deserialize(&key.public_key)?,
+        )),
+        None => None,
+    };
+
+    Ok(libsignal_protocol::PreKeyBundle::new(
+        bundle.registration_id,
+        bundle.device_id.into(),
+        pre_key,
+        SignedPreKeyId::from(bundle.signed_pre_key.key_id),
+        PublicKey::deserialize(&bundle.signed_pre_key.public_key)?,
+        bundle.signed_pre_key.signature.to_vec(),
+        identity_key,
+    )?
+    .with_kyber_pre_key(
+        KyberPreKeyId::from(bundle.pq_pre_key.key_id),
+        kem::PublicKey::deserialize(&bundle.pq_pre_key.public_key)?,
+        bundle.pq_pre_key.signature.to_vec(),
+    ))
+}
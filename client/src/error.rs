@@ -1,15 +1,20 @@
 use derive_more::derive::{Display, Error, From};
 use libsignal_core::curve::CurveError;
 use libsignal_protocol::SignalProtocolError;
-use sam_common::LibError;
+use sam_common::{api::ErrorResponse, sam_message::ErrorType, LibError};
 use sqlx::{sqlite::SqliteError, Error as SqlxError};
 use std::panic::AssertUnwindSafe;
+use tokio_tungstenite::tungstenite::Error as WebSocketError;
 
 #[derive(Debug, Display, Error, From)]
 pub enum ClientError {
     #[display("Failed to parse an invalid ServiceId: {_0}")]
     #[error(ignore)]
     InvalidServiceId(String),
+    #[display("No contact named {_0}")]
+    #[error(ignore)]
+    #[from(ignore)]
+    UnknownContact(String),
     SignalProtocol(SignalProtocolError),
     Sqlite(SqliteError),
     #[from(ignore)]
@@ -17,9 +22,27 @@ pub enum ClientError {
     Sqlx(AssertUnwindSafe<SqlxError>),
     Lib(LibError),
     Curve(CurveError),
+    Http(reqwest::Error),
+    #[display("Server error {_0}")]
+    Server(ErrorResponse),
+    WebSocket(WebSocketError),
+    #[display("Server rejected message: {_0:?}")]
+    #[error(ignore)]
+    #[from(ignore)]
+    MessageRejected(ErrorType),
     NoAccountId,
+    NoDeviceId,
     NoPassword,
     NoUsername,
+    NoAccessKey,
+    NoEnvelopeSource,
+    NoTrustRoot,
+    UnsupportedEnvelopeType,
+    WebSocketClosed,
+    WebSocketDisconnected,
+    InvalidProvisioningUrl,
+    ProvisioningMessageMalformed,
+    ProvisioningDecryptionFailed,
 }
 
 impl From<SqlxError> for ClientError {
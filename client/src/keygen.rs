@@ -1,13 +1,45 @@
+use std::time::Duration;
+
 use crate::signal_time_now;
-use crate::storage::{ProvidesKeyId, Store, StoreType};
+use crate::storage::{ProvidesKeyId, RotatingKeyStore, Store, StoreType};
 use crate::ClientError;
 use async_trait::async_trait;
 use libsignal_protocol::kem::KeyType;
 use libsignal_protocol::{
     GenericSignedPreKey, IdentityKeyStore, KeyPair, KyberPreKeyRecord, KyberPreKeyStore,
-    PreKeyRecord, PreKeyStore, SignedPreKeyRecord, SignedPreKeyStore,
+    PreKeyRecord, PreKeyStore, SignedPreKeyRecord, SignedPreKeyStore, Timestamp,
 };
 use rand::{CryptoRng, Rng};
+use sam_common::api::keys::{EcPreKey, PqPreKey, RegistrationPreKeys};
+
+/// Number of one-time keys of each kind generated at a time
+pub const PRE_KEY_BATCH_SIZE: usize = 100;
+
+/// When the keys published for a device are replenished and rotated
+#[derive(Debug, Clone)]
+pub struct KeyMaintenanceConfig {
+    /// A new batch of one-time keys is uploaded once the server has fewer left
+    pub pre_key_threshold: u32,
+    /// How often the signed pre key and the last resort Kyber key are replaced
+    pub rotation_interval: Duration,
+    /// How long a replaced key is kept after its successor was created, so
+    /// messages encrypted for it while it was still published can be decrypted
+    pub grace_period: Duration,
+    /// How often a long running client checks whether keys have to be
+    /// replenished or rotated, independent of incoming messages
+    pub check_interval: Duration,
+}
+
+impl Default for KeyMaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            pre_key_threshold: 10,
+            rotation_interval: Duration::from_secs(7 * 24 * 60 * 60),
+            grace_period: Duration::from_secs(30 * 24 * 60 * 60),
+            check_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct PreKeyCollection {
@@ -17,6 +49,17 @@ pub struct PreKeyCollection {
     pub pq_pre_keys: Vec<KyberPreKeyRecord>,
 }
 
+impl From<PreKeyCollection> for RegistrationPreKeys {
+    fn from(value: PreKeyCollection) -> Self {
+        Self {
+            pre_keys: Some(value.pre_keys.into_iter().map(EcPreKey::from).collect()),
+            signed_pre_key: value.signed_pre_key.into(),
+            pq_pre_keys: Some(value.pq_pre_keys.into_iter().map(PqPreKey::from).collect()),
+            pq_last_resort_pre_key: value.pq_last_resort_pre_key.into(),
+        }
+    }
+}
+
 #[async_trait(?Send)]
 pub trait KeyManager {
     async fn generate_pre_key<R: Rng + CryptoRng>(
@@ -31,10 +74,34 @@ pub trait KeyManager {
 
     async fn generate_kyber_pre_key(&mut self) -> Result<KyberPreKeyRecord, ClientError>;
 
+    async fn generate_pre_keys<R: Rng + CryptoRng>(
+        &mut self,
+        count: usize,
+        csprng: &mut R,
+    ) -> Result<Vec<PreKeyRecord>, ClientError>;
+
+    async fn generate_kyber_pre_keys(
+        &mut self,
+        count: usize,
+    ) -> Result<Vec<KyberPreKeyRecord>, ClientError>;
+
+    /// Unlike one-time Kyber keys the last resort key is kept until it is
+    /// rotated out, see [`KeyManager::prune_rotating_keys`]
+    async fn generate_last_resort_kyber_pre_key(
+        &mut self,
+    ) -> Result<KyberPreKeyRecord, ClientError>;
+
     async fn generate_key_bundle<R: Rng + CryptoRng>(
         &mut self,
         csprng: &mut R,
     ) -> Result<PreKeyCollection, ClientError>;
+
+    /// Creation time of the newest signed pre key, `None` if there is none yet
+    async fn signed_pre_key_timestamp(&self) -> Result<Option<Timestamp>, ClientError>;
+
+    /// Removes signed pre keys and last resort Kyber keys whose successor was
+    /// created more than `grace_period` ago. The newest keys are always kept.
+    async fn prune_rotating_keys(&mut self, grace_period: Duration) -> Result<(), ClientError>;
 }
 
 #[async_trait(?Send)]
@@ -71,7 +138,7 @@ impl<T: StoreType> KeyManager for Store<T> {
             SignedPreKeyRecord::new(id, signal_time_now(), &signed_pre_key_pair, &signature);
 
         self.signed_pre_key_store
-            .save_signed_pre_key(id, &record)
+            .save_rotating_key(id, &record)
             .await?;
 
         Ok(record)
@@ -94,24 +161,65 @@ impl<T: StoreType> KeyManager for Store<T> {
         Ok(record)
     }
 
-    async fn generate_key_bundle<R>(
+    async fn generate_pre_keys<R>(
         &mut self,
-        mut csprng: &mut R,
-    ) -> Result<PreKeyCollection, ClientError>
+        count: usize,
+        csprng: &mut R,
+    ) -> Result<Vec<PreKeyRecord>, ClientError>
     where
         R: Rng + CryptoRng,
     {
-        let mut pre_keys: Vec<PreKeyRecord> = Vec::new();
-        let mut pq_pre_keys: Vec<KyberPreKeyRecord> = Vec::new();
-
-        for _ in 0..100 {
-            pre_keys.push(self.generate_pre_key(&mut csprng).await?);
+        let mut pre_keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            pre_keys.push(self.generate_pre_key(csprng).await?);
+        }
+        Ok(pre_keys)
+    }
 
+    async fn generate_kyber_pre_keys(
+        &mut self,
+        count: usize,
+    ) -> Result<Vec<KyberPreKeyRecord>, ClientError> {
+        let mut pq_pre_keys = Vec::with_capacity(count);
+        for _ in 0..count {
             pq_pre_keys.push(self.generate_kyber_pre_key().await?);
         }
+        Ok(pq_pre_keys)
+    }
+
+    async fn generate_last_resort_kyber_pre_key(
+        &mut self,
+    ) -> Result<KyberPreKeyRecord, ClientError> {
+        let id = self.kyber_pre_key_store.next_key_id().await?;
+        let record = KyberPreKeyRecord::generate(
+            KeyType::Kyber1024,
+            id,
+            self.identity_key_store
+                .get_identity_key_pair()
+                .await?
+                .private_key(),
+        )?;
+
+        self.kyber_pre_key_store
+            .save_rotating_key(id, &record)
+            .await?;
+        Ok(record)
+    }
+
+    async fn generate_key_bundle<R>(
+        &mut self,
+        mut csprng: &mut R,
+    ) -> Result<PreKeyCollection, ClientError>
+    where
+        R: Rng + CryptoRng,
+    {
+        let pre_keys = self
+            .generate_pre_keys(PRE_KEY_BATCH_SIZE, &mut csprng)
+            .await?;
+        let pq_pre_keys = self.generate_kyber_pre_keys(PRE_KEY_BATCH_SIZE).await?;
 
         let signed_pre_key = self.generate_signed_pre_key(&mut csprng).await?;
-        let pq_last_resort_pre_key = self.generate_kyber_pre_key().await?;
+        let pq_last_resort_pre_key = self.generate_last_resort_kyber_pre_key().await?;
 
         Ok(PreKeyCollection {
             pre_keys,
@@ -120,4 +228,122 @@ impl<T: StoreType> KeyManager for Store<T> {
             pq_last_resort_pre_key,
         })
     }
+
+    async fn signed_pre_key_timestamp(&self) -> Result<Option<Timestamp>, ClientError> {
+        let Some(id) = self
+            .signed_pre_key_store
+            .rotating_key_ids()
+            .await?
+            .last()
+            .copied()
+        else {
+            return Ok(None);
+        };
+        let record = self.signed_pre_key_store.get_signed_pre_key(id).await?;
+        Ok(Some(record.timestamp()?))
+    }
+
+    async fn prune_rotating_keys(&mut self, grace_period: Duration) -> Result<(), ClientError> {
+        let cutoff = signal_time_now()
+            .epoch_millis()
+            .saturating_sub(grace_period.as_millis() as u64);
+
+        let mut signed_pre_keys = Vec::new();
+        for id in self.signed_pre_key_store.rotating_key_ids().await? {
+            let record = self.signed_pre_key_store.get_signed_pre_key(id).await?;
+            signed_pre_keys.push((id, record.timestamp()?));
+        }
+        for id in superseded(&signed_pre_keys, cutoff) {
+            self.signed_pre_key_store.remove_rotating_key(id).await?;
+        }
+
+        let mut last_resort_keys = Vec::new();
+        for id in self.kyber_pre_key_store.rotating_key_ids().await? {
+            let record = self.kyber_pre_key_store.get_kyber_pre_key(id).await?;
+            last_resort_keys.push((id, record.timestamp()?));
+        }
+        for id in superseded(&last_resort_keys, cutoff) {
+            self.kyber_pre_key_store.remove_rotating_key(id).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keys, oldest first, whose successor was created at or before `cutoff`
+fn superseded<I: Copy>(keys: &[(I, Timestamp)], cutoff: u64) -> Vec<I> {
+    keys.windows(2)
+        .filter(|pair| pair[1].1.epoch_millis() <= cutoff)
+        .map(|pair| pair[0].0)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use libsignal_protocol::{GenericSignedPreKey, KyberPreKeyStore};
+    use rand::rngs::OsRng;
+
+    use crate::storage::{inmem::InMemoryStoreConfig, RotatingKeyStore, StoreConfig};
+
+    use super::KeyManager;
+
+    #[tokio::test]
+    async fn test_rotated_keys_are_pruned_after_grace_period() {
+        let mut store = InMemoryStoreConfig::default()
+            .load_store()
+            .await
+            .expect("Can create store");
+        let bundle = store
+            .generate_key_bundle(&mut OsRng)
+            .await
+            .expect("Can generate key bundle");
+        let signed_pre_key = store
+            .generate_signed_pre_key(&mut OsRng)
+            .await
+            .expect("Can rotate signed pre key");
+        let last_resort_key = store
+            .generate_last_resort_kyber_pre_key()
+            .await
+            .expect("Can rotate last resort key");
+
+        store
+            .prune_rotating_keys(Duration::from_secs(60 * 60))
+            .await
+            .expect("Can prune keys");
+        assert!(store
+            .signed_pre_key_store
+            .rotating_key_ids()
+            .await
+            .is_ok_and(|ids| ids.len() == 2));
+        assert!(store
+            .kyber_pre_key_store
+            .rotating_key_ids()
+            .await
+            .is_ok_and(|ids| ids.len() == 2));
+
+        store
+            .prune_rotating_keys(Duration::ZERO)
+            .await
+            .expect("Can prune keys");
+        assert!(store
+            .signed_pre_key_store
+            .rotating_key_ids()
+            .await
+            .is_ok_and(|ids| ids == vec![signed_pre_key.id().expect("Has id")]));
+        assert!(store
+            .kyber_pre_key_store
+            .rotating_key_ids()
+            .await
+            .is_ok_and(|ids| ids == vec![last_resort_key.id().expect("Has id")]));
+
+        // one-time keys are not rotating keys and stay
+        let pq_pre_key = bundle.pq_pre_keys.first().expect("Has pq pre key");
+        assert!(store
+            .kyber_pre_key_store
+            .get_kyber_pre_key(pq_pre_key.id().expect("Has id"))
+            .await
+            .is_ok());
+    }
 }
@@ -1,8 +1,13 @@
+pub mod client;
 pub mod error;
 pub mod keygen;
+pub mod provisioning;
+pub mod sealed_sender;
 pub mod storage;
 pub mod time;
+pub mod transport;
 
+pub use client::Client;
 pub use error::ClientError;
 
 pub use time::signal_time_now;
@@ -0,0 +1,248 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine as _};
+use hkdf::Hkdf;
+use libsignal_protocol::{IdentityKeyPair, KeyPair, PublicKey};
+use rand::{CryptoRng, Rng};
+use sam_common::{
+    address::{AccountId, ProvisioningId},
+    api::{LinkDeviceToken, ProvisionEnvelope, ProvisionMessage},
+};
+use sha2::Sha256;
+
+use crate::{
+    transport::{HttpClient, ProvisioningSocket},
+    ClientError,
+};
+
+const PROVISIONING_INFO: &[u8] = b"SAM Provisioning Message";
+const NONCE_LENGTH: usize = 12;
+
+/// Shown by a new device, e.g. as a QR code, and entered on the primary
+/// device. It tells the primary device where to send the provisioning message
+/// and whom to encrypt it to.
+#[derive(Debug, Clone)]
+pub struct ProvisioningUrl {
+    pub address: ProvisioningId,
+    pub public_key: PublicKey,
+}
+
+impl Display for ProvisioningUrl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sam://link?address={}&key={}",
+            self.address,
+            BASE64_URL_SAFE_NO_PAD.encode(self.public_key.serialize())
+        )
+    }
+}
+
+impl FromStr for ProvisioningUrl {
+    type Err = ClientError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let query = url
+            .trim()
+            .strip_prefix("sam://link?")
+            .ok_or(ClientError::InvalidProvisioningUrl)?;
+
+        let mut address = None;
+        let mut public_key = None;
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match name {
+                "address" => address = ProvisioningId::from_str(value).ok(),
+                "key" => {
+                    public_key = BASE64_URL_SAFE_NO_PAD
+                        .decode(value)
+                        .ok()
+                        .and_then(|key| PublicKey::deserialize(&key).ok())
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            address: address.ok_or(ClientError::InvalidProvisioningUrl)?,
+            public_key: public_key.ok_or(ClientError::InvalidProvisioningUrl)?,
+        })
+    }
+}
+
+/// What a new device learns from the primary device of the account it joins
+pub struct ProvisionedAccount {
+    pub account_id: AccountId,
+    pub username: String,
+    pub identity_key_pair: IdentityKeyPair,
+    pub unidentified_access_key: Vec<u8>,
+    pub token: LinkDeviceToken,
+}
+
+/// A new device waiting on the provisioning websocket. The ephemeral key
+/// pair only lives as long as the session, so a provisioning url can only be
+/// used once.
+pub struct ProvisioningSession {
+    socket: ProvisioningSocket,
+    key_pair: KeyPair,
+}
+
+impl ProvisioningSession {
+    pub async fn start<R: Rng + CryptoRng>(
+        http: &HttpClient,
+        csprng: &mut R,
+    ) -> Result<Self, ClientError> {
+        Ok(Self {
+            socket: ProvisioningSocket::connect(&http.provisioning_url()).await?,
+            key_pair: KeyPair::generate(csprng),
+        })
+    }
+
+    /// Has to be handed to the primary device
+    pub fn url(&self) -> ProvisioningUrl {
+        ProvisioningUrl {
+            address: self.socket.address(),
+            public_key: self.key_pair.public_key,
+        }
+    }
+
+    /// Waits for the primary device to send the provisioning message
+    pub async fn receive(self) -> Result<ProvisionedAccount, ClientError> {
+        let envelope = self.socket.receive().await?;
+        let message = decrypt_provision_message(&self.key_pair, &envelope)?;
+
+        Ok(ProvisionedAccount {
+            account_id: message.account_id,
+            username: message.username,
+            identity_key_pair: IdentityKeyPair::try_from(message.identity_key_pair.as_slice())?,
+            unidentified_access_key: message.unidentified_access_key,
+            token: message.token,
+        })
+    }
+}
+
+/// Encrypts `message` to the ephemeral key of a new device. The key is agreed
+/// with a fresh ephemeral key of our own, which is sent along in the envelope.
+pub fn encrypt_provision_message<R: Rng + CryptoRng>(
+    recipient: &PublicKey,
+    message: &ProvisionMessage,
+    csprng: &mut R,
+) -> Result<ProvisionEnvelope, ClientError> {
+    let ephemeral = KeyPair::generate(csprng);
+    let public_key = ephemeral.public_key.serialize().to_vec();
+    let cipher = provisioning_cipher(&ephemeral.private_key.calculate_agreement(recipient)?)?;
+
+    let plaintext =
+        serde_json::to_vec(message).map_err(|_| ClientError::ProvisioningMessageMalformed)?;
+    let nonce: [u8; NONCE_LENGTH] = csprng.gen();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &public_key,
+            },
+        )
+        .map_err(|_| ClientError::ProvisioningMessageMalformed)?;
+
+    Ok(ProvisionEnvelope {
+        public_key,
+        body: [nonce.as_slice(), &ciphertext].concat(),
+    })
+}
+
+pub fn decrypt_provision_message(
+    key_pair: &KeyPair,
+    envelope: &ProvisionEnvelope,
+) -> Result<ProvisionMessage, ClientError> {
+    if envelope.body.len() < NONCE_LENGTH {
+        return Err(ClientError::ProvisioningMessageMalformed);
+    }
+    let (nonce, ciphertext) = envelope.body.split_at(NONCE_LENGTH);
+
+    let sender = PublicKey::deserialize(&envelope.public_key)?;
+    let cipher = provisioning_cipher(&key_pair.private_key.calculate_agreement(&sender)?)?;
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &envelope.public_key,
+            },
+        )
+        .map_err(|_| ClientError::ProvisioningDecryptionFailed)?;
+
+    serde_json::from_slice(&plaintext).map_err(|_| ClientError::ProvisioningMessageMalformed)
+}
+
+fn provisioning_cipher(shared_secret: &[u8]) -> Result<Aes256Gcm, ClientError> {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, shared_secret)
+        .expand(PROVISIONING_INFO, &mut key)
+        .map_err(|_| ClientError::ProvisioningDecryptionFailed)?;
+    Aes256Gcm::new_from_slice(&key).map_err(|_| ClientError::ProvisioningDecryptionFailed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use libsignal_protocol::{IdentityKeyPair, KeyPair};
+    use rand::rngs::OsRng;
+    use sam_common::{
+        address::{AccountId, ProvisioningId},
+        api::{LinkDeviceToken, ProvisionMessage},
+    };
+
+    use crate::ClientError;
+
+    use super::{decrypt_provision_message, encrypt_provision_message, ProvisioningUrl};
+
+    #[test]
+    fn test_provision_message_roundtrip() {
+        let mut rng = OsRng;
+        let new_device = KeyPair::generate(&mut rng);
+        let identity = IdentityKeyPair::generate(&mut rng);
+        let message = ProvisionMessage {
+            account_id: AccountId::generate(),
+            username: "alice".to_string(),
+            identity_key_pair: identity.serialize().to_vec(),
+            unidentified_access_key: vec![7; 16],
+            token: LinkDeviceToken::new("id".to_string(), "token".to_string()),
+        };
+
+        let envelope = encrypt_provision_message(&new_device.public_key, &message, &mut rng)
+            .expect("Can encrypt provision message");
+        let decrypted =
+            decrypt_provision_message(&new_device, &envelope).expect("New device can decrypt");
+        assert!(decrypted.account_id == message.account_id);
+        assert!(decrypted.identity_key_pair == message.identity_key_pair);
+        assert!(decrypted.unidentified_access_key == message.unidentified_access_key);
+        assert!(decrypted.token.token() == "token");
+
+        let eavesdropper = KeyPair::generate(&mut rng);
+        assert!(matches!(
+            decrypt_provision_message(&eavesdropper, &envelope),
+            Err(ClientError::ProvisioningDecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_provisioning_url_roundtrip() {
+        let url = ProvisioningUrl {
+            address: ProvisioningId::generate(),
+            public_key: KeyPair::generate(&mut OsRng).public_key,
+        };
+
+        let parsed = ProvisioningUrl::from_str(&url.to_string()).expect("Can parse url");
+        assert!(parsed.address == url.address);
+        assert!(parsed.public_key == url.public_key);
+        assert!(ProvisioningUrl::from_str("sam://link?address=nope").is_err());
+    }
+}
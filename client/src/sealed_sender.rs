@@ -0,0 +1,231 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use libsignal_protocol::{
+    sealed_sender_decrypt, sealed_sender_encrypt, ProtocolAddress, PublicKey,
+    SealedSenderDecryptionResult, SenderCertificate, SessionStore, SignalProtocolError,
+};
+use rand::{CryptoRng, Rng};
+use sam_common::{
+    address::{AccountId, DeviceId},
+    sam_message::ClientEnvelope,
+};
+
+use crate::{
+    signal_time_now,
+    storage::{AccountStore, Store, StoreType},
+    ClientError,
+};
+
+pub const ACCESS_KEY_LENGTH: usize = 16;
+
+/// Generates the key others present to the server when they send sealed
+/// messages to the account. It is chosen when the account is registered and
+/// handed to linked devices when they are provisioned, contacts have to be
+/// given it, e.g. in an identified message.
+pub fn generate_access_key<R: Rng + CryptoRng>(csprng: &mut R) -> [u8; ACCESS_KEY_LENGTH] {
+    let mut key = [0u8; ACCESS_KEY_LENGTH];
+    csprng.fill(&mut key);
+    key
+}
+
+/// Encrypts `plaintext` for each of the recipient's `devices` and wraps it in a sealed
+/// sender envelope, so the server only learns who the message is for.
+///
+/// A session must already exist with every device and `unidentified_access_key`
+/// must be the key the recipient registered with the server.
+pub async fn seal<T: StoreType, R: Rng + CryptoRng>(
+    store: &mut Store<T>,
+    recipient: AccountId,
+    devices: &[DeviceId],
+    sender_certificate: &SenderCertificate,
+    unidentified_access_key: &[u8],
+    plaintext: &[u8],
+    csprng: &mut R,
+) -> Result<ClientEnvelope, ClientError> {
+    let mut content = HashMap::new();
+    let mut registration_ids = HashMap::new();
+
+    for device_id in devices {
+        let address = ProtocolAddress::new(recipient.to_string(), (**device_id).into());
+        let session = store
+            .session_store
+            .load_session(&address)
+            .await?
+            .ok_or_else(|| SignalProtocolError::SessionNotFound(address.clone()))?;
+        registration_ids.insert(*device_id, session.remote_registration_id()?.into());
+
+        let ciphertext = sealed_sender_encrypt(
+            &address,
+            sender_certificate,
+            plaintext,
+            &mut store.session_store,
+            &mut store.identity_key_store,
+            SystemTime::now(),
+            csprng,
+        )
+        .await?;
+        content.insert(*device_id, ciphertext);
+    }
+
+    Ok(ClientEnvelope::unidentified(
+        recipient,
+        content,
+        registration_ids,
+        unidentified_access_key.to_vec(),
+    ))
+}
+
+/// Opens the content of a sealed sender envelope addressed to this device, the
+/// sender is only trusted if its certificate chains up to `trust_root`
+pub async fn unseal<T: StoreType>(
+    store: &mut Store<T>,
+    ciphertext: &[u8],
+    trust_root: &PublicKey,
+    local_device_id: DeviceId,
+) -> Result<SealedSenderDecryptionResult, ClientError> {
+    let local_account_id = store.account_store.get_account_id().await?;
+
+    Ok(sealed_sender_decrypt(
+        ciphertext,
+        trust_root,
+        signal_time_now(),
+        None,
+        local_account_id.to_string(),
+        (*local_device_id).into(),
+        &mut store.identity_key_store,
+        &mut store.session_store,
+        &mut store.pre_key_store,
+        &store.signed_pre_key_store,
+        &mut store.kyber_pre_key_store,
+    )
+    .await?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use libsignal_protocol::{
+        process_prekey_bundle, GenericSignedPreKey, IdentityKeyPair, KeyPair, PreKeyBundle,
+        ProtocolAddress, SenderCertificate, ServerCertificate, Timestamp,
+    };
+    use rand::rngs::OsRng;
+    use sam_common::{
+        address::{AccountId, DeviceId},
+        sam_message::EnvelopeType,
+    };
+
+    use crate::{
+        keygen::KeyManager,
+        storage::{inmem::InMemoryStoreConfig, AccountStore, StoreConfig},
+    };
+
+    use super::{generate_access_key, seal, unseal};
+
+    #[test]
+    fn test_access_keys_are_random() {
+        let mut rng = OsRng;
+        assert!(generate_access_key(&mut rng) != generate_access_key(&mut rng));
+    }
+
+    #[tokio::test]
+    async fn test_seal_and_unseal() {
+        let mut rng = OsRng;
+        let alice_pair = IdentityKeyPair::generate(&mut rng);
+        let bob_pair = IdentityKeyPair::generate(&mut rng);
+        let alice_id = AccountId::generate();
+        let bob_id = AccountId::generate();
+
+        let mut alice = InMemoryStoreConfig::default()
+            .create_store(alice_pair, 1u32)
+            .await
+            .expect("Can create alice store");
+        alice
+            .account_store
+            .set_account_id(alice_id)
+            .await
+            .expect("Can set account id");
+        let mut bob = InMemoryStoreConfig::default()
+            .create_store(bob_pair, 2u32)
+            .await
+            .expect("Can create bob store");
+        bob.account_store
+            .set_account_id(bob_id)
+            .await
+            .expect("Can set account id");
+
+        let keys = bob
+            .generate_key_bundle(&mut rng)
+            .await
+            .expect("Can generate key bundle");
+        let pre_key = keys.pre_keys.first().expect("Has pre key");
+        let pq_pre_key = keys.pq_pre_keys.first().expect("Has pq pre key");
+        let bundle = PreKeyBundle::new(
+            2,
+            1.into(),
+            Some((
+                pre_key.id().expect("Has id"),
+                pre_key.public_key().expect("Has public key"),
+            )),
+            keys.signed_pre_key.id().expect("Has id"),
+            keys.signed_pre_key.public_key().expect("Has public key"),
+            keys.signed_pre_key.signature().expect("Has signature"),
+            *bob_pair.identity_key(),
+        )
+        .expect("Can create bundle")
+        .with_kyber_pre_key(
+            pq_pre_key.id().expect("Has id"),
+            pq_pre_key.public_key().expect("Has public key"),
+            pq_pre_key.signature().expect("Has signature"),
+        );
+        process_prekey_bundle(
+            &ProtocolAddress::new(bob_id.to_string(), 1.into()),
+            &mut alice.session_store,
+            &mut alice.identity_key_store,
+            &bundle,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await
+        .expect("Alice can process bob's bundle");
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+        let server_certificate =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)
+                .expect("Can create server certificate");
+        let sender_certificate = SenderCertificate::new(
+            alice_id.to_string(),
+            None,
+            *alice_pair.public_key(),
+            1.into(),
+            Timestamp::from_epoch_millis(u64::MAX),
+            server_certificate,
+            &server_key.private_key,
+            &mut rng,
+        )
+        .expect("Can create sender certificate");
+
+        let envelope = seal(
+            &mut alice,
+            bob_id,
+            &[DeviceId::from(1)],
+            &sender_certificate,
+            &[7; 16],
+            b"hi bob<3",
+            &mut rng,
+        )
+        .await
+        .expect("Alice can seal message");
+        assert!(envelope.r#type() == EnvelopeType::UnidentifiedSender);
+        assert!(envelope.source_account_id.is_none());
+        assert!(envelope.registration_ids.get(&1) == Some(&2));
+
+        let ciphertext = envelope.content.get(&1).expect("Has content for bob");
+        let opened = unseal(&mut bob, ciphertext, &trust_root.public_key, 1.into())
+            .await
+            .expect("Bob can unseal message");
+        assert!(opened.message == b"hi bob<3");
+        assert!(opened.sender_uuid().expect("Has sender") == alice_id.to_string());
+    }
+}
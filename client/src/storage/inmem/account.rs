@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use sam_common::address::AccountId;
+use sam_common::address::{AccountId, DeviceId};
 
 use crate::{storage::AccountStore, ClientError};
 
@@ -7,7 +7,9 @@ use crate::{storage::AccountStore, ClientError};
 pub struct InMemoryAccountStore {
     username: Option<String>,
     account_id: Option<AccountId>,
+    device_id: Option<DeviceId>,
     password: Option<String>,
+    unidentified_access_key: Option<Vec<u8>>,
 }
 
 #[async_trait(?Send)]
@@ -19,6 +21,13 @@ impl AccountStore for InMemoryAccountStore {
     async fn get_account_id(&self) -> Result<AccountId, ClientError> {
         Ok(self.account_id.ok_or(ClientError::NoAccountId)?)
     }
+    async fn set_device_id(&mut self, device_id: DeviceId) -> Result<(), ClientError> {
+        self.device_id = Some(device_id);
+        Ok(())
+    }
+    async fn get_device_id(&self) -> Result<DeviceId, ClientError> {
+        Ok(self.device_id.ok_or(ClientError::NoDeviceId)?)
+    }
     async fn set_password(&mut self, password: String) -> Result<(), ClientError> {
         self.password = Some(password);
         Ok(())
@@ -33,4 +42,14 @@ impl AccountStore for InMemoryAccountStore {
     async fn get_username(&self) -> Result<String, ClientError> {
         Ok(self.username.clone().ok_or(ClientError::NoUsername)?)
     }
+    async fn set_unidentified_access_key(&mut self, key: Vec<u8>) -> Result<(), ClientError> {
+        self.unidentified_access_key = Some(key);
+        Ok(())
+    }
+    async fn get_unidentified_access_key(&self) -> Result<Vec<u8>, ClientError> {
+        Ok(self
+            .unidentified_access_key
+            .clone()
+            .ok_or(ClientError::NoAccessKey)?)
+    }
 }
@@ -1,6 +1,32 @@
-use crate::storage::ContactStore;
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use sam_common::address::AccountId;
+
+use crate::{storage::ContactStore, ClientError};
 
 #[derive(Debug, Default)]
-pub struct InMemoryContactStore {}
+pub struct InMemoryContactStore {
+    contacts: BTreeMap<String, AccountId>,
+}
 
-impl ContactStore for InMemoryContactStore {}
+#[async_trait(?Send)]
+impl ContactStore for InMemoryContactStore {
+    async fn add_contact(&mut self, name: &str, account_id: AccountId) -> Result<(), ClientError> {
+        self.contacts.insert(name.to_string(), account_id);
+        Ok(())
+    }
+    async fn get_contact(&self, name: &str) -> Result<AccountId, ClientError> {
+        self.contacts
+            .get(name)
+            .copied()
+            .ok_or_else(|| ClientError::UnknownContact(name.to_string()))
+    }
+    async fn get_contacts(&self) -> Result<Vec<(String, AccountId)>, ClientError> {
+        Ok(self
+            .contacts
+            .iter()
+            .map(|(name, account_id)| (name.clone(), *account_id))
+            .collect())
+    }
+}
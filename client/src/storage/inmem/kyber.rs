@@ -1,13 +1,54 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use async_trait::async_trait;
-use libsignal_protocol::{InMemKyberPreKeyStore, KyberPreKeyId};
+use libsignal_protocol::{KyberPreKeyId, KyberPreKeyRecord, KyberPreKeyStore, SignalProtocolError};
+
+use crate::{
+    storage::{ProvidesKeyId, RotatingKeyStore},
+    ClientError,
+};
+
+#[derive(Debug, Default)]
+pub struct InMemoryKyberPreKeyStore {
+    kyber_pre_keys: BTreeMap<KyberPreKeyId, KyberPreKeyRecord>,
+    last_resort_keys: BTreeSet<KyberPreKeyId>,
+}
+
+#[async_trait(?Send)]
+impl KyberPreKeyStore for InMemoryKyberPreKeyStore {
+    async fn get_kyber_pre_key(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+    ) -> Result<KyberPreKeyRecord, SignalProtocolError> {
+        self.kyber_pre_keys
+            .get(&kyber_prekey_id)
+            .cloned()
+            .ok_or(SignalProtocolError::InvalidKyberPreKeyId)
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.kyber_pre_keys.insert(kyber_prekey_id, record.clone());
+        Ok(())
+    }
 
-use crate::{storage::ProvidesKeyId, ClientError};
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        _kyber_prekey_id: KyberPreKeyId,
+    ) -> Result<(), SignalProtocolError> {
+        Ok(())
+    }
+}
 
 #[async_trait(?Send)]
-impl ProvidesKeyId<KyberPreKeyId> for InMemKyberPreKeyStore {
+impl ProvidesKeyId<KyberPreKeyId> for InMemoryKyberPreKeyStore {
     async fn next_key_id(&self) -> Result<KyberPreKeyId, ClientError> {
         let max: u32 = self
-            .all_kyber_pre_key_ids()
+            .kyber_pre_keys
+            .keys()
             .max()
             .cloned()
             .map(|id| id.into())
@@ -15,3 +56,28 @@ impl ProvidesKeyId<KyberPreKeyId> for InMemKyberPreKeyStore {
         Ok((max + 1).into())
     }
 }
+
+/// Only last resort keys rotate, one-time keys are never pruned
+#[async_trait(?Send)]
+impl RotatingKeyStore<KyberPreKeyId, KyberPreKeyRecord> for InMemoryKyberPreKeyStore {
+    async fn save_rotating_key(
+        &mut self,
+        id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<(), ClientError> {
+        self.save_kyber_pre_key(id, record).await?;
+        self.last_resort_keys.insert(id);
+        Ok(())
+    }
+
+    async fn rotating_key_ids(&self) -> Result<Vec<KyberPreKeyId>, ClientError> {
+        Ok(self.last_resort_keys.iter().cloned().collect())
+    }
+
+    async fn remove_rotating_key(&mut self, id: KyberPreKeyId) -> Result<(), ClientError> {
+        if self.last_resort_keys.remove(&id) {
+            self.kyber_pre_keys.remove(&id);
+        }
+        Ok(())
+    }
+}
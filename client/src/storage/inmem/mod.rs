@@ -4,12 +4,14 @@ use super::{Store, StoreConfig, StoreType};
 use account::InMemoryAccountStore;
 use async_trait::async_trait;
 use contact::InMemoryContactStore;
+use kyber::InMemoryKyberPreKeyStore;
 use libsignal_protocol::{
-    IdentityKeyPair, InMemIdentityKeyStore, InMemKyberPreKeyStore, InMemPreKeyStore,
-    InMemSenderKeyStore, InMemSessionStore, InMemSignedPreKeyStore,
+    IdentityKeyPair, InMemIdentityKeyStore, InMemPreKeyStore, InMemSenderKeyStore,
+    InMemSessionStore,
 };
 use rand::rngs::OsRng;
 use sam_common::address::RegistrationId;
+use signed_pre_key::InMemorySignedPreKeyStore;
 
 pub mod account;
 pub mod contact;
@@ -29,9 +31,9 @@ impl StoreType for InMemoryStoreType {
 
     type PreKeyStore = InMemPreKeyStore;
 
-    type SignedPreKeyStore = InMemSignedPreKeyStore;
+    type SignedPreKeyStore = InMemorySignedPreKeyStore;
 
-    type KyberPreKeyStore = InMemKyberPreKeyStore;
+    type KyberPreKeyStore = InMemoryKyberPreKeyStore;
 
     type SessionStore = InMemSessionStore;
 
@@ -54,8 +56,8 @@ impl StoreConfig for InMemoryStoreConfig {
         Ok(InMemoryStore::builder()
             .identity_key_store(InMemIdentityKeyStore::new(key_pair, registration_id.into()))
             .pre_key_store(InMemPreKeyStore::default())
-            .signed_pre_key_store(InMemSignedPreKeyStore::default())
-            .kyber_pre_key_store(InMemKyberPreKeyStore::default())
+            .signed_pre_key_store(InMemorySignedPreKeyStore::default())
+            .kyber_pre_key_store(InMemoryKyberPreKeyStore::default())
             .sender_key_store(InMemSenderKeyStore::default())
             .session_store(InMemSessionStore::default())
             .account_store(InMemoryAccountStore::default())
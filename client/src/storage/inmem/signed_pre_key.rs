@@ -1,13 +1,45 @@
-use crate::storage::ProvidesKeyId;
+use std::collections::BTreeMap;
+
+use crate::storage::{ProvidesKeyId, RotatingKeyStore};
 use crate::ClientError;
 use async_trait::async_trait;
-use libsignal_protocol::{InMemSignedPreKeyStore, SignedPreKeyId};
+use libsignal_protocol::{
+    SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord, SignedPreKeyStore,
+};
+
+#[derive(Debug, Default)]
+pub struct InMemorySignedPreKeyStore {
+    signed_pre_keys: BTreeMap<SignedPreKeyId, SignedPreKeyRecord>,
+}
+
+#[async_trait(?Send)]
+impl SignedPreKeyStore for InMemorySignedPreKeyStore {
+    async fn get_signed_pre_key(
+        &self,
+        id: SignedPreKeyId,
+    ) -> Result<SignedPreKeyRecord, SignalProtocolError> {
+        self.signed_pre_keys
+            .get(&id)
+            .cloned()
+            .ok_or(SignalProtocolError::InvalidSignedPreKeyId)
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.signed_pre_keys.insert(id, record.clone());
+        Ok(())
+    }
+}
 
 #[async_trait(?Send)]
-impl ProvidesKeyId<SignedPreKeyId> for InMemSignedPreKeyStore {
+impl ProvidesKeyId<SignedPreKeyId> for InMemorySignedPreKeyStore {
     async fn next_key_id(&self) -> Result<SignedPreKeyId, ClientError> {
         let max: u32 = self
-            .all_signed_pre_key_ids()
+            .signed_pre_keys
+            .keys()
             .max()
             .cloned()
             .map(|id| id.into())
@@ -15,3 +47,23 @@ impl ProvidesKeyId<SignedPreKeyId> for InMemSignedPreKeyStore {
         Ok((max + 1).into())
     }
 }
+
+#[async_trait(?Send)]
+impl RotatingKeyStore<SignedPreKeyId, SignedPreKeyRecord> for InMemorySignedPreKeyStore {
+    async fn save_rotating_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<(), ClientError> {
+        Ok(self.save_signed_pre_key(id, record).await?)
+    }
+
+    async fn rotating_key_ids(&self) -> Result<Vec<SignedPreKeyId>, ClientError> {
+        Ok(self.signed_pre_keys.keys().cloned().collect())
+    }
+
+    async fn remove_rotating_key(&mut self, id: SignedPreKeyId) -> Result<(), ClientError> {
+        self.signed_pre_keys.remove(&id);
+        Ok(())
+    }
+}
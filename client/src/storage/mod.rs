@@ -2,8 +2,9 @@ use crate::ClientError;
 use async_trait::async_trait;
 use bon::Builder;
 use libsignal_protocol::{
-    IdentityKeyPair, IdentityKeyStore, KyberPreKeyId, KyberPreKeyStore, PreKeyId, PreKeyStore,
-    SenderKeyStore, SessionStore, SignedPreKeyId, SignedPreKeyStore,
+    IdentityKeyPair, IdentityKeyStore, KyberPreKeyId, KyberPreKeyRecord, KyberPreKeyStore,
+    PreKeyId, PreKeyStore, SenderKeyStore, SessionStore, SignedPreKeyId, SignedPreKeyRecord,
+    SignedPreKeyStore,
 };
 use std::fmt::Debug;
 
@@ -31,13 +32,30 @@ pub trait ProvidesKeyId<T> {
     async fn next_key_id(&self) -> Result<T, ClientError>;
 }
 
+/// Keys that are replaced on a schedule instead of being used up, i.e. signed
+/// pre keys and last resort Kyber keys. Replaced keys are kept for a while so
+/// messages that were encrypted with them can still be decrypted.
+#[async_trait(?Send)]
+pub trait RotatingKeyStore<I, R> {
+    async fn save_rotating_key(&mut self, id: I, record: &R) -> Result<(), ClientError>;
+
+    /// Ids of all stored rotating keys, oldest first
+    async fn rotating_key_ids(&self) -> Result<Vec<I>, ClientError>;
+
+    async fn remove_rotating_key(&mut self, id: I) -> Result<(), ClientError>;
+}
+
 pub trait StoreType {
     type ContactStore: ContactStore;
     type AccountStore: AccountStore;
     type IdentityKeyStore: IdentityKeyStore;
     type PreKeyStore: PreKeyStore + ProvidesKeyId<PreKeyId>;
-    type SignedPreKeyStore: SignedPreKeyStore + ProvidesKeyId<SignedPreKeyId>;
-    type KyberPreKeyStore: KyberPreKeyStore + ProvidesKeyId<KyberPreKeyId>;
+    type SignedPreKeyStore: SignedPreKeyStore
+        + ProvidesKeyId<SignedPreKeyId>
+        + RotatingKeyStore<SignedPreKeyId, SignedPreKeyRecord>;
+    type KyberPreKeyStore: KyberPreKeyStore
+        + ProvidesKeyId<KyberPreKeyId>
+        + RotatingKeyStore<KyberPreKeyId, KyberPreKeyRecord>;
     type SessionStore: SessionStore;
     type SenderKeyStore: SenderKeyStore;
 }
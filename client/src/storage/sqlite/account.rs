@@ -2,7 +2,7 @@ use std::str::FromStr as _;
 
 use crate::{storage::AccountStore, ClientError};
 use async_trait::async_trait;
-use sam_common::address::AccountId;
+use sam_common::address::{AccountId, DeviceId};
 use sqlx::{Error as SqlxError, Pool, Sqlite};
 
 #[derive(Debug)]
@@ -51,6 +51,37 @@ impl AccountStore for SqliteAccountStore {
         }
     }
 
+    async fn set_device_id(&mut self, device_id: DeviceId) -> Result<(), ClientError> {
+        let id = *device_id;
+        sqlx::query!(
+            r#"
+            DELETE FROM DeviceId;
+            INSERT INTO DeviceId
+            VALUES (?)
+            "#,
+            id
+        )
+        .execute(&self.database)
+        .await
+        .map(|_| ())
+        .map_err(ClientError::from)
+    }
+
+    async fn get_device_id(&self) -> Result<DeviceId, ClientError> {
+        match sqlx::query!(
+            r#"
+            SELECT * FROM DeviceId;
+            "#,
+        )
+        .fetch_one(&self.database)
+        .await
+        {
+            Err(SqlxError::RowNotFound) => Err(ClientError::NoDeviceId),
+            Ok(rec) => Ok(DeviceId::from(rec.device_id as u32)),
+            Err(err) => Err(ClientError::from(err)),
+        }
+    }
+
     async fn set_password(&mut self, password: String) -> Result<(), ClientError> {
         sqlx::query!(
             r#"
@@ -110,4 +141,34 @@ impl AccountStore for SqliteAccountStore {
             Err(err) => Err(ClientError::from(err)),
         }
     }
+
+    async fn set_unidentified_access_key(&mut self, key: Vec<u8>) -> Result<(), ClientError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM UnidentifiedAccessKey;
+            INSERT INTO UnidentifiedAccessKey
+            VALUES (?)
+            "#,
+            key
+        )
+        .execute(&self.database)
+        .await
+        .map(|_| ())
+        .map_err(ClientError::from)
+    }
+
+    async fn get_unidentified_access_key(&self) -> Result<Vec<u8>, ClientError> {
+        match sqlx::query!(
+            r#"
+            SELECT * FROM UnidentifiedAccessKey;
+            "#,
+        )
+        .fetch_one(&self.database)
+        .await
+        {
+            Err(SqlxError::RowNotFound) => Err(ClientError::NoAccessKey),
+            Ok(rec) => Ok(rec.access_key),
+            Err(err) => Err(ClientError::from(err)),
+        }
+    }
 }
@@ -1,88 +1,72 @@
-use sqlx::{Pool, Sqlite};
+use std::str::FromStr as _;
 
-use crate::storage::ContactStore;
+use async_trait::async_trait;
+use sam_common::address::AccountId;
+use sqlx::{Error as SqlxError, Pool, Sqlite};
+
+use crate::{storage::ContactStore, ClientError};
 
 #[derive(Debug)]
 pub struct SqliteContactStore {
-    _database: Pool<Sqlite>,
+    database: Pool<Sqlite>,
 }
 
 impl SqliteContactStore {
     pub fn new(database: Pool<Sqlite>) -> Self {
-        Self {
-            _database: database,
-        }
+        Self { database }
     }
 }
 
-impl ContactStore for SqliteContactStore {}
-
-/*
-
-   #[tokio::test]
-   async fn store_and_load_contact() {
-       // store_contact
-       // load_contact
-       let device = Device::new(connect().await);
-
-       let contacts = vec![new_contact(), new_contact(), new_contact()];
-
-       device.store_contact(&contacts[0]).await.unwrap();
-       device.store_contact(&contacts[1]).await.unwrap();
-       device.store_contact(&contacts[2]).await.unwrap();
-
-       let retrived_contacts = device.load_contacts().await.unwrap();
-
-       assert_eq!(contacts, retrived_contacts);
-   }
-
-   #[tokio::test]
-   async fn insert_and_get_address_by_nickname() {
-       // insert_address_for_nickname
-       // get_address_by_nickname
-       let device = Device::new(connect().await);
-
-       let nicknames = vec!["Alice", "Bob", "Charlie"];
-
-       let nickname_map = HashMap::from([
-           (nicknames[0], new_service_id()),
-           (nicknames[1], new_service_id()),
-           (nicknames[2], new_service_id()),
-       ]);
+#[async_trait(?Send)]
+impl ContactStore for SqliteContactStore {
+    async fn add_contact(&mut self, name: &str, account_id: AccountId) -> Result<(), ClientError> {
+        let service_id = account_id.to_string();
+        sqlx::query!(
+            r#"
+            INSERT INTO Nicknames (name, service_id)
+            VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET service_id = excluded.service_id
+            "#,
+            name,
+            service_id
+        )
+        .execute(&self.database)
+        .await
+        .map(|_| ())
+        .map_err(ClientError::from)
+    }
 
-       device
-           .insert_service_id_for_nickname(nicknames[0], &nickname_map[nicknames[0]])
-           .await
-           .unwrap();
-       device
-           .insert_service_id_for_nickname(nicknames[1], &nickname_map[nicknames[1]])
-           .await
-           .unwrap();
-       device
-           .insert_service_id_for_nickname(nicknames[2], &nickname_map[nicknames[2]])
-           .await
-           .unwrap();
+    async fn get_contact(&self, name: &str) -> Result<AccountId, ClientError> {
+        match sqlx::query!(
+            r#"
+            SELECT service_id FROM Nicknames WHERE name = ?
+            "#,
+            name
+        )
+        .fetch_one(&self.database)
+        .await
+        {
+            Err(SqlxError::RowNotFound) => Err(ClientError::UnknownContact(name.to_string())),
+            Ok(rec) => AccountId::from_str(&rec.service_id)
+                .map_err(|_| ClientError::InvalidServiceId(rec.service_id)),
+            Err(err) => Err(ClientError::from(err)),
+        }
+    }
 
-       assert_eq!(
-           device
-               .get_service_id_by_nickname(nicknames[0])
-               .await
-               .unwrap(),
-           nickname_map[nicknames[0]]
-       );
-       assert_eq!(
-           device
-               .get_service_id_by_nickname(nicknames[1])
-               .await
-               .unwrap(),
-           nickname_map[nicknames[1]]
-       );
-       assert_eq!(
-           device
-               .get_service_id_by_nickname(nicknames[2])
-               .await
-               .unwrap(),
-           nickname_map[nicknames[2]]
-       );
-   }
-*/
+    async fn get_contacts(&self) -> Result<Vec<(String, AccountId)>, ClientError> {
+        sqlx::query!(
+            r#"
+            SELECT name, service_id FROM Nicknames ORDER BY name
+            "#
+        )
+        .fetch_all(&self.database)
+        .await?
+        .into_iter()
+        .map(|rec| {
+            AccountId::from_str(&rec.service_id)
+                .map(|account_id| (rec.name, account_id))
+                .map_err(|_| ClientError::InvalidServiceId(rec.service_id))
+        })
+        .collect()
+    }
+}
@@ -6,7 +6,10 @@ use libsignal_protocol::{
 };
 use sqlx::{Pool, Sqlite};
 
-use crate::{storage::ProvidesKeyId, ClientError};
+use crate::{
+    storage::{ProvidesKeyId, RotatingKeyStore},
+    ClientError,
+};
 
 #[derive(Debug)]
 pub struct SqliteKyberPreKeyStore {
@@ -24,26 +27,26 @@ impl ProvidesKeyId<KyberPreKeyId> for SqliteKyberPreKeyStore {
     async fn next_key_id(&self) -> Result<KyberPreKeyId, ClientError> {
         sqlx::query!(
             r#"
-            WITH max_signed_pre_key_id_table AS (
+            WITH max_kyber_pre_key_id_table AS (
                 SELECT
                     1 AS _id,
-                    MAX(signed_pre_key_id) AS max_signed_pre_key_id
+                    MAX(kyber_pre_key_id) AS max_kyber_pre_key_id
                 FROM
-                    DeviceSignedPreKeyStore
+                    DeviceKyberPreKeyStore
                 )
                 SELECT
-                    CASE WHEN spk.max_signed_pre_key_id IS NOT NULL
-                    THEN spk.max_signed_pre_key_id
+                    CASE WHEN kpk.max_kyber_pre_key_id IS NOT NULL
+                    THEN kpk.max_kyber_pre_key_id
                     ELSE
                     0
-                    END AS spkid
+                    END AS kpkid
                 FROM
-                    max_signed_pre_key_id_table spk
+                    max_kyber_pre_key_id_table kpk
                 "#
         )
         .fetch_one(&self.database)
         .await
-        .map(|row| KyberPreKeyId::from(row.spkid as u32))
+        .map(|row| KyberPreKeyId::from(row.kpkid as u32 + 1))
         .map_err(ClientError::from)
     }
 }
@@ -122,3 +125,67 @@ impl KyberPreKeyStore for SqliteKyberPreKeyStore {
         Ok(())
     }
 }
+
+/// Only last resort keys rotate, one-time keys are never pruned
+#[async_trait(?Send)]
+impl RotatingKeyStore<KyberPreKeyId, KyberPreKeyRecord> for SqliteKyberPreKeyStore {
+    async fn save_rotating_key(
+        &mut self,
+        id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<(), ClientError> {
+        let id: u32 = id.into();
+        let rec = BASE64_STANDARD.encode(record.serialize()?);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO DeviceKyberPreKeyStore (kyber_pre_key_id, kyber_pre_key_record, last_resort)
+            VALUES (?, ?, TRUE)
+            ON CONFLICT(kyber_pre_key_id) DO UPDATE SET kyber_pre_key_record = ?, last_resort = TRUE
+            "#,
+            id,
+            rec,
+            rec
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(())
+    }
+
+    async fn rotating_key_ids(&self) -> Result<Vec<KyberPreKeyId>, ClientError> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                kyber_pre_key_id
+            FROM
+                DeviceKyberPreKeyStore
+            WHERE
+                last_resort
+            ORDER BY
+                kyber_pre_key_id
+            "#
+        )
+        .fetch_all(&self.database)
+        .await?
+        .into_iter()
+        .map(|row| KyberPreKeyId::from(row.kyber_pre_key_id as u32))
+        .collect())
+    }
+
+    async fn remove_rotating_key(&mut self, id: KyberPreKeyId) -> Result<(), ClientError> {
+        let id: u32 = id.into();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM
+                DeviceKyberPreKeyStore
+            WHERE
+                kyber_pre_key_id = ? AND last_resort
+            "#,
+            id
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(())
+    }
+}
@@ -8,7 +8,10 @@ use pre_key::SqlitePreKeyStore;
 use sender_key::SqliteSenderKeyStore;
 use session::SqliteSessionStore;
 use signed_pre_key::SqliteSignedPreKeyStore;
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Pool, Sqlite,
+};
 
 use crate::ClientError;
 
@@ -51,7 +54,7 @@ impl StoreType for SqliteStoreType {
 }
 
 pub type SqliteStore = Store<SqliteStoreType>;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SqliteStoreConfig {
     database: Pool<Sqlite>,
 }
@@ -70,6 +73,23 @@ pub async fn connect_to_in_memory() -> Pool<Sqlite> {
     pool
 }
 
+/// Opens the database file at `path`, creating it if it does not exist yet
+pub async fn connect_to_file(path: &str) -> Pool<Sqlite> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    let pool = SqlitePoolOptions::new()
+        .connect_with(options)
+        .await
+        .expect("Could not connect to database");
+    sqlx::migrate!("database/migrations")
+        .run(&pool)
+        .await
+        .expect("should be able to run migrations");
+
+    pool
+}
+
 impl SqliteStoreConfig {
     pub fn new(database: Pool<Sqlite>) -> Self {
         Self { database }
@@ -78,6 +98,10 @@ impl SqliteStoreConfig {
         let database = connect_to_in_memory().await;
         Self { database }
     }
+    pub async fn file(path: &str) -> Self {
+        let database = connect_to_file(path).await;
+        Self { database }
+    }
 }
 
 type BuilderProperties = SetSessionStore<
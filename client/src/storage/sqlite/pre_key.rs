@@ -24,9 +24,9 @@ impl ProvidesKeyId<PreKeyId> for SqlitePreKeyStore {
             WITH max_pre_key_id_table AS (
                 SELECT
                     1 AS _id,
-                    MAX(id) AS max_pre_key_id
+                    MAX(pre_key_id) AS max_pre_key_id
                 FROM
-                    DeviceSignedPreKeyStore
+                    DevicePreKeyStore
                 )
                 SELECT
                     CASE WHEN pk.max_pre_key_id IS NOT NULL
@@ -40,7 +40,7 @@ impl ProvidesKeyId<PreKeyId> for SqlitePreKeyStore {
         )
         .fetch_one(&self.database)
         .await
-        .map(|row| PreKeyId::from(row.pkid as u32))
+        .map(|row| PreKeyId::from(row.pkid as u32 + 1))
         .map_err(ClientError::from)
     }
 }
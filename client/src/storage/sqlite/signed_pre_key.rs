@@ -6,7 +6,10 @@ use libsignal_protocol::{
 };
 use sqlx::{Pool, Sqlite};
 
-use crate::{storage::ProvidesKeyId, ClientError};
+use crate::{
+    storage::{ProvidesKeyId, RotatingKeyStore},
+    ClientError,
+};
 
 #[derive(Debug)]
 pub struct SqliteSignedPreKeyStore {
@@ -27,23 +30,23 @@ impl ProvidesKeyId<SignedPreKeyId> for SqliteSignedPreKeyStore {
             WITH max_signed_pre_key_id_table AS (
                 SELECT
                     1 AS _id,
-                    MAX(id) AS max_signed_pre_key_id
+                    MAX(signed_pre_key_id) AS max_signed_pre_key_id
                 FROM
                     DeviceSignedPreKeyStore
                 )
                 SELECT
-                    CASE WHEN pk.max_signed_pre_key_id IS NOT NULL
-                    THEN pk.max_signed_pre_key_id
+                    CASE WHEN spk.max_signed_pre_key_id IS NOT NULL
+                    THEN spk.max_signed_pre_key_id
                     ELSE
                     0
-                    END AS pkid
+                    END AS spkid
                 FROM
-                    max_signed_pre_key_id_table pk
+                    max_signed_pre_key_id_table spk
                 "#
         )
         .fetch_one(&self.database)
         .await
-        .map(|row| SignedPreKeyId::from(row.pkid as u32))
+        .map(|row| SignedPreKeyId::from(row.spkid as u32 + 1))
         .map_err(ClientError::from)
     }
 }
@@ -123,3 +126,49 @@ impl SignedPreKeyStore for SqliteSignedPreKeyStore {
         })
     }
 }
+
+#[async_trait(?Send)]
+impl RotatingKeyStore<SignedPreKeyId, SignedPreKeyRecord> for SqliteSignedPreKeyStore {
+    async fn save_rotating_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<(), ClientError> {
+        Ok(self.save_signed_pre_key(id, record).await?)
+    }
+
+    async fn rotating_key_ids(&self) -> Result<Vec<SignedPreKeyId>, ClientError> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                signed_pre_key_id
+            FROM
+                DeviceSignedPreKeyStore
+            ORDER BY
+                signed_pre_key_id
+            "#
+        )
+        .fetch_all(&self.database)
+        .await?
+        .into_iter()
+        .map(|row| SignedPreKeyId::from(row.signed_pre_key_id as u32))
+        .collect())
+    }
+
+    async fn remove_rotating_key(&mut self, id: SignedPreKeyId) -> Result<(), ClientError> {
+        let id: u32 = id.into();
+
+        sqlx::query!(
+            r#"
+            DELETE FROM
+                DeviceSignedPreKeyStore
+            WHERE
+                signed_pre_key_id = ?
+            "#,
+            id
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use sam_common::address::AccountId;
+use sam_common::address::{AccountId, DeviceId};
 
 use crate::ClientError;
 
@@ -7,8 +7,12 @@ use crate::ClientError;
 pub trait AccountStore {
     async fn set_account_id(&mut self, account_id: AccountId) -> Result<(), ClientError>;
     async fn get_account_id(&self) -> Result<AccountId, ClientError>;
+    async fn set_device_id(&mut self, device_id: DeviceId) -> Result<(), ClientError>;
+    async fn get_device_id(&self) -> Result<DeviceId, ClientError>;
     async fn set_password(&mut self, password: String) -> Result<(), ClientError>;
     async fn get_password(&self) -> Result<String, ClientError>;
     async fn set_username(&mut self, username: String) -> Result<(), ClientError>;
     async fn get_username(&self) -> Result<String, ClientError>;
+    async fn set_unidentified_access_key(&mut self, key: Vec<u8>) -> Result<(), ClientError>;
+    async fn get_unidentified_access_key(&self) -> Result<Vec<u8>, ClientError>;
 }
@@ -1 +1,13 @@
-pub trait ContactStore {}
+use async_trait::async_trait;
+use sam_common::address::AccountId;
+
+use crate::ClientError;
+
+#[async_trait(?Send)]
+pub trait ContactStore {
+    /// Stores `account_id` under `name`, replacing any contact with the same name
+    async fn add_contact(&mut self, name: &str, account_id: AccountId) -> Result<(), ClientError>;
+    async fn get_contact(&self, name: &str) -> Result<AccountId, ClientError>;
+    /// All contacts ordered by name
+    async fn get_contacts(&self) -> Result<Vec<(String, AccountId)>, ClientError>;
+}
@@ -0,0 +1,473 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use reqwest::{RequestBuilder, Response};
+use sam_common::{
+    address::{AccountId, DeviceId, ProvisioningId},
+    api::{
+        keys::PreKeyBundles, ChangePasswordRequest, ChangeUsernameRequest, DeviceInfoList,
+        ErrorCode, ErrorResponse, LinkDeviceRequest, LinkDeviceResponse, LinkDeviceToken,
+        PreKeyCount, ProvisionEnvelope, PublishPreKeys, RegistrationRequest, RegistrationResponse,
+        RenameDeviceRequest, SenderCertificateResponse, SessionToken, UsernameLookupResponse,
+    },
+    time_now_millis,
+};
+
+use crate::ClientError;
+
+use super::Credentials;
+
+/// A session is renewed this long before it expires, so requests in flight
+/// do not race the expiry
+const SESSION_RENEW_MILLIS: u64 = 30_000;
+
+/// Client for the REST routes of a sam server
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    base_url: String,
+    /// Session tokens by device, with the password they were issued for
+    sessions: Arc<Mutex<HashMap<String, (String, SessionToken)>>>,
+}
+
+impl HttpClient {
+    /// `base_url` is the scheme and authority of the server, e.g. `https://localhost:8080`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            sessions: Arc::default(),
+        }
+    }
+
+    /// Sends `request`, a failed request is turned into the error reported by the server
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status_error = response.error_for_status_ref().err();
+        match response.json::<ErrorResponse>().await {
+            Ok(error) => Err(ClientError::Server(error)),
+            // not every failure comes from the server, e.g. a proxy in between
+            Err(err) => Err(status_error.unwrap_or(err).into()),
+        }
+    }
+
+    /// Sends `request` with a session token of the device, the password is
+    /// only sent when a new session is needed
+    async fn send_authorized(
+        &self,
+        credentials: &Credentials,
+        request: RequestBuilder,
+    ) -> Result<Response, ClientError> {
+        let session = self.session(credentials).await?;
+        let result = self.send(request.bearer_auth(session.token)).await;
+        if let Err(ClientError::Server(error)) = &result {
+            // the token was revoked or expired early, the next request logs in again
+            if matches!(
+                error.code,
+                ErrorCode::Unauthorized | ErrorCode::SessionExpired
+            ) {
+                self.forget_session(credentials);
+            }
+        }
+        result
+    }
+
+    /// Sends `request` with the password of the device, for requests a
+    /// session token is not enough for
+    async fn send_with_password(
+        &self,
+        credentials: &Credentials,
+        request: RequestBuilder,
+    ) -> Result<Response, ClientError> {
+        self.send(request.basic_auth(credentials.username(), Some(&credentials.password)))
+            .await
+    }
+
+    async fn session(&self, credentials: &Credentials) -> Result<SessionToken, ClientError> {
+        let renew_at = time_now_millis() + u128::from(SESSION_RENEW_MILLIS);
+        let cached = self
+            .sessions
+            .lock()
+            .expect("Session cache is not poisoned")
+            .get(&credentials.username())
+            .filter(|(password, session)| {
+                *password == credentials.password && u128::from(session.expires) > renew_at
+            })
+            .map(|(_, session)| session.clone());
+        if let Some(session) = cached {
+            return Ok(session);
+        }
+
+        let session = self.create_session(credentials).await?;
+        self.sessions
+            .lock()
+            .expect("Session cache is not poisoned")
+            .insert(
+                credentials.username(),
+                (credentials.password.clone(), session.clone()),
+            );
+        Ok(session)
+    }
+
+    fn forget_session(&self, credentials: &Credentials) {
+        self.sessions
+            .lock()
+            .expect("Session cache is not poisoned")
+            .remove(&credentials.username());
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// The websocket lives on the same server, `http` becomes `ws` and `https` becomes `wss`
+    pub fn websocket_url(&self) -> String {
+        self.ws_url("/api/v1/websocket")
+    }
+
+    /// Websocket on which a new device waits to be provisioned
+    pub fn provisioning_url(&self) -> String {
+        self.ws_url("/api/v1/provisioning")
+    }
+
+    fn ws_url(&self, path: &str) -> String {
+        let url = self.url(path);
+        match url.strip_prefix("http") {
+            Some(rest) => format!("ws{rest}"),
+            None => url,
+        }
+    }
+
+    pub async fn register(
+        &self,
+        username: &str,
+        password: &str,
+        registration: &RegistrationRequest,
+    ) -> Result<RegistrationResponse, ClientError> {
+        Ok(self
+            .send(
+                self.client
+                    .post(self.url("/api/v1/account"))
+                    .basic_auth(username, Some(password))
+                    .json(registration),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Trades the password of the device for a session token, other requests
+    /// do this on their own when needed
+    pub async fn create_session(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<SessionToken, ClientError> {
+        Ok(self
+            .send(
+                self.client
+                    .post(self.url("/api/v1/session"))
+                    .basic_auth(credentials.username(), Some(&credentials.password)),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn delete_account(&self, credentials: &Credentials) -> Result<(), ClientError> {
+        self.send_with_password(credentials, self.client.delete(self.url("/api/v1/account")))
+            .await?;
+        Ok(())
+    }
+
+    /// Resolves a username to its account, the server limits how often an
+    /// account may do this
+    pub async fn lookup_username(
+        &self,
+        credentials: &Credentials,
+        username: &str,
+    ) -> Result<AccountId, ClientError> {
+        let response: UsernameLookupResponse = self
+            .send_authorized(
+                credentials,
+                self.client.get(self.url(&format!(
+                    "/api/v1/account/username/{}",
+                    encode_path_segment(username)
+                ))),
+            )
+            .await?
+            .json()
+            .await?;
+        Ok(response.account_id)
+    }
+
+    pub async fn change_username(
+        &self,
+        credentials: &Credentials,
+        username: &str,
+    ) -> Result<(), ClientError> {
+        self.send_with_password(
+            credentials,
+            self.client
+                .put(self.url("/api/v1/account/username"))
+                .json(&ChangeUsernameRequest {
+                    username: username.to_string(),
+                }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn pre_key_bundles(
+        &self,
+        credentials: &Credentials,
+        account_id: AccountId,
+    ) -> Result<PreKeyBundles, ClientError> {
+        Ok(self
+            .send_authorized(
+                credentials,
+                self.client
+                    .get(self.url(&format!("/api/v1/keys/{account_id}"))),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Key bundle of a single device, unlike [`HttpClient::pre_key_bundles`]
+    /// this only uses up a one-time key of that device
+    pub async fn pre_key_bundle(
+        &self,
+        credentials: &Credentials,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<PreKeyBundles, ClientError> {
+        Ok(self
+            .send_authorized(
+                credentials,
+                self.client
+                    .get(self.url(&format!("/api/v1/keys/{account_id}/{device_id}"))),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn publish_pre_keys(
+        &self,
+        credentials: &Credentials,
+        keys: &PublishPreKeys,
+    ) -> Result<(), ClientError> {
+        self.send_authorized(
+            credentials,
+            self.client.put(self.url("/api/v1/keys")).json(keys),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Number of one-time keys the server has left for this device
+    pub async fn pre_key_count(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<PreKeyCount, ClientError> {
+        Ok(self
+            .send_authorized(credentials, self.client.get(self.url("/api/v1/keys/count")))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Sender certificate that vouches for this device in sealed messages
+    pub async fn sender_certificate(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<SenderCertificateResponse, ClientError> {
+        Ok(self
+            .send_authorized(
+                credentials,
+                self.client.get(self.url("/api/v1/certificate/delivery")),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Creates a token the primary device hands to a new device so it can link itself
+    pub async fn provision_device(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<LinkDeviceToken, ClientError> {
+        Ok(self
+            .send_with_password(
+                credentials,
+                self.client.get(self.url("/api/v1/devices/provision")),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Keeps a token created by [`HttpClient::provision_device`] from being used
+    pub async fn revoke_device_token(
+        &self,
+        credentials: &Credentials,
+        token_id: &str,
+    ) -> Result<(), ClientError> {
+        self.send_with_password(
+            credentials,
+            self.client
+                .delete(self.url(&format!("/api/v1/devices/provision/{token_id}"))),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Relays an encrypted provisioning message to the new device waiting at `address`
+    pub async fn send_provisioning_message(
+        &self,
+        credentials: &Credentials,
+        address: ProvisioningId,
+        envelope: &ProvisionEnvelope,
+    ) -> Result<(), ClientError> {
+        self.send_with_password(
+            credentials,
+            self.client
+                .put(self.url(&format!("/api/v1/provisioning/{address}")))
+                .json(envelope),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Links a new device, `password` becomes the password of that device
+    pub async fn link_device(
+        &self,
+        password: &str,
+        request: &LinkDeviceRequest,
+    ) -> Result<LinkDeviceResponse, ClientError> {
+        Ok(self
+            .send(
+                self.client
+                    .post(self.url("/api/v1/devices/link"))
+                    .basic_auth(request.token.id(), Some(password))
+                    .json(request),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// All devices linked to the account
+    pub async fn devices(&self, credentials: &Credentials) -> Result<DeviceInfoList, ClientError> {
+        Ok(self
+            .send_authorized(credentials, self.client.get(self.url("/api/v1/devices")))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn rename_device(
+        &self,
+        credentials: &Credentials,
+        device_id: DeviceId,
+        name: &str,
+    ) -> Result<(), ClientError> {
+        self.send_authorized(
+            credentials,
+            self.client
+                .put(self.url(&format!("/api/v1/device/{device_id}/name")))
+                .json(&RenameDeviceRequest {
+                    name: name.to_string(),
+                }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unlink_device(
+        &self,
+        credentials: &Credentials,
+        device_id: DeviceId,
+    ) -> Result<(), ClientError> {
+        self.send_authorized(
+            credentials,
+            self.client
+                .delete(self.url(&format!("/api/v1/device/{device_id}"))),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Hands the primary role of the authenticated device to `device_id`
+    pub async fn transfer_primary(
+        &self,
+        credentials: &Credentials,
+        device_id: DeviceId,
+    ) -> Result<(), ClientError> {
+        self.send_with_password(
+            credentials,
+            self.client
+                .put(self.url(&format!("/api/v1/device/{device_id}/primary"))),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Sends the current password, a session token is not enough to change it
+    pub async fn change_password(
+        &self,
+        credentials: &Credentials,
+        password: &str,
+    ) -> Result<(), ClientError> {
+        self.send_with_password(
+            credentials,
+            self.client
+                .put(self.url("/api/v1/device/password"))
+                .json(&ChangePasswordRequest {
+                    password: password.to_string(),
+                }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Sends the current password, a session token is not enough to take
+    /// over another device
+    pub async fn reset_device_password(
+        &self,
+        credentials: &Credentials,
+        device_id: DeviceId,
+        password: &str,
+    ) -> Result<(), ClientError> {
+        self.send_with_password(
+            credentials,
+            self.client
+                .put(self.url(&format!("/api/v1/device/{device_id}/password")))
+                .json(&ChangePasswordRequest {
+                    password: password.to_string(),
+                }),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Percent-encodes everything but unreserved characters, so user input stays
+/// a single path segment
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,43 @@
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use sam_common::address::{AccountId, DeviceId};
+
+use crate::{storage::AccountStore, ClientError};
+
+pub mod http;
+pub mod provisioning;
+pub mod websocket;
+
+pub use http::HttpClient;
+pub use provisioning::ProvisioningSocket;
+pub use websocket::WebSocketClient;
+
+/// Basic auth credentials of a registered device
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub account_id: AccountId,
+    pub device_id: DeviceId,
+    pub password: String,
+}
+
+impl Credentials {
+    pub async fn from_store(store: &impl AccountStore) -> Result<Self, ClientError> {
+        Ok(Self {
+            account_id: store.get_account_id().await?,
+            device_id: store.get_device_id().await?,
+            password: store.get_password().await?,
+        })
+    }
+
+    /// The server identifies devices as `<account id>.<device id>`
+    pub fn username(&self) -> String {
+        format!("{}.{}", self.account_id, self.device_id)
+    }
+
+    /// Value of the `Authorization` header for requests made by this device
+    pub fn authorization(&self) -> String {
+        format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}:{}", self.username(), self.password))
+        )
+    }
+}
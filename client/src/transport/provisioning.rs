@@ -0,0 +1,52 @@
+use futures_util::StreamExt as _;
+use sam_common::{
+    address::ProvisioningId,
+    api::{ProvisionEnvelope, ProvisioningAddress},
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::ClientError;
+
+/// Connection of a new device to the provisioning websocket. The server
+/// assigns an address on connect and relays a single envelope to it.
+pub struct ProvisioningSocket {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    address: ProvisioningId,
+}
+
+impl ProvisioningSocket {
+    /// Connects without credentials and waits for the assigned address
+    pub async fn connect(url: &str) -> Result<Self, ClientError> {
+        let (mut socket, _) = connect_async(url).await?;
+        let address = serde_json::from_str::<ProvisioningAddress>(&next_text(&mut socket).await?)
+            .map_err(|_| ClientError::ProvisioningMessageMalformed)?
+            .address;
+        Ok(Self { socket, address })
+    }
+
+    /// Where the primary device sends the provisioning message
+    pub fn address(&self) -> ProvisioningId {
+        self.address
+    }
+
+    /// Waits for the primary device, the server closes the connection once
+    /// the envelope was delivered or the wait expired
+    pub async fn receive(mut self) -> Result<ProvisionEnvelope, ClientError> {
+        serde_json::from_str(&next_text(&mut self.socket).await?)
+            .map_err(|_| ClientError::ProvisioningMessageMalformed)
+    }
+}
+
+async fn next_text(
+    socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+) -> Result<String, ClientError> {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Text(text))) => return Ok(text.to_string()),
+            Some(Ok(Message::Close(_))) | None => return Err(ClientError::WebSocketClosed),
+            Some(Err(err)) => return Err(err.into()),
+            Some(Ok(_)) => {}
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use prost::Message as _;
+use sam_common::{
+    address::MessageId,
+    sam_message::{
+        ClientEnvelope, ClientMessage, MessageType, Notification, ServerEnvelope, ServerMessage,
+    },
+};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot, watch},
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        http::{
+            header::{AUTHORIZATION, RETRY_AFTER},
+            HeaderValue, StatusCode,
+        },
+        protocol::frame::coding::CloseCode,
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::ClientError;
+
+use super::Credentials;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Where the background task forwards what the server pushes
+struct Receivers {
+    envelopes: mpsc::UnboundedSender<(Vec<u8>, ServerEnvelope)>,
+    notifications: mpsc::UnboundedSender<Notification>,
+}
+
+enum Command {
+    /// A message the server answers with an ack or an error
+    Send(ClientMessage, oneshot::Sender<ServerMessage>),
+    /// An ack or error for a message received from the server
+    Reply(ClientMessage),
+}
+
+/// Connection to the message websocket of a sam server.
+///
+/// The connection is kept open by a background task, which reconnects with
+/// exponential backoff whenever the connection drops. Dropping the client
+/// closes the connection once outstanding acks have been sent.
+pub struct WebSocketClient {
+    commands: mpsc::UnboundedSender<Command>,
+    envelopes: mpsc::UnboundedReceiver<(Vec<u8>, ServerEnvelope)>,
+    /// An envelope taken by [`WebSocketClient::wait_for_envelope`] that has
+    /// not been handed to a handler yet
+    next_envelope: Option<(Vec<u8>, ServerEnvelope)>,
+    notifications: Option<mpsc::UnboundedReceiver<Notification>>,
+}
+
+impl WebSocketClient {
+    /// Fails if the first connection cannot be made, later connection losses
+    /// are recovered from in the background with the credentials current at
+    /// that time
+    pub async fn connect(
+        url: impl Into<String>,
+        credentials: watch::Receiver<Credentials>,
+    ) -> Result<Self, ClientError> {
+        let url = url.into();
+        let current = credentials.borrow().clone();
+        let socket = open(&url, &current).await?;
+
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+        let (envelope_sender, envelopes) = mpsc::unbounded_channel();
+        let (notification_sender, notifications) = mpsc::unbounded_channel();
+        tokio::spawn(run(
+            url,
+            credentials,
+            socket,
+            command_receiver,
+            Receivers {
+                envelopes: envelope_sender,
+                notifications: notification_sender,
+            },
+        ));
+
+        Ok(Self {
+            commands,
+            envelopes,
+            next_envelope: None,
+            notifications: Some(notifications),
+        })
+    }
+
+    /// Hands out the notifications pushed by the server, e.g. when the
+    /// one-time keys of this device run low. Can only be taken once, they are
+    /// not acknowledged and are dropped if nobody listens.
+    pub fn take_notifications(&mut self) -> Option<mpsc::UnboundedReceiver<Notification>> {
+        self.notifications.take()
+    }
+
+    /// Sends `envelope` and waits for the server to accept or reject it
+    pub async fn send(&self, envelope: ClientEnvelope) -> Result<ServerMessage, ClientError> {
+        let message = ClientMessage::builder()
+            .id(MessageId::generate().into())
+            .r#type(MessageType::Message as i32)
+            .message(envelope)
+            .build();
+
+        let (responder, response) = oneshot::channel();
+        self.commands
+            .send(Command::Send(message, responder))
+            .map_err(|_| ClientError::WebSocketClosed)?;
+        response
+            .await
+            .map_err(|_| ClientError::WebSocketDisconnected)
+    }
+
+    /// Waits until an envelope is ready for [`WebSocketClient::receive`]
+    /// without processing it. Unlike `receive` it is cancel safe, so it can be
+    /// raced against other work in `tokio::select!`.
+    pub async fn wait_for_envelope(&mut self) -> Result<(), ClientError> {
+        if self.next_envelope.is_none() {
+            let envelope = self
+                .envelopes
+                .recv()
+                .await
+                .ok_or(ClientError::WebSocketClosed)?;
+            self.next_envelope = Some(envelope);
+        }
+        Ok(())
+    }
+
+    /// Waits for the next envelope and hands it to `handler`.
+    ///
+    /// The envelope is acknowledged once `handler` succeeds, so it must only
+    /// return after the envelope has been persisted. If it fails the server is
+    /// told the envelope could not be processed and delivers it again later.
+    pub async fn receive<F, Fut, R>(&mut self, handler: F) -> Result<R, ClientError>
+    where
+        F: FnOnce(ServerEnvelope) -> Fut,
+        Fut: Future<Output = Result<R, ClientError>>,
+    {
+        self.wait_for_envelope().await?;
+        let (id, envelope) = self
+            .next_envelope
+            .take()
+            .expect("An envelope is ready after waiting");
+
+        let result = handler(envelope).await;
+        let r#type = match result {
+            Ok(_) => MessageType::Ack,
+            Err(_) => MessageType::Error,
+        };
+        let reply = ClientMessage::builder()
+            .id(id)
+            .r#type(r#type as i32)
+            .build();
+        self.commands
+            .send(Command::Reply(reply))
+            .map_err(|_| ClientError::WebSocketClosed)?;
+
+        result
+    }
+}
+
+async fn open(url: &str, credentials: &Credentials) -> Result<Socket, tungstenite::Error> {
+    let mut request = url.into_client_request()?;
+    let authorization = HeaderValue::from_str(&credentials.authorization())
+        .map_err(|err| tungstenite::Error::HttpFormat(err.into()))?;
+    request.headers_mut().insert(AUTHORIZATION, authorization);
+
+    let (socket, _) = connect_async(request).await?;
+    Ok(socket)
+}
+
+async fn run(
+    url: String,
+    credentials: watch::Receiver<Credentials>,
+    mut socket: Socket,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    receivers: Receivers,
+) {
+    loop {
+        // messages waiting for a response are dropped with the connection,
+        // their senders see a disconnect
+        let mut pending = HashMap::new();
+        if serve(&mut socket, &mut commands, &receivers, &mut pending).await {
+            return;
+        }
+
+        socket = match reconnect(&url, &credentials, &commands).await {
+            Some(socket) => socket,
+            None => return,
+        };
+    }
+}
+
+/// Relays messages until the connection drops, returns `true` if the client is
+/// gone or the server does not want it to reconnect
+async fn serve(
+    socket: &mut Socket,
+    commands: &mut mpsc::UnboundedReceiver<Command>,
+    receivers: &Receivers,
+    pending: &mut HashMap<Vec<u8>, oneshot::Sender<ServerMessage>>,
+) -> bool {
+    loop {
+        tokio::select! {
+            frame = socket.next() => match frame {
+                Some(Ok(Message::Binary(bytes))) => {
+                    let Ok(message) = ServerMessage::decode(bytes) else {
+                        continue;
+                    };
+                    match message.r#type() {
+                        MessageType::Message => {
+                            // if the client is gone the envelope is left
+                            // unacknowledged and delivered again later
+                            if let Some(envelope) = message.message {
+                                let _ = receivers.envelopes.send((message.id, envelope));
+                            }
+                        }
+                        MessageType::Notification => {
+                            if let Some(notification) = message.notification {
+                                let _ = receivers.notifications.send(notification);
+                            }
+                        }
+                        MessageType::Ack | MessageType::Error => {
+                            if let Some(responder) = pending.remove(&message.id) {
+                                let _ = responder.send(message);
+                            }
+                        }
+                    }
+                }
+                // the server shut the device out, e.g. because it was unlinked
+                Some(Ok(Message::Close(Some(frame)))) if frame.code == CloseCode::Policy => {
+                    return true
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return false,
+                Some(Ok(_)) => {}
+            },
+            command = commands.recv() => match command {
+                Some(Command::Send(message, responder)) => {
+                    let id = message.id.clone();
+                    if send(socket, message).await.is_err() {
+                        return false;
+                    }
+                    pending.insert(id, responder);
+                }
+                Some(Command::Reply(message)) => {
+                    if send(socket, message).await.is_err() {
+                        return false;
+                    }
+                }
+                None => {
+                    let _ = socket.close(None).await;
+                    return true;
+                }
+            },
+        }
+    }
+}
+
+async fn send(socket: &mut Socket, message: ClientMessage) -> Result<(), tungstenite::Error> {
+    socket
+        .send(Message::Binary(message.encode_to_vec().into()))
+        .await
+}
+
+/// Retries until a connection is made, gives up if the client is gone or the
+/// server rejects the credentials as they will not become valid by waiting.
+/// A rate limited attempt is retried once the server allows it.
+async fn reconnect(
+    url: &str,
+    credentials: &watch::Receiver<Credentials>,
+    commands: &mpsc::UnboundedReceiver<Command>,
+) -> Option<Socket> {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        tokio::time::sleep(backoff).await;
+        if commands.is_closed() {
+            return None;
+        }
+        let current = credentials.borrow().clone();
+        match open(url, &current).await {
+            Ok(socket) => return Some(socket),
+            Err(tungstenite::Error::Http(response))
+                if response.status() == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                backoff = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .map_or_else(
+                        || next_backoff(backoff),
+                        |seconds| Duration::from_secs(seconds).max(INITIAL_BACKOFF),
+                    );
+            }
+            Err(tungstenite::Error::Http(response)) if response.status().is_client_error() => {
+                return None
+            }
+            Err(_) => backoff = next_backoff(backoff),
+        }
+    }
+}
+
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use sam_common::address::AccountId;
+    use tokio::{
+        io::AsyncWriteExt,
+        net::TcpListener,
+        sync::{mpsc, watch},
+    };
+
+    use crate::transport::Credentials;
+
+    use super::{next_backoff, reconnect, INITIAL_BACKOFF, MAX_BACKOFF};
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        assert_eq!(next_backoff(INITIAL_BACKOFF), Duration::from_millis(500));
+        assert_eq!(next_backoff(Duration::from_secs(20)), MAX_BACKOFF);
+        assert_eq!(next_backoff(MAX_BACKOFF), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_waits_out_rate_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Can bind listener");
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("Can accept");
+            stream
+                .write_all(
+                    b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n",
+                )
+                .await
+                .expect("Can answer");
+            drop(stream);
+
+            let (stream, _) = listener.accept().await.expect("Can accept");
+            tokio_tungstenite::accept_async(stream)
+                .await
+                .expect("Can accept websocket")
+        });
+
+        let (_, credentials) = watch::channel(Credentials {
+            account_id: AccountId::generate(),
+            device_id: 1.into(),
+            password: "password".to_string(),
+        });
+        let (_commands, command_receiver) = mpsc::unbounded_channel();
+        let socket = tokio::time::timeout(
+            Duration::from_secs(5),
+            reconnect(&url, &credentials, &command_receiver),
+        )
+        .await
+        .expect("Reconnects in time");
+
+        assert!(socket.is_some());
+        server.await.expect("Server accepted the retry");
+    }
+}
@@ -0,0 +1,589 @@
+use std::time::Duration;
+
+use libsignal_protocol::IdentityKeyStore as _;
+use rand::rngs::OsRng;
+use sam_client::{
+    keygen::KeyMaintenanceConfig,
+    provisioning::ProvisioningSession,
+    storage::{
+        inmem::{InMemoryStoreConfig, InMemoryStoreType},
+        AccountStore, RotatingKeyStore, StoreConfig,
+    },
+    transport::{Credentials, HttpClient},
+    Client, ClientError,
+};
+use sam_common::{
+    address::{DeviceAddress, RegistrationId},
+    api::ErrorCode,
+    sam_message::{EnvelopeType, ErrorType},
+};
+use sam_server::{
+    managers::in_memory::InMemStateType, start_server, state::ServerState, ServerConfig,
+};
+
+async fn start_test_server(address: &str) {
+    start_server_with_state(address, ServerState::in_memory("test".to_string(), 600, 10)).await;
+}
+
+async fn start_server_with_state(address: &str, state: ServerState<InMemStateType>) {
+    let config = ServerConfig {
+        state,
+        addr: address.parse().expect("Can parse socket address"),
+        tls: None,
+    };
+    tokio::spawn(start_server(config));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+async fn client(address: &str) -> Client<InMemoryStoreType> {
+    let store = InMemoryStoreConfig::default()
+        .load_store()
+        .await
+        .expect("Can create store");
+    Client::new(store, HttpClient::new(format!("http://{address}")))
+}
+
+#[tokio::test]
+async fn alice_and_bob_can_exchange_messages() {
+    let address = "127.0.0.1:8100";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let mut bob = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    let bob_id = bob
+        .register("bob", "cheeseburger", "laptop", &mut OsRng)
+        .await
+        .expect("Bob can register");
+
+    let alice_ws = alice.connect_websocket().await.expect("Alice can connect");
+    let mut bob_ws = bob.connect_websocket().await.expect("Bob can connect");
+
+    alice
+        .send_message(&alice_ws, bob_id, b"hi bob<3", &mut OsRng)
+        .await
+        .expect("Alice can send to bob");
+    let (source, plaintext) = bob
+        .receive_message(&mut bob_ws, &mut OsRng)
+        .await
+        .expect("Bob can receive");
+    assert!(source.account_id() == alice_id);
+    assert!(plaintext == b"hi bob<3");
+
+    alice
+        .send_message(&alice_ws, bob_id, b"are you there?", &mut OsRng)
+        .await
+        .expect("Alice can send to bob again");
+    let (_, plaintext) = bob
+        .receive_message(&mut bob_ws, &mut OsRng)
+        .await
+        .expect("Bob can receive the second message");
+    assert!(plaintext == b"are you there?");
+}
+
+#[tokio::test]
+async fn offline_messages_are_delivered_once() {
+    let address = "127.0.0.1:8101";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let mut bob = client(address).await;
+    alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    let bob_id = bob
+        .register("bob", "cheeseburger", "laptop", &mut OsRng)
+        .await
+        .expect("Bob can register");
+
+    let alice_ws = alice.connect_websocket().await.expect("Alice can connect");
+    alice
+        .send_message(&alice_ws, bob_id, b"hi bob<3", &mut OsRng)
+        .await
+        .expect("Alice can send to offline bob");
+
+    let mut bob_ws = bob.connect_websocket().await.expect("Bob can connect");
+    let (_, plaintext) = bob
+        .receive_message(&mut bob_ws, &mut OsRng)
+        .await
+        .expect("Bob receives the stored message");
+    assert!(plaintext == b"hi bob<3");
+
+    // the message was acked, so it is gone from the server
+    drop(bob_ws);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut bob_ws = bob.connect_websocket().await.expect("Bob can reconnect");
+    assert!(tokio::time::timeout(
+        Duration::from_millis(300),
+        bob.receive_message(&mut bob_ws, &mut OsRng)
+    )
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn server_errors_are_decoded() {
+    let address = "127.0.0.1:8102";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+
+    let credentials = Credentials {
+        account_id: alice_id,
+        device_id: 1.into(),
+        password: "wrong".to_string(),
+    };
+    let result = HttpClient::new(format!("http://{address}"))
+        .delete_account(&credentials)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Server(error)) if error.code == ErrorCode::Unauthorized
+    ));
+}
+
+#[tokio::test]
+async fn low_pre_keys_are_reported() {
+    let address = "127.0.0.1:8103";
+    // every fetch of a bundle takes the registration keys below the threshold
+    let state = ServerState::in_memory("test".to_string(), 600, 10).with_pre_key_threshold(100);
+    start_server_with_state(address, state).await;
+
+    let mut alice = client(address).await;
+    let mut bob = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    bob.register("bob", "cheeseburger", "laptop", &mut OsRng)
+        .await
+        .expect("Bob can register");
+
+    let http = HttpClient::new(format!("http://{address}"));
+    let credentials = Credentials::from_store(&alice.store().account_store)
+        .await
+        .expect("Alice has credentials");
+    let count = http
+        .pre_key_count(&credentials)
+        .await
+        .expect("Alice can count her keys");
+    assert!(count.pre_keys == 100 && count.pq_pre_keys == 100);
+
+    let mut alice_ws = alice.connect_websocket().await.expect("Alice can connect");
+    let mut notifications = alice_ws
+        .take_notifications()
+        .expect("Notifications are taken once");
+    assert!(alice_ws.take_notifications().is_none());
+
+    bob.process_pre_key_bundles(alice_id, &mut OsRng)
+        .await
+        .expect("Bob can fetch alices keys");
+    let notification = tokio::time::timeout(Duration::from_millis(300), notifications.recv())
+        .await
+        .expect("Alice is notified in time")
+        .expect("Alice is notified");
+    let count = notification
+        .pre_key_count
+        .expect("Notification has a count");
+    assert!(count.pre_keys == 99 && count.pq_pre_keys == 99);
+}
+
+#[tokio::test]
+async fn keys_are_replenished_and_rotated() {
+    let address = "127.0.0.1:8104";
+    start_test_server(address).await;
+
+    let maintenance = KeyMaintenanceConfig {
+        pre_key_threshold: 100,
+        rotation_interval: Duration::ZERO,
+        grace_period: Duration::ZERO,
+        ..Default::default()
+    };
+    let mut alice = client(address).await.with_key_maintenance(maintenance);
+    let mut bob = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    bob.register("bob", "cheeseburger", "laptop", &mut OsRng)
+        .await
+        .expect("Bob can register");
+    bob.process_pre_key_bundles(alice_id, &mut OsRng)
+        .await
+        .expect("Bob can fetch alices keys");
+
+    alice
+        .maintain_keys(&mut OsRng)
+        .await
+        .expect("Alice can maintain her keys");
+
+    let http = HttpClient::new(format!("http://{address}"));
+    let credentials = Credentials::from_store(&alice.store().account_store)
+        .await
+        .expect("Alice has credentials");
+    let count = http
+        .pre_key_count(&credentials)
+        .await
+        .expect("Alice can count her keys");
+    assert!(count.pre_keys == 199 && count.pq_pre_keys == 199);
+
+    // the registration keys were replaced and pruned right away
+    let signed_pre_key_ids = alice
+        .store()
+        .signed_pre_key_store
+        .rotating_key_ids()
+        .await
+        .expect("Alice has signed pre keys");
+    assert!(signed_pre_key_ids.len() == 1);
+    assert!(alice
+        .store()
+        .kyber_pre_key_store
+        .rotating_key_ids()
+        .await
+        .is_ok_and(|ids| ids.len() == 1));
+
+    let bundles = http
+        .pre_key_bundles(&credentials, alice_id)
+        .await
+        .expect("Alice can fetch her bundle");
+    let signed_pre_key_id: u32 = signed_pre_key_ids[0].into();
+    assert!(bundles.bundles[0].signed_pre_key.key_id == signed_pre_key_id);
+}
+
+#[tokio::test]
+async fn new_device_can_be_provisioned() {
+    let address = "127.0.0.1:8105";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+
+    let http = HttpClient::new(format!("http://{address}"));
+    let session = ProvisioningSession::start(&http, &mut OsRng)
+        .await
+        .expect("New device can start provisioning");
+    alice
+        .provision_new_device(&session.url(), &mut OsRng)
+        .await
+        .expect("Alice can provision her new device");
+    let account = session
+        .receive()
+        .await
+        .expect("New device receives account");
+    assert!(account.account_id == alice_id);
+    assert!(account.username == "alice");
+
+    let identity = alice
+        .store()
+        .identity_key_store
+        .get_identity_key_pair()
+        .await
+        .expect("Alice has an identity");
+    assert!(account.identity_key_pair.public_key() == identity.public_key());
+
+    let store = InMemoryStoreConfig::default()
+        .create_store(
+            account.identity_key_pair,
+            RegistrationId::generate(&mut OsRng),
+        )
+        .await
+        .expect("Can create store");
+    let mut laptop = Client::new(store, http);
+    let laptop_address = laptop
+        .link_device(
+            "alice",
+            "bob<3",
+            "laptop",
+            account.token,
+            account.unidentified_access_key,
+            &mut OsRng,
+        )
+        .await
+        .expect("New device can link");
+    assert!(laptop_address.account_id() == alice_id);
+    assert!(*laptop_address.device_id() == 2);
+    let access_key = alice
+        .unidentified_access_key()
+        .await
+        .expect("Alice has an access key");
+    assert!(laptop
+        .unidentified_access_key()
+        .await
+        .is_ok_and(|key| key == access_key));
+}
+
+#[tokio::test]
+async fn usernames_can_be_looked_up_and_changed() {
+    let address = "127.0.0.1:8106";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let mut bob = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    bob.register("bob", "cheeseburger", "laptop", &mut OsRng)
+        .await
+        .expect("Bob can register");
+
+    let mut mallory = client(address).await;
+    let result = mallory
+        .register("alice", "hunter2", "phone", &mut OsRng)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Server(error)) if error.code == ErrorCode::UsernameTaken
+    ));
+
+    assert!(bob
+        .lookup_username("alice")
+        .await
+        .is_ok_and(|id| id == alice_id));
+
+    alice
+        .change_username("alice in wonderland")
+        .await
+        .expect("Alice can change her username");
+    assert!(alice
+        .store()
+        .account_store
+        .get_username()
+        .await
+        .is_ok_and(|username| username == "alice in wonderland"));
+    assert!(bob
+        .lookup_username("alice in wonderland")
+        .await
+        .is_ok_and(|id| id == alice_id));
+}
+
+#[tokio::test]
+async fn password_can_be_changed() {
+    let address = "127.0.0.1:8107";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+
+    alice
+        .change_password("hunter2")
+        .await
+        .expect("Alice can change her password");
+    assert!(alice
+        .store()
+        .account_store
+        .get_password()
+        .await
+        .is_ok_and(|password| password == "hunter2"));
+    assert!(alice.devices().await.is_ok());
+
+    let credentials = Credentials {
+        account_id: alice_id,
+        device_id: 1.into(),
+        password: "bob<3".to_string(),
+    };
+    let result = HttpClient::new(format!("http://{address}"))
+        .devices(&credentials)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Server(error)) if error.code == ErrorCode::Unauthorized
+    ));
+}
+
+#[tokio::test]
+async fn session_token_authenticates_requests() {
+    let address = "127.0.0.1:8108";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+
+    let credentials = Credentials::from_store(&alice.store().account_store)
+        .await
+        .expect("Alice has credentials");
+    let session = HttpClient::new(format!("http://{address}"))
+        .create_session(&credentials)
+        .await
+        .expect("Alice can create a session");
+
+    let response = reqwest::Client::new()
+        .get(format!("http://{address}/api/v1/devices"))
+        .bearer_auth(&session.token)
+        .send()
+        .await
+        .expect("Server responds");
+    assert!(response.status().is_success());
+
+    let response = reqwest::Client::new()
+        .get(format!("http://{address}/api/v1/devices"))
+        .bearer_auth(format!("{}x", session.token))
+        .send()
+        .await
+        .expect("Server responds");
+    assert!(response.status() == reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn sealed_messages_hide_the_sender_from_the_server() {
+    let address = "127.0.0.1:8109";
+    let state = ServerState::in_memory("test".to_string(), 600, 10);
+    let trust_root = *state.certificates.trust_root();
+    start_server_with_state(address, state).await;
+
+    let mut alice = client(address).await;
+    let mut bob = client(address).await.with_trust_root(trust_root);
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    let bob_id = bob
+        .register("bob", "cheeseburger", "laptop", &mut OsRng)
+        .await
+        .expect("Bob can register");
+    let access_key = bob
+        .unidentified_access_key()
+        .await
+        .expect("Bob has an access key");
+
+    let mut alice_ws = alice.connect_websocket().await.expect("Alice can connect");
+    let mut bob_ws = bob.connect_websocket().await.expect("Bob can connect");
+
+    assert!(matches!(
+        alice
+            .send_sealed_message(&alice_ws, bob_id, &[0; 16], b"hi bob<3", &mut OsRng)
+            .await,
+        Err(ClientError::MessageRejected(
+            ErrorType::UnauthorizedDelivery
+        ))
+    ));
+    alice
+        .send_sealed_message(&alice_ws, bob_id, &access_key, b"hi bob<3", &mut OsRng)
+        .await
+        .expect("Alice can send a sealed message to bob");
+
+    let (source, plaintext) = bob
+        .receive_message(&mut bob_ws, &mut OsRng)
+        .await
+        .expect("Bob can open the sealed message");
+    assert!(source == DeviceAddress::new(alice_id, 1.into()));
+    assert!(plaintext == b"hi bob<3");
+
+    // the certificate told bob who wrote, so he can answer
+    bob.send_message(&bob_ws, alice_id, b"hi alice", &mut OsRng)
+        .await
+        .expect("Bob can answer");
+    let (source, plaintext) = alice
+        .receive_message(&mut alice_ws, &mut OsRng)
+        .await
+        .expect("Alice can receive the answer");
+    assert!(source == DeviceAddress::new(bob_id, 1.into()));
+    assert!(plaintext == b"hi alice");
+}
+
+#[tokio::test]
+async fn each_device_gets_the_type_of_its_message() {
+    let address = "127.0.0.1:8110";
+    start_test_server(address).await;
+
+    let mut alice = client(address).await;
+    let mut bob = client(address).await;
+    let alice_id = alice
+        .register("alice", "bob<3", "phone", &mut OsRng)
+        .await
+        .expect("Alice can register");
+    let bob_id = bob
+        .register("bob", "cheeseburger", "phone", &mut OsRng)
+        .await
+        .expect("Bob can register");
+
+    let http = HttpClient::new(format!("http://{address}"));
+    let session = ProvisioningSession::start(&http, &mut OsRng)
+        .await
+        .expect("New device can start provisioning");
+    bob.provision_new_device(&session.url(), &mut OsRng)
+        .await
+        .expect("Bob can provision his laptop");
+    let account = session.receive().await.expect("Laptop receives account");
+    let store = InMemoryStoreConfig::default()
+        .create_store(
+            account.identity_key_pair,
+            RegistrationId::generate(&mut OsRng),
+        )
+        .await
+        .expect("Can create store");
+    let mut laptop = Client::new(store, http);
+    laptop
+        .link_device(
+            "bob",
+            "cheeseburger",
+            "laptop",
+            account.token,
+            account.unidentified_access_key,
+            &mut OsRng,
+        )
+        .await
+        .expect("Laptop can link");
+
+    let mut alice_ws = alice.connect_websocket().await.expect("Alice can connect");
+    let mut bob_ws = bob.connect_websocket().await.expect("Bob can connect");
+    let mut laptop_ws = laptop
+        .connect_websocket()
+        .await
+        .expect("Laptop can connect");
+
+    alice
+        .send_message(&alice_ws, bob_id, b"hi bob<3", &mut OsRng)
+        .await
+        .expect("Alice can send to bob");
+    for (device, websocket) in [(&mut bob, &mut bob_ws), (&mut laptop, &mut laptop_ws)] {
+        let (_, plaintext) = device
+            .receive_message(websocket, &mut OsRng)
+            .await
+            .expect("Every device of bob receives the message");
+        assert!(plaintext == b"hi bob<3");
+    }
+
+    // only the phone answers, so only its session with alice is confirmed
+    bob.send_message(&bob_ws, alice_id, b"hi alice", &mut OsRng)
+        .await
+        .expect("Bob can answer");
+    alice
+        .receive_message(&mut alice_ws, &mut OsRng)
+        .await
+        .expect("Alice receives the answer");
+
+    let envelope = alice
+        .encrypt(bob_id, b"how are you?", &mut OsRng)
+        .await
+        .expect("Alice can encrypt");
+    assert!(envelope.type_for(1) == EnvelopeType::SignalMessage);
+    assert!(envelope.type_for(2) == EnvelopeType::PreKeySignalMessage);
+    alice_ws.send(envelope).await.expect("Alice can send");
+
+    for (device, websocket) in [(&mut bob, &mut bob_ws), (&mut laptop, &mut laptop_ws)] {
+        let (source, plaintext) = device
+            .receive_message(websocket, &mut OsRng)
+            .await
+            .expect("Every device of bob decrypts its message");
+        assert!(source == DeviceAddress::new(alice_id, 1.into()));
+        assert!(plaintext == b"how are you?");
+    }
+}
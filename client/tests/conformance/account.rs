@@ -1,7 +1,7 @@
 use super::{in_mem, sqlite};
 use sam_client::storage::AccountStore;
 use sam_client::ClientError;
-use sam_common::address::AccountId;
+use sam_common::address::{AccountId, DeviceId};
 
 macro_rules! test_account_store {
     ( [ $( ($struct:ty, $factory:expr) ),* ]) => {
@@ -21,6 +21,16 @@ macro_rules! test_account_store {
                 async fn [< $struct _account_id_can_be_stored_and_retrieved >]() {
                     account_id_can_be_stored_and_retrieved($factory().await.account_store).await;
                 }
+
+                #[tokio::test]
+                async fn [< $struct _device_id_can_be_stored_and_retrieved >]() {
+                    device_id_can_be_stored_and_retrieved($factory().await.account_store).await;
+                }
+
+                #[tokio::test]
+                async fn [< $struct _access_key_can_be_stored_and_retrieved >]() {
+                    access_key_can_be_stored_and_retrieved($factory().await.account_store).await;
+                }
             }
         )*
     };
@@ -39,6 +49,16 @@ async fn account_id_can_be_stored_and_retrieved(mut account_store: impl AccountS
     assert_eq!(account_store.get_account_id().await.unwrap(), account_id);
 }
 
+async fn device_id_can_be_stored_and_retrieved(mut account_store: impl AccountStore) {
+    let device_id = DeviceId::from(2);
+    assert!(matches!(
+        account_store.get_device_id().await.unwrap_err(),
+        ClientError::NoDeviceId
+    ));
+    assert!(account_store.set_device_id(device_id).await.is_ok());
+    assert_eq!(account_store.get_device_id().await.unwrap(), device_id);
+}
+
 async fn password_can_be_stored_and_retrieved(mut account_store: impl AccountStore) {
     let password = "MyPassword".to_owned();
     assert!(matches!(
@@ -65,6 +85,25 @@ async fn username_can_be_stored_and_retrieved(mut account_store: impl AccountSto
     assert_eq!(account_store.get_username().await.unwrap(), username);
 }
 
+async fn access_key_can_be_stored_and_retrieved(mut account_store: impl AccountStore) {
+    let key = vec![7; 16];
+    assert!(matches!(
+        account_store
+            .get_unidentified_access_key()
+            .await
+            .unwrap_err(),
+        ClientError::NoAccessKey
+    ));
+    assert!(account_store
+        .set_unidentified_access_key(key.clone())
+        .await
+        .is_ok());
+    assert_eq!(
+        account_store.get_unidentified_access_key().await.unwrap(),
+        key
+    );
+}
+
 test_account_store!([
     (sqlite_account_store, sqlite),
     (in_memory_account_store, in_mem)
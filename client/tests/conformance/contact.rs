@@ -0,0 +1,62 @@
+use super::{in_mem, sqlite};
+use sam_client::storage::ContactStore;
+use sam_client::ClientError;
+use sam_common::address::AccountId;
+
+macro_rules! test_contact_store {
+    ( [ $( ($struct:ty, $factory:expr) ),* ]) => {
+        $(
+            paste::paste! {
+                #[tokio::test]
+                async fn [< $struct _contact_can_be_stored_and_retrieved >]() {
+                    contact_can_be_stored_and_retrieved($factory().await.contact_store).await;
+                }
+
+                #[tokio::test]
+                async fn [< $struct _contact_can_be_replaced >]() {
+                    contact_can_be_replaced($factory().await.contact_store).await;
+                }
+
+                #[tokio::test]
+                async fn [< $struct _contacts_are_listed_by_name >]() {
+                    contacts_are_listed_by_name($factory().await.contact_store).await;
+                }
+            }
+        )*
+    };
+}
+
+async fn contact_can_be_stored_and_retrieved(mut contact_store: impl ContactStore) {
+    let account_id = AccountId::generate();
+    assert!(matches!(
+        contact_store.get_contact("bob").await.unwrap_err(),
+        ClientError::UnknownContact(_)
+    ));
+    assert!(contact_store.add_contact("bob", account_id).await.is_ok());
+    assert_eq!(contact_store.get_contact("bob").await.unwrap(), account_id);
+}
+
+async fn contact_can_be_replaced(mut contact_store: impl ContactStore) {
+    let old_id = AccountId::generate();
+    let new_id = AccountId::generate();
+    assert!(contact_store.add_contact("bob", old_id).await.is_ok());
+    assert!(contact_store.add_contact("bob", new_id).await.is_ok());
+    assert_eq!(contact_store.get_contact("bob").await.unwrap(), new_id);
+}
+
+async fn contacts_are_listed_by_name(mut contact_store: impl ContactStore) {
+    let alice_id = AccountId::generate();
+    let bob_id = AccountId::generate();
+    assert!(contact_store.get_contacts().await.unwrap().is_empty());
+    assert!(contact_store.add_contact("bob", bob_id).await.is_ok());
+    assert!(contact_store.add_contact("alice", alice_id).await.is_ok());
+    assert_eq!(
+        contact_store.get_contacts().await.unwrap(),
+        vec![("alice".to_string(), alice_id), ("bob".to_string(), bob_id)]
+    );
+}
+
+test_contact_store!([
+    (sqlite_contact_store, sqlite),
+    (in_memory_contact_store, in_mem)
+]);
@@ -1,9 +1,11 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     prost_build::Config::new()
         .type_attribute("ClientEnvelope", "#[derive(bon::Builder)]")
+        .field_attribute("ClientEnvelope.types", "#[builder(default)]")
         .type_attribute("ServerEnvelope", "#[derive(bon::Builder)]")
         .type_attribute("ClientMessage", "#[derive(bon::Builder)]")
         .type_attribute("ServerMessage", "#[derive(bon::Builder)]")
+        .type_attribute("Notification", "#[derive(bon::Builder)]")
         .include_file("_includes.rs")
         .compile_protos(&["proto/Envelope.proto"], &["proto"])?;
 
@@ -68,6 +68,7 @@ macro_rules! define_uuid_type {
 
 define_uuid_type!(AccountId);
 define_uuid_type!(MessageId);
+define_uuid_type!(ProvisioningId);
 
 #[derive(
     Copy,
@@ -1,16 +1,23 @@
 use libsignal_protocol::IdentityKey;
 use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
 
 use crate::address::AccountId;
 
 use super::{device::DeviceActivationInfo, keys::id_key};
 
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RegistrationRequest {
     #[serde(with = "id_key")]
     pub identity_key: IdentityKey,
     pub device_activation: DeviceActivationInfo,
+    /// Key senders must present to deliver sealed sender messages to this account,
+    /// accounts without one can only receive messages from identified senders
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(default)]
+    pub unidentified_access_key: Option<Box<[u8]>>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -18,3 +25,15 @@ pub struct RegistrationRequest {
 pub struct RegistrationResponse {
     pub account_id: AccountId,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UsernameLookupResponse {
+    pub account_id: AccountId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeUsernameRequest {
+    pub username: String,
+}
@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+/// The trust root the certificate chains up to is not part of the response,
+/// clients pin it out of band, e.g. from their config or a build-time constant,
+/// so a server cannot vouch for certificates it issued itself.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderCertificateResponse {
+    /// Serialized `SenderCertificate` for the authenticated device
+    #[serde_as(as = "Base64")]
+    pub certificate: Box<[u8]>,
+}
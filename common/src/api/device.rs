@@ -1,6 +1,14 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
 use serde::{Deserialize, Serialize};
 
-use crate::address::{AccountId, DeviceId, RegistrationId};
+use crate::{
+    address::{AccountId, DeviceId, RegistrationId},
+    LibError,
+};
 
 use super::keys::RegistrationPreKeys;
 
@@ -45,3 +53,63 @@ pub struct DeviceActivationInfo {
     pub registration_id: RegistrationId,
     pub key_bundle: RegistrationPreKeys,
 }
+
+/// Every account has exactly one primary device, only it may manage the
+/// account and its other devices
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceRole {
+    Primary,
+    Linked,
+}
+
+impl Display for DeviceRole {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DeviceRole::Primary => write!(f, "primary"),
+            DeviceRole::Linked => write!(f, "linked"),
+        }
+    }
+}
+
+impl FromStr for DeviceRole {
+    type Err = LibError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(DeviceRole::Primary),
+            "linked" => Ok(DeviceRole::Linked),
+            _ => Err(LibError::UnknownDeviceRole),
+        }
+    }
+}
+
+/// What the server knows about a linked device, the credentials are left out
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub id: DeviceId,
+    pub name: String,
+    pub registration_id: RegistrationId,
+    pub role: DeviceRole,
+    /// Milliseconds since the unix epoch at which the device was linked
+    pub created: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfoList {
+    pub devices: Vec<DeviceInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameDeviceRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordRequest {
+    pub password: String,
+}
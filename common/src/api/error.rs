@@ -0,0 +1,100 @@
+use derive_more::derive::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+/// Stable, machine-readable reason a request failed. New codes may be added,
+/// clients should treat unknown codes like [`ErrorCode::Internal`].
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request could not be parsed
+    MalformedRequest,
+    /// Uploaded keys are not signed by the identity key of the account
+    InvalidKeySignature,
+    /// An uploaded key is not a valid public key
+    InvalidKey,
+    /// The credentials are missing or do not match a device
+    Unauthorized,
+    /// The session token is no longer valid, a new one has to be requested
+    /// with the password of the device
+    SessionExpired,
+    /// The sealed sender access key does not match the recipient
+    UnauthorizedDelivery,
+    /// The device is authenticated but not allowed to perform the action
+    Forbidden,
+    /// The device link token was not issued by this server
+    InvalidLinkToken,
+    /// The device link token is no longer valid
+    LinkTokenExpired,
+    /// The device link token was already used to link a device
+    LinkTokenUsed,
+    /// The device link token was revoked by the primary device
+    LinkTokenRevoked,
+    /// No new device is waiting at the provisioning address
+    ProvisioningNotFound,
+    /// The primary device cannot be unlinked, its role has to be transferred
+    /// to another device first
+    DeviceIsPrimary,
+    AccountNotFound,
+    DeviceNotFound,
+    KeyNotFound,
+    EnvelopeNotFound,
+    AccountExists,
+    /// Another account already uses the username
+    UsernameTaken,
+    DeviceExists,
+    /// A key id is used twice in an upload or is already in use by the device
+    KeyExists,
+    EnvelopeExists,
+    /// Too many requests, the `Retry-After` header tells when to try again
+    RateLimited,
+    #[serde(other)]
+    Internal,
+}
+
+/// JSON body of every failed REST request
+#[derive(Debug, Display, Error, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[display("{code}: {message}")]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    /// Human readable description, not meant to be matched on
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ErrorCode, ErrorResponse};
+
+    #[test]
+    fn test_error_response_json() {
+        let response = ErrorResponse::new(ErrorCode::AccountNotFound, "AccountNotExist");
+        let json = serde_json::to_string(&response).expect("Can serialize error");
+
+        assert_eq!(
+            json,
+            r#"{"code":"ACCOUNT_NOT_FOUND","message":"AccountNotExist"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ErrorResponse>(&json).expect("Can deserialize error"),
+            response
+        );
+    }
+
+    #[test]
+    fn test_unknown_error_code_is_internal() {
+        let response: ErrorResponse =
+            serde_json::from_str(r#"{"code":"SOMETHING_NEW","message":""}"#)
+                .expect("Can deserialize error");
+
+        assert_eq!(response.code, ErrorCode::Internal);
+    }
+}
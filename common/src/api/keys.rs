@@ -4,7 +4,7 @@ use libsignal_protocol::{
 use serde::{Deserialize, Serialize};
 use serde_with::{base64::Base64, serde_as};
 
-use crate::LibError;
+use crate::{sam_message, LibError};
 
 macro_rules! define_key {
     ($name:ident) => {
@@ -138,6 +138,32 @@ impl PreKeyBundle {
     }
 }
 
+/// Number of one-time keys the server still holds for a device
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PreKeyCount {
+    pub pre_keys: u32,
+    pub pq_pre_keys: u32,
+}
+
+impl From<PreKeyCount> for sam_message::PreKeyCount {
+    fn from(value: PreKeyCount) -> Self {
+        Self {
+            pre_keys: value.pre_keys,
+            pq_pre_keys: value.pq_pre_keys,
+        }
+    }
+}
+
+impl From<sam_message::PreKeyCount> for PreKeyCount {
+    fn from(value: sam_message::PreKeyCount) -> Self {
+        Self {
+            pre_keys: value.pre_keys,
+            pq_pre_keys: value.pq_pre_keys,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PublishPreKeys {
@@ -1,9 +1,28 @@
 pub mod account;
+pub mod certificate;
 pub mod device;
+pub mod error;
 pub mod keys;
+pub mod provisioning;
+pub mod session;
 
-pub use account::{RegistrationRequest, RegistrationResponse};
+pub use account::{
+    ChangeUsernameRequest, RegistrationRequest, RegistrationResponse, UsernameLookupResponse,
+};
 
-pub use device::{LinkDeviceRequest, LinkDeviceResponse, LinkDeviceToken};
+pub use certificate::SenderCertificateResponse;
 
-pub use keys::{EcPreKey, Key, PqPreKey, PreKeyBundle, PublishPreKeys, SignedEcPreKey, SignedKey};
+pub use device::{
+    ChangePasswordRequest, DeviceInfo, DeviceInfoList, DeviceRole, LinkDeviceRequest,
+    LinkDeviceResponse, LinkDeviceToken, RenameDeviceRequest,
+};
+
+pub use error::{ErrorCode, ErrorResponse};
+
+pub use provisioning::{ProvisionEnvelope, ProvisionMessage, ProvisioningAddress};
+
+pub use session::SessionToken;
+
+pub use keys::{
+    EcPreKey, Key, PqPreKey, PreKeyBundle, PreKeyCount, PublishPreKeys, SignedEcPreKey, SignedKey,
+};
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use crate::address::{AccountId, ProvisioningId};
+
+use super::LinkDeviceToken;
+
+/// First message on the provisioning websocket, the primary device sends the
+/// provisioning message for the new device to this address
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisioningAddress {
+    pub address: ProvisioningId,
+}
+
+/// A [`ProvisionMessage`] encrypted to the ephemeral key of the new device,
+/// the server relays it without being able to read it
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisionEnvelope {
+    /// Serialized ephemeral public key of the primary device
+    #[serde_as(as = "Base64")]
+    pub public_key: Vec<u8>,
+    #[serde_as(as = "Base64")]
+    pub body: Vec<u8>,
+}
+
+/// Everything a new device needs to join an account. It contains the private
+/// identity key of the account, so it is only ever sent encrypted.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisionMessage {
+    pub account_id: AccountId,
+    pub username: String,
+    /// Serialized `IdentityKeyPair` of the account
+    #[serde_as(as = "Base64")]
+    pub identity_key_pair: Vec<u8>,
+    /// Key contacts present to send sealed messages to the account
+    #[serde_as(as = "Base64")]
+    pub unidentified_access_key: Vec<u8>,
+    pub token: LinkDeviceToken,
+}
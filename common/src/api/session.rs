@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Bearer token that authenticates a device without its password
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionToken {
+    pub token: String,
+    /// Unix time in milliseconds after which the token is rejected
+    pub expires: u64,
+}
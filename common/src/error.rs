@@ -9,4 +9,5 @@ pub enum LibError {
     #[error(ignore)]
     AuthorizationError(String),
     RegistrationKeyFieldsRequired,
+    UnknownDeviceRole,
 }
@@ -1,26 +1,75 @@
 use std::collections::HashMap;
 
 use crate::{
-    address::{AccountId, DeviceAddress, DeviceId, MessageId},
+    address::{AccountId, DeviceAddress, DeviceId, MessageId, RegistrationId},
     sam_message::{ClientEnvelope, EnvelopeType, ServerEnvelope},
 };
 
 impl ClientEnvelope {
+    /// Creates an envelope from `source`, `content` holds the type and the
+    /// ciphertext for each device of `recipient`
     pub fn new(
-        r#type: EnvelopeType,
         recipient: AccountId,
         source: DeviceAddress,
+        content: HashMap<DeviceId, (EnvelopeType, Vec<u8>)>,
+        registration_ids: HashMap<DeviceId, RegistrationId>,
+    ) -> Self {
+        let types = content
+            .iter()
+            .map(|(id, (r#type, _))| ((*id).into(), (*r#type).into()))
+            .collect();
+        Self {
+            // every device has its own type, this only tells the envelope
+            // apart from sealed sender envelopes
+            r#type: EnvelopeType::SignalMessage.into(),
+            destination_account_id: recipient.into(),
+            source_account_id: Some(source.account_id().into()),
+            source_device_id: Some(source.device_id().into()),
+            content: content
+                .into_iter()
+                .map(|(id, (_, bytes))| (id.into(), bytes))
+                .collect(),
+            registration_ids: registration_ids
+                .into_iter()
+                .map(|(id, registration_id)| (id.into(), registration_id.into()))
+                .collect(),
+            unidentified_access_key: None,
+            types,
+        }
+    }
+
+    /// The type of the content for `device_id`, envelopes without a type for
+    /// the device fall back to the type of the envelope
+    pub fn type_for(&self, device_id: u32) -> EnvelopeType {
+        self.types
+            .get(&device_id)
+            .and_then(|r#type| EnvelopeType::try_from(*r#type).ok())
+            .unwrap_or(self.r#type())
+    }
+
+    /// Creates a sealed sender envelope, the source is hidden inside the content
+    /// and the server authorizes delivery with the recipients access key instead
+    pub fn unidentified(
+        recipient: AccountId,
         content: HashMap<DeviceId, Vec<u8>>,
+        registration_ids: HashMap<DeviceId, RegistrationId>,
+        unidentified_access_key: Vec<u8>,
     ) -> Self {
         Self {
-            r#type: r#type.into(),
+            r#type: EnvelopeType::UnidentifiedSender.into(),
             destination_account_id: recipient.into(),
-            source_account_id: source.account_id().into(),
-            source_device_id: source.device_id().into(),
+            source_account_id: None,
+            source_device_id: None,
             content: content
                 .into_iter()
                 .map(|(id, bytes)| (id.into(), bytes))
                 .collect(),
+            registration_ids: registration_ids
+                .into_iter()
+                .map(|(id, registration_id)| (id.into(), registration_id.into()))
+                .collect(),
+            unidentified_access_key: Some(unidentified_access_key),
+            types: HashMap::new(),
         }
     }
 }
@@ -37,8 +86,8 @@ impl ServerEnvelope {
             r#type: r#type.into(),
             destination_account_id: destination.account_id().into(),
             destination_device_id: destination.device_id().into(),
-            source_account_id: source.account_id().into(),
-            source_device_id: source.device_id().into(),
+            source_account_id: Some(source.account_id().into()),
+            source_device_id: Some(source.device_id().into()),
             content,
             id: id.into(),
         }
@@ -50,7 +99,8 @@ mod envelope_test {
     use crate::{
         address::{DeviceAddress, MessageId},
         sam_message::{
-            ClientEnvelope, ClientMessage, EnvelopeType, MessageType, ServerEnvelope, ServerMessage,
+            ClientEnvelope, ClientMessage, EnvelopeType, ErrorType, MessageType, ServerEnvelope,
+            ServerMessage,
         },
     };
     use std::collections::HashMap;
@@ -87,13 +137,20 @@ mod envelope_test {
 
         let message_uuid = Uuid::new_v4().to_string();
         let envelope: ClientEnvelope = ClientEnvelope::new(
-            EnvelopeType::SignalMessage,
             alice_address.account_id(),
             bob_address,
             HashMap::from([
-                (1.into(), vec![10, 20, 30]),
-                (2.into(), vec![40, 50, 60]),
-                (3.into(), vec![70, 80, 90]),
+                (1.into(), (EnvelopeType::SignalMessage, vec![10, 20, 30])),
+                (
+                    2.into(),
+                    (EnvelopeType::PreKeySignalMessage, vec![40, 50, 60]),
+                ),
+                (3.into(), (EnvelopeType::SignalMessage, vec![70, 80, 90])),
+            ]),
+            HashMap::from([
+                (1.into(), 11.into()),
+                (2.into(), 22.into()),
+                (3.into(), 33.into()),
             ]),
         );
         let message: ClientMessage = ClientMessage {
@@ -118,12 +175,36 @@ mod envelope_test {
             bob_address.account_id(),
             envelope
                 .source_account_id
+                .expect("envelope should have a source")
                 .try_into()
                 .expect("should be able to convert envelope account id to AccountId"),
         );
         assert_eq!(envelope.content.get(&1), Some(&vec![10, 20, 30]));
         assert_eq!(envelope.content.get(&2), Some(&vec![40, 50, 60]));
         assert_eq!(envelope.content.get(&3), Some(&vec![70, 80, 90]));
+        assert_eq!(envelope.registration_ids.get(&2), Some(&22));
+        assert_eq!(envelope.type_for(1), EnvelopeType::SignalMessage);
+        assert_eq!(envelope.type_for(2), EnvelopeType::PreKeySignalMessage);
+        assert_eq!(envelope.unidentified_access_key, None);
+    }
+
+    #[test]
+    fn unidentified_client_envelope_test() {
+        let alice_address = DeviceAddress::random();
+
+        let envelope = ClientEnvelope::unidentified(
+            alice_address.account_id(),
+            HashMap::from([(1.into(), vec![10, 20, 30])]),
+            HashMap::from([(1.into(), 11.into())]),
+            vec![1; 16],
+        );
+
+        assert_eq!(envelope.r#type, EnvelopeType::UnidentifiedSender.into());
+        assert_eq!(envelope.source_account_id, None);
+        assert_eq!(envelope.source_device_id, None);
+        assert_eq!(envelope.unidentified_access_key, Some(vec![1; 16]));
+        assert_eq!(envelope.content.get(&1), Some(&vec![10, 20, 30]));
+        assert_eq!(envelope.registration_ids.get(&1), Some(&11));
     }
 
     #[test]
@@ -133,6 +214,9 @@ mod envelope_test {
             r#type: MessageType::Ack.into(),
             id: uuid.into(),
             message: None,
+            error: None,
+            device_mismatch: None,
+            notification: None,
         };
 
         assert_eq!(ack.r#type, MessageType::Ack.into());
@@ -149,9 +233,13 @@ mod envelope_test {
             r#type: MessageType::Error.into(),
             id: error_uuid.into(),
             message: None,
+            error: Some(ErrorType::SourceMismatch.into()),
+            device_mismatch: None,
+            notification: None,
         };
 
         assert_eq!(error.r#type, MessageType::Error.into());
+        assert_eq!(error.error(), ErrorType::SourceMismatch);
         assert_eq!(
             error_uuid,
             error
@@ -176,6 +264,9 @@ mod envelope_test {
             r#type: MessageType::Message.into(),
             id: message_uuid.into(),
             message: Some(envelope.clone()),
+            error: None,
+            device_mismatch: None,
+            notification: None,
         };
 
         let id: Vec<u8> = message_uuid.into();
@@ -198,6 +289,7 @@ mod envelope_test {
             bob_address.account_id(),
             envelope
                 .source_account_id
+                .expect("envelope should have a source")
                 .try_into()
                 .expect("should be able to convert envelope account id to MessageId")
         );
@@ -0,0 +1,68 @@
+CREATE TABLE Accounts (
+  id        UUID PRIMARY KEY,
+  username  TEXT NOT NULL,
+  identity  BYTEA NOT NULL
+);
+
+CREATE TABLE Devices (
+  account_id       UUID NOT NULL,
+  id               BIGINT NOT NULL,
+  name             TEXT NOT NULL,
+  creation         BIGINT NOT NULL,
+  registration_id  BIGINT NOT NULL,
+  password_hash    TEXT NOT NULL,
+  password_salt    TEXT NOT NULL,
+  PRIMARY KEY (account_id, id)
+);
+
+CREATE TABLE PreKeys (
+  id          BIGSERIAL PRIMARY KEY,
+  account_id  UUID NOT NULL,
+  device_id   BIGINT NOT NULL,
+  key_id      BIGINT NOT NULL,
+  public_key  BYTEA NOT NULL,
+  UNIQUE (account_id, device_id, key_id)
+);
+
+CREATE TABLE SignedPreKeys (
+  account_id  UUID NOT NULL,
+  device_id   BIGINT NOT NULL,
+  key_id      BIGINT NOT NULL,
+  public_key  BYTEA NOT NULL,
+  signature   BYTEA NOT NULL,
+  PRIMARY KEY (account_id, device_id)
+);
+
+CREATE TABLE PqPreKeys (
+  id          BIGSERIAL PRIMARY KEY,
+  account_id  UUID NOT NULL,
+  device_id   BIGINT NOT NULL,
+  key_id      BIGINT NOT NULL,
+  public_key  BYTEA NOT NULL,
+  signature   BYTEA NOT NULL,
+  UNIQUE (account_id, device_id, key_id)
+);
+
+CREATE TABLE LastResortKeys (
+  account_id  UUID NOT NULL,
+  device_id   BIGINT NOT NULL,
+  key_id      BIGINT NOT NULL,
+  public_key  BYTEA NOT NULL,
+  signature   BYTEA NOT NULL,
+  PRIMARY KEY (account_id, device_id)
+);
+
+CREATE TABLE Envelopes (
+  account_id  UUID NOT NULL,
+  device_id   BIGINT NOT NULL,
+  id          UUID NOT NULL,
+  envelope    BYTEA NOT NULL,
+  PRIMARY KEY (account_id, device_id, id)
+);
+
+CREATE TABLE PendingMessages (
+  account_id   UUID NOT NULL,
+  device_id    BIGINT NOT NULL,
+  envelope_id  UUID NOT NULL,
+  PRIMARY KEY (account_id, device_id, envelope_id)
+);
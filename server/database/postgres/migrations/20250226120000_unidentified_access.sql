@@ -0,0 +1 @@
+ALTER TABLE Accounts ADD COLUMN unidentified_access_key BYTEA;
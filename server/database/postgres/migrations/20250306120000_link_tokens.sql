@@ -0,0 +1,6 @@
+CREATE TABLE LinkTokens (
+  id          TEXT PRIMARY KEY,
+  account_id  UUID NOT NULL,
+  used        BOOLEAN NOT NULL DEFAULT FALSE,
+  revoked     BOOLEAN NOT NULL DEFAULT FALSE
+);
@@ -0,0 +1,2 @@
+ALTER TABLE Devices ADD COLUMN role TEXT NOT NULL DEFAULT 'linked';
+UPDATE Devices SET role = 'primary' WHERE id = 1;
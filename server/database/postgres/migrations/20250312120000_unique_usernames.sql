@@ -0,0 +1 @@
+CREATE UNIQUE INDEX accounts_username ON Accounts (username);
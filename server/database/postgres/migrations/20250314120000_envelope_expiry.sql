@@ -0,0 +1,6 @@
+-- unix time in milliseconds, envelopes stored before this migration expire a
+-- full retention period after it
+ALTER TABLE Envelopes ADD COLUMN created BIGINT NOT NULL DEFAULT 0;
+UPDATE Envelopes SET created = (EXTRACT(EPOCH FROM now()) * 1000)::BIGINT;
+
+CREATE INDEX envelopes_created ON Envelopes (created);
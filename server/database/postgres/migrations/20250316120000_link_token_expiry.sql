@@ -0,0 +1,6 @@
+-- unix time in milliseconds, tokens issued before this migration are pruned a
+-- day after it
+ALTER TABLE LinkTokens ADD COLUMN expires BIGINT NOT NULL DEFAULT 0;
+UPDATE LinkTokens SET expires = (EXTRACT(EPOCH FROM now()) * 1000)::BIGINT + 86400000;
+
+CREATE INDEX link_tokens_expires ON LinkTokens (expires);
@@ -0,0 +1,4 @@
+-- insertion order, the created timestamps of envelopes can tie
+ALTER TABLE Envelopes ADD COLUMN seq BIGSERIAL;
+
+CREATE INDEX envelopes_seq ON Envelopes (account_id, device_id, seq);
@@ -0,0 +1,68 @@
+CREATE TABLE Accounts (
+  id        BLOB PRIMARY KEY,
+  username  TEXT NOT NULL,
+  identity  BLOB NOT NULL
+);
+
+CREATE TABLE Devices (
+  account_id       BLOB NOT NULL,
+  id               INTEGER NOT NULL,
+  name             TEXT NOT NULL,
+  creation         INTEGER NOT NULL,
+  registration_id  INTEGER NOT NULL,
+  password_hash    TEXT NOT NULL,
+  password_salt    TEXT NOT NULL,
+  PRIMARY KEY (account_id, id)
+);
+
+CREATE TABLE PreKeys (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  account_id  BLOB NOT NULL,
+  device_id   INTEGER NOT NULL,
+  key_id      INTEGER NOT NULL,
+  public_key  BLOB NOT NULL,
+  UNIQUE (account_id, device_id, key_id)
+);
+
+CREATE TABLE SignedPreKeys (
+  account_id  BLOB NOT NULL,
+  device_id   INTEGER NOT NULL,
+  key_id      INTEGER NOT NULL,
+  public_key  BLOB NOT NULL,
+  signature   BLOB NOT NULL,
+  PRIMARY KEY (account_id, device_id)
+);
+
+CREATE TABLE PqPreKeys (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  account_id  BLOB NOT NULL,
+  device_id   INTEGER NOT NULL,
+  key_id      INTEGER NOT NULL,
+  public_key  BLOB NOT NULL,
+  signature   BLOB NOT NULL,
+  UNIQUE (account_id, device_id, key_id)
+);
+
+CREATE TABLE LastResortKeys (
+  account_id  BLOB NOT NULL,
+  device_id   INTEGER NOT NULL,
+  key_id      INTEGER NOT NULL,
+  public_key  BLOB NOT NULL,
+  signature   BLOB NOT NULL,
+  PRIMARY KEY (account_id, device_id)
+);
+
+CREATE TABLE Envelopes (
+  account_id  BLOB NOT NULL,
+  device_id   INTEGER NOT NULL,
+  id          BLOB NOT NULL,
+  envelope    BLOB NOT NULL,
+  PRIMARY KEY (account_id, device_id, id)
+);
+
+CREATE TABLE PendingMessages (
+  account_id   BLOB NOT NULL,
+  device_id    INTEGER NOT NULL,
+  envelope_id  BLOB NOT NULL,
+  PRIMARY KEY (account_id, device_id, envelope_id)
+);
@@ -0,0 +1,6 @@
+-- unix time in milliseconds, envelopes stored before this migration expire a
+-- full retention period after it
+ALTER TABLE Envelopes ADD COLUMN created INTEGER NOT NULL DEFAULT 0;
+UPDATE Envelopes SET created = CAST(strftime('%s', 'now') AS INTEGER) * 1000;
+
+CREATE INDEX envelopes_created ON Envelopes (created);
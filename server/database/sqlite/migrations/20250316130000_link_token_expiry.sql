@@ -0,0 +1,6 @@
+-- unix time in milliseconds, tokens issued before this migration are pruned a
+-- day after it
+ALTER TABLE LinkTokens ADD COLUMN expires INTEGER NOT NULL DEFAULT 0;
+UPDATE LinkTokens SET expires = (CAST(strftime('%s', 'now') AS INTEGER) + 86400) * 1000;
+
+CREATE INDEX link_tokens_expires ON LinkTokens (expires);
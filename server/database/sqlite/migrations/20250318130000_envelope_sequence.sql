@@ -0,0 +1,5 @@
+-- insertion order, the created timestamps of envelopes can tie
+ALTER TABLE Envelopes ADD COLUMN seq INTEGER NOT NULL DEFAULT 0;
+UPDATE Envelopes SET seq = rowid;
+
+CREATE INDEX envelopes_seq ON Envelopes (account_id, device_id, seq);
@@ -0,0 +1,3 @@
+-- usernames that differ only in case belong to the same account
+DROP INDEX accounts_username;
+CREATE UNIQUE INDEX accounts_username ON Accounts (username COLLATE NOCASE);
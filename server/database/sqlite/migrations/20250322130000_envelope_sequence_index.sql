@@ -0,0 +1,3 @@
+-- lets the next sequence number be read from the end of the index instead of
+-- scanning every envelope on insert
+CREATE INDEX envelopes_seq_only ON Envelopes (seq);
@@ -1,12 +1,16 @@
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::LazyLock;
 
-use axum::extract::FromRequestParts;
+use axum::extract::{ConnectInfo, FromRequestParts};
 use axum::http::request::Parts;
-use axum_extra::headers::authorization::Basic;
+use axum_extra::headers::authorization::{Basic, Bearer};
 use axum_extra::headers::Authorization;
 use axum_extra::TypedHeader;
 use sam_common::address::AccountId;
 
+use crate::auth::password::Password;
+use crate::auth::session::{decode_session_token, verify_session_token};
 use crate::managers::entities::account::Account;
 use crate::managers::entities::device::Device;
 use crate::managers::traits::account_manager::AccountManager;
@@ -15,6 +19,13 @@ use crate::state::state_type::StateType;
 use crate::state::ServerState;
 use crate::ServerError;
 
+/// Verified in place of the password of a device that does not exist, so
+/// rejecting it takes as long as rejecting a wrong password
+static DUMMY_PASSWORD: LazyLock<Password> = LazyLock::new(|| {
+    Password::generate("not the password of any device".to_string())
+        .expect("Can hash dummy password")
+});
+
 #[derive(Clone)]
 pub struct AuthenticatedUser {
     account: Account,
@@ -31,13 +42,14 @@ impl AuthenticatedUser {
     }
 }
 
-impl<T: StateType> FromRequestParts<ServerState<T>> for AuthenticatedUser {
-    type Rejection = ServerError;
-
-    async fn from_request_parts(
+impl AuthenticatedUser {
+    /// Checks the password from a Basic authorization header, argon2 makes
+    /// this slow on purpose. Wrong passwords are limited per account and peer
+    /// address, so guesses from elsewhere cannot lock the owner out.
+    async fn from_password<T: StateType>(
         parts: &mut Parts,
         state: &ServerState<T>,
-    ) -> Result<Self, Self::Rejection> {
+    ) -> Result<Self, ServerError> {
         let (userinfo, password) = {
             let TypedHeader(basic) =
                 TypedHeader::<Authorization<Basic>>::from_request_parts(parts, &state)
@@ -54,33 +66,163 @@ impl<T: StateType> FromRequestParts<ServerState<T>> for AuthenticatedUser {
             .parse()
             .map_err(|_| ServerError::AuthBasicParseError)?;
 
-        let account = { state.accounts.get_account(account_id).await? };
-        let device = { state.devices.get_device(account_id, device_id).await? };
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| peer.ip())
+            .ok_or_else(|| ServerError::Custom("Peer address is unknown".to_string()))?;
+
+        state.failed_logins.peek(&(account_id, peer)).await?;
+        let user = match state.accounts.get_account(account_id).await {
+            Ok(account) => state
+                .devices
+                .get_device(account_id, device_id)
+                .await
+                .map(|device| Self { account, device }),
+            Err(err) => Err(err),
+        };
+
+        let verified = match user {
+            Ok(user) => user.device.password().verify(password).map(|_| user),
+            // unknown devices are rejected like a wrong password and after as
+            // much hashing, so neither the response nor its timing reveals
+            // which accounts exist
+            Err(ServerError::AccountNotExist | ServerError::DeviceNotExist) => {
+                let _ = DUMMY_PASSWORD.verify(password);
+                Err(ServerError::WrongPassword)
+            }
+            Err(err) => Err(err),
+        };
+
+        match verified {
+            Ok(user) => {
+                state.account_requests.check(account_id).await?;
+                Ok(user)
+            }
+            Err(ServerError::WrongPassword) => {
+                // a full bucket is already reported by the peek above
+                let _ = state.failed_logins.check((account_id, peer)).await;
+                Err(ServerError::WrongPassword)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Checks a session token from a Bearer authorization header, tokens of
+    /// unlinked devices are rejected as their device no longer exists
+    async fn from_session<T: StateType>(
+        state: &ServerState<T>,
+        token: &str,
+    ) -> Result<Self, ServerError> {
+        let claims = decode_session_token(token)?;
+        let account = state
+            .accounts
+            .get_account(claims.account_id())
+            .await
+            .map_err(invalid_session)?;
+        let device = state
+            .devices
+            .get_device(claims.account_id(), claims.device_id())
+            .await
+            .map_err(invalid_session)?;
 
-        device.password().verify(password)?;
+        let secret = state.devices.link_secret().await?;
+        verify_session_token(&secret, &claims, device.password())?;
+        state.account_requests.check(claims.account_id()).await?;
         Ok(Self { account, device })
     }
 }
 
+/// Accepts a session token or the password of the device
+impl<T: StateType> FromRequestParts<ServerState<T>> for AuthenticatedUser {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<T>,
+    ) -> Result<Self, Self::Rejection> {
+        // only authenticated requests count against the account, so others
+        // cannot use up its requests
+        match TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await {
+            Ok(TypedHeader(bearer)) => Self::from_session(state, bearer.token()).await,
+            Err(_) => Self::from_password(parts, state).await,
+        }
+    }
+}
+
+/// An [`AuthenticatedUser`] that sent the password of its device, for routes
+/// that must not be reachable with a session token alone
+#[derive(Clone)]
+pub struct PasswordUser(pub AuthenticatedUser);
+
+impl<T: StateType> FromRequestParts<ServerState<T>> for PasswordUser {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<T>,
+    ) -> Result<Self, Self::Rejection> {
+        AuthenticatedUser::from_password(parts, state)
+            .await
+            .map(Self)
+    }
+}
+
+/// A [`PasswordUser`] whose device is the primary device of the account, for
+/// routes that manage the account or its other devices. A stolen session
+/// token is not enough for those.
+#[derive(Clone)]
+pub struct PasswordPrimaryUser(pub AuthenticatedUser);
+
+impl<T: StateType> FromRequestParts<ServerState<T>> for PasswordPrimaryUser {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<T>,
+    ) -> Result<Self, Self::Rejection> {
+        let PasswordUser(user) = PasswordUser::from_request_parts(parts, state).await?;
+        if !user.device().is_primary() {
+            return Err(ServerError::DeviceUnAuth);
+        }
+        Ok(Self(user))
+    }
+}
+
+fn invalid_session(err: ServerError) -> ServerError {
+    match err {
+        ServerError::AccountNotExist | ServerError::DeviceNotExist => {
+            ServerError::SessionTokenInvalid
+        }
+        err => err,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
         auth::{authenticated_user::AuthenticatedUser, password::Password},
         managers::{
             entities::{account::Account, device::Device},
+            in_memory::InMemStateType,
+            rate_limit::RateLimit,
             traits::{account_manager::AccountManager, device_manager::DeviceManager},
         },
+        routes::test_utils::create_user,
         state::ServerState,
+        ServerError,
     };
+    use std::{net::SocketAddr, time::Duration};
+
     use axum::{
         body::Body,
-        extract::{FromRequest, FromRequestParts, Request},
+        extract::{ConnectInfo, FromRequest, FromRequestParts, Request},
         http::{header::AUTHORIZATION, request::Parts},
     };
     use base64::{engine::general_purpose::STANDARD, Engine as _};
     use libsignal_protocol::IdentityKeyPair;
     use rand::rngs::OsRng;
-    use sam_common::address::AccountId;
+    use sam_common::{address::AccountId, api::DeviceRole};
 
     #[tokio::test]
     async fn test_from_request_parts() {
@@ -116,6 +258,7 @@ mod test {
             .password(Password::generate(account_pwd).expect("abc3 can create password"))
             .creation(0)
             .registration_id(1.into())
+            .role(DeviceRole::Primary)
             .build();
 
         let account_id = account.id();
@@ -127,6 +270,7 @@ mod test {
 
         let request = Request::builder()
             .header(AUTHORIZATION, auth_header)
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))))
             .body(Body::empty())
             .unwrap();
 
@@ -138,4 +282,66 @@ mod test {
             result.is_ok_and(|au| au.account().id() == account_id && au.device().id() == device_id)
         )
     }
+
+    async fn basic_parts(
+        state: &ServerState<InMemStateType>,
+        account_id: AccountId,
+        password: &str,
+        peer: [u8; 4],
+    ) -> Parts {
+        let request = Request::builder()
+            .header(
+                AUTHORIZATION,
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{account_id}.1:{password}"))
+                ),
+            )
+            .extension(ConnectInfo(SocketAddr::from((peer, 1234))))
+            .body(Body::empty())
+            .unwrap();
+        Parts::from_request(request, state).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unknown_account_is_a_wrong_password() {
+        let state = ServerState::in_memory_test();
+        let mut parts = basic_parts(&state, AccountId::generate(), "password", [10, 0, 0, 1]).await;
+
+        assert!(matches!(
+            AuthenticatedUser::from_request_parts(&mut parts, &state).await,
+            Err(ServerError::WrongPassword)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_passwords_do_not_limit_the_owner() {
+        let mut state = ServerState::in_memory_test()
+            .with_account_request_limit(RateLimit::new(1, Duration::from_secs(60)))
+            .with_failed_login_limit(RateLimit::new(1, Duration::from_secs(60)));
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+
+        let mut parts = basic_parts(&state, account_id, "guess", [10, 0, 0, 2]).await;
+        assert!(matches!(
+            AuthenticatedUser::from_request_parts(&mut parts, &state).await,
+            Err(ServerError::WrongPassword)
+        ));
+        let mut parts = basic_parts(&state, account_id, "password", [10, 0, 0, 2]).await;
+        assert!(matches!(
+            AuthenticatedUser::from_request_parts(&mut parts, &state).await,
+            Err(ServerError::RateLimited(_))
+        ));
+
+        // failures neither use up the requests of the account nor block
+        // other peers
+        let mut parts = basic_parts(&state, account_id, "password", [10, 0, 0, 1]).await;
+        assert!(AuthenticatedUser::from_request_parts(&mut parts, &state)
+            .await
+            .is_ok());
+        let mut parts = basic_parts(&state, account_id, "password", [10, 0, 0, 1]).await;
+        assert!(matches!(
+            AuthenticatedUser::from_request_parts(&mut parts, &state).await,
+            Err(ServerError::RateLimited(_))
+        ));
+    }
 }
@@ -0,0 +1,155 @@
+use libsignal_protocol::{
+    IdentityKey, KeyPair, PrivateKey, PublicKey, SenderCertificate, ServerCertificate, Timestamp,
+};
+use rand::{CryptoRng, Rng};
+use sam_common::{
+    address::{AccountId, DeviceId},
+    time_now_millis,
+};
+
+use crate::ServerError;
+
+/// Sender certificates are short lived, clients are expected to fetch a new one daily
+const SENDER_CERTIFICATE_LIFETIME_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Issues the sender certificates used for sealed sender.
+///
+/// The trust root only signs the server certificate, the certificate key
+/// signs the sender certificates handed out to devices.
+#[derive(Clone)]
+pub struct CertificateSigner {
+    trust_root: PublicKey,
+    certificate: ServerCertificate,
+    certificate_key: PrivateKey,
+}
+
+impl CertificateSigner {
+    pub fn new<R: Rng + CryptoRng>(
+        trust_root: &KeyPair,
+        key_id: u32,
+        rng: &mut R,
+    ) -> Result<Self, ServerError> {
+        let certificate_key = KeyPair::generate(rng);
+        let certificate = ServerCertificate::new(
+            key_id,
+            certificate_key.public_key,
+            &trust_root.private_key,
+            rng,
+        )
+        .map_err(|_| ServerError::SenderCertificate)?;
+
+        Ok(Self {
+            trust_root: trust_root.public_key,
+            certificate,
+            certificate_key: certificate_key.private_key,
+        })
+    }
+
+    /// Creates a signer for the trust root with the serialized private key
+    /// `trust_root_key`, certificates issued by it stay valid across restarts
+    pub fn from_trust_root_key<R: Rng + CryptoRng>(
+        trust_root_key: &[u8],
+        rng: &mut R,
+    ) -> Result<Self, ServerError> {
+        let private_key =
+            PrivateKey::deserialize(trust_root_key).map_err(|_| ServerError::SenderCertificate)?;
+        let public_key = private_key
+            .public_key()
+            .map_err(|_| ServerError::SenderCertificate)?;
+        Self::new(&KeyPair::new(public_key, private_key), 1, rng)
+    }
+
+    /// Creates a signer with a fresh trust root, certificates issued by it
+    /// cannot be verified after a restart
+    pub fn generate<R: Rng + CryptoRng>(rng: &mut R) -> Self {
+        Self::new(&KeyPair::generate(rng), 1, rng).expect("Can sign server certificate")
+    }
+
+    pub fn trust_root(&self) -> &PublicKey {
+        &self.trust_root
+    }
+
+    pub fn sender_certificate<R: Rng + CryptoRng>(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        identity: &IdentityKey,
+        rng: &mut R,
+    ) -> Result<SenderCertificate, ServerError> {
+        let expiration = time_now_millis() as u64 + SENDER_CERTIFICATE_LIFETIME_MILLIS;
+
+        SenderCertificate::new(
+            account_id.to_string(),
+            None,
+            *identity.public_key(),
+            (*device_id).into(),
+            Timestamp::from_epoch_millis(expiration),
+            self.certificate.clone(),
+            &self.certificate_key,
+            rng,
+        )
+        .map_err(|_| ServerError::SenderCertificate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libsignal_protocol::{IdentityKeyPair, KeyPair, Timestamp};
+    use rand::rngs::OsRng;
+    use sam_common::{address::AccountId, time_now_millis};
+
+    use crate::ServerError;
+
+    use super::CertificateSigner;
+
+    #[test]
+    fn test_sender_certificate_is_signed_by_trust_root() {
+        let mut rng = OsRng;
+        let trust_root = KeyPair::generate(&mut rng);
+        let signer = CertificateSigner::new(&trust_root, 1, &mut rng).expect("Can create signer");
+
+        let account_id = AccountId::generate();
+        let identity = IdentityKeyPair::generate(&mut rng);
+        let certificate = signer
+            .sender_certificate(account_id, 2.into(), identity.identity_key(), &mut rng)
+            .expect("Can issue sender certificate");
+
+        let now = Timestamp::from_epoch_millis(time_now_millis() as u64);
+        assert!(certificate
+            .validate(&trust_root.public_key, now)
+            .expect("Can validate certificate"));
+        assert!(certificate.sender_uuid().expect("Has sender") == account_id.to_string());
+        assert!(u32::from(certificate.sender_device_id().expect("Has device")) == 2);
+        assert!(!certificate
+            .validate(&KeyPair::generate(&mut rng).public_key, now)
+            .expect("Can validate certificate"));
+    }
+
+    #[test]
+    fn test_signer_from_trust_root_key_keeps_trust_root() {
+        let mut rng = OsRng;
+        let trust_root = KeyPair::generate(&mut rng);
+        let signer =
+            CertificateSigner::from_trust_root_key(&trust_root.private_key.serialize(), &mut rng)
+                .expect("Can create signer");
+
+        let identity = IdentityKeyPair::generate(&mut rng);
+        let certificate = signer
+            .sender_certificate(
+                AccountId::generate(),
+                1.into(),
+                identity.identity_key(),
+                &mut rng,
+            )
+            .expect("Can issue sender certificate");
+        let now = Timestamp::from_epoch_millis(time_now_millis() as u64);
+        assert!(*signer.trust_root() == trust_root.public_key);
+        assert!(certificate
+            .validate(&trust_root.public_key, now)
+            .expect("Can validate certificate"));
+        assert!(matches!(
+            CertificateSigner::from_trust_root_key(b"not a key", &mut rng),
+            Err(ServerError::SenderCertificate)
+        ));
+    }
+}
@@ -1,7 +1,7 @@
 use std::{str::FromStr, time::Duration};
 
 use base64::{
-    prelude::{BASE64_STANDARD_NO_PAD, BASE64_URL_SAFE},
+    prelude::{BASE64_URL_SAFE, BASE64_URL_SAFE_NO_PAD},
     Engine,
 };
 use hkdf::hmac::{Hmac, Mac};
@@ -18,11 +18,17 @@ pub fn create_token(secret: &str, account_id: AccountId) -> LinkDeviceToken {
     LinkDeviceToken::new(id, token)
 }
 
+/// Checks that `token` was issued by this server and has not expired. Whether
+/// it was already used or revoked is tracked by the device manager.
 pub fn verify_token(
     secret: &str,
     expire_seconds: u64,
-    token: LinkDeviceToken,
+    token: &LinkDeviceToken,
 ) -> Result<AccountId, ServerError> {
+    if token.id() != create_id(token.token()) {
+        return Err(ServerError::DeviceTokenMalformed);
+    }
+
     let (claims, b64_signature) = token
         .token()
         .split_once(":")
@@ -75,5 +81,6 @@ fn create_id(token: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(token.as_bytes());
     let digest = hasher.finalize();
-    BASE64_STANDARD_NO_PAD.encode(digest)
+    // the id is part of the revocation url
+    BASE64_URL_SAFE_NO_PAD.encode(digest)
 }
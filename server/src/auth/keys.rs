@@ -13,3 +13,27 @@ pub fn verify_key<T: SignedKey>(identity: &IdentityKey, key: &T) -> Result<(), S
         Ok(())
     }
 }
+
+/// Checks the access key a sealed sender presented against the one registered
+/// by the recipient, without leaking how much of the key matched
+pub fn verify_access_key(
+    expected: Option<&[u8]>,
+    presented: Option<&[u8]>,
+) -> Result<(), ServerError> {
+    let (Some(expected), Some(presented)) = (expected, presented) else {
+        return Err(ServerError::UnauthorizedDelivery);
+    };
+    if expected.len() != presented.len() {
+        return Err(ServerError::UnauthorizedDelivery);
+    }
+
+    let difference = expected
+        .iter()
+        .zip(presented)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if difference != 0 {
+        Err(ServerError::UnauthorizedDelivery)
+    } else {
+        Ok(())
+    }
+}
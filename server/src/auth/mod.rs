@@ -1,4 +1,6 @@
 pub mod authenticated_user;
+pub mod certificate;
 pub mod device;
 pub mod keys;
 pub mod password;
+pub mod session;
@@ -22,6 +22,14 @@ impl Password {
         Ok(Self { hash, salt })
     }
 
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn salt(&self) -> &str {
+        self.salt.as_str()
+    }
+
     pub fn verify(&self, password: String) -> Result<(), ServerError> {
         let pwd_hash = PasswordHash::new(&self.hash).map_err(|_| ServerError::PasswordHashError)?;
         let res = Argon2::default().verify_password(password.as_bytes(), &pwd_hash);
@@ -0,0 +1,184 @@
+use std::{str::FromStr, time::Duration};
+
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use hkdf::hmac::{Hmac, Mac};
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::SessionToken,
+    time_now_millis,
+};
+use sha2::Sha256;
+
+use crate::{auth::password::Password, ServerError};
+
+/// Keeps session signatures apart from link token signatures made with the same secret
+const SESSION_CONTEXT: &[u8] = b"sam-session";
+
+/// The claims of a session token, readable before the token is verified so
+/// the device it was issued to can be looked up
+pub struct SessionClaims<'a> {
+    account_id: AccountId,
+    device_id: DeviceId,
+    expires: u64,
+    claims: &'a str,
+    signature: &'a str,
+}
+
+impl SessionClaims<'_> {
+    pub fn account_id(&self) -> AccountId {
+        self.account_id
+    }
+
+    pub fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+}
+
+/// Issues a token for the device that owns `password`. The signature covers
+/// the password hash, so changing the password or unlinking the device
+/// revokes every token issued to it.
+pub fn create_session_token(
+    secret: &str,
+    account_id: AccountId,
+    device_id: DeviceId,
+    password: &Password,
+    lifetime: Duration,
+) -> SessionToken {
+    let expires = (time_now_millis() + lifetime.as_millis()) as u64;
+    let claims = format!("{account_id}.{device_id}.{expires}");
+    let signature = create_signature(secret, &claims, password);
+    SessionToken {
+        token: format!("{claims}.{}", BASE64_URL_SAFE_NO_PAD.encode(signature)),
+        expires,
+    }
+}
+
+pub fn decode_session_token(token: &str) -> Result<SessionClaims<'_>, ServerError> {
+    let (claims, signature) = token
+        .rsplit_once(".")
+        .ok_or(ServerError::SessionTokenInvalid)?;
+    let mut parts = claims.split(".");
+    let (Some(account_id), Some(device_id), Some(expires), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ServerError::SessionTokenInvalid);
+    };
+
+    Ok(SessionClaims {
+        account_id: AccountId::from_str(account_id)
+            .map_err(|_| ServerError::SessionTokenInvalid)?,
+        device_id: device_id
+            .parse()
+            .map_err(|_| ServerError::SessionTokenInvalid)?,
+        expires: expires
+            .parse()
+            .map_err(|_| ServerError::SessionTokenInvalid)?,
+        claims,
+        signature,
+    })
+}
+
+/// Checks that the token was issued by this server for the device that owns
+/// `password` and has not expired
+pub fn verify_session_token(
+    secret: &str,
+    claims: &SessionClaims,
+    password: &Password,
+) -> Result<(), ServerError> {
+    let signature = BASE64_URL_SAFE_NO_PAD
+        .decode(claims.signature)
+        .map_err(|_| ServerError::SessionTokenInvalid)?;
+    session_mac(secret, claims.claims, password)
+        .verify_slice(&signature)
+        .map_err(|_| ServerError::SessionTokenInvalid)?;
+
+    if u128::from(claims.expires) < time_now_millis() {
+        return Err(ServerError::SessionExpired);
+    }
+    Ok(())
+}
+
+fn create_signature(secret: &str, claims: &str, password: &Password) -> Vec<u8> {
+    session_mac(secret, claims, password)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+fn session_mac(secret: &str, claims: &str, password: &Password) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(SESSION_CONTEXT);
+    mac.update(claims.as_bytes());
+    mac.update(password.hash().as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use sam_common::address::AccountId;
+
+    use crate::{auth::password::Password, ServerError};
+
+    use super::{create_session_token, decode_session_token, verify_session_token};
+
+    #[test]
+    fn test_session_token_is_bound_to_device_password() {
+        let account_id = AccountId::generate();
+        let password = Password::generate("bob<3".to_string()).expect("Can hash password");
+        let session = create_session_token(
+            "secret",
+            account_id,
+            2.into(),
+            &password,
+            Duration::from_secs(60),
+        );
+
+        let claims = decode_session_token(&session.token).expect("Can decode token");
+        assert!(claims.account_id() == account_id);
+        assert!(claims.device_id() == 2.into());
+        assert!(verify_session_token("secret", &claims, &password).is_ok());
+
+        let other = Password::generate("bob<3".to_string()).expect("Can hash password");
+        assert!(matches!(
+            verify_session_token("secret", &claims, &other),
+            Err(ServerError::SessionTokenInvalid)
+        ));
+        assert!(matches!(
+            verify_session_token("other secret", &claims, &password),
+            Err(ServerError::SessionTokenInvalid)
+        ));
+
+        let forged = session.token.replacen(".2.", ".3.", 1);
+        let claims = decode_session_token(&forged).expect("Can decode token");
+        assert!(matches!(
+            verify_session_token("secret", &claims, &password),
+            Err(ServerError::SessionTokenInvalid)
+        ));
+    }
+
+    #[test]
+    fn test_expired_session_token_is_rejected() {
+        let password = Password::generate("bob<3".to_string()).expect("Can hash password");
+        let session = create_session_token(
+            "secret",
+            AccountId::generate(),
+            1.into(),
+            &password,
+            Duration::ZERO,
+        );
+        std::thread::sleep(Duration::from_millis(2));
+
+        let claims = decode_session_token(&session.token).expect("Can decode token");
+        assert!(matches!(
+            verify_session_token("secret", &claims, &password),
+            Err(ServerError::SessionExpired)
+        ));
+        assert!(matches!(
+            decode_session_token("not a token"),
+            Err(ServerError::SessionTokenInvalid)
+        ));
+    }
+}
@@ -0,0 +1,668 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use clap::{Args, ValueEnum};
+use derive_more::derive::{Display, Error, From};
+use rand::rngs::OsRng;
+use serde::Deserialize;
+
+use crate::{
+    auth::certificate::CertificateSigner,
+    managers::rate_limit::RateLimit,
+    state::{
+        DEFAULT_ACCOUNT_REQUEST_LIMIT, DEFAULT_ENVELOPE_LIMIT, DEFAULT_FAILED_LOGIN_LIMIT,
+        DEFAULT_IP_REQUEST_LIMIT, DEFAULT_MESSAGE_QUEUE_LIMIT, DEFAULT_MESSAGE_RETENTION,
+        DEFAULT_PRE_KEY_THRESHOLD, DEFAULT_SESSION_LIFETIME, DEFAULT_USERNAME_LOOKUP_LIMIT,
+    },
+};
+
+/// Settings of a `sam-server` process, read from a TOML file.
+///
+/// Every setting can be overridden with a command line flag or the matching
+/// `SAM_*` environment variable, see [`Overrides`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: SocketAddr,
+    pub link_secret: Option<String>,
+    pub provision_expire_seconds: u64,
+    /// Seconds a session token stays valid, devices log in again afterwards
+    pub session_expire_seconds: u64,
+    pub message_buffer_size: usize,
+    /// Seconds an undelivered envelope is kept before it is dropped
+    pub message_retention_seconds: u64,
+    /// Undelivered envelopes kept per device, the oldest are dropped first
+    pub message_queue_limit: usize,
+    /// Devices are notified once fewer one-time keys are left, zero disables it
+    pub pre_key_threshold: u32,
+    /// File with the base64 encoded private key of the sealed sender trust
+    /// root, required for the persistent backends
+    pub certificate_key: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    pub database: DatabaseConfig,
+    pub rate_limits: RateLimitsConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: Backend,
+    /// Connection url, required for the SQL backends
+    pub url: Option<String>,
+}
+
+/// Each limit is written as `{ burst = 10, interval_ms = 1000 }`, a burst of
+/// zero disables it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitsConfig {
+    /// Requests per peer address
+    pub ip: RateLimit,
+    /// Authenticated requests per account
+    pub account: RateLimit,
+    /// Wrong passwords per account and peer address
+    pub failed_logins: RateLimit,
+    /// Envelopes per device on the websocket
+    pub envelopes: RateLimit,
+    /// Username lookups, applied both per account and per peer address
+    pub username_lookups: RateLimit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    InMemory,
+    Sqlite,
+    Postgres,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct Overrides {
+    /// Address the server binds to
+    #[arg(long, env = "SAM_ADDRESS")]
+    pub address: Option<SocketAddr>,
+    /// Secret used to sign device link tokens
+    #[arg(long, env = "SAM_LINK_SECRET")]
+    pub link_secret: Option<String>,
+    /// Seconds a device link token stays valid
+    #[arg(long, env = "SAM_PROVISION_EXPIRE_SECONDS")]
+    pub provision_expire_seconds: Option<u64>,
+    /// Seconds a session token stays valid
+    #[arg(long, env = "SAM_SESSION_EXPIRE_SECONDS")]
+    pub session_expire_seconds: Option<u64>,
+    /// Number of envelopes buffered for each connected device
+    #[arg(long, env = "SAM_MESSAGE_BUFFER_SIZE")]
+    pub message_buffer_size: Option<usize>,
+    /// Seconds an undelivered envelope is kept
+    #[arg(long, env = "SAM_MESSAGE_RETENTION_SECONDS")]
+    pub message_retention_seconds: Option<u64>,
+    /// Number of undelivered envelopes kept for each device
+    #[arg(long, env = "SAM_MESSAGE_QUEUE_LIMIT")]
+    pub message_queue_limit: Option<usize>,
+    /// Number of one-time keys below which a device is notified
+    #[arg(long, env = "SAM_PRE_KEY_THRESHOLD")]
+    pub pre_key_threshold: Option<u32>,
+    /// File with the base64 encoded private key of the sealed sender trust root
+    #[arg(long, env = "SAM_CERTIFICATE_KEY")]
+    pub certificate_key: Option<PathBuf>,
+    /// PEM encoded TLS certificate chain
+    #[arg(long, env = "SAM_TLS_CERTIFICATE")]
+    pub tls_certificate: Option<PathBuf>,
+    /// PEM encoded TLS private key
+    #[arg(long, env = "SAM_TLS_PRIVATE_KEY")]
+    pub tls_private_key: Option<PathBuf>,
+    /// Where the server keeps its state
+    #[arg(long, env = "SAM_DATABASE_BACKEND")]
+    pub database_backend: Option<Backend>,
+    /// Connection url of the database
+    #[arg(long, env = "SAM_DATABASE_URL")]
+    pub database_url: Option<String>,
+    /// Requests a peer address may send at once, zero disables the limit
+    #[arg(long, env = "SAM_RATE_LIMIT_IP_BURST")]
+    pub rate_limit_ip_burst: Option<u32>,
+    /// Milliseconds after which a peer address may send another request
+    #[arg(long, env = "SAM_RATE_LIMIT_IP_INTERVAL_MS")]
+    pub rate_limit_ip_interval_ms: Option<u64>,
+    /// Authenticated requests an account may send at once, zero disables the limit
+    #[arg(long, env = "SAM_RATE_LIMIT_ACCOUNT_BURST")]
+    pub rate_limit_account_burst: Option<u32>,
+    /// Milliseconds after which an account may send another request
+    #[arg(long, env = "SAM_RATE_LIMIT_ACCOUNT_INTERVAL_MS")]
+    pub rate_limit_account_interval_ms: Option<u64>,
+    /// Wrong passwords a peer address may send for an account at once, zero
+    /// disables the limit
+    #[arg(long, env = "SAM_RATE_LIMIT_FAILED_LOGINS_BURST")]
+    pub rate_limit_failed_logins_burst: Option<u32>,
+    /// Milliseconds after which a peer address may try another password
+    #[arg(long, env = "SAM_RATE_LIMIT_FAILED_LOGINS_INTERVAL_MS")]
+    pub rate_limit_failed_logins_interval_ms: Option<u64>,
+    /// Envelopes a device may send at once, zero disables the limit
+    #[arg(long, env = "SAM_RATE_LIMIT_ENVELOPES_BURST")]
+    pub rate_limit_envelopes_burst: Option<u32>,
+    /// Milliseconds after which a device may send another envelope
+    #[arg(long, env = "SAM_RATE_LIMIT_ENVELOPES_INTERVAL_MS")]
+    pub rate_limit_envelopes_interval_ms: Option<u64>,
+    /// Usernames an account may look up at once, zero disables the limit
+    #[arg(long, env = "SAM_RATE_LIMIT_USERNAME_LOOKUPS_BURST")]
+    pub rate_limit_username_lookups_burst: Option<u32>,
+    /// Milliseconds after which an account may look up another username
+    #[arg(long, env = "SAM_RATE_LIMIT_USERNAME_LOOKUPS_INTERVAL_MS")]
+    pub rate_limit_username_lookups_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum ConfigError {
+    #[display("Could not read config file: {_0}")]
+    Read(std::io::Error),
+    #[display("Could not parse config file: {_0}")]
+    Parse(toml::de::Error),
+    #[display("A link secret must be configured")]
+    MissingLinkSecret,
+    #[display("The provisioning expiry must be at least one second")]
+    InvalidProvisionExpiry,
+    #[display("The session expiry must be at least one second")]
+    InvalidSessionExpiry,
+    #[display("Could not read certificate key: {_0}")]
+    #[from(ignore)]
+    ReadCertificateKey(std::io::Error),
+    #[display("The certificate key must be a base64 encoded private key")]
+    InvalidCertificateKey,
+    #[display("The message buffer size must be at least one")]
+    InvalidMessageBufferSize,
+    #[display("The message retention must be at least one second")]
+    InvalidMessageRetention,
+    #[display("The message queue limit must be at least one")]
+    InvalidMessageQueueLimit,
+    #[display("Rate limits need an interval of at least one millisecond")]
+    InvalidRateLimitInterval,
+    #[display("TLS requires both a certificate and a private key")]
+    IncompleteTls,
+    #[display("TLS file '{}' does not exist", _0.display())]
+    #[error(ignore)]
+    #[from(ignore)]
+    MissingTlsFile(PathBuf),
+    #[display("The {_0:?} backend requires a database url")]
+    #[error(ignore)]
+    #[from(ignore)]
+    MissingDatabaseUrl(Backend),
+    #[display(
+        "The {_0:?} backend requires a certificate key, or sender certificates break on restart"
+    )]
+    #[error(ignore)]
+    #[from(ignore)]
+    MissingCertificateKey(Backend),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            link_secret: None,
+            provision_expire_seconds: 600,
+            session_expire_seconds: DEFAULT_SESSION_LIFETIME.as_secs(),
+            message_buffer_size: 10,
+            message_retention_seconds: DEFAULT_MESSAGE_RETENTION.as_secs(),
+            message_queue_limit: DEFAULT_MESSAGE_QUEUE_LIMIT,
+            pre_key_threshold: DEFAULT_PRE_KEY_THRESHOLD,
+            certificate_key: None,
+            tls: None,
+            database: DatabaseConfig::default(),
+            rate_limits: RateLimitsConfig::default(),
+        }
+    }
+}
+
+impl Default for RateLimitsConfig {
+    fn default() -> Self {
+        Self {
+            ip: DEFAULT_IP_REQUEST_LIMIT,
+            account: DEFAULT_ACCOUNT_REQUEST_LIMIT,
+            failed_logins: DEFAULT_FAILED_LOGIN_LIMIT,
+            envelopes: DEFAULT_ENVELOPE_LIMIT,
+            username_lookups: DEFAULT_USERNAME_LOOKUP_LIMIT,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Applies the flags and environment variables that were set
+    pub fn apply(mut self, overrides: Overrides) -> Result<Self, ConfigError> {
+        if let Some(address) = overrides.address {
+            self.address = address;
+        }
+        if let Some(link_secret) = overrides.link_secret {
+            self.link_secret = Some(link_secret);
+        }
+        if let Some(seconds) = overrides.provision_expire_seconds {
+            self.provision_expire_seconds = seconds;
+        }
+        if let Some(seconds) = overrides.session_expire_seconds {
+            self.session_expire_seconds = seconds;
+        }
+        if let Some(size) = overrides.message_buffer_size {
+            self.message_buffer_size = size;
+        }
+        if let Some(seconds) = overrides.message_retention_seconds {
+            self.message_retention_seconds = seconds;
+        }
+        if let Some(limit) = overrides.message_queue_limit {
+            self.message_queue_limit = limit;
+        }
+        if let Some(threshold) = overrides.pre_key_threshold {
+            self.pre_key_threshold = threshold;
+        }
+        if let Some(path) = overrides.certificate_key {
+            self.certificate_key = Some(path);
+        }
+        if let Some(backend) = overrides.database_backend {
+            self.database.backend = backend;
+        }
+        if let Some(url) = overrides.database_url {
+            self.database.url = Some(url);
+        }
+        let limits = &mut self.rate_limits;
+        override_limit(
+            &mut limits.ip,
+            overrides.rate_limit_ip_burst,
+            overrides.rate_limit_ip_interval_ms,
+        );
+        override_limit(
+            &mut limits.account,
+            overrides.rate_limit_account_burst,
+            overrides.rate_limit_account_interval_ms,
+        );
+        override_limit(
+            &mut limits.failed_logins,
+            overrides.rate_limit_failed_logins_burst,
+            overrides.rate_limit_failed_logins_interval_ms,
+        );
+        override_limit(
+            &mut limits.envelopes,
+            overrides.rate_limit_envelopes_burst,
+            overrides.rate_limit_envelopes_interval_ms,
+        );
+        override_limit(
+            &mut limits.username_lookups,
+            overrides.rate_limit_username_lookups_burst,
+            overrides.rate_limit_username_lookups_interval_ms,
+        );
+
+        // a single flag may replace one half of the tls section from the file
+        let certificate = overrides
+            .tls_certificate
+            .or_else(|| self.tls.as_ref().map(|tls| tls.certificate.clone()));
+        let private_key = overrides
+            .tls_private_key
+            .or_else(|| self.tls.as_ref().map(|tls| tls.private_key.clone()));
+        self.tls = match (certificate, private_key) {
+            (Some(certificate), Some(private_key)) => Some(TlsConfig {
+                certificate,
+                private_key,
+            }),
+            (None, None) => None,
+            _ => return Err(ConfigError::IncompleteTls),
+        };
+
+        Ok(self)
+    }
+
+    /// Loads the sealed sender trust root from the certificate key file, if
+    /// one is configured
+    pub fn certificate_signer(&self) -> Result<Option<CertificateSigner>, ConfigError> {
+        let Some(path) = &self.certificate_key else {
+            return Ok(None);
+        };
+        let contents = std::fs::read_to_string(path).map_err(ConfigError::ReadCertificateKey)?;
+        let key = BASE64_STANDARD
+            .decode(contents.trim())
+            .map_err(|_| ConfigError::InvalidCertificateKey)?;
+        CertificateSigner::from_trust_root_key(&key, &mut OsRng)
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidCertificateKey)
+    }
+
+    /// Checks the settings before anything is started
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self
+            .link_secret
+            .as_ref()
+            .is_none_or(|secret| secret.is_empty())
+        {
+            return Err(ConfigError::MissingLinkSecret);
+        }
+        if self.provision_expire_seconds == 0 {
+            return Err(ConfigError::InvalidProvisionExpiry);
+        }
+        if self.session_expire_seconds == 0 {
+            return Err(ConfigError::InvalidSessionExpiry);
+        }
+        if self.message_buffer_size == 0 {
+            return Err(ConfigError::InvalidMessageBufferSize);
+        }
+        if self.message_retention_seconds == 0 {
+            return Err(ConfigError::InvalidMessageRetention);
+        }
+        if self.message_queue_limit == 0 {
+            return Err(ConfigError::InvalidMessageQueueLimit);
+        }
+        let limits = &self.rate_limits;
+        if [
+            limits.ip,
+            limits.account,
+            limits.failed_logins,
+            limits.envelopes,
+            limits.username_lookups,
+        ]
+        .iter()
+        .any(|limit| !limit.is_disabled() && limit.interval.is_zero())
+        {
+            return Err(ConfigError::InvalidRateLimitInterval);
+        }
+        if let Some(tls) = &self.tls {
+            for path in [&tls.certificate, &tls.private_key] {
+                if !path.is_file() {
+                    return Err(ConfigError::MissingTlsFile(path.clone()));
+                }
+            }
+        }
+        match self.database.backend {
+            Backend::InMemory => Ok(()),
+            backend if self.database.url.is_none() => Err(ConfigError::MissingDatabaseUrl(backend)),
+            backend if self.certificate_key.is_none() => {
+                Err(ConfigError::MissingCertificateKey(backend))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn override_limit(limit: &mut RateLimit, burst: Option<u32>, interval_ms: Option<u64>) {
+    if let Some(burst) = burst {
+        limit.burst = burst;
+    }
+    if let Some(interval_ms) = interval_ms {
+        limit.interval = Duration::from_millis(interval_ms);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, time::Duration};
+
+    use base64::{prelude::BASE64_STANDARD, Engine as _};
+    use libsignal_protocol::KeyPair;
+    use rand::rngs::OsRng;
+
+    use crate::{managers::rate_limit::RateLimit, state::DEFAULT_IP_REQUEST_LIMIT};
+
+    use super::{Backend, Config, ConfigError, Overrides, TlsConfig};
+
+    const CONFIG: &str = r#"
+        address = "0.0.0.0:443"
+        link_secret = "secret"
+        provision_expire_seconds = 60
+        session_expire_seconds = 300
+        message_buffer_size = 32
+        message_retention_seconds = 86400
+        message_queue_limit = 50
+        pre_key_threshold = 20
+        certificate_key = "trust-root.key"
+
+        [database]
+        backend = "sqlite"
+        url = "sqlite://sam-server.db"
+
+        [rate_limits]
+        failed_logins = { burst = 3, interval_ms = 60000 }
+        envelopes = { burst = 5, interval_ms = 200 }
+        username_lookups = { burst = 0, interval_ms = 0 }
+    "#;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(CONFIG).expect("Can parse config");
+
+        assert_eq!(config.address, "0.0.0.0:443".parse().unwrap());
+        assert_eq!(config.link_secret.as_deref(), Some("secret"));
+        assert_eq!(config.provision_expire_seconds, 60);
+        assert_eq!(config.session_expire_seconds, 300);
+        assert_eq!(config.message_buffer_size, 32);
+        assert_eq!(config.message_retention_seconds, 86400);
+        assert_eq!(config.message_queue_limit, 50);
+        assert_eq!(config.pre_key_threshold, 20);
+        assert_eq!(
+            config.certificate_key,
+            Some(PathBuf::from("trust-root.key"))
+        );
+        assert_eq!(config.database.backend, Backend::Sqlite);
+        assert_eq!(
+            config.rate_limits.envelopes,
+            RateLimit::new(5, Duration::from_millis(200))
+        );
+        assert_eq!(
+            config.rate_limits.failed_logins,
+            RateLimit::new(3, Duration::from_secs(60))
+        );
+        assert!(config.rate_limits.username_lookups.is_disabled());
+        assert_eq!(config.rate_limits.ip, DEFAULT_IP_REQUEST_LIMIT);
+        assert!(config.tls.is_none());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_missing_settings_use_defaults() {
+        let config = Config::parse("link_secret = \"secret\"").expect("Can parse config");
+
+        assert_eq!(
+            config,
+            Config {
+                link_secret: Some("secret".to_string()),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_setting_is_rejected() {
+        assert!(matches!(
+            Config::parse("port = 8080"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_overrides_replace_file_settings() {
+        let config = Config::parse(CONFIG)
+            .expect("Can parse config")
+            .apply(Overrides {
+                link_secret: Some("other".to_string()),
+                database_backend: Some(Backend::InMemory),
+                rate_limit_envelopes_interval_ms: Some(50),
+                message_queue_limit: Some(10),
+                ..Overrides::default()
+            })
+            .expect("Can apply overrides");
+
+        assert_eq!(config.link_secret.as_deref(), Some("other"));
+        assert_eq!(config.database.backend, Backend::InMemory);
+        assert_eq!(config.message_buffer_size, 32);
+        assert_eq!(config.message_queue_limit, 10);
+        assert_eq!(
+            config.rate_limits.envelopes,
+            RateLimit::new(5, Duration::from_millis(50))
+        );
+    }
+
+    #[test]
+    fn test_tls_override_completes_file_settings() {
+        let config = Config {
+            tls: Some(TlsConfig {
+                certificate: PathBuf::from("cert.pem"),
+                private_key: PathBuf::from("key.pem"),
+            }),
+            ..Config::default()
+        }
+        .apply(Overrides {
+            tls_private_key: Some(PathBuf::from("other.pem")),
+            ..Overrides::default()
+        })
+        .expect("Can apply overrides");
+
+        assert_eq!(
+            config.tls,
+            Some(TlsConfig {
+                certificate: PathBuf::from("cert.pem"),
+                private_key: PathBuf::from("other.pem"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_half_tls_config_is_rejected() {
+        assert!(matches!(
+            Config::default().apply(Overrides {
+                tls_certificate: Some(PathBuf::from("cert.pem")),
+                ..Overrides::default()
+            }),
+            Err(ConfigError::IncompleteTls)
+        ));
+    }
+
+    #[test]
+    fn test_certificate_key_is_loaded_from_file() {
+        let trust_root = KeyPair::generate(&mut OsRng);
+        let path = std::env::temp_dir().join(format!("sam-trust-root-{}.key", std::process::id()));
+        std::fs::write(
+            &path,
+            format!(
+                "{}\n",
+                BASE64_STANDARD.encode(trust_root.private_key.serialize())
+            ),
+        )
+        .expect("Can write certificate key");
+
+        let config = Config {
+            certificate_key: Some(path.clone()),
+            ..Config::default()
+        };
+        let signer = config.certificate_signer();
+        std::fs::write(&path, "not base64").expect("Can write certificate key");
+        let invalid = config.certificate_signer();
+        std::fs::remove_file(&path).expect("Can remove certificate key");
+
+        assert!(signer
+            .expect("Can load certificate key")
+            .is_some_and(|signer| *signer.trust_root() == trust_root.public_key));
+        assert!(matches!(invalid, Err(ConfigError::InvalidCertificateKey)));
+        assert!(matches!(
+            config.certificate_signer(),
+            Err(ConfigError::ReadCertificateKey(_))
+        ));
+        assert!(Config::default()
+            .certificate_signer()
+            .is_ok_and(|signer| signer.is_none()));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(matches!(
+            Config::default().validate(),
+            Err(ConfigError::MissingLinkSecret)
+        ));
+
+        let valid = Config {
+            link_secret: Some("secret".to_string()),
+            ..Config::default()
+        };
+        assert!(valid.validate().is_ok());
+
+        let mut config = valid.clone();
+        config.provision_expire_seconds = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidProvisionExpiry)
+        ));
+
+        let mut config = valid.clone();
+        config.session_expire_seconds = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidSessionExpiry)
+        ));
+
+        let mut config = valid.clone();
+        config.message_buffer_size = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidMessageBufferSize)
+        ));
+
+        let mut config = valid.clone();
+        config.message_retention_seconds = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidMessageRetention)
+        ));
+
+        let mut config = valid.clone();
+        config.message_queue_limit = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidMessageQueueLimit)
+        ));
+
+        let mut config = valid.clone();
+        config.rate_limits.account.interval = Duration::ZERO;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidRateLimitInterval)
+        ));
+
+        let mut config = valid.clone();
+        config.database.backend = Backend::Postgres;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MissingDatabaseUrl(Backend::Postgres))
+        ));
+
+        let mut config = valid.clone();
+        config.database.backend = Backend::Sqlite;
+        config.database.url = Some("sqlite://sam-server.db".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MissingCertificateKey(Backend::Sqlite))
+        ));
+        config.certificate_key = Some(PathBuf::from("trust-root.key"));
+        assert!(config.validate().is_ok());
+
+        let mut config = valid;
+        config.tls = Some(TlsConfig {
+            certificate: PathBuf::from("/does/not/exist.pem"),
+            private_key: PathBuf::from("/does/not/exist.key"),
+        });
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MissingTlsFile(_))
+        ));
+    }
+}
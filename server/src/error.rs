@@ -1,7 +1,16 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use std::time::Duration;
+
+use axum::{
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use derive_more::derive::{Display, Error};
 use log::debug;
-use sam_common::LibError;
+use sam_common::{
+    api::{ErrorCode, ErrorResponse},
+    LibError,
+};
 
 pub type Result<T> = std::result::Result<T, ServerError>;
 
@@ -11,20 +20,37 @@ pub enum ServerError {
     Custom(String),
     Lib(LibError),
     KeyVerification,
+    KeyMalformed,
+    KeyExists,
+    SenderCertificate,
+    UnauthorizedDelivery,
     DeviceTokenMalformed,
     DeviceSignatureDecodeError,
     DeviceWrongSignature,
     DeviceLinkTooSlow,
-    DeviceProvisionUnAuth,
+    DeviceTokenNotExist,
+    DeviceTokenUsed,
+    DeviceTokenRevoked,
+    ProvisioningNotExist,
     DeviceUnAuth,
+    DeviceIsPrimary,
     AccountIDUnParsable,
     PasswordHashError,
     WrongPassword,
     AuthBasicParseError,
+    SessionTokenInvalid,
+    SessionExpired,
     AccountNotExist,
     AccountExists,
+    UsernameTaken,
+    UsernameInvalid,
+    /// Carries the time until the next request is allowed
+    #[display("RateLimited")]
+    #[error(ignore)]
+    RateLimited(Duration),
     DeviceNotExist,
     DeviceExists,
+    DeviceNameInvalid,
     KeyNotExist,
     EnvelopeExists,
     EnvelopeNotExists,
@@ -32,47 +58,128 @@ pub enum ServerError {
     MessageSubscriberNotExists,
     WebSocketDecodeError,
     WebSocketDisconnected,
+    WebSocketForceClosed,
     WebSocketSendError,
     MessageAlreadyPending,
     MessageNotPending,
     EnvelopeMalformed,
     MessageSubscriberSendErorr,
+    Database(sqlx::Error),
+    DatabaseDecodeError,
+    DatabaseTransactionClosed,
+}
+
+impl From<sqlx::Error> for ServerError {
+    fn from(value: sqlx::Error) -> Self {
+        ServerError::Database(value)
+    }
+}
+
+impl ServerError {
+    /// Status code and error code reported to REST clients
+    pub fn status(&self) -> (StatusCode, ErrorCode) {
+        match self {
+            ServerError::Custom(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal),
+            ServerError::Lib(LibError::RegistrationKeyFieldsRequired) => {
+                (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest)
+            }
+            ServerError::Lib(LibError::AuthorizationError(_)) => {
+                (StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized)
+            }
+            ServerError::Lib(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal),
+            ServerError::KeyVerification => {
+                (StatusCode::BAD_REQUEST, ErrorCode::InvalidKeySignature)
+            }
+            ServerError::KeyMalformed => (StatusCode::BAD_REQUEST, ErrorCode::InvalidKey),
+            ServerError::KeyExists => (StatusCode::CONFLICT, ErrorCode::KeyExists),
+            ServerError::SenderCertificate => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal)
+            }
+            ServerError::UnauthorizedDelivery => {
+                (StatusCode::UNAUTHORIZED, ErrorCode::UnauthorizedDelivery)
+            }
+            ServerError::DeviceTokenMalformed => {
+                (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest)
+            }
+            ServerError::DeviceSignatureDecodeError => {
+                (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest)
+            }
+            ServerError::DeviceWrongSignature => {
+                (StatusCode::UNAUTHORIZED, ErrorCode::InvalidLinkToken)
+            }
+            ServerError::DeviceLinkTooSlow => (StatusCode::GONE, ErrorCode::LinkTokenExpired),
+            ServerError::DeviceTokenNotExist => {
+                (StatusCode::NOT_FOUND, ErrorCode::InvalidLinkToken)
+            }
+            ServerError::DeviceTokenUsed => (StatusCode::GONE, ErrorCode::LinkTokenUsed),
+            ServerError::DeviceTokenRevoked => (StatusCode::GONE, ErrorCode::LinkTokenRevoked),
+            ServerError::ProvisioningNotExist => {
+                (StatusCode::NOT_FOUND, ErrorCode::ProvisioningNotFound)
+            }
+            ServerError::DeviceUnAuth => (StatusCode::FORBIDDEN, ErrorCode::Forbidden),
+            ServerError::DeviceIsPrimary => (StatusCode::CONFLICT, ErrorCode::DeviceIsPrimary),
+            ServerError::AccountIDUnParsable => {
+                (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest)
+            }
+            ServerError::PasswordHashError => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal)
+            }
+            ServerError::WrongPassword => (StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized),
+            ServerError::AuthBasicParseError => (StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized),
+            ServerError::SessionTokenInvalid => (StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized),
+            ServerError::SessionExpired => (StatusCode::UNAUTHORIZED, ErrorCode::SessionExpired),
+            ServerError::AccountNotExist => (StatusCode::NOT_FOUND, ErrorCode::AccountNotFound),
+            ServerError::AccountExists => (StatusCode::CONFLICT, ErrorCode::AccountExists),
+            ServerError::UsernameTaken => (StatusCode::CONFLICT, ErrorCode::UsernameTaken),
+            ServerError::UsernameInvalid => (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest),
+            ServerError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, ErrorCode::RateLimited),
+            ServerError::DeviceNotExist => (StatusCode::NOT_FOUND, ErrorCode::DeviceNotFound),
+            ServerError::DeviceExists => (StatusCode::CONFLICT, ErrorCode::DeviceExists),
+            ServerError::DeviceNameInvalid => {
+                (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest)
+            }
+            ServerError::KeyNotExist => (StatusCode::NOT_FOUND, ErrorCode::KeyNotFound),
+            ServerError::EnvelopeExists => (StatusCode::CONFLICT, ErrorCode::EnvelopeExists),
+            ServerError::EnvelopeNotExists => (StatusCode::NOT_FOUND, ErrorCode::EnvelopeNotFound),
+            ServerError::EnvelopeMalformed => {
+                (StatusCode::BAD_REQUEST, ErrorCode::MalformedRequest)
+            }
+            ServerError::MessageSubscriberExists
+            | ServerError::MessageSubscriberNotExists
+            | ServerError::WebSocketDecodeError
+            | ServerError::WebSocketDisconnected
+            | ServerError::WebSocketForceClosed
+            | ServerError::WebSocketSendError
+            | ServerError::MessageAlreadyPending
+            | ServerError::MessageNotPending
+            | ServerError::MessageSubscriberSendErorr
+            | ServerError::Database(_)
+            | ServerError::DatabaseDecodeError
+            | ServerError::DatabaseTransactionClosed => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal)
+            }
+        }
+    }
 }
 
 impl IntoResponse for ServerError {
     fn into_response(self) -> axum::response::Response {
         debug!("ServerError occured: {}", self);
-        match self {
-            ServerError::Custom(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::Lib(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::KeyVerification => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceTokenMalformed => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceSignatureDecodeError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceWrongSignature => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceLinkTooSlow => StatusCode::FORBIDDEN,
-            ServerError::DeviceProvisionUnAuth => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::AccountIDUnParsable => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::PasswordHashError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::WrongPassword => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::AuthBasicParseError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::AccountNotExist => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::AccountExists => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceNotExist => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceExists => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::KeyNotExist => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::EnvelopeExists => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::EnvelopeNotExists => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::MessageSubscriberExists => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::MessageSubscriberNotExists => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::WebSocketDecodeError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::WebSocketDisconnected => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::WebSocketSendError => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::MessageAlreadyPending => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::MessageNotPending => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::EnvelopeMalformed => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::MessageSubscriberSendErorr => StatusCode::INTERNAL_SERVER_ERROR,
-            ServerError::DeviceUnAuth => StatusCode::UNAUTHORIZED,
+        let (status, code) = self.status();
+        // internal errors may carry database details, those stay in the log
+        let message = if status.is_server_error() {
+            "Internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+        let mut response = (status, Json(ErrorResponse::new(code, message))).into_response();
+        if let ServerError::RateLimited(wait) = self {
+            // whole seconds, rounded up so clients do not retry too early
+            let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
         }
-        .into_response()
+        response
     }
 }
@@ -1,4 +1,5 @@
 pub mod auth;
+pub mod config;
 pub mod error;
 pub mod logic;
 pub mod managers;
@@ -10,4 +11,4 @@ pub mod state;
 mod test_utils;
 
 pub use error::ServerError;
-pub use server::{start_server, ServerConfig};
+pub use server::{install_crypto_provider, start_server, ServerConfig};
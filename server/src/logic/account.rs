@@ -1,18 +1,22 @@
+use std::net::IpAddr;
+
 use sam_common::{
-    address::AccountId,
-    api::account::{RegistrationRequest, RegistrationResponse},
+    address::{AccountId, DeviceId},
+    api::{
+        account::{RegistrationRequest, RegistrationResponse},
+        DeviceRole,
+    },
 };
 
 use crate::{
-    logic::device::create_device,
+    logic::{
+        device::{create_device, remove_device_data},
+        keys::validate_keybundle,
+    },
     managers::{
-        entities::account::Account,
+        entities::account::{normalize_username, Account},
         traits::{
-            account_manager::AccountManager,
-            device_manager::DeviceManager,
-            key_manager::{
-                LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
-            },
+            account_manager::AccountManager, device_manager::DeviceManager,
             message_manager::MessageManager,
         },
     },
@@ -20,50 +24,77 @@ use crate::{
     ServerError,
 };
 
+/// Removes the account with all of its devices and disconnects the devices
+/// once the removal is committed
 pub async fn delete_account<T: StateType>(
     state: &mut ServerState<T>,
     account_id: AccountId,
 ) -> Result<(), ServerError> {
+    let mut transaction = state.begin().await?;
+    let result = remove_account_data(&mut transaction, account_id).await;
+    let devices = transaction.finish(result).await?;
+
+    for device_id in devices {
+        state.messages.disconnect(account_id, device_id).await;
+    }
+    Ok(())
+}
+
+/// Returns the devices the account had
+async fn remove_account_data<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+) -> Result<Vec<DeviceId>, ServerError> {
+    let devices = state.devices.get_devices(account_id).await?;
+    for device_id in &devices {
+        remove_device_data(state, account_id, *device_id).await?;
+    }
+    state.devices.remove_link_tokens(account_id).await?;
+
+    state.accounts.remove_account(account_id).await?;
+    Ok(devices)
+}
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+/// Checks a normalized username. Surrounding whitespace is rejected rather
+/// than trimmed, and a colon would break the Basic auth header.
+fn validate_username(username: &str) -> Result<(), ServerError> {
+    if username.is_empty()
+        || username.len() > MAX_USERNAME_LENGTH
+        || username.trim() != username
+        || username.contains(':')
     {
-        for device_id in state.devices.get_devices(account_id).await? {
-            if let Some(msgs) = state.messages.get_envelope_ids(account_id, device_id).await {
-                for msg_id in msgs {
-                    state
-                        .messages
-                        .remove_envelope(account_id, device_id, msg_id)
-                        .await?;
-                }
-            }
-
-            if let Some(ids) = state.keys.get_pre_key_ids(account_id, device_id).await? {
-                for id in ids {
-                    state.keys.remove_pre_key(account_id, device_id, id).await?
-                }
-            }
-
-            state
-                .keys
-                .remove_signed_pre_key(account_id, device_id)
-                .await?;
-
-            if let Some(ids) = state.keys.get_pq_pre_key_ids(account_id, device_id).await? {
-                for id in ids {
-                    state
-                        .keys
-                        .remove_pq_pre_key(account_id, device_id, id)
-                        .await?
-                }
-            }
-            state
-                .keys
-                .remove_last_resort_key(account_id, device_id)
-                .await?;
-
-            state.devices.remove_device(account_id, device_id).await?;
-        }
+        return Err(ServerError::UsernameInvalid);
     }
+    Ok(())
+}
+
+/// Resolves a username to its account. Lookups are limited per requesting
+/// account and per peer address, so the username directory cannot be
+/// enumerated by registering more accounts.
+pub async fn lookup_username<T: StateType>(
+    state: &ServerState<T>,
+    requester: AccountId,
+    peer: IpAddr,
+    username: &str,
+) -> Result<AccountId, ServerError> {
+    state.ip_username_lookups.check(peer).await?;
+    state.username_lookups.check(requester).await?;
+    state
+        .accounts
+        .get_account_id(&normalize_username(username))
+        .await
+}
 
-    state.accounts.remove_account(account_id).await
+pub async fn change_username<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    username: &str,
+) -> Result<(), ServerError> {
+    let username = normalize_username(username);
+    validate_username(&username)?;
+    state.accounts.set_username(account_id, &username).await
 }
 
 pub async fn create_account<T: StateType>(
@@ -72,23 +103,39 @@ pub async fn create_account<T: StateType>(
     username: String,
     password: String,
 ) -> Result<RegistrationResponse, ServerError> {
+    let username = normalize_username(&username);
+    validate_username(&username)?;
+    // writes to the in-memory state are not rolled back, so a bad bundle has
+    // to be rejected before the account is added
+    validate_keybundle(
+        &registration.identity_key,
+        &registration.device_activation.key_bundle.clone().into(),
+    )?;
+
     let account = Account::builder()
         .id(AccountId::generate())
         .username(username)
         .identity(registration.identity_key)
+        .maybe_unidentified_access_key(registration.unidentified_access_key)
         .build();
 
-    state.accounts.add_account(&account).await?;
-
-    create_device(
-        state,
-        account.id(),
-        account.identity(),
-        registration.device_activation,
-        1.into(),
-        password,
-    )
-    .await?;
+    let mut transaction = state.begin().await?;
+    let result = async {
+        transaction.accounts.add_account(&account).await?;
+
+        create_device(
+            &mut transaction,
+            account.id(),
+            registration.device_activation,
+            1.into(),
+            DeviceRole::Primary,
+            password,
+        )
+        .await
+    }
+    .await;
+    transaction.finish(result).await?;
+
     Ok(RegistrationResponse {
         account_id: account.id(),
     })
@@ -96,21 +143,31 @@ pub async fn create_account<T: StateType>(
 
 #[cfg(test)]
 mod test {
+    use std::{net::IpAddr, time::Duration};
+
     use libsignal_protocol::IdentityKeyPair;
     use rand::rngs::OsRng;
-    use sam_common::api::{device::DeviceActivationInfo, Key, RegistrationRequest};
+    use sam_common::{
+        address::AccountId,
+        api::{device::DeviceActivationInfo, Key, RegistrationRequest},
+    };
 
     use crate::{
-        logic::account::{create_account, delete_account},
-        managers::traits::{
-            account_manager::AccountManager,
-            device_manager::DeviceManager,
-            key_manager::{
-                LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
+        logic::account::{change_username, create_account, delete_account, lookup_username},
+        managers::{
+            rate_limit::RateLimit,
+            traits::{
+                account_manager::AccountManager,
+                device_manager::DeviceManager,
+                key_manager::{
+                    LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
+                },
+                message_manager::{Dispatch, MessageManager},
             },
         },
         state::ServerState,
         test_utils::create_publish_pre_keys,
+        ServerError,
     };
 
     #[tokio::test]
@@ -136,6 +193,7 @@ mod test {
                 .try_into()
                 .expect("Can make RegistrationPreKeys"),
             },
+            unidentified_access_key: None,
         };
 
         let alice_id = create_account(
@@ -157,7 +215,7 @@ mod test {
 
         assert!(account.id() == alice_id);
         assert!(*account.identity() == *id);
-        assert!(account.username() == "RealAlice");
+        assert!(account.username() == "realalice");
 
         // Check if device is created
         let device = state
@@ -227,6 +285,7 @@ mod test {
                 .try_into()
                 .expect("Can make RegistrationPreKeys"),
             },
+            unidentified_access_key: None,
         };
 
         let alice_id = create_account(
@@ -239,10 +298,18 @@ mod test {
         .map(|r| r.account_id)
         .expect("Alice can create account");
 
+        let mut phone = state
+            .messages
+            .subscribe(alice_id, 1.into())
+            .await
+            .expect("Alice's phone can subscribe");
+
         delete_account(&mut state, alice_id)
             .await
             .expect("Alice can delete account");
 
+        assert!(phone.recv().await == Some(Dispatch::Disconnect));
+
         assert!(state.accounts.get_account(alice_id).await.is_err());
         assert!(state.devices.get_device(alice_id, 1.into()).await.is_err());
         assert!(state
@@ -266,4 +333,126 @@ mod test {
             .await
             .is_ok_and(|ids| ids.is_none()));
     }
+
+    #[tokio::test]
+    async fn test_create_account_with_tampered_bundle() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let mut key_bundle =
+            create_publish_pre_keys(Some(vec![0]), Some(1), Some(vec![33]), Some(2), &pair, rng);
+        if let Some(keys) = key_bundle.pq_pre_keys.as_mut() {
+            keys[0].signature[0] ^= 1;
+        }
+        let reg = RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: key_bundle.try_into().expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+
+        assert!(matches!(
+            create_account(
+                &mut state,
+                reg,
+                "RealAlice".to_string(),
+                "bob<3".to_string(),
+            )
+            .await,
+            Err(ServerError::KeyVerification)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_usernames_are_unique_and_lookups_limited() {
+        let mut state = ServerState::in_memory_test()
+            .with_username_lookup_limit(RateLimit::new(1, Duration::from_secs(60)));
+
+        let pair = IdentityKeyPair::generate(&mut OsRng);
+        let registration = || RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(None, Some(1), None, Some(2), &pair, OsRng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+
+        let alice_id = create_account(
+            &mut state,
+            registration(),
+            "RealAlice".to_string(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+        assert!(matches!(
+            create_account(
+                &mut state,
+                registration(),
+                "RealAlice".to_string(),
+                "mallory".to_string(),
+            )
+            .await,
+            Err(ServerError::UsernameTaken)
+        ));
+        assert!(matches!(
+            create_account(
+                &mut state,
+                registration(),
+                "REALALICE".to_string(),
+                "mallory".to_string(),
+            )
+            .await,
+            Err(ServerError::UsernameTaken)
+        ));
+        assert!(matches!(
+            create_account(
+                &mut state,
+                registration(),
+                " Alice".to_string(),
+                "mallory".to_string(),
+            )
+            .await,
+            Err(ServerError::UsernameInvalid)
+        ));
+
+        let bob_id = AccountId::generate();
+        let bob_peer = IpAddr::from([10, 0, 0, 1]);
+        assert!(lookup_username(&state, bob_id, bob_peer, "realALICE")
+            .await
+            .is_ok_and(|id| id == alice_id));
+        assert!(matches!(
+            lookup_username(&state, bob_id, bob_peer, "RealAlice").await,
+            Err(ServerError::RateLimited(_))
+        ));
+        assert!(matches!(
+            lookup_username(&state, AccountId::generate(), bob_peer, "RealAlice").await,
+            Err(ServerError::RateLimited(_))
+        ));
+        assert!(matches!(
+            lookup_username(&state, bob_id, IpAddr::from([10, 0, 0, 2]), "RealAlice").await,
+            Err(ServerError::RateLimited(_))
+        ));
+
+        change_username(&mut state, alice_id, "Alice")
+            .await
+            .expect("Alice can change her username");
+        assert!(lookup_username(
+            &state,
+            AccountId::generate(),
+            IpAddr::from([10, 0, 0, 3]),
+            "Alice"
+        )
+        .await
+        .is_ok_and(|id| id == alice_id));
+    }
 }
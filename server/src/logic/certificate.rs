@@ -0,0 +1,29 @@
+use rand::rngs::OsRng;
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::certificate::SenderCertificateResponse,
+};
+
+use crate::{
+    managers::traits::account_manager::AccountManager,
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+pub async fn create_sender_certificate<T: StateType>(
+    state: &ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+) -> Result<SenderCertificateResponse, ServerError> {
+    let identity = *state.accounts.get_account(account_id).await?.identity();
+    let certificate = state
+        .certificates
+        .sender_certificate(account_id, device_id, &identity, &mut OsRng)?;
+
+    Ok(SenderCertificateResponse {
+        certificate: certificate
+            .serialized()
+            .map_err(|_| ServerError::SenderCertificate)?
+            .into(),
+    })
+}
@@ -1,10 +1,14 @@
-use libsignal_protocol::IdentityKey;
+use log::warn;
 use sam_common::{
     address::{AccountId, DeviceId},
     api::{
-        device::{DeviceActivationInfo, LinkDeviceRequest, LinkDeviceResponse},
+        device::{
+            DeviceActivationInfo, DeviceInfo, DeviceInfoList, DeviceRole, LinkDeviceRequest,
+            LinkDeviceResponse,
+        },
         LinkDeviceToken,
     },
+    sam_message::{Notification, NotificationType},
     time_now_millis,
 };
 
@@ -15,22 +19,43 @@ use crate::{
     },
     managers::{
         entities::device::Device,
-        traits::{account_manager::AccountManager, device_manager::DeviceManager},
+        traits::{
+            account_manager::AccountManager,
+            device_manager::DeviceManager,
+            key_manager::{
+                LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
+            },
+            message_manager::MessageManager,
+        },
     },
     state::{state_type::StateType, ServerState},
     ServerError,
 };
 
-use super::keys::add_keybundle;
+use super::keys::{store_keybundle, validate_keybundle};
 
+/// Issues a link token for `account_id`, it can be used to link a single
+/// device until it expires or is revoked
 pub async fn create_device_token<T: StateType>(
-    state: &ServerState<T>,
+    state: &mut ServerState<T>,
     account_id: AccountId,
 ) -> Result<LinkDeviceToken, ServerError> {
-    Ok(create_token(
-        &state.devices.link_secret().await?,
-        account_id,
-    ))
+    let token = create_token(&state.devices.link_secret().await?, account_id);
+    let expires = time_now_millis() as u64 + state.devices.provision_expire_seconds().await? * 1000;
+    state
+        .devices
+        .add_link_token(account_id, token.id(), expires)
+        .await?;
+    Ok(token)
+}
+
+/// Keeps an unused link token from linking a device
+pub async fn revoke_device_token<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    token_id: &str,
+) -> Result<(), ServerError> {
+    state.devices.revoke_link_token(account_id, token_id).await
 }
 
 pub async fn link_device<T: StateType>(
@@ -41,22 +66,44 @@ pub async fn link_device<T: StateType>(
     let account_id = verify_token(
         &state.devices.link_secret().await?,
         state.devices.provision_expire_seconds().await?,
-        device_link.token,
+        &device_link.token,
     )?;
 
     let account = state.accounts.get_account(account_id).await?;
+    validate_keybundle(
+        account.identity(),
+        &device_link.device_activation.key_bundle.clone().into(),
+    )?;
 
-    let next_id = state.devices.next_device_id(account_id).await?;
+    state
+        .devices
+        .check_link_token(account_id, device_link.token.id())
+        .await?;
 
-    create_device(
-        state,
-        account_id,
-        account.identity(),
-        device_link.device_activation,
-        next_id,
-        password,
-    )
-    .await?;
+    // the token is consumed last, so a device that fails to be created does
+    // not use it up. Writes to the in-memory state are not rolled back, which
+    // is why the token was checked before anything is written.
+    let mut transaction = state.begin().await?;
+    let result = async {
+        let next_id = transaction.devices.next_device_id(account_id).await?;
+        create_device(
+            &mut transaction,
+            account_id,
+            device_link.device_activation,
+            next_id,
+            DeviceRole::Linked,
+            password,
+        )
+        .await?;
+
+        transaction
+            .devices
+            .consume_link_token(account_id, device_link.token.id())
+            .await
+            .map(|_| next_id)
+    }
+    .await;
+    let next_id = transaction.finish(result).await?;
 
     Ok(LinkDeviceResponse {
         account_id,
@@ -64,20 +111,209 @@ pub async fn link_device<T: StateType>(
     })
 }
 
+/// Metadata of every device linked to `account_id`, ordered by device id
+pub async fn list_devices<T: StateType>(
+    state: &ServerState<T>,
+    account_id: AccountId,
+) -> Result<DeviceInfoList, ServerError> {
+    let mut ids = state.devices.get_devices(account_id).await?;
+    ids.sort();
+
+    let mut devices = Vec::new();
+    for id in ids {
+        let device = state.devices.get_device(account_id, id).await?;
+        devices.push(DeviceInfo {
+            id: device.id(),
+            name: device.name().to_string(),
+            registration_id: device.registration_id(),
+            role: device.role(),
+            created: device.creation() as u64,
+        });
+    }
+
+    Ok(DeviceInfoList { devices })
+}
+
+pub async fn rename_device<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+    name: String,
+) -> Result<(), ServerError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ServerError::DeviceNameInvalid);
+    }
+
+    state
+        .devices
+        .rename_device(account_id, device_id, name)
+        .await
+}
+
+/// Replaces the password of a device, its open websocket stays connected
+pub async fn change_device_password<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+    password: String,
+) -> Result<(), ServerError> {
+    let password = Password::generate(password)?;
+    state
+        .devices
+        .set_device_password(account_id, device_id, &password)
+        .await
+}
+
+/// Replaces the password of a linked device on behalf of the primary device
+/// and closes the websocket of the device, so the old password stops working
+/// right away
+pub async fn reset_device_password<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+    password: String,
+) -> Result<(), ServerError> {
+    change_device_password(state, account_id, device_id, password).await?;
+    state.messages.disconnect(account_id, device_id).await;
+    Ok(())
+}
+
+/// Makes `device_id` the primary device of the account in place of
+/// `primary_id`, which stays linked as a regular device
+pub async fn transfer_primary<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    primary_id: DeviceId,
+    device_id: DeviceId,
+) -> Result<(), ServerError> {
+    if primary_id == device_id {
+        return Ok(());
+    }
+    // fail before anything is written, the in-memory state is not rolled back
+    state.devices.get_device(account_id, device_id).await?;
+
+    let mut transaction = state.begin().await?;
+    let result = async {
+        transaction
+            .devices
+            .set_device_role(account_id, device_id, DeviceRole::Primary)
+            .await?;
+        transaction
+            .devices
+            .set_device_role(account_id, primary_id, DeviceRole::Linked)
+            .await
+    }
+    .await;
+    transaction.finish(result).await
+}
+
+/// Removes the device with its keys and queued messages, closes its websocket
+/// and tells the remaining devices of the account about it. The primary
+/// device has to hand over its role before it can be unlinked.
 pub async fn unlink_device<T: StateType>(
     state: &mut ServerState<T>,
     account_id: AccountId,
     device_id: DeviceId,
 ) -> Result<(), ServerError> {
+    if state
+        .devices
+        .get_device(account_id, device_id)
+        .await?
+        .is_primary()
+    {
+        return Err(ServerError::DeviceIsPrimary);
+    }
+
+    let mut transaction = state.begin().await?;
+    let result = remove_device_data(&mut transaction, account_id, device_id).await;
+    transaction.finish(result).await?;
+
+    state.messages.disconnect(account_id, device_id).await;
+
+    // the account has no devices left once its last device is unlinked
+    let devices = state
+        .devices
+        .get_devices(account_id)
+        .await
+        .unwrap_or_default();
+    for other in devices {
+        if let Err(err) = state
+            .messages
+            .notify(account_id, other, device_unlinked(device_id))
+            .await
+        {
+            warn!(
+                "Could not notify device '{}.{}' about unlinked device '{}'",
+                account_id, *other, err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a device together with everything stored for it
+pub(crate) async fn remove_device_data<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+) -> Result<(), ServerError> {
+    if let Some(msgs) = state.messages.get_envelope_ids(account_id, device_id).await {
+        for msg_id in msgs {
+            state
+                .messages
+                .remove_envelope(account_id, device_id, msg_id)
+                .await?;
+        }
+    }
+    state
+        .messages
+        .remove_pending_messages(account_id, device_id)
+        .await?;
+
+    if let Some(ids) = state.keys.get_pre_key_ids(account_id, device_id).await? {
+        for id in ids {
+            state.keys.remove_pre_key(account_id, device_id, id).await?
+        }
+    }
+
+    state
+        .keys
+        .remove_signed_pre_key(account_id, device_id)
+        .await?;
+
+    if let Some(ids) = state.keys.get_pq_pre_key_ids(account_id, device_id).await? {
+        for id in ids {
+            state
+                .keys
+                .remove_pq_pre_key(account_id, device_id, id)
+                .await?
+        }
+    }
+    state
+        .keys
+        .remove_last_resort_key(account_id, device_id)
+        .await?;
+
     state.devices.remove_device(account_id, device_id).await
 }
 
+fn device_unlinked(device_id: DeviceId) -> Notification {
+    Notification::builder()
+        .r#type(NotificationType::DeviceUnlinked as i32)
+        .device_id(*device_id)
+        .build()
+}
+
+/// Adds a device with its keys, the key bundle must have been checked with
+/// [`validate_keybundle`] before anything was written for the device
 pub async fn create_device<T: StateType>(
     state: &mut ServerState<T>,
     account_id: AccountId,
-    identity: &IdentityKey,
     device_info: DeviceActivationInfo,
     device_id: DeviceId,
+    role: DeviceRole,
     password: String,
 ) -> Result<(), ServerError> {
     let device = Device::builder()
@@ -86,18 +322,23 @@ pub async fn create_device<T: StateType>(
         .name(device_info.name)
         .creation(time_now_millis())
         .password(Password::generate(password)?)
+        .role(role)
         .build();
 
-    state.devices.add_device(account_id, &device).await?;
+    let mut transaction = state.begin().await?;
+    let result = async {
+        transaction.devices.add_device(account_id, &device).await?;
 
-    add_keybundle(
-        state,
-        identity,
-        account_id,
-        device_id,
-        device_info.key_bundle.into(),
-    )
-    .await
+        store_keybundle(
+            &mut transaction,
+            account_id,
+            device_id,
+            device_info.key_bundle.into(),
+        )
+        .await
+    }
+    .await;
+    transaction.finish(result).await
 }
 
 #[cfg(test)]
@@ -105,23 +346,33 @@ mod test {
     use libsignal_protocol::IdentityKeyPair;
     use rand::rngs::OsRng;
     use sam_common::{
-        address::AccountId,
-        api::{device::DeviceActivationInfo, Key, RegistrationRequest},
+        address::{AccountId, MessageId},
+        api::{
+            device::{DeviceActivationInfo, DeviceRole},
+            Key, LinkDeviceToken, RegistrationRequest,
+        },
+        sam_message::{EnvelopeType, NotificationType, ServerEnvelope},
     };
 
     use crate::{
         logic::{
             account::create_account,
-            device::{create_device, create_device_token, link_device, unlink_device},
+            device::{
+                change_device_password, create_device, create_device_token, link_device,
+                list_devices, rename_device, reset_device_password, revoke_device_token,
+                transfer_primary, unlink_device,
+            },
         },
         managers::traits::{
             device_manager::DeviceManager,
             key_manager::{
                 LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
             },
+            message_manager::{Dispatch, MessageManager},
         },
         state::ServerState,
         test_utils::{create_device_link, create_publish_pre_keys},
+        ServerError,
     };
 
     #[tokio::test]
@@ -152,9 +403,9 @@ mod test {
         create_device(
             &mut state,
             account_id,
-            pair.identity_key(),
             device_info,
             1.into(),
+            DeviceRole::Primary,
             account_pwd.clone(),
         )
         .await
@@ -169,6 +420,7 @@ mod test {
 
         assert!(device.registration_id() == 1.into());
         assert!(device.name() == "a");
+        assert!(device.is_primary());
         device
             .password()
             .verify(account_pwd)
@@ -228,9 +480,9 @@ mod test {
         create_device(
             &mut state,
             account_id,
-            pair.identity_key(),
             device_info,
             1.into(),
+            DeviceRole::Linked,
             account_pwd.clone(),
         )
         .await
@@ -247,10 +499,132 @@ mod test {
             .is_err());
     }
 
+    #[tokio::test]
+    async fn test_unlink_device_cleans_up_and_notifies() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let account_id = AccountId::generate();
+
+        for device_id in [1u32, 2] {
+            let device_info = DeviceActivationInfo {
+                name: format!("device {device_id}"),
+                registration_id: device_id.into(),
+                key_bundle: create_publish_pre_keys(
+                    Some(vec![0]),
+                    Some(1),
+                    Some(vec![33]),
+                    Some(2),
+                    &pair,
+                    rng,
+                )
+                .try_into()
+                .expect("Can make RegistrationPreKeys"),
+            };
+            create_device(
+                &mut state,
+                account_id,
+                device_info,
+                device_id.into(),
+                if device_id == 1 {
+                    DeviceRole::Primary
+                } else {
+                    DeviceRole::Linked
+                },
+                "huntermotherboard7".to_string(),
+            )
+            .await
+            .expect("Devices can be created");
+        }
+
+        let mut primary = state
+            .messages
+            .subscribe(account_id, 1.into())
+            .await
+            .expect("Primary can subscribe");
+        let mut laptop = state
+            .messages
+            .subscribe(account_id, 2.into())
+            .await
+            .expect("Laptop can subscribe");
+
+        let envelope_id = MessageId::generate();
+        let envelope = ServerEnvelope::builder()
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .destination_account_id(account_id.into())
+            .destination_device_id(2)
+            .content(b"hi".to_vec())
+            .id(envelope_id.into())
+            .build();
+        state
+            .messages
+            .insert_envelope(account_id, 2.into(), envelope_id, envelope)
+            .await
+            .expect("Can queue envelope for laptop");
+        state
+            .messages
+            .add_pending_message(account_id, 2.into(), envelope_id)
+            .await
+            .expect("Envelope can be pending");
+
+        unlink_device(&mut state, account_id, 2.into())
+            .await
+            .expect("Laptop can be unlinked");
+
+        assert!(laptop.recv().await == Some(Dispatch::Disconnect));
+        let Some(Dispatch::Notification(notification)) = primary.recv().await else {
+            panic!("Primary is notified about the unlinked laptop");
+        };
+        assert!(notification.r#type() == NotificationType::DeviceUnlinked);
+        assert!(notification.device_id == Some(2));
+
+        assert!(state
+            .messages
+            .get_envelope_ids(account_id, 2.into())
+            .await
+            .is_none());
+        assert!(state
+            .messages
+            .add_pending_message(account_id, 2.into(), envelope_id)
+            .await
+            .is_ok());
+        assert!(state
+            .keys
+            .get_pre_key_ids(account_id, 2.into())
+            .await
+            .is_ok_and(|ids| ids.is_none()));
+        assert!(state
+            .keys
+            .get_pq_pre_key_ids(account_id, 2.into())
+            .await
+            .is_ok_and(|ids| ids.is_none()));
+        assert!(state
+            .keys
+            .get_signed_pre_key(account_id, 2.into())
+            .await
+            .is_err());
+        assert!(state
+            .keys
+            .get_last_resort_key(account_id, 2.into())
+            .await
+            .is_err());
+        assert!(state
+            .keys
+            .get_pre_key_ids(account_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids == Some(vec![0])));
+
+        assert!(matches!(
+            unlink_device(&mut state, account_id, 2.into()).await,
+            Err(ServerError::DeviceNotExist)
+        ));
+    }
+
     #[tokio::test]
     async fn test_create_device_token() {
-        let state = ServerState::in_memory_test();
-        assert!(create_device_token(&state, AccountId::generate())
+        let mut state = ServerState::in_memory_test();
+        assert!(create_device_token(&mut state, AccountId::generate())
             .await
             .is_ok())
     }
@@ -277,6 +651,7 @@ mod test {
                 .try_into()
                 .expect("Can make RegistrationPreKeys"),
             },
+            unidentified_access_key: None,
         };
 
         let alice_id = create_account(
@@ -289,7 +664,7 @@ mod test {
         .map(|r| r.account_id)
         .expect("Alice can create account");
 
-        let token = create_device_token(&state, alice_id)
+        let token = create_device_token(&mut state, alice_id)
             .await
             .expect("Alice can create device token");
 
@@ -306,4 +681,348 @@ mod test {
 
         assert!(res.account_id == alice_id);
     }
+
+    #[tokio::test]
+    async fn test_link_device_token_is_single_use() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let reg = RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+        let alice_id = create_account(
+            &mut state,
+            reg,
+            "RealAlice".to_string(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+        let device_link = |token: LinkDeviceToken| {
+            let key_bundle = create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+                .try_into()
+                .expect("Can make RegistrationPreKeys");
+            create_device_link(token, "Alice Laptop", 2.into(), key_bundle)
+        };
+
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        link_device(
+            &mut state,
+            device_link(token.clone()),
+            "charlie<3".to_string(),
+        )
+        .await
+        .expect("Alice can link device");
+        assert!(matches!(
+            link_device(
+                &mut state,
+                device_link(token.clone()),
+                "charlie<3".to_string()
+            )
+            .await,
+            Err(ServerError::DeviceTokenUsed)
+        ));
+        assert!(matches!(
+            revoke_device_token(&mut state, alice_id, token.id()).await,
+            Err(ServerError::DeviceTokenUsed)
+        ));
+
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        let forged = LinkDeviceToken::new("forged".to_string(), token.token().to_string());
+        assert!(matches!(
+            link_device(&mut state, device_link(forged), "charlie<3".to_string()).await,
+            Err(ServerError::DeviceTokenMalformed)
+        ));
+
+        revoke_device_token(&mut state, alice_id, token.id())
+            .await
+            .expect("Alice can revoke unused token");
+        assert!(matches!(
+            link_device(&mut state, device_link(token), "charlie<3".to_string()).await,
+            Err(ServerError::DeviceTokenRevoked)
+        ));
+        assert!(matches!(
+            revoke_device_token(&mut state, alice_id, "unknown").await,
+            Err(ServerError::DeviceTokenNotExist)
+        ));
+        assert!(state
+            .devices
+            .get_devices(alice_id)
+            .await
+            .is_ok_and(|devices| devices.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn test_link_device_with_tampered_bundle() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let reg = RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+        let alice_id = create_account(
+            &mut state,
+            reg,
+            "RealAlice".to_string(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+
+        // keys signed by another identity
+        let mallory = IdentityKeyPair::generate(&mut rng);
+        let key_bundle =
+            create_publish_pre_keys(Some(vec![0]), Some(1), None, Some(2), &mallory, rng)
+                .try_into()
+                .expect("Can make RegistrationPreKeys");
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        let device_link = create_device_link(token.clone(), "Mallory Laptop", 2.into(), key_bundle);
+
+        assert!(matches!(
+            link_device(&mut state, device_link, "charlie<3".to_string()).await,
+            Err(ServerError::KeyVerification)
+        ));
+        assert!(state
+            .devices
+            .check_link_token(alice_id, token.id())
+            .await
+            .is_ok());
+        assert!(state
+            .devices
+            .get_devices(alice_id)
+            .await
+            .is_ok_and(|devices| devices.len() == 1));
+        assert!(state
+            .keys
+            .get_pre_key_ids(alice_id, 2.into())
+            .await
+            .is_ok_and(|ids| ids.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_rename_devices() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let reg = RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+        let alice_id = create_account(
+            &mut state,
+            reg,
+            "RealAlice".to_string(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        let key_bundle = create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+            .try_into()
+            .expect("Can make RegistrationPreKeys");
+        let device_link = create_device_link(token, "Alice Laptop", 2.into(), key_bundle);
+        link_device(&mut state, device_link, "charlie<3".to_string())
+            .await
+            .expect("Alice can link device");
+
+        rename_device(&mut state, alice_id, 2.into(), " Work Laptop ".to_string())
+            .await
+            .expect("Alice can rename her laptop");
+
+        let devices = list_devices(&state, alice_id)
+            .await
+            .expect("Alice can list her devices")
+            .devices;
+        assert!(devices.len() == 2);
+        assert!(devices[0].id == 1.into() && devices[0].name == "Alice Phone");
+        assert!(devices[1].id == 2.into() && devices[1].name == "Work Laptop");
+        assert!(devices[1].registration_id == 2.into());
+
+        assert!(matches!(
+            rename_device(&mut state, alice_id, 2.into(), "  ".to_string()).await,
+            Err(ServerError::DeviceNameInvalid)
+        ));
+        assert!(matches!(
+            rename_device(&mut state, alice_id, 3.into(), "Tablet".to_string()).await,
+            Err(ServerError::DeviceNotExist)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_transfer_primary() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let reg = RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+        let alice_id = create_account(
+            &mut state,
+            reg,
+            "RealAlice".to_string(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        let key_bundle = create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+            .try_into()
+            .expect("Can make RegistrationPreKeys");
+        let device_link = create_device_link(token, "Alice Laptop", 2.into(), key_bundle);
+        link_device(&mut state, device_link, "charlie<3".to_string())
+            .await
+            .expect("Alice can link device");
+
+        let devices = list_devices(&state, alice_id)
+            .await
+            .expect("Alice can list her devices")
+            .devices;
+        assert!(devices[0].role == DeviceRole::Primary);
+        assert!(devices[1].role == DeviceRole::Linked);
+        assert!(matches!(
+            unlink_device(&mut state, alice_id, 1.into()).await,
+            Err(ServerError::DeviceIsPrimary)
+        ));
+
+        assert!(matches!(
+            transfer_primary(&mut state, alice_id, 1.into(), 3.into()).await,
+            Err(ServerError::DeviceNotExist)
+        ));
+        transfer_primary(&mut state, alice_id, 1.into(), 2.into())
+            .await
+            .expect("Alice can make her laptop primary");
+
+        let devices = list_devices(&state, alice_id)
+            .await
+            .expect("Alice can list her devices")
+            .devices;
+        assert!(devices[0].role == DeviceRole::Linked);
+        assert!(devices[1].role == DeviceRole::Primary);
+        unlink_device(&mut state, alice_id, 1.into())
+            .await
+            .expect("Alice can unlink her phone");
+    }
+
+    #[tokio::test]
+    async fn test_change_and_reset_device_password() {
+        let mut state = ServerState::in_memory_test();
+
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let reg = RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        };
+        let alice_id = create_account(
+            &mut state,
+            reg,
+            "RealAlice".to_string(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        let key_bundle = create_publish_pre_keys(None, Some(1), None, Some(2), &pair, rng)
+            .try_into()
+            .expect("Can make RegistrationPreKeys");
+        let device_link = create_device_link(token, "Alice Laptop", 2.into(), key_bundle);
+        link_device(&mut state, device_link, "charlie<3".to_string())
+            .await
+            .expect("Alice can link device");
+
+        change_device_password(&mut state, alice_id, 1.into(), "dave<3".to_string())
+            .await
+            .expect("Alice can change the password of her phone");
+        let phone = state
+            .devices
+            .get_device(alice_id, 1.into())
+            .await
+            .expect("Alice has a phone");
+        assert!(phone.password().verify("bob<3".to_string()).is_err());
+        phone
+            .password()
+            .verify("dave<3".to_string())
+            .expect("The new password is set");
+
+        reset_device_password(&mut state, alice_id, 2.into(), "eve<3".to_string())
+            .await
+            .expect("Alice can reset the password of her laptop");
+        let laptop = state
+            .devices
+            .get_device(alice_id, 2.into())
+            .await
+            .expect("Alice has a laptop");
+        assert!(laptop.password().verify("charlie<3".to_string()).is_err());
+        laptop
+            .password()
+            .verify("eve<3".to_string())
+            .expect("The new password is set");
+
+        assert!(matches!(
+            reset_device_password(&mut state, alice_id, 3.into(), "eve<3".to_string()).await,
+            Err(ServerError::DeviceNotExist)
+        ));
+    }
 }
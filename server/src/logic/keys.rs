@@ -1,14 +1,20 @@
-use libsignal_protocol::IdentityKey;
+use std::collections::HashSet;
+
+use libsignal_protocol::{kem, IdentityKey, PublicKey};
+use log::warn;
 use sam_common::{
     address::{AccountId, DeviceId, RegistrationId},
-    api::keys::{Key, PreKeyBundle, PreKeyBundles, PublishPreKeys},
+    api::keys::{Key, PreKeyBundle, PreKeyBundles, PreKeyCount, PublishPreKeys},
+    sam_message::{Notification, NotificationType},
 };
 
 use crate::{
+    auth::keys::verify_key,
     managers::traits::{
         account_manager::AccountManager,
         device_manager::DeviceManager,
         key_manager::{LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager},
+        message_manager::MessageManager,
     },
     state::{state_type::StateType, ServerState},
     ServerError,
@@ -51,6 +57,8 @@ pub async fn get_keybundle<T: StateType>(
         }
     };
 
+    notify_if_pre_keys_low(state, account_id, device_id).await?;
+
     Ok(PreKeyBundle {
         device_id: device_id.into(),
         registration_id: registration_id.into(),
@@ -60,12 +68,167 @@ pub async fn get_keybundle<T: StateType>(
     })
 }
 
+pub async fn get_pre_key_count<T: StateType>(
+    state: &ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+) -> Result<PreKeyCount, ServerError> {
+    let pre_keys = state
+        .keys
+        .get_pre_key_ids(account_id, device_id)
+        .await?
+        .map_or(0, |ids| ids.len());
+    let pq_pre_keys = state
+        .keys
+        .get_pq_pre_key_ids(account_id, device_id)
+        .await?
+        .map_or(0, |ids| ids.len());
+
+    Ok(PreKeyCount {
+        pre_keys: pre_keys as u32,
+        pq_pre_keys: pq_pre_keys as u32,
+    })
+}
+
+/// Tells the device to upload more keys while it has fewer one-time keys of
+/// either kind than the threshold. Notifications for offline devices are
+/// dropped, so this also runs when a device connects.
+pub async fn notify_if_pre_keys_low<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+) -> Result<(), ServerError> {
+    let count = get_pre_key_count(state, account_id, device_id).await?;
+    if count.pre_keys >= state.pre_key_threshold && count.pq_pre_keys >= state.pre_key_threshold {
+        return Ok(());
+    }
+
+    if let Err(err) = state
+        .messages
+        .notify(account_id, device_id, pre_keys_low(count))
+        .await
+    {
+        warn!(
+            "Could not notify device '{}.{}' about low pre keys '{}'",
+            account_id, *device_id, err
+        );
+    }
+    Ok(())
+}
+
+fn pre_keys_low(count: PreKeyCount) -> Notification {
+    Notification::builder()
+        .r#type(NotificationType::PreKeysLow as i32)
+        .pre_key_count(count.into())
+        .build()
+}
+
+/// Checks every key of a bundle before anything is stored: the keys must
+/// decode, signed keys must be signed by `identity` and no key id may be used
+/// twice. Kyber pre keys and the last resort key share their id space.
+pub fn validate_keybundle(
+    identity: &IdentityKey,
+    key_bundle: &PublishPreKeys,
+) -> Result<(), ServerError> {
+    let mut pre_key_ids = HashSet::new();
+    for key in key_bundle.pre_keys.iter().flatten() {
+        PublicKey::deserialize(key.public_key()).map_err(|_| ServerError::KeyMalformed)?;
+        if !pre_key_ids.insert(key.id()) {
+            return Err(ServerError::KeyExists);
+        }
+    }
+
+    if let Some(key) = &key_bundle.signed_pre_key {
+        PublicKey::deserialize(key.public_key()).map_err(|_| ServerError::KeyMalformed)?;
+        verify_key(identity, key)?;
+    }
+
+    let mut pq_pre_key_ids = HashSet::new();
+    for key in key_bundle
+        .pq_pre_keys
+        .iter()
+        .flatten()
+        .chain(&key_bundle.pq_last_resort_pre_key)
+    {
+        kem::PublicKey::deserialize(key.public_key()).map_err(|_| ServerError::KeyMalformed)?;
+        verify_key(identity, key)?;
+        if !pq_pre_key_ids.insert(key.id()) {
+            return Err(ServerError::KeyExists);
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates `key_bundle` and stores it for a device that may already have
+/// keys, nothing is stored if any key is rejected
 pub async fn add_keybundle<T: StateType>(
     state: &mut ServerState<T>,
     identity: &IdentityKey,
     account_id: AccountId,
     device_id: DeviceId,
     key_bundle: PublishPreKeys,
+) -> Result<(), ServerError> {
+    validate_keybundle(identity, &key_bundle)?;
+
+    // the check only gives an early answer, a concurrent upload can still
+    // store the same id before the write, which the managers then reject
+    // with KeyExists through the unique key ids
+    let mut transaction = state.begin().await?;
+    let result = async {
+        check_stored_ids(&transaction, account_id, device_id, &key_bundle).await?;
+        store_keybundle(&mut transaction, account_id, device_id, key_bundle).await
+    }
+    .await;
+    transaction.finish(result).await
+}
+
+/// Rejects one-time keys whose ids are already stored for the device, the
+/// last resort key may not reuse the id of a stored Kyber pre key either
+async fn check_stored_ids<T: StateType>(
+    state: &ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+    key_bundle: &PublishPreKeys,
+) -> Result<(), ServerError> {
+    let stored_ids = state
+        .keys
+        .get_pre_key_ids(account_id, device_id)
+        .await?
+        .unwrap_or_default();
+    if key_bundle
+        .pre_keys
+        .iter()
+        .flatten()
+        .any(|key| stored_ids.contains(&key.id()))
+    {
+        return Err(ServerError::KeyExists);
+    }
+
+    let stored_ids = state
+        .keys
+        .get_pq_pre_key_ids(account_id, device_id)
+        .await?
+        .unwrap_or_default();
+    if key_bundle
+        .pq_pre_keys
+        .iter()
+        .flatten()
+        .chain(&key_bundle.pq_last_resort_pre_key)
+        .any(|key| stored_ids.contains(&key.id()))
+    {
+        return Err(ServerError::KeyExists);
+    }
+
+    Ok(())
+}
+
+/// Stores a bundle that has already passed [`validate_keybundle`]
+pub(crate) async fn store_keybundle<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+    key_bundle: PublishPreKeys,
 ) -> Result<(), ServerError> {
     if let Some(pre_keys) = key_bundle.pre_keys {
         for pre_key in pre_keys {
@@ -78,7 +241,7 @@ pub async fn add_keybundle<T: StateType>(
     if let Some(key) = key_bundle.signed_pre_key {
         state
             .keys
-            .set_signed_pre_key(account_id, device_id, identity, key)
+            .set_signed_pre_key(account_id, device_id, key)
             .await?;
     }
 
@@ -86,7 +249,7 @@ pub async fn add_keybundle<T: StateType>(
         for pre_key in pre_keys {
             state
                 .keys
-                .add_pq_pre_key(account_id, device_id, identity, pre_key)
+                .add_pq_pre_key(account_id, device_id, pre_key)
                 .await?;
         }
     }
@@ -94,7 +257,7 @@ pub async fn add_keybundle<T: StateType>(
     if let Some(key) = key_bundle.pq_last_resort_pre_key {
         state
             .keys
-            .set_last_resort_key(account_id, device_id, identity, key)
+            .set_last_resort_key(account_id, device_id, key)
             .await?
     }
     Ok(())
@@ -131,6 +294,24 @@ pub async fn get_keybundles<T: StateType>(
     })
 }
 
+/// Key bundle of a single device of `account_id`, only that device gives up
+/// a one-time key
+pub async fn get_device_keybundle<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+) -> Result<PreKeyBundles, ServerError> {
+    let identity_key = { *state.accounts.get_account(account_id).await?.identity() };
+    let device = state.devices.get_device(account_id, device_id).await?;
+
+    let bundle = get_keybundle(state, account_id, device.registration_id(), device.id()).await?;
+
+    Ok(PreKeyBundles {
+        identity_key,
+        bundles: vec![bundle],
+    })
+}
+
 pub async fn publish_keybundle<T: StateType>(
     state: &mut ServerState<T>,
     account_id: AccountId,
@@ -146,11 +327,17 @@ pub async fn publish_keybundle<T: StateType>(
 mod test {
     use libsignal_protocol::IdentityKeyPair;
     use rand::rngs::OsRng;
-    use sam_common::{address::AccountId, api::Key};
+    use sam_common::{
+        address::AccountId,
+        api::{DeviceRole, Key},
+    };
 
     use crate::{
         auth::password::Password,
-        logic::keys::{add_keybundle, get_keybundle, get_keybundles, publish_keybundle},
+        logic::keys::{
+            add_keybundle, get_device_keybundle, get_keybundle, get_keybundles, get_pre_key_count,
+            notify_if_pre_keys_low, publish_keybundle, validate_keybundle,
+        },
         managers::{
             entities::{account::Account, device::Device},
             traits::{
@@ -159,10 +346,12 @@ mod test {
                 key_manager::{
                     LastResortKeyManager, PqPreKeyManager, PreKeyManager as _, SignedPreKeyManager,
                 },
+                message_manager::{Dispatch, MessageManager},
             },
         },
         state::ServerState,
         test_utils::create_publish_pre_keys,
+        ServerError,
     };
 
     #[tokio::test]
@@ -335,6 +524,7 @@ mod test {
             .password(Password::generate("dave<3".to_string()).expect("Alice can create password"))
             .creation(0)
             .registration_id(1.into())
+            .role(DeviceRole::Primary)
             .build();
 
         let account_id = account.id();
@@ -371,4 +561,436 @@ mod test {
         assert!(bundle.signed_pre_key.id() == 22);
         assert!(bundle.pq_pre_key.id() == 1);
     }
+
+    #[tokio::test]
+    async fn test_get_device_keybundle() {
+        let mut state = ServerState::in_memory_test();
+        let pair = IdentityKeyPair::generate(&mut OsRng);
+
+        let account = Account::builder()
+            .id(AccountId::generate())
+            .identity(*pair.identity_key())
+            .username("Alice".to_string())
+            .build();
+        let account_id = account.id();
+        state
+            .accounts
+            .add_account(&account)
+            .await
+            .expect("Can add account");
+
+        for id in [1u32, 2] {
+            let device = Device::builder()
+                .id(id.into())
+                .name("Alice Device".to_string())
+                .password(
+                    Password::generate("dave<3".to_string()).expect("Alice can create password"),
+                )
+                .creation(0)
+                .registration_id(id.into())
+                .role(DeviceRole::Linked)
+                .build();
+            state
+                .devices
+                .add_device(account_id, &device)
+                .await
+                .expect("Alice can add device");
+
+            let key_bundle = create_publish_pre_keys(
+                Some(vec![1, 2]),
+                Some(22),
+                Some(vec![1, 2]),
+                Some(33),
+                &pair,
+                OsRng,
+            );
+            publish_keybundle(&mut state, account_id, id.into(), key_bundle)
+                .await
+                .expect("Alice can publish bundle");
+        }
+
+        let bundles = get_device_keybundle(&mut state, account_id, 2.into())
+            .await
+            .expect("User can get the bundle of alices second device");
+        assert!(bundles.identity_key == *pair.identity_key());
+        assert!(bundles.bundles.len() == 1);
+        assert!(bundles.bundles[0].device_id == 2);
+        assert!(bundles.bundles[0].registration_id == 2);
+
+        // the other device keeps its one-time keys
+        assert!(state
+            .keys
+            .get_pre_key_ids(account_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids.is_some_and(|ids| ids.len() == 2)));
+        assert!(state
+            .keys
+            .get_pre_key_ids(account_id, 2.into())
+            .await
+            .is_ok_and(|ids| ids.is_some_and(|ids| ids.len() == 1)));
+
+        assert!(matches!(
+            get_device_keybundle(&mut state, account_id, 3.into()).await,
+            Err(ServerError::DeviceNotExist)
+        ));
+    }
+
+    #[test]
+    fn test_validate_keybundle_rejects_tampered_keys() {
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let other = IdentityKeyPair::generate(&mut rng);
+        let bundle = create_publish_pre_keys(
+            Some(vec![1, 2]),
+            Some(1),
+            Some(vec![1]),
+            Some(33),
+            &pair,
+            rng,
+        );
+
+        assert!(validate_keybundle(pair.identity_key(), &bundle).is_ok());
+        assert!(matches!(
+            validate_keybundle(other.identity_key(), &bundle),
+            Err(ServerError::KeyVerification)
+        ));
+
+        let mut tampered = bundle.clone();
+        if let Some(key) = tampered.signed_pre_key.as_mut() {
+            key.signature[0] ^= 1;
+        }
+        assert!(matches!(
+            validate_keybundle(pair.identity_key(), &tampered),
+            Err(ServerError::KeyVerification)
+        ));
+
+        let mut tampered = bundle.clone();
+        if let Some(key) = tampered.pq_last_resort_pre_key.as_mut() {
+            key.signature[0] ^= 1;
+        }
+        assert!(matches!(
+            validate_keybundle(pair.identity_key(), &tampered),
+            Err(ServerError::KeyVerification)
+        ));
+
+        let mut tampered = bundle.clone();
+        if let Some(keys) = tampered.pre_keys.as_mut() {
+            keys[1].public_key = Box::new([0; 3]);
+        }
+        assert!(matches!(
+            validate_keybundle(pair.identity_key(), &tampered),
+            Err(ServerError::KeyMalformed)
+        ));
+
+        let mut tampered = bundle;
+        if let Some(keys) = tampered.pq_pre_keys.as_mut() {
+            keys[0].public_key = keys[0].public_key[..16].into();
+        }
+        assert!(matches!(
+            validate_keybundle(pair.identity_key(), &tampered),
+            Err(ServerError::KeyMalformed)
+        ));
+    }
+
+    #[test]
+    fn test_validate_keybundle_rejects_duplicate_ids() {
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+
+        let bundle = create_publish_pre_keys(
+            Some(vec![1, 1]),
+            Some(1),
+            Some(vec![1]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        assert!(matches!(
+            validate_keybundle(pair.identity_key(), &bundle),
+            Err(ServerError::KeyExists)
+        ));
+
+        let bundle = create_publish_pre_keys(
+            Some(vec![1]),
+            Some(1),
+            Some(vec![1, 33]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        assert!(matches!(
+            validate_keybundle(pair.identity_key(), &bundle),
+            Err(ServerError::KeyExists)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_keybundle_is_all_or_nothing() {
+        let mut state = ServerState::in_memory_test();
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let account_id = AccountId::generate();
+
+        // every key but the last is valid, the bundle has to be rejected
+        // before anything is written since the in-memory state cannot roll back
+        let mut key_bundle = create_publish_pre_keys(
+            Some(vec![1, 2]),
+            Some(1),
+            Some(vec![1, 2]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        if let Some(key) = key_bundle.pq_last_resort_pre_key.as_mut() {
+            key.signature[0] ^= 1;
+        }
+
+        assert!(matches!(
+            add_keybundle(
+                &mut state,
+                pair.identity_key(),
+                account_id,
+                1.into(),
+                key_bundle,
+            )
+            .await,
+            Err(ServerError::KeyVerification)
+        ));
+        assert!(state
+            .keys
+            .get_pre_key_ids(account_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids.is_none()));
+        assert!(state
+            .keys
+            .get_signed_pre_key(account_id, 1.into())
+            .await
+            .is_err());
+        assert!(state
+            .keys
+            .get_pq_pre_key_ids(account_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_add_keybundle_rejects_stored_ids() {
+        let mut state = ServerState::in_memory_test();
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let account_id = AccountId::generate();
+
+        let key_bundle = create_publish_pre_keys(
+            Some(vec![1, 2]),
+            Some(1),
+            Some(vec![1]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        add_keybundle(
+            &mut state,
+            pair.identity_key(),
+            account_id,
+            1.into(),
+            key_bundle,
+        )
+        .await
+        .expect("User can create key bundle");
+
+        let key_bundle = create_publish_pre_keys(Some(vec![2, 3]), None, None, None, &pair, rng);
+        assert!(matches!(
+            add_keybundle(
+                &mut state,
+                pair.identity_key(),
+                account_id,
+                1.into(),
+                key_bundle,
+            )
+            .await,
+            Err(ServerError::KeyExists)
+        ));
+        assert!(state
+            .keys
+            .get_pre_key_ids(account_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids == Some(vec![1, 2])));
+
+        // the last resort key may not take the id of a stored Kyber pre key
+        let key_bundle = create_publish_pre_keys(None, None, None, Some(1), &pair, rng);
+        assert!(matches!(
+            add_keybundle(
+                &mut state,
+                pair.identity_key(),
+                account_id,
+                1.into(),
+                key_bundle,
+            )
+            .await,
+            Err(ServerError::KeyExists)
+        ));
+        assert!(state
+            .keys
+            .get_last_resort_key(account_id, 1.into())
+            .await
+            .is_ok_and(|key| key.id() == 33));
+    }
+
+    #[tokio::test]
+    async fn test_get_pre_key_count() {
+        let mut state = ServerState::in_memory_test();
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let account_id = AccountId::generate();
+
+        let count = get_pre_key_count(&state, account_id, 1.into())
+            .await
+            .expect("Devices without keys have a count");
+        assert_eq!(count.pre_keys, 0);
+        assert_eq!(count.pq_pre_keys, 0);
+
+        let key_bundle = create_publish_pre_keys(
+            Some(vec![1, 2]),
+            Some(1),
+            Some(vec![1]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        add_keybundle(
+            &mut state,
+            pair.identity_key(),
+            account_id,
+            1.into(),
+            key_bundle,
+        )
+        .await
+        .expect("User can create key bundle");
+
+        let count = get_pre_key_count(&state, account_id, 1.into())
+            .await
+            .expect("Can count keys");
+        assert_eq!(count.pre_keys, 2);
+        assert_eq!(count.pq_pre_keys, 1);
+    }
+
+    #[tokio::test]
+    async fn test_low_pre_keys_are_notified() {
+        let mut state = ServerState::in_memory_test().with_pre_key_threshold(2);
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let account_id = AccountId::generate();
+
+        let key_bundle = create_publish_pre_keys(
+            Some(vec![1, 2, 3]),
+            Some(1),
+            Some(vec![1, 2, 3, 4]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        add_keybundle(
+            &mut state,
+            pair.identity_key(),
+            account_id,
+            1.into(),
+            key_bundle,
+        )
+        .await
+        .expect("User can create key bundle");
+
+        let mut receiver = state
+            .messages
+            .subscribe(account_id, 1.into())
+            .await
+            .expect("Can subscribe");
+
+        // 2 ec keys left, still at the threshold
+        get_keybundle(&mut state, account_id, 1.into(), 1.into())
+            .await
+            .expect("Can get bundle");
+        assert!(receiver.try_recv().is_err());
+
+        // 1 ec key left, below the threshold
+        get_keybundle(&mut state, account_id, 1.into(), 1.into())
+            .await
+            .expect("Can get bundle");
+        let Ok(Dispatch::Notification(notification)) = receiver.try_recv() else {
+            panic!("Device is notified about low keys");
+        };
+        let count = notification
+            .pre_key_count
+            .expect("Notification has a count");
+        assert_eq!(count.pre_keys, 1);
+        assert_eq!(count.pq_pre_keys, 2);
+
+        // 0 ec keys and 1 kyber key left, still below the threshold
+        get_keybundle(&mut state, account_id, 1.into(), 1.into())
+            .await
+            .expect("Can get bundle");
+        assert!(matches!(receiver.try_recv(), Ok(Dispatch::Notification(_))));
+
+        // only the last resort key is left, every fetch reminds the device
+        get_keybundle(&mut state, account_id, 1.into(), 1.into())
+            .await
+            .expect("Can get bundle");
+        assert!(matches!(receiver.try_recv(), Ok(Dispatch::Notification(_))));
+
+        // a device that was offline is told when it connects
+        notify_if_pre_keys_low(&mut state, account_id, 1.into())
+            .await
+            .expect("Can check pre key count");
+        let Ok(Dispatch::Notification(notification)) = receiver.try_recv() else {
+            panic!("Device is notified about low keys");
+        };
+        let count = notification
+            .pre_key_count
+            .expect("Notification has a count");
+        assert_eq!(count.pre_keys, 0);
+        assert_eq!(count.pq_pre_keys, 0);
+    }
+
+    #[tokio::test]
+    async fn test_enough_pre_keys_are_not_notified() {
+        let mut state = ServerState::in_memory_test().with_pre_key_threshold(2);
+        let mut rng = OsRng;
+        let pair = IdentityKeyPair::generate(&mut rng);
+        let account_id = AccountId::generate();
+
+        let key_bundle = create_publish_pre_keys(
+            Some(vec![1, 2]),
+            Some(1),
+            Some(vec![1, 2]),
+            Some(33),
+            &pair,
+            rng,
+        );
+        add_keybundle(
+            &mut state,
+            pair.identity_key(),
+            account_id,
+            1.into(),
+            key_bundle,
+        )
+        .await
+        .expect("User can create key bundle");
+
+        let mut receiver = state
+            .messages
+            .subscribe(account_id, 1.into())
+            .await
+            .expect("Can subscribe");
+        notify_if_pre_keys_low(&mut state, account_id, 1.into())
+            .await
+            .expect("Can check pre key count");
+        assert!(receiver.try_recv().is_err());
+
+        let mut state = state.with_pre_key_threshold(0);
+        for _ in 0..3 {
+            get_keybundle(&mut state, account_id, 1.into(), 1.into())
+                .await
+                .expect("Can get bundle");
+        }
+        assert!(receiver.try_recv().is_err());
+    }
 }
@@ -1,25 +1,32 @@
 use crate::{
-    auth::authenticated_user::AuthenticatedUser,
-    managers::traits::message_manager::MessageManager,
+    auth::{authenticated_user::AuthenticatedUser, keys::verify_access_key},
+    managers::traits::{
+        account_manager::AccountManager, device_manager::DeviceManager,
+        message_manager::MessageManager,
+    },
     state::{state_type::StateType, ServerState},
     ServerError,
 };
 use log::{error, warn};
 use sam_common::{
-    address::AccountId,
-    sam_message::{ClientMessage, ServerEnvelope, ServerMessage},
+    address::MessageId,
+    sam_message::{ClientEnvelope, EnvelopeType, ErrorType, MessageType},
 };
 use sam_common::{
-    address::MessageId,
-    sam_message::{ClientEnvelope, MessageType},
+    address::{AccountId, DeviceAddress, DeviceId},
+    sam_message::{ClientMessage, DeviceMismatch, Notification, ServerEnvelope, ServerMessage},
 };
 
 macro_rules! error_message {
     ($msg_id:expr) => {
+        error_message!($msg_id, ErrorType::MalformedMessage)
+    };
+    ($msg_id:expr, $error:expr) => {
         Ok(Some(
             ServerMessage::builder()
                 .r#type(MessageType::Error as i32)
                 .id($msg_id)
+                .error($error as i32)
                 .build(),
         ))
     };
@@ -38,7 +45,7 @@ pub async fn handle_client_message<T: StateType>(
     match message.r#type() {
         MessageType::Message => {
             if let Some(envelope) = message.message {
-                handle_client_evelope(state, message_id, envelope).await
+                handle_client_evelope(state, auth_user, message_id, envelope).await
             } else {
                 error_message!(message_id.into())
             }
@@ -93,22 +100,87 @@ pub async fn handle_client_message<T: StateType>(
 
 async fn handle_client_evelope<T: StateType>(
     state: &mut ServerState<T>,
+    auth_user: &AuthenticatedUser,
     message_id: MessageId,
-    envelope: ClientEnvelope,
+    mut envelope: ClientEnvelope,
 ) -> Result<Option<ServerMessage>, ServerError> {
+    let sender = DeviceAddress::new(auth_user.account().id(), auth_user.device().id());
+    if state.envelopes.check(sender).await.is_err() {
+        return error_message!(message_id.into(), ErrorType::RateLimited);
+    }
+
     let dest_id = match AccountId::try_from(envelope.destination_account_id.clone()) {
         Ok(id) => id,
         Err(_) => return error_message!(message_id.into()),
     };
 
-    for (device_id, cipher) in envelope.content {
+    let source = if envelope.r#type() == EnvelopeType::UnidentifiedSender {
+        // sealed sender envelopes are authorized by the recipient's access key,
+        // the sender stays hidden inside the content
+        let account = match state.accounts.get_account(dest_id).await {
+            Ok(account) => account,
+            Err(ServerError::AccountNotExist) => {
+                return error_message!(message_id.into(), ErrorType::UnknownRecipient)
+            }
+            Err(e) => return Err(e),
+        };
+        let access = verify_access_key(
+            account.unidentified_access_key(),
+            envelope.unidentified_access_key.as_deref(),
+        );
+        if access.is_err() {
+            return error_message!(message_id.into(), ErrorType::UnauthorizedDelivery);
+        }
+        None
+    } else {
+        let source_id = auth_user.account().id();
+        let source_device_id = auth_user.device().id();
+        let is_source = envelope
+            .source_account_id
+            .clone()
+            .is_some_and(|id| AccountId::try_from(id).is_ok_and(|id| id == source_id))
+            && envelope.source_device_id == Some(*source_device_id);
+        if !is_source {
+            warn!(
+                "websocket user '{}' sent an envelope with a source that is not their own",
+                auth_user.account().username()
+            );
+            return error_message!(message_id.into(), ErrorType::SourceMismatch);
+        }
+        Some((source_id, source_device_id))
+    };
+
+    let mismatch = match check_devices(state, dest_id, source, &envelope).await {
+        Ok(mismatch) => mismatch,
+        Err(ServerError::AccountNotExist) => {
+            return error_message!(message_id.into(), ErrorType::UnknownRecipient)
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some((error, mismatch)) = mismatch {
+        return Ok(Some(
+            ServerMessage::builder()
+                .r#type(MessageType::Error as i32)
+                .id(message_id.into())
+                .error(error as i32)
+                .device_mismatch(mismatch)
+                .build(),
+        ));
+    }
+
+    for (device_id, cipher) in std::mem::take(&mut envelope.content) {
+        // a sealed envelope hides the type of the message inside it
+        let r#type = match source {
+            Some(_) => envelope.type_for(device_id),
+            None => EnvelopeType::UnidentifiedSender,
+        };
         let id = MessageId::generate();
         let server_envelope = ServerEnvelope::builder()
-            .r#type(envelope.r#type)
+            .r#type(r#type as i32)
             .destination_account_id(envelope.destination_account_id.clone())
             .destination_device_id(device_id)
-            .source_account_id(envelope.source_account_id.clone())
-            .source_device_id(envelope.source_device_id)
+            .maybe_source_account_id(source.map(|(id, _)| id.into()))
+            .maybe_source_device_id(source.map(|(_, source_device)| source_device.into()))
             .content(cipher)
             .id(id.into_bytes().to_vec())
             .build();
@@ -116,6 +188,10 @@ async fn handle_client_evelope<T: StateType>(
             .messages
             .insert_envelope(dest_id, device_id.into(), id, server_envelope)
             .await?;
+        state
+            .messages
+            .trim_envelopes(dest_id, device_id.into(), state.message_queue_limit)
+            .await?;
     }
 
     Ok(Some(
@@ -126,6 +202,70 @@ async fn handle_client_evelope<T: StateType>(
     ))
 }
 
+/// Compares the devices an envelope is addressed to with the devices of the recipient.
+/// Missing or extra devices take precedence over stale ones, as the sender has to
+/// fetch new key bundles in both cases.
+async fn check_devices<T: StateType>(
+    state: &ServerState<T>,
+    dest_id: AccountId,
+    source: Option<(AccountId, DeviceId)>,
+    envelope: &ClientEnvelope,
+) -> Result<Option<(ErrorType, DeviceMismatch)>, ServerError> {
+    let mut devices = state.devices.get_devices(dest_id).await?;
+    devices.sort();
+    // a device never sends a message to itself
+    if let Some((source_id, source_device_id)) = source {
+        if dest_id == source_id {
+            devices.retain(|id| *id != source_device_id);
+        }
+    }
+
+    let missing_devices: Vec<u32> = devices
+        .iter()
+        .map(|id| **id)
+        .filter(|id| !envelope.content.contains_key(id))
+        .collect();
+    let mut extra_devices: Vec<u32> = envelope
+        .content
+        .keys()
+        .filter(|id| !devices.contains(&DeviceId::from(**id)))
+        .copied()
+        .collect();
+    extra_devices.sort();
+
+    if !missing_devices.is_empty() || !extra_devices.is_empty() {
+        return Ok(Some((
+            ErrorType::MismatchedDevices,
+            DeviceMismatch {
+                missing_devices,
+                extra_devices,
+                stale_devices: Vec::new(),
+            },
+        )));
+    }
+
+    let mut stale_devices = Vec::new();
+    for device_id in devices {
+        let device = state.devices.get_device(dest_id, device_id).await?;
+        if envelope.registration_ids.get(&*device_id).copied() != Some(*device.registration_id()) {
+            stale_devices.push(*device_id);
+        }
+    }
+
+    if stale_devices.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some((
+            ErrorType::StaleDevices,
+            DeviceMismatch {
+                missing_devices: Vec::new(),
+                extra_devices: Vec::new(),
+                stale_devices,
+            },
+        )))
+    }
+}
+
 pub async fn handle_server_envelope<T: StateType>(
     state: &mut ServerState<T>,
     auth_user: &AuthenticatedUser,
@@ -149,3 +289,12 @@ pub async fn handle_server_envelope<T: StateType>(
             .build(),
     ))
 }
+
+/// Notifications are not tracked, the client must not acknowledge them
+pub fn handle_notification(notification: Notification) -> ServerMessage {
+    ServerMessage::builder()
+        .id(MessageId::generate().into())
+        .r#type(MessageType::Notification as i32)
+        .notification(notification)
+        .build()
+}
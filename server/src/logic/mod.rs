@@ -1,5 +1,9 @@
 pub mod account;
+pub mod certificate;
 pub mod device;
 pub mod keys;
 mod message;
+pub mod provisioning;
+pub mod retention;
+pub mod session;
 pub mod websocket;
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use log::{info, warn};
+use sam_common::{
+    address::ProvisioningId,
+    api::{ProvisionEnvelope, ProvisioningAddress},
+};
+use tokio::sync::oneshot;
+
+use crate::{
+    managers::traits::device_manager::DeviceManager,
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+/// Serves a new device until it has received its provisioning message. The
+/// device may wait as long as a link token is valid, after that the
+/// connection is closed.
+pub async fn init_provisioning_websocket<T: StateType>(
+    state: ServerState<T>,
+    mut socket: WebSocket,
+) {
+    let (address, envelope) = state.provisioning.subscribe().await;
+    info!("New device waiting for provisioning at '{}'", address);
+
+    let result = relay_provisioning(&state, &mut socket, address, envelope).await;
+    state.provisioning.unsubscribe(address).await;
+
+    let close = match result {
+        Ok(_) => CloseFrame {
+            code: 1000,
+            reason: "Provisioned".into(),
+        },
+        Err(ServerError::DeviceLinkTooSlow) => CloseFrame {
+            code: 1001,
+            reason: "Provisioning expired".into(),
+        },
+        Err(ServerError::WebSocketDisconnected) => return,
+        Err(err) => {
+            warn!("Provisioning at '{}' failed '{}'", address, err);
+            CloseFrame {
+                code: 1011,
+                reason: "Internal server error".into(),
+            }
+        }
+    };
+    let _ = socket.send(Message::Close(Some(close))).await;
+}
+
+async fn relay_provisioning<T: StateType>(
+    state: &ServerState<T>,
+    socket: &mut WebSocket,
+    address: ProvisioningId,
+    envelope: oneshot::Receiver<ProvisionEnvelope>,
+) -> Result<(), ServerError> {
+    send_json(socket, &ProvisioningAddress { address }).await?;
+
+    let expire = Duration::from_secs(state.devices.provision_expire_seconds().await?);
+    let envelope = tokio::select! {
+        envelope = tokio::time::timeout(expire, envelope) => match envelope {
+            Ok(Ok(envelope)) => envelope,
+            Ok(Err(_)) => return Err(ServerError::ProvisioningNotExist),
+            Err(_) => return Err(ServerError::DeviceLinkTooSlow),
+        },
+        _ = wait_for_close(socket) => return Err(ServerError::WebSocketDisconnected),
+    };
+
+    send_json(socket, &envelope).await
+}
+
+/// The new device has nothing to say, anything but a close is ignored
+async fn wait_for_close(socket: &mut WebSocket) {
+    while let Some(Ok(message)) = socket.recv().await {
+        if let Message::Close(_) = message {
+            return;
+        }
+    }
+}
+
+async fn send_json<S: serde::Serialize>(
+    socket: &mut WebSocket,
+    value: &S,
+) -> Result<(), ServerError> {
+    let json = serde_json::to_string(value).map_err(|_| ServerError::WebSocketSendError)?;
+    socket
+        .send(Message::Text(json.into()))
+        .await
+        .map_err(|_| ServerError::WebSocketSendError)
+}
+
+/// Relays the provisioning message of a primary device to the new device
+/// waiting at `address`
+pub async fn send_provisioning_message<T: StateType>(
+    state: &ServerState<T>,
+    address: ProvisioningId,
+    envelope: ProvisionEnvelope,
+) -> Result<(), ServerError> {
+    state.provisioning.send(address, envelope).await
+}
@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use log::{error, info};
+use sam_common::time_now_millis;
+
+use crate::{
+    managers::traits::{device_manager::DeviceManager, message_manager::MessageManager},
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+/// Expired envelopes and link tokens are looked for at least this often
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Drops envelopes stored longer ago than the retention period, together with
+/// their pending entries
+pub async fn remove_expired_envelopes<T: StateType>(
+    state: &mut ServerState<T>,
+) -> Result<u64, ServerError> {
+    let created_before = time_now_millis().saturating_sub(state.message_retention.as_millis());
+    state
+        .messages
+        .remove_expired_envelopes(created_before as u64)
+        .await
+}
+
+/// Drops link tokens that can no longer link a device
+pub async fn remove_expired_link_tokens<T: StateType>(
+    state: &mut ServerState<T>,
+) -> Result<u64, ServerError> {
+    state
+        .devices
+        .remove_expired_link_tokens(time_now_millis() as u64)
+        .await
+}
+
+/// Removes expired envelopes and link tokens until the server stops
+pub async fn run_reaper<T: StateType>(mut state: ServerState<T>) {
+    let mut interval = tokio::time::interval(
+        state
+            .message_retention
+            .clamp(Duration::from_secs(1), REAP_INTERVAL),
+    );
+
+    loop {
+        interval.tick().await;
+        match remove_expired_envelopes(&mut state).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} expired envelopes", removed),
+            Err(err) => error!("Failed to remove expired envelopes '{}'", err),
+        }
+        match remove_expired_link_tokens(&mut state).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} expired link tokens", removed),
+            Err(err) => error!("Failed to remove expired link tokens '{}'", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use sam_common::{
+        address::{AccountId, MessageId},
+        sam_message::{EnvelopeType, ServerEnvelope},
+        time_now_millis,
+    };
+
+    use crate::{
+        logic::retention::remove_expired_envelopes,
+        managers::traits::message_manager::MessageManager, state::ServerState, ServerError,
+    };
+
+    fn envelope(account_id: AccountId, id: MessageId, server_timestamp: u64) -> ServerEnvelope {
+        ServerEnvelope::builder()
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .destination_account_id(account_id.into())
+            .destination_device_id(1)
+            .source_account_id(AccountId::generate().into())
+            .source_device_id(1)
+            .content(b"hi".to_vec())
+            .id(id.into())
+            .server_timestamp(server_timestamp)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_remove_expired_envelopes() {
+        let mut state =
+            ServerState::in_memory_test().with_message_retention(Duration::from_secs(60));
+        let account_id = AccountId::generate();
+        let now = time_now_millis() as u64;
+        let expired_id = MessageId::generate();
+        let fresh_id = MessageId::generate();
+
+        for (id, timestamp) in [(expired_id, now - 120_000), (fresh_id, now)] {
+            state
+                .messages
+                .insert_envelope(
+                    account_id,
+                    1.into(),
+                    id,
+                    envelope(account_id, id, timestamp),
+                )
+                .await
+                .expect("Can insert envelope");
+            state
+                .messages
+                .add_pending_message(account_id, 1.into(), id)
+                .await
+                .expect("Can add pending message");
+        }
+
+        assert_eq!(
+            remove_expired_envelopes(&mut state)
+                .await
+                .expect("Can remove expired envelopes"),
+            1
+        );
+        assert!(matches!(
+            state
+                .messages
+                .get_envelope(account_id, 1.into(), expired_id)
+                .await,
+            Err(ServerError::EnvelopeNotExists)
+        ));
+        assert!(matches!(
+            state
+                .messages
+                .remove_pending_message(account_id, 1.into(), expired_id)
+                .await,
+            Err(ServerError::MessageNotPending)
+        ));
+        assert!(state
+            .messages
+            .get_envelope_ids(account_id, 1.into())
+            .await
+            .is_some_and(|ids| ids == vec![fresh_id]));
+    }
+}
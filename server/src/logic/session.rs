@@ -0,0 +1,24 @@
+use sam_common::{address::AccountId, api::SessionToken};
+
+use crate::{
+    auth::session::create_session_token,
+    managers::{entities::device::Device, traits::device_manager::DeviceManager},
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+/// Issues a session token to a device that authenticated with its password
+pub async fn create_session<T: StateType>(
+    state: &ServerState<T>,
+    account_id: AccountId,
+    device: &Device,
+) -> Result<SessionToken, ServerError> {
+    let secret = state.devices.link_secret().await?;
+    Ok(create_session_token(
+        &secret,
+        account_id,
+        device.id(),
+        device.password(),
+        state.session_lifetime,
+    ))
+}
@@ -5,21 +5,18 @@ use futures_util::{
 };
 use log::{error, info};
 use prost::Message as _;
-use sam_common::{
-    address::MessageId,
-    sam_message::{ClientMessage, ServerMessage},
-};
+use sam_common::sam_message::{ClientMessage, ServerMessage};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
     auth::authenticated_user::AuthenticatedUser,
-    managers::traits::message_manager::MessageManager,
+    managers::traits::message_manager::{Dispatch, MessageManager},
     state::{state_type::StateType, ServerState},
     ServerError,
 };
 
-use super::message::{handle_client_message, handle_server_envelope};
+use super::message::{handle_client_message, handle_notification, handle_server_envelope};
 
 macro_rules! closing_err {
     ($username:expr, $err:expr) => {
@@ -34,7 +31,7 @@ pub async fn init_websocket<T: StateType>(
     state: ServerState<T>,
     auth_user: AuthenticatedUser,
     socket: WebSocket,
-    dispatch: Receiver<MessageId>,
+    dispatch: Receiver<Dispatch>,
 ) {
     info!("{} Connected!", auth_user.account().username());
 
@@ -106,6 +103,19 @@ async fn websocket_message_sender<T: StateType>(
                 .await
                 .map_err(|_| ServerError::WebSocketSendError),
             Err(ServerError::WebSocketDisconnected) => Err(ServerError::WebSocketDisconnected),
+            Err(ServerError::WebSocketForceClosed) => {
+                let res = sender
+                    .send(Message::Close(Some(CloseFrame {
+                        code: 1008,
+                        reason: "Disconnected by the server".into(),
+                    })))
+                    .await
+                    .map_err(|_| ServerError::WebSocketSendError);
+                match res {
+                    Ok(_) => Err(ServerError::WebSocketForceClosed),
+                    Err(x) => Err(x),
+                }
+            }
             Err(err) => {
                 let res = sender
                     .send(Message::Close(Some(CloseFrame {
@@ -126,7 +136,7 @@ async fn websocket_message_sender<T: StateType>(
             Ok(_) => continue,
             Err(err) => {
                 match err {
-                    ServerError::WebSocketDisconnected => break,
+                    ServerError::WebSocketDisconnected | ServerError::WebSocketForceClosed => break,
                     _ => closing_err!(auth_user.account().username(), err),
                 }
                 break;
@@ -142,19 +152,29 @@ async fn websocket_message_sender<T: StateType>(
 
 async fn websocket_dispatcher<T: StateType>(
     mut state: ServerState<T>,
-    mut dispatch: Receiver<MessageId>,
+    mut dispatch: Receiver<Dispatch>,
     message_producer: Sender<Result<Option<ServerMessage>, ServerError>>,
     auth_user: AuthenticatedUser,
 ) {
-    while let Some(msg_id) = dispatch.recv().await {
-        let msg_res = state
-            .messages
-            .get_envelope(auth_user.account().id(), auth_user.device().id(), msg_id)
-            .await;
-
-        let msg_res = match msg_res {
-            Ok(envelope) => handle_server_envelope(&mut state, &auth_user, envelope).await,
-            Err(e) => Err(e),
+    let account_id = auth_user.account().id();
+    let device_id = auth_user.device().id();
+
+    while let Some(next) = dispatch.recv().await {
+        let msg_res = match next {
+            Dispatch::Envelope(msg_id) => {
+                match state
+                    .messages
+                    .get_envelope(account_id, device_id, msg_id)
+                    .await
+                {
+                    Ok(envelope) => handle_server_envelope(&mut state, &auth_user, envelope).await,
+                    // expired or dropped from a full queue before it was delivered
+                    Err(ServerError::EnvelopeNotExists) => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            Dispatch::Notification(notification) => Ok(Some(handle_notification(notification))),
+            Dispatch::Disconnect => Err(ServerError::WebSocketForceClosed),
         };
 
         let is_msg_res_err = msg_res.is_err();
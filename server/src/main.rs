@@ -1,16 +1,160 @@
-use sam_server::{start_server, state::ServerState, ServerConfig};
+use std::{path::PathBuf, process::exit, time::Duration};
+
+use axum_server::tls_rustls::RustlsConfig;
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use clap::Parser;
+use log::error;
+use sam_server::{
+    auth::certificate::CertificateSigner,
+    config::{Backend, Config, Overrides},
+    install_crypto_provider,
+    managers::{postgres::connect_to_postgres, sqlite::connect_to_sqlite},
+    start_server,
+    state::{state_type::StateType, ServerState},
+    ServerConfig,
+};
+
+/// The sam instant messenger server
+#[derive(Debug, Parser)]
+struct Cli {
+    /// TOML file with the server settings, flags and environment variables take precedence
+    #[arg(long, short, env = "SAM_CONFIG")]
+    config: Option<PathBuf>,
+    /// Prints the base64 encoded trust root that clients pin for sealed sender, then exits
+    #[arg(long)]
+    print_trust_root: bool,
+    #[command(flatten)]
+    overrides: Overrides,
+}
 
 #[tokio::main]
 pub async fn main() {
     env_logger::init();
-    let state = ServerState::in_memory("test".to_string(), 600, 10);
-
-    let config = ServerConfig {
-        state,
-        addr: "127.0.0.1:8080"
-            .parse()
-            .expect("Unable to parse socket address"),
-        tls: None,
+    let cli = Cli::parse();
+
+    let config = match cli.config {
+        Some(path) => Config::from_file(&path),
+        None => Ok(Config::default()),
+    }
+    .and_then(|config| config.apply(cli.overrides))
+    .and_then(|config| config.validate().map(|_| config));
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Invalid configuration: {}", err);
+            exit(1);
+        }
+    };
+
+    let certificates = match config.certificate_signer() {
+        Ok(certificates) => certificates,
+        Err(err) => {
+            error!("Invalid configuration: {}", err);
+            exit(1);
+        }
+    };
+    if cli.print_trust_root {
+        match &certificates {
+            Some(certificates) => {
+                println!(
+                    "{}",
+                    BASE64_STANDARD.encode(certificates.trust_root().serialize())
+                );
+                exit(0);
+            }
+            None => {
+                error!("No certificate key is configured");
+                exit(1);
+            }
+        }
+    }
+
+    let tls = match &config.tls {
+        Some(tls) => {
+            install_crypto_provider();
+            match RustlsConfig::from_pem_file(&tls.certificate, &tls.private_key).await {
+                Ok(tls) => Some(tls),
+                Err(err) => {
+                    error!("Could not load TLS certificate: {}", err);
+                    exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+
+    let link_secret = config.link_secret.clone().unwrap_or_default();
+    let database_url = config.database.url.clone().unwrap_or_default();
+    let result = match config.database.backend {
+        Backend::InMemory => {
+            let state = ServerState::in_memory(
+                link_secret,
+                config.provision_expire_seconds,
+                config.message_buffer_size,
+            );
+            serve(state, &config, certificates, tls).await
+        }
+        Backend::Sqlite => match connect_to_sqlite(&database_url).await {
+            Ok(database) => {
+                let state = ServerState::sqlite(
+                    database,
+                    link_secret,
+                    config.provision_expire_seconds,
+                    config.message_buffer_size,
+                );
+                serve(state, &config, certificates, tls).await
+            }
+            Err(err) => {
+                error!("Could not open SQLite database: {}", err);
+                exit(1);
+            }
+        },
+        Backend::Postgres => match connect_to_postgres(&database_url).await {
+            Ok(database) => {
+                let state = ServerState::postgres(
+                    database,
+                    link_secret,
+                    config.provision_expire_seconds,
+                    config.message_buffer_size,
+                );
+                serve(state, &config, certificates, tls).await
+            }
+            Err(err) => {
+                error!("Could not connect to Postgres: {}", err);
+                exit(1);
+            }
+        },
+    };
+
+    if let Err(err) = result {
+        error!("Server stopped: {}", err);
+        exit(1);
+    }
+}
+
+async fn serve<T: StateType>(
+    state: ServerState<T>,
+    config: &Config,
+    certificates: Option<CertificateSigner>,
+    tls: Option<RustlsConfig>,
+) -> Result<(), std::io::Error> {
+    let state = match certificates {
+        Some(certificates) => state.with_certificate_signer(certificates),
+        None => state,
     };
-    start_server(config).await.unwrap();
+    start_server(ServerConfig {
+        state: state
+            .with_pre_key_threshold(config.pre_key_threshold)
+            .with_session_lifetime(Duration::from_secs(config.session_expire_seconds))
+            .with_message_retention(Duration::from_secs(config.message_retention_seconds))
+            .with_message_queue_limit(config.message_queue_limit)
+            .with_ip_request_limit(config.rate_limits.ip)
+            .with_account_request_limit(config.rate_limits.account)
+            .with_failed_login_limit(config.rate_limits.failed_logins)
+            .with_envelope_limit(config.rate_limits.envelopes)
+            .with_username_lookup_limit(config.rate_limits.username_lookups),
+        addr: config.address,
+        tls,
+    })
+    .await
 }
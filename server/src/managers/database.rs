@@ -0,0 +1,115 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use sqlx::{pool::PoolConnection, Pool, Transaction};
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+use crate::ServerError;
+
+type SharedTransaction<DB> = Arc<Mutex<Option<Transaction<'static, DB>>>>;
+
+/// Handle to a SQL database, either backed by the connection pool or by a
+/// transaction shared between every manager of a transactional `ServerState`
+pub struct Database<DB: sqlx::Database> {
+    pool: Pool<DB>,
+    transaction: Option<SharedTransaction<DB>>,
+    owner: bool,
+}
+
+impl<DB: sqlx::Database> Clone for Database<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            transaction: self.transaction.clone(),
+            owner: self.owner,
+        }
+    }
+}
+
+pub enum DatabaseConnection<'a, DB: sqlx::Database> {
+    Pool(PoolConnection<DB>),
+    Transaction(MappedMutexGuard<'a, Transaction<'static, DB>>),
+}
+
+impl<DB: sqlx::Database> Deref for DatabaseConnection<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            DatabaseConnection::Pool(connection) => connection,
+            DatabaseConnection::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+impl<DB: sqlx::Database> DerefMut for DatabaseConnection<'_, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            DatabaseConnection::Pool(connection) => connection,
+            DatabaseConnection::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+impl<DB: sqlx::Database> Database<DB> {
+    pub fn new(pool: Pool<DB>) -> Self {
+        Self {
+            pool,
+            transaction: None,
+            owner: false,
+        }
+    }
+
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+
+    pub async fn connection(&self) -> Result<DatabaseConnection<'_, DB>, ServerError> {
+        match &self.transaction {
+            None => Ok(DatabaseConnection::Pool(self.pool.acquire().await?)),
+            Some(transaction) => MutexGuard::try_map(transaction.lock().await, |tx| tx.as_mut())
+                .map(DatabaseConnection::Transaction)
+                .map_err(|_| ServerError::DatabaseTransactionClosed),
+        }
+    }
+
+    /// Starts a new transaction, or joins the current one if there is one.
+    /// Only the handle that started a transaction can commit or roll it back.
+    pub async fn begin(&self) -> Result<Self, ServerError> {
+        if self.transaction.is_some() {
+            return Ok(Self {
+                owner: false,
+                ..self.clone()
+            });
+        }
+
+        Ok(Self {
+            pool: self.pool.clone(),
+            transaction: Some(Arc::new(Mutex::new(Some(self.pool.begin().await?)))),
+            owner: true,
+        })
+    }
+
+    pub async fn commit(&self) -> Result<(), ServerError> {
+        match self.take_transaction().await {
+            Some(transaction) => transaction.commit().await.map_err(ServerError::from),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn rollback(&self) -> Result<(), ServerError> {
+        match self.take_transaction().await {
+            Some(transaction) => transaction.rollback().await.map_err(ServerError::from),
+            None => Ok(()),
+        }
+    }
+
+    async fn take_transaction(&self) -> Option<Transaction<'static, DB>> {
+        match (&self.transaction, self.owner) {
+            (Some(transaction), true) => transaction.lock().await.take(),
+            _ => None,
+        }
+    }
+}
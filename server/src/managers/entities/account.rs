@@ -1,11 +1,13 @@
 use libsignal_protocol::IdentityKey;
 use sam_common::address::AccountId;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Clone, bon::Builder, Debug)]
 pub struct Account {
     id: AccountId,
     username: String,
     identity: IdentityKey,
+    unidentified_access_key: Option<Box<[u8]>>,
 }
 
 impl Account {
@@ -17,7 +19,21 @@ impl Account {
         &self.username
     }
 
+    pub fn set_username(&mut self, username: String) {
+        self.username = username;
+    }
+
     pub fn identity(&self) -> &IdentityKey {
         &self.identity
     }
+
+    pub fn unidentified_access_key(&self) -> Option<&[u8]> {
+        self.unidentified_access_key.as_deref()
+    }
+}
+
+/// Brings a username into the form it is stored and looked up in, so names
+/// that only differ in case or Unicode representation refer to one account.
+pub fn normalize_username(username: &str) -> String {
+    username.nfkc().collect::<String>().to_lowercase()
 }
@@ -1,4 +1,7 @@
-use sam_common::address::{DeviceId, RegistrationId};
+use sam_common::{
+    address::{DeviceId, RegistrationId},
+    api::DeviceRole,
+};
 
 use crate::auth::password::Password;
 
@@ -9,6 +12,7 @@ pub struct Device {
     name: String,
     creation: u128,
     password: Password,
+    role: DeviceRole,
 }
 
 impl Device {
@@ -24,6 +28,10 @@ impl Device {
         &self.name
     }
 
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
     pub fn creation(&self) -> u128 {
         self.creation
     }
@@ -31,4 +39,20 @@ impl Device {
     pub fn password(&self) -> &Password {
         &self.password
     }
+
+    pub fn set_password(&mut self, password: Password) {
+        self.password = password;
+    }
+
+    pub fn role(&self) -> DeviceRole {
+        self.role
+    }
+
+    pub fn set_role(&mut self, role: DeviceRole) {
+        self.role = role;
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.role == DeviceRole::Primary
+    }
 }
@@ -4,13 +4,17 @@ use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
 
 use crate::{
-    managers::{entities::account::Account, traits::account_manager::AccountManager},
+    managers::{
+        entities::account::{normalize_username, Account},
+        traits::account_manager::AccountManager,
+    },
     ServerError,
 };
 
 #[derive(Clone)]
 pub struct InMemoryAccountManager {
     accounts: Arc<Mutex<HashMap<AccountId, Account>>>,
+    usernames: Arc<Mutex<HashMap<String, AccountId>>>,
 }
 
 impl Default for InMemoryAccountManager {
@@ -23,6 +27,7 @@ impl InMemoryAccountManager {
     pub fn new() -> Self {
         InMemoryAccountManager {
             accounts: Arc::new(Mutex::new(HashMap::new())),
+            usernames: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -38,23 +43,65 @@ impl AccountManager for InMemoryAccountManager {
             .cloned()
     }
 
+    async fn get_account_id(&self, username: &str) -> Result<AccountId, ServerError> {
+        self.usernames
+            .lock()
+            .await
+            .get(&normalize_username(username))
+            .copied()
+            .ok_or(ServerError::AccountNotExist)
+    }
+
     async fn add_account(&mut self, account: &Account) -> Result<(), ServerError> {
-        if self.accounts.lock().await.contains_key(&account.id()) {
+        let mut accounts = self.accounts.lock().await;
+        let mut usernames = self.usernames.lock().await;
+        if accounts.contains_key(&account.id()) {
             return Err(ServerError::AccountExists);
         }
-        self.accounts
-            .lock()
-            .await
-            .insert(account.id(), account.clone());
+        let username = normalize_username(account.username());
+        if usernames.contains_key(&username) {
+            return Err(ServerError::UsernameTaken);
+        }
+        usernames.insert(username, account.id());
+        accounts.insert(account.id(), account.clone());
+        Ok(())
+    }
+
+    async fn set_username(
+        &mut self,
+        account_id: AccountId,
+        username: &str,
+    ) -> Result<(), ServerError> {
+        let mut accounts = self.accounts.lock().await;
+        let mut usernames = self.usernames.lock().await;
+        let account = accounts
+            .get_mut(&account_id)
+            .ok_or(ServerError::AccountNotExist)?;
+        let normalized = normalize_username(username);
+        if usernames
+            .get(&normalized)
+            .is_some_and(|owner| *owner != account_id)
+        {
+            return Err(ServerError::UsernameTaken);
+        }
+
+        usernames.remove(&normalize_username(account.username()));
+        usernames.insert(normalized, account_id);
+        account.set_username(username.to_string());
         Ok(())
     }
 
     async fn remove_account(&mut self, account_id: AccountId) -> Result<(), ServerError> {
-        self.accounts
+        let account = self
+            .accounts
             .lock()
             .await
             .remove(&account_id)
-            .ok_or(ServerError::AccountNotExist)
-            .map(|_| ())
+            .ok_or(ServerError::AccountNotExist)?;
+        self.usernames
+            .lock()
+            .await
+            .remove(&normalize_username(account.username()));
+        Ok(())
     }
 }
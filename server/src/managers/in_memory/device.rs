@@ -1,4 +1,7 @@
-use sam_common::address::{AccountId, DeviceAddress, DeviceId};
+use sam_common::{
+    address::{AccountId, DeviceAddress, DeviceId},
+    api::DeviceRole,
+};
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
@@ -7,14 +10,23 @@ use std::{
 use tokio::sync::Mutex;
 
 use crate::{
+    auth::password::Password,
     managers::{entities::device::Device, traits::device_manager::DeviceManager},
     ServerError,
 };
 
+#[derive(Clone, Copy, PartialEq)]
+enum LinkTokenState {
+    Unused,
+    Used,
+    Revoked,
+}
+
 #[derive(Clone)]
 pub struct InMemoryDeviceManager {
     devices: Arc<Mutex<HashMap<DeviceAddress, Device>>>,
     account_devices: Arc<Mutex<HashMap<AccountId, HashSet<DeviceAddress>>>>,
+    link_tokens: Arc<Mutex<HashMap<String, (AccountId, LinkTokenState, u64)>>>,
     link_secret: String,
     provision_expire_seconds: u64,
 }
@@ -24,6 +36,7 @@ impl InMemoryDeviceManager {
         InMemoryDeviceManager {
             devices: Arc::new(Mutex::new(HashMap::new())),
             account_devices: Arc::new(Mutex::new(HashMap::new())),
+            link_tokens: Arc::new(Mutex::new(HashMap::new())),
             link_secret,
             provision_expire_seconds,
         }
@@ -105,6 +118,54 @@ impl DeviceManager for InMemoryDeviceManager {
         Ok(())
     }
 
+    async fn rename_device(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        name: &str,
+    ) -> Result<(), ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        self.devices
+            .lock()
+            .await
+            .get_mut(&key)
+            .ok_or(ServerError::DeviceNotExist)
+            .map(|device| device.set_name(name.to_string()))
+    }
+
+    async fn set_device_role(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        role: DeviceRole,
+    ) -> Result<(), ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        self.devices
+            .lock()
+            .await
+            .get_mut(&key)
+            .ok_or(ServerError::DeviceNotExist)
+            .map(|device| device.set_role(role))
+    }
+
+    async fn set_device_password(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        password: &Password,
+    ) -> Result<(), ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        self.devices
+            .lock()
+            .await
+            .get_mut(&key)
+            .ok_or(ServerError::DeviceNotExist)
+            .map(|device| device.set_password(password.clone()))
+    }
+
     async fn remove_device(
         &mut self,
         account_id: AccountId,
@@ -112,15 +173,107 @@ impl DeviceManager for InMemoryDeviceManager {
     ) -> Result<(), ServerError> {
         let key = DeviceAddress::new(account_id, device_id);
 
-        if let Some(x) = self.account_devices.lock().await.get_mut(&account_id) {
-            x.remove(&key);
+        {
+            let mut account_devices = self.account_devices.lock().await;
+            if let Some(x) = account_devices.get_mut(&account_id) {
+                x.remove(&key);
+                if x.is_empty() {
+                    account_devices.remove(&account_id);
+                }
+            }
         }
 
         self.devices
             .lock()
             .await
             .remove(&key)
-            .ok_or(ServerError::AccountNotExist)
+            .ok_or(ServerError::DeviceNotExist)
             .map(|_| ())
     }
+
+    async fn add_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+        expires: u64,
+    ) -> Result<(), ServerError> {
+        self.link_tokens
+            .lock()
+            .await
+            .entry(token_id.to_string())
+            .or_insert((account_id, LinkTokenState::Unused, expires));
+        Ok(())
+    }
+
+    async fn check_link_token(
+        &self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError> {
+        match self.link_tokens.lock().await.get(token_id) {
+            Some((owner, state, _)) if *owner == account_id => match state {
+                LinkTokenState::Unused => Ok(()),
+                LinkTokenState::Used => Err(ServerError::DeviceTokenUsed),
+                LinkTokenState::Revoked => Err(ServerError::DeviceTokenRevoked),
+            },
+            _ => Err(ServerError::DeviceTokenNotExist),
+        }
+    }
+
+    async fn consume_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError> {
+        let mut link_tokens = self.link_tokens.lock().await;
+        let state = match link_tokens.get_mut(token_id) {
+            Some((owner, state, _)) if *owner == account_id => state,
+            _ => return Err(ServerError::DeviceTokenNotExist),
+        };
+
+        match state {
+            LinkTokenState::Unused => {
+                *state = LinkTokenState::Used;
+                Ok(())
+            }
+            LinkTokenState::Used => Err(ServerError::DeviceTokenUsed),
+            LinkTokenState::Revoked => Err(ServerError::DeviceTokenRevoked),
+        }
+    }
+
+    async fn revoke_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError> {
+        let mut link_tokens = self.link_tokens.lock().await;
+        let state = match link_tokens.get_mut(token_id) {
+            Some((owner, state, _)) if *owner == account_id => state,
+            _ => return Err(ServerError::DeviceTokenNotExist),
+        };
+
+        if *state == LinkTokenState::Used {
+            return Err(ServerError::DeviceTokenUsed);
+        }
+        *state = LinkTokenState::Revoked;
+        Ok(())
+    }
+
+    async fn remove_link_tokens(&mut self, account_id: AccountId) -> Result<(), ServerError> {
+        self.link_tokens
+            .lock()
+            .await
+            .retain(|_, (owner, _, _)| *owner != account_id);
+        Ok(())
+    }
+
+    async fn remove_expired_link_tokens(
+        &mut self,
+        expired_before: u64,
+    ) -> Result<u64, ServerError> {
+        let mut link_tokens = self.link_tokens.lock().await;
+        let before = link_tokens.len();
+        link_tokens.retain(|_, (_, _, expires)| *expires >= expired_before);
+        Ok((before - link_tokens.len()) as u64)
+    }
 }
@@ -1,16 +1,11 @@
-use std::{
-    collections::{hash_map::Entry, HashMap},
-    sync::Arc,
-};
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
-    auth::keys::verify_key,
     managers::traits::key_manager::{
         LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
     },
     ServerError,
 };
-use libsignal_protocol::IdentityKey;
 use sam_common::{
     address::{AccountId, DeviceAddress, DeviceId},
     api::keys::{EcPreKey, Key, PqPreKey, SignedEcPreKey},
@@ -81,18 +76,15 @@ impl PreKeyManager for InMemoryKeyManager {
         device_id: DeviceId,
         key: EcPreKey,
     ) -> Result<(), ServerError> {
-        let dkey = DeviceAddress::new(account_id, device_id);
-
-        if let Entry::Vacant(e) = self.pre_keys.lock().await.entry(dkey) {
-            e.insert(Vec::new());
+        let mut pre_keys = self.pre_keys.lock().await;
+        let keys = pre_keys
+            .entry(DeviceAddress::new(account_id, device_id))
+            .or_default();
+        if keys.iter().any(|stored| stored.id() == key.id()) {
+            return Err(ServerError::KeyExists);
         }
-
-        self.pre_keys
-            .lock()
-            .await
-            .get_mut(&dkey)
-            .map(|keys| keys.push(key))
-            .ok_or(ServerError::AccountNotExist)
+        keys.push(key);
+        Ok(())
     }
 
     async fn remove_pre_key(
@@ -142,13 +134,10 @@ impl SignedPreKeyManager for InMemoryKeyManager {
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-        identity: &IdentityKey,
         key: SignedEcPreKey,
     ) -> Result<(), ServerError> {
         let dkey = DeviceAddress::new(account_id, device_id);
 
-        verify_key(identity, &key)?;
-
         let _ = self.signed_pre_keys.lock().await.insert(dkey, key);
         Ok(())
     }
@@ -202,23 +191,17 @@ impl PqPreKeyManager for InMemoryKeyManager {
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-        identity: &IdentityKey,
         key: PqPreKey,
     ) -> Result<(), ServerError> {
-        let dkey = DeviceAddress::new(account_id, device_id);
-
-        verify_key(identity, &key)?;
-
-        if let Entry::Vacant(e) = self.pq_pre_keys.lock().await.entry(dkey) {
-            e.insert(Vec::new());
+        let mut pq_pre_keys = self.pq_pre_keys.lock().await;
+        let keys = pq_pre_keys
+            .entry(DeviceAddress::new(account_id, device_id))
+            .or_default();
+        if keys.iter().any(|stored| stored.id() == key.id()) {
+            return Err(ServerError::KeyExists);
         }
-
-        self.pq_pre_keys
-            .lock()
-            .await
-            .get_mut(&dkey)
-            .map(|keys| keys.push(key))
-            .ok_or(ServerError::AccountNotExist)
+        keys.push(key);
+        Ok(())
     }
 
     async fn remove_pq_pre_key(
@@ -272,13 +255,10 @@ impl LastResortKeyManager for InMemoryKeyManager {
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-        identity: &IdentityKey,
         key: PqPreKey,
     ) -> Result<(), ServerError> {
         let dkey = DeviceAddress::new(account_id, device_id);
 
-        verify_key(identity, &key)?;
-
         let _ = self.last_resort_keys.lock().await.insert(dkey, key);
         Ok(())
     }
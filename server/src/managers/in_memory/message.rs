@@ -1,23 +1,29 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use sam_common::{
     address::{AccountId, DeviceAddress, DeviceId},
-    sam_message::ServerEnvelope,
+    sam_message::{Notification, ServerEnvelope},
+    time_now_millis,
 };
 use tokio::sync::{mpsc, Mutex};
 
 use crate::{
-    managers::traits::message_manager::{EnvelopeId, MessageManager},
+    managers::traits::message_manager::{Dispatch, EnvelopeId, MessageManager},
     ServerError,
 };
 
 #[derive(Clone)]
 pub struct InMemoryMessageManager {
-    envelopes: Arc<Mutex<HashMap<DeviceAddress, HashMap<EnvelopeId, ServerEnvelope>>>>,
-    subscribers: Arc<Mutex<HashMap<DeviceAddress, mpsc::Sender<EnvelopeId>>>>,
+    /// Envelopes with the order they were inserted in
+    envelopes: Arc<Mutex<HashMap<DeviceAddress, HashMap<EnvelopeId, (u64, ServerEnvelope)>>>>,
+    sequence: Arc<AtomicU64>,
+    subscribers: Arc<Mutex<HashMap<DeviceAddress, mpsc::Sender<Dispatch>>>>,
     pending_messages: Arc<Mutex<HashSet<EnvelopeKey>>>,
     channel_buffer: usize,
 }
@@ -32,6 +38,7 @@ impl InMemoryMessageManager {
     pub fn new(channel_buffer: usize) -> Self {
         InMemoryMessageManager {
             envelopes: Arc::new(Mutex::new(HashMap::new())),
+            sequence: Arc::new(AtomicU64::new(0)),
             subscribers: Arc::new(Mutex::new(HashMap::new())),
             pending_messages: Arc::new(Mutex::new(HashSet::new())),
             channel_buffer,
@@ -46,9 +53,12 @@ impl MessageManager for InMemoryMessageManager {
         account_id: AccountId,
         device_id: DeviceId,
         envelope_id: EnvelopeId,
-        message: ServerEnvelope,
+        mut message: ServerEnvelope,
     ) -> Result<(), ServerError> {
         let key = DeviceAddress::new(account_id, device_id);
+        message
+            .server_timestamp
+            .get_or_insert_with(|| time_now_millis() as u64);
 
         self.envelopes.lock().await.entry(key).or_default();
 
@@ -62,10 +72,11 @@ impl MessageManager for InMemoryMessageManager {
             return Err(ServerError::EnvelopeExists);
         };
 
-        let _ = msgs.and_then(|map| map.insert(envelope_id, message));
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let _ = msgs.and_then(|map| map.insert(envelope_id, (sequence, message)));
         if let Some(sender) = self.subscribers.lock().await.get(&key) {
             sender
-                .send(envelope_id)
+                .send(Dispatch::Envelope(envelope_id))
                 .await
                 .map_err(|_| ServerError::MessageSubscriberSendErorr)?;
         }
@@ -83,9 +94,9 @@ impl MessageManager for InMemoryMessageManager {
         match self.envelopes.lock().await.get(&key) {
             Some(msgs) => msgs
                 .get(&envelope_id)
-                .cloned()
+                .map(|(_, envelope)| envelope.clone())
                 .ok_or(ServerError::EnvelopeNotExists),
-            None => Err(ServerError::AccountNotExist),
+            None => Err(ServerError::EnvelopeNotExists),
         }
     }
 
@@ -102,7 +113,7 @@ impl MessageManager for InMemoryMessageManager {
                 .remove(&envelope_id)
                 .ok_or(ServerError::EnvelopeNotExists)
                 .map(|_| ()),
-            None => Err(ServerError::AccountNotExist),
+            None => Err(ServerError::EnvelopeNotExists),
         }
     }
 
@@ -118,13 +129,14 @@ impl MessageManager for InMemoryMessageManager {
             .await
             .get(&key)
             .map(|msgs| msgs.keys().cloned().collect::<Vec<EnvelopeId>>())
+            .filter(|ids| !ids.is_empty())
     }
 
     async fn subscribe(
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-    ) -> Result<mpsc::Receiver<EnvelopeId>, ServerError> {
+    ) -> Result<mpsc::Receiver<Dispatch>, ServerError> {
         let key = DeviceAddress::new(account_id, device_id);
         let (sender, receiver) = mpsc::channel(self.channel_buffer);
 
@@ -139,11 +151,34 @@ impl MessageManager for InMemoryMessageManager {
     async fn unsubscribe(&mut self, account_id: AccountId, device_id: DeviceId) {
         let key = DeviceAddress::new(account_id, device_id);
 
-        if self.subscribers.lock().await.contains_key(&key) {
-            return;
+        self.subscribers.lock().await.remove(&key);
+    }
+
+    async fn disconnect(&mut self, account_id: AccountId, device_id: DeviceId) {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        let sender = self.subscribers.lock().await.remove(&key);
+        if let Some(sender) = sender {
+            // the websocket may already be gone, then there is nothing to close
+            let _ = sender.send(Dispatch::Disconnect).await;
         }
+    }
 
-        self.subscribers.lock().await.remove(&key);
+    async fn notify(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        notification: Notification,
+    ) -> Result<(), ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        match self.subscribers.lock().await.get(&key) {
+            Some(sender) => sender
+                .send(Dispatch::Notification(notification))
+                .await
+                .map_err(|_| ServerError::MessageSubscriberSendErorr),
+            None => Ok(()),
+        }
     }
 
     async fn dispatch_envelopes(
@@ -160,7 +195,7 @@ impl MessageManager for InMemoryMessageManager {
                     Some(sender) => {
                         for id in ids {
                             sender
-                                .send(id)
+                                .send(Dispatch::Envelope(id))
                                 .await
                                 .map_err(|_| ServerError::MessageSubscriberSendErorr)?;
                         }
@@ -203,6 +238,71 @@ impl MessageManager for InMemoryMessageManager {
         Ok(())
     }
 
+    async fn remove_pending_messages(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError> {
+        self.pending_messages
+            .lock()
+            .await
+            .retain(|key| key.account_id != account_id || key.device_id != device_id);
+        Ok(())
+    }
+
+    async fn remove_expired_envelopes(&mut self, created_before: u64) -> Result<u64, ServerError> {
+        let mut envelopes = self.envelopes.lock().await;
+        let mut pending = self.pending_messages.lock().await;
+
+        let mut removed = 0;
+        for (address, msgs) in envelopes.iter_mut() {
+            msgs.retain(|id, (_, envelope)| {
+                let expired = envelope.server_timestamp() < created_before;
+                if expired {
+                    pending.remove(&EnvelopeKey::new(
+                        address.account_id(),
+                        address.device_id(),
+                        *id,
+                    ));
+                    removed += 1;
+                }
+                !expired
+            });
+        }
+        envelopes.retain(|_, msgs| !msgs.is_empty());
+        Ok(removed)
+    }
+
+    async fn trim_envelopes(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        limit: usize,
+    ) -> Result<u64, ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+        let mut envelopes = self.envelopes.lock().await;
+        let Some(msgs) = envelopes.get_mut(&key) else {
+            return Ok(0);
+        };
+        if msgs.len() <= limit {
+            return Ok(0);
+        }
+
+        let mut oldest = msgs
+            .iter()
+            .map(|(id, (sequence, _))| (*sequence, *id))
+            .collect::<Vec<_>>();
+        oldest.sort_by_key(|(sequence, _)| *sequence);
+        let excess = msgs.len() - limit;
+
+        let mut pending = self.pending_messages.lock().await;
+        for (_, id) in oldest.into_iter().take(excess) {
+            msgs.remove(&id);
+            pending.remove(&EnvelopeKey::new(account_id, device_id, id));
+        }
+        Ok(excess as u64)
+    }
+
     async fn channel_buffer(&self) -> usize {
         self.channel_buffer
     }
@@ -12,16 +12,38 @@ pub mod test_utils;
 
 use account::InMemoryAccountManager;
 
-use crate::state::{state_type::StateType, ServerState};
+use crate::{
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
 
 #[derive(Clone)]
 pub struct InMemStateType;
 
+/// The in-memory state does not survive a restart, so there is nothing a
+/// transaction could protect: writes are applied immediately and a rollback
+/// leaves them in place. Writes spanning several managers are therefore not
+/// atomic, a failure halfway through keeps everything written before it.
+#[async_trait::async_trait]
 impl StateType for InMemStateType {
     type AccountManager = InMemoryAccountManager;
     type DeviceManager = InMemoryDeviceManager;
     type MessageManager = InMemoryMessageManager;
     type KeyManager = InMemoryKeyManager;
+
+    async fn begin_transaction(
+        state: &ServerState<Self>,
+    ) -> Result<ServerState<Self>, ServerError> {
+        Ok(state.clone())
+    }
+
+    async fn commit_transaction(_state: ServerState<Self>) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    async fn rollback_transaction(_state: ServerState<Self>) -> Result<(), ServerError> {
+        Ok(())
+    }
 }
 
 impl ServerState<InMemStateType> {
@@ -1,3 +1,8 @@
+pub mod database;
 pub mod entities;
 pub mod in_memory;
+pub mod postgres;
+pub mod provisioning;
+pub mod rate_limit;
+pub mod sqlite;
 pub mod traits;
@@ -0,0 +1,265 @@
+use account::PostgresAccountManager;
+use device::PostgresDeviceManager;
+use keys::PostgresKeyManager;
+use message::PostgresMessageManager;
+use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+
+pub mod account;
+pub mod device;
+pub mod keys;
+pub mod message;
+
+use crate::{
+    managers::database::Database,
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+#[derive(Clone)]
+pub struct PostgresStateType;
+
+#[async_trait::async_trait]
+impl StateType for PostgresStateType {
+    type AccountManager = PostgresAccountManager;
+    type DeviceManager = PostgresDeviceManager;
+    type MessageManager = PostgresMessageManager;
+    type KeyManager = PostgresKeyManager;
+
+    async fn begin_transaction(
+        state: &ServerState<Self>,
+    ) -> Result<ServerState<Self>, ServerError> {
+        let database = state.accounts.database().begin().await?;
+
+        Ok(ServerState {
+            accounts: state.accounts.with_database(database.clone()),
+            devices: state.devices.with_database(database.clone()),
+            messages: state.messages.with_database(database.clone()),
+            keys: state.keys.with_database(database),
+            ..state.clone()
+        })
+    }
+
+    async fn commit_transaction(state: ServerState<Self>) -> Result<(), ServerError> {
+        state.accounts.database().commit().await
+    }
+
+    async fn rollback_transaction(state: ServerState<Self>) -> Result<(), ServerError> {
+        state.accounts.database().rollback().await
+    }
+}
+
+/// Connects to the database at `database_url` and runs any pending migrations
+pub async fn connect_to_postgres(database_url: &str) -> Result<Pool<Postgres>, ServerError> {
+    let pool = PgPoolOptions::new().connect(database_url).await?;
+    sqlx::migrate!("database/postgres/migrations")
+        .run(&pool)
+        .await
+        .map_err(sqlx::Error::from)?;
+
+    Ok(pool)
+}
+
+impl ServerState<PostgresStateType> {
+    pub fn postgres(
+        database: Pool<Postgres>,
+        link_secret: String,
+        provision_expire_seconds: u64,
+        message_buffer: usize,
+    ) -> Self {
+        let database = Database::new(database);
+
+        ServerState::new(
+            PostgresAccountManager::new(database.clone()),
+            PostgresDeviceManager::new(database.clone(), link_secret, provision_expire_seconds),
+            PostgresMessageManager::new(database.clone(), message_buffer),
+            PostgresKeyManager::new(database),
+        )
+    }
+
+    /// Requires `POSTGRES_DATABASE_URL` to point at a running Postgres instance
+    #[cfg(test)]
+    pub async fn postgres_test() -> Self {
+        use crate::managers::in_memory::test_utils::LINK_SECRET;
+
+        let database_url = std::env::var("POSTGRES_DATABASE_URL")
+            .expect("POSTGRES_DATABASE_URL must be set to run Postgres tests");
+        let database = connect_to_postgres(&database_url)
+            .await
+            .expect("Can connect to Postgres");
+
+        ServerState::postgres(database, LINK_SECRET.to_string(), 600, 10)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libsignal_protocol::IdentityKeyPair;
+    use rand::rngs::OsRng;
+    use sam_common::{
+        address::AccountId,
+        api::{device::DeviceActivationInfo, Key, RegistrationRequest},
+    };
+
+    use crate::{
+        logic::{
+            account::{create_account, delete_account},
+            device::{create_device_token, link_device, unlink_device},
+            keys::get_keybundles,
+        },
+        managers::traits::{
+            account_manager::AccountManager,
+            device_manager::DeviceManager,
+            key_manager::{LastResortKeyManager, PqPreKeyManager, PreKeyManager},
+        },
+        state::ServerState,
+        test_utils::{create_device_link, create_publish_pre_keys},
+        ServerError,
+    };
+
+    fn registration(pair: &IdentityKeyPair) -> RegistrationRequest {
+        RegistrationRequest {
+            identity_key: *pair.identity_key(),
+            device_activation: DeviceActivationInfo {
+                name: "Alice Phone".to_string(),
+                registration_id: 1.into(),
+                key_bundle: create_publish_pre_keys(
+                    Some(vec![0, 1]),
+                    Some(1),
+                    Some(vec![33]),
+                    Some(2),
+                    pair,
+                    OsRng,
+                )
+                .try_into()
+                .expect("Can make RegistrationPreKeys"),
+            },
+            unidentified_access_key: None,
+        }
+    }
+
+    /// The database is shared between runs and usernames are unique
+    fn username() -> String {
+        format!("alice-{}", AccountId::generate())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Postgres instance"]
+    async fn test_create_and_delete_account() {
+        let mut state = ServerState::postgres_test().await;
+        let pair = IdentityKeyPair::generate(&mut OsRng);
+        let username = username();
+
+        let alice_id = create_account(
+            &mut state,
+            registration(&pair),
+            username.clone(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+
+        let account = state
+            .accounts
+            .get_account(alice_id)
+            .await
+            .expect("Alice has an account");
+        assert!(account.username() == username);
+        assert!(*account.identity() == *pair.identity_key());
+
+        let device = state
+            .devices
+            .get_device(alice_id, 1.into())
+            .await
+            .expect("Alice has primary device");
+        assert!(device.name() == "Alice Phone");
+        device
+            .password()
+            .verify("bob<3".to_string())
+            .expect("Alice loves bob");
+
+        assert!(state
+            .keys
+            .get_pre_key_ids(alice_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids == Some(vec![0, 1])));
+        assert!(state
+            .keys
+            .get_pq_pre_key_ids(alice_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids == Some(vec![33])));
+
+        delete_account(&mut state, alice_id)
+            .await
+            .expect("Alice can delete account");
+
+        assert!(matches!(
+            state.accounts.get_account(alice_id).await,
+            Err(ServerError::AccountNotExist)
+        ));
+        assert!(state.devices.get_device(alice_id, 1.into()).await.is_err());
+        assert!(state
+            .keys
+            .get_last_resort_key(alice_id, 1.into())
+            .await
+            .is_err());
+        assert!(state
+            .keys
+            .get_pre_key_ids(alice_id, 1.into())
+            .await
+            .is_ok_and(|ids| ids.is_none()));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Postgres instance"]
+    async fn test_link_and_unlink_device() {
+        let mut state = ServerState::postgres_test().await;
+        let pair = IdentityKeyPair::generate(&mut OsRng);
+
+        let alice_id = create_account(
+            &mut state,
+            registration(&pair),
+            username(),
+            "bob<3".to_string(),
+        )
+        .await
+        .map(|r| r.account_id)
+        .expect("Alice can create account");
+
+        let token = create_device_token(&mut state, alice_id)
+            .await
+            .expect("Alice can create device token");
+        let key_bundle = create_publish_pre_keys(None, Some(1), None, Some(2), &pair, OsRng)
+            .try_into()
+            .expect("Can make RegistrationPreKeys");
+        let device_link = create_device_link(token, "Alice Laptop", 2.into(), key_bundle);
+
+        let res = link_device(&mut state, device_link, "charlie<3".to_string())
+            .await
+            .expect("Alice can link device");
+        assert!(res.account_id == alice_id);
+        assert!(res.device_id == 2.into());
+
+        let bundles = get_keybundles(&mut state, alice_id)
+            .await
+            .expect("Bob can fetch Alice's bundles");
+        assert!(bundles.bundles.len() == 2);
+        assert!(bundles
+            .bundles
+            .iter()
+            .any(|bundle| bundle.device_id == 1 && bundle.pre_key.as_ref().unwrap().id() == 0));
+
+        unlink_device(&mut state, alice_id, 2.into())
+            .await
+            .expect("Alice can unlink laptop");
+        assert!(state
+            .devices
+            .get_devices(alice_id)
+            .await
+            .is_ok_and(|ids| ids == vec![1.into()]));
+
+        delete_account(&mut state, alice_id)
+            .await
+            .expect("Alice can delete account");
+    }
+}
@@ -0,0 +1,45 @@
+use std::{collections::HashMap, sync::Arc};
+
+use sam_common::{address::ProvisioningId, api::ProvisionEnvelope};
+use tokio::sync::{oneshot, Mutex};
+
+use crate::ServerError;
+
+/// Hands provisioning messages from primary devices to new devices waiting on
+/// the provisioning websocket. Nothing is persisted, a new device that is not
+/// connected cannot be provisioned.
+#[derive(Clone, Default)]
+pub struct ProvisioningRelay {
+    waiting: Arc<Mutex<HashMap<ProvisioningId, oneshot::Sender<ProvisionEnvelope>>>>,
+}
+
+impl ProvisioningRelay {
+    /// Gives a new device an address, the envelope sent to it is delivered
+    /// through the receiver
+    pub async fn subscribe(&self) -> (ProvisioningId, oneshot::Receiver<ProvisionEnvelope>) {
+        let (sender, receiver) = oneshot::channel();
+        let address = ProvisioningId::generate();
+        self.waiting.lock().await.insert(address, sender);
+        (address, receiver)
+    }
+
+    pub async fn unsubscribe(&self, address: ProvisioningId) {
+        self.waiting.lock().await.remove(&address);
+    }
+
+    /// Delivers `envelope` to the device waiting at `address`, each address
+    /// receives a single envelope
+    pub async fn send(
+        &self,
+        address: ProvisioningId,
+        envelope: ProvisionEnvelope,
+    ) -> Result<(), ServerError> {
+        self.waiting
+            .lock()
+            .await
+            .remove(&address)
+            .ok_or(ServerError::ProvisioningNotExist)?
+            .send(envelope)
+            .map_err(|_| ServerError::ProvisioningNotExist)
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::ServerError;
+
+/// Buckets are only pruned once a limiter tracks this many keys
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Allows `burst` requests at once, after that one request per `interval`.
+/// A burst of zero disables the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(from = "RateLimitSetting")]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self { burst, interval }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.burst == 0
+    }
+}
+
+/// How a [`RateLimit`] is written in the config file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateLimitSetting {
+    burst: u32,
+    interval_ms: u64,
+}
+
+impl From<RateLimitSetting> for RateLimit {
+    fn from(setting: RateLimitSetting) -> Self {
+        Self::new(setting.burst, Duration::from_millis(setting.interval_ms))
+    }
+}
+
+/// Counts requests per key in memory, so limits are per server process and
+/// reset on restart
+#[derive(Clone)]
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    /// Point in time at which the bucket of a key is full again
+    buckets: Arc<Mutex<HashMap<K, Instant>>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Takes a request from the bucket of `key`, fails with the time until the
+    /// next request is allowed if the bucket is empty
+    pub async fn check(&self, key: K) -> Result<(), ServerError> {
+        if self.limit.is_disabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, full| *full > now);
+        }
+
+        let full = buckets.get(&key).copied().unwrap_or(now).max(now);
+        self.wait(full, now)?;
+        buckets.insert(key, full + self.limit.interval);
+        Ok(())
+    }
+
+    /// Fails like [`RateLimiter::check`] while the bucket of `key` is empty,
+    /// without taking a request from it
+    pub async fn peek(&self, key: &K) -> Result<(), ServerError> {
+        if self.limit.is_disabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let full = self.buckets.lock().await.get(key).copied().unwrap_or(now);
+        self.wait(full.max(now), now)
+    }
+
+    fn wait(&self, full: Instant, now: Instant) -> Result<(), ServerError> {
+        let tolerance = self.limit.interval * self.limit.burst.saturating_sub(1);
+        let wait = (full - now).saturating_sub(tolerance);
+        if !wait.is_zero() {
+            return Err(ServerError::RateLimited(wait));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::ServerError;
+
+    use super::{RateLimit, RateLimiter};
+
+    #[tokio::test]
+    async fn test_rate_limiter_allows_burst_per_key() {
+        let limiter = RateLimiter::new(RateLimit::new(2, Duration::from_millis(100)));
+
+        assert!(limiter.check("alice").await.is_ok());
+        assert!(limiter.check("alice").await.is_ok());
+        assert!(matches!(
+            limiter.check("alice").await,
+            Err(ServerError::RateLimited(wait)) if wait <= Duration::from_millis(100)
+        ));
+        assert!(limiter.check("bob").await.is_ok());
+
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        assert!(limiter.check("alice").await.is_ok());
+        assert!(limiter.check("alice").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_peek_takes_no_request() {
+        let limiter = RateLimiter::new(RateLimit::new(1, Duration::from_secs(60)));
+
+        assert!(limiter.peek(&"alice").await.is_ok());
+        assert!(limiter.peek(&"alice").await.is_ok());
+        assert!(limiter.check("alice").await.is_ok());
+        assert!(matches!(
+            limiter.peek(&"alice").await,
+            Err(ServerError::RateLimited(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_without_burst_is_disabled() {
+        let limiter = RateLimiter::new(RateLimit::new(0, Duration::from_secs(60)));
+
+        for _ in 0..10 {
+            assert!(limiter.check("alice").await.is_ok());
+        }
+    }
+}
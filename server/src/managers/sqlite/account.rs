@@ -0,0 +1,162 @@
+use libsignal_protocol::IdentityKey;
+use sam_common::address::AccountId;
+use sqlx::{Row as _, Sqlite};
+use uuid::Uuid;
+
+use crate::{
+    managers::{
+        database::Database, entities::account::Account, traits::account_manager::AccountManager,
+    },
+    ServerError,
+};
+
+#[derive(Clone)]
+pub struct SqliteAccountManager {
+    database: Database<Sqlite>,
+}
+
+impl SqliteAccountManager {
+    pub fn new(database: Database<Sqlite>) -> Self {
+        Self { database }
+    }
+
+    pub(crate) fn database(&self) -> &Database<Sqlite> {
+        &self.database
+    }
+
+    pub(crate) fn with_database(&self, database: Database<Sqlite>) -> Self {
+        Self { database }
+    }
+}
+
+/// The unique index on the username is the only constraint a write to
+/// `Accounts` can violate besides the primary key
+fn username_conflict(err: sqlx::Error) -> ServerError {
+    match err.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => ServerError::UsernameTaken,
+        _ => err.into(),
+    }
+}
+
+#[async_trait::async_trait]
+impl AccountManager for SqliteAccountManager {
+    async fn get_account(&self, id: AccountId) -> Result<Account, ServerError> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                username, identity, unidentified_access_key
+            FROM
+                Accounts
+            WHERE
+                id = ?
+            "#,
+        )
+        .bind(*id.uuid())
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .ok_or(ServerError::AccountNotExist)?;
+
+        let identity = IdentityKey::decode(&row.try_get::<Vec<u8>, _>("identity")?)
+            .map_err(|_| ServerError::DatabaseDecodeError)?;
+
+        Ok(Account::builder()
+            .id(id)
+            .username(row.try_get("username")?)
+            .identity(identity)
+            .maybe_unidentified_access_key(
+                row.try_get::<Option<Vec<u8>>, _>("unidentified_access_key")?
+                    .map(Vec::into_boxed_slice),
+            )
+            .build())
+    }
+
+    async fn get_account_id(&self, username: &str) -> Result<AccountId, ServerError> {
+        let id = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT
+                id
+            FROM
+                Accounts
+            WHERE
+                username = ? COLLATE NOCASE
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .ok_or(ServerError::AccountNotExist)?;
+
+        Ok(id.into())
+    }
+
+    async fn add_account(&mut self, account: &Account) -> Result<(), ServerError> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO Accounts (id, username, identity, unidentified_access_key)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+        )
+        .bind(*account.id().uuid())
+        .bind(account.username())
+        .bind(account.identity().serialize().to_vec())
+        .bind(account.unidentified_access_key())
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map_err(username_conflict)?
+        .rows_affected();
+
+        if inserted == 0 {
+            return Err(ServerError::AccountExists);
+        }
+        Ok(())
+    }
+
+    async fn set_username(
+        &mut self,
+        account_id: AccountId,
+        username: &str,
+    ) -> Result<(), ServerError> {
+        let updated = sqlx::query(
+            r#"
+            UPDATE
+                Accounts
+            SET
+                username = ?
+            WHERE
+                id = ?
+            "#,
+        )
+        .bind(username)
+        .bind(*account_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map_err(username_conflict)?
+        .rows_affected();
+
+        if updated == 0 {
+            return Err(ServerError::AccountNotExist);
+        }
+        Ok(())
+    }
+
+    async fn remove_account(&mut self, account_id: AccountId) -> Result<(), ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                Accounts
+            WHERE
+                id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Err(ServerError::AccountNotExist);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+use std::str::FromStr as _;
+
+use argon2::password_hash::SaltString;
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::DeviceRole,
+};
+use sqlx::{sqlite::SqliteRow, Row as _, Sqlite};
+
+use crate::{
+    auth::password::Password,
+    managers::{
+        database::Database, entities::device::Device, traits::device_manager::DeviceManager,
+    },
+    ServerError,
+};
+
+#[derive(Clone)]
+pub struct SqliteDeviceManager {
+    database: Database<Sqlite>,
+    link_secret: String,
+    provision_expire_seconds: u64,
+}
+
+impl SqliteDeviceManager {
+    pub fn new(
+        database: Database<Sqlite>,
+        link_secret: String,
+        provision_expire_seconds: u64,
+    ) -> Self {
+        Self {
+            database,
+            link_secret,
+            provision_expire_seconds,
+        }
+    }
+
+    pub(crate) fn with_database(&self, database: Database<Sqlite>) -> Self {
+        Self {
+            database,
+            ..self.clone()
+        }
+    }
+
+    /// Explains why a link token could not be updated
+    async fn link_token_error(
+        &self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<ServerError, ServerError> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                used, revoked
+            FROM
+                LinkTokens
+            WHERE
+                id = ? AND account_id = ?
+            "#,
+        )
+        .bind(token_id)
+        .bind(*account_id.uuid())
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(ServerError::DeviceTokenNotExist);
+        };
+        if row.try_get::<bool, _>("used")? {
+            Ok(ServerError::DeviceTokenUsed)
+        } else if row.try_get::<bool, _>("revoked")? {
+            Ok(ServerError::DeviceTokenRevoked)
+        } else {
+            Ok(ServerError::DeviceTokenNotExist)
+        }
+    }
+}
+
+fn device_from_row(row: SqliteRow) -> Result<Device, ServerError> {
+    let salt = SaltString::from_b64(&row.try_get::<String, _>("password_salt")?)
+        .map_err(|_| ServerError::DatabaseDecodeError)?;
+    let password = Password::builder()
+        .hash(row.try_get("password_hash")?)
+        .salt(salt)
+        .build();
+
+    Ok(Device::builder()
+        .id((row.try_get::<i64, _>("id")? as u32).into())
+        .name(row.try_get("name")?)
+        .creation(row.try_get::<i64, _>("creation")? as u128)
+        .registration_id((row.try_get::<i64, _>("registration_id")? as u32).into())
+        .password(password)
+        .role(
+            DeviceRole::from_str(&row.try_get::<String, _>("role")?)
+                .map_err(|_| ServerError::DatabaseDecodeError)?,
+        )
+        .build())
+}
+
+#[async_trait::async_trait]
+impl DeviceManager for SqliteDeviceManager {
+    async fn get_device(&self, account_id: AccountId, id: DeviceId) -> Result<Device, ServerError> {
+        sqlx::query(
+            r#"
+            SELECT
+                id, name, creation, registration_id, password_hash, password_salt, role
+            FROM
+                Devices
+            WHERE
+                account_id = ? AND id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*id))
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .ok_or(ServerError::DeviceNotExist)
+        .and_then(device_from_row)
+    }
+
+    async fn get_devices(&self, account_id: AccountId) -> Result<Vec<DeviceId>, ServerError> {
+        let devices = sqlx::query(
+            r#"
+            SELECT
+                id
+            FROM
+                Devices
+            WHERE
+                account_id = ?
+            ORDER BY
+                id
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .fetch_all(&mut *self.database.connection().await?)
+        .await?
+        .into_iter()
+        .map(|row| {
+            row.try_get::<i64, _>("id")
+                .map(|id| DeviceId::from(id as u32))
+        })
+        .collect::<Result<Vec<DeviceId>, sqlx::Error>>()?;
+
+        if devices.is_empty() {
+            return Err(ServerError::AccountNotExist);
+        }
+        Ok(devices)
+    }
+
+    async fn next_device_id(&self, account_id: AccountId) -> Result<DeviceId, ServerError> {
+        let devices = self.get_devices(account_id).await?;
+        for (i, &num) in devices.iter().enumerate() {
+            if *num != (i as u32) + 1 {
+                return Ok(((i as u32) + 1).into());
+            }
+        }
+        Ok((devices.len() as u32 + 1).into())
+    }
+
+    async fn link_secret(&self) -> Result<String, ServerError> {
+        Ok(self.link_secret.clone())
+    }
+
+    async fn provision_expire_seconds(&self) -> Result<u64, ServerError> {
+        Ok(self.provision_expire_seconds)
+    }
+
+    async fn add_device(
+        &mut self,
+        account_id: AccountId,
+        device: &Device,
+    ) -> Result<(), ServerError> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO Devices (account_id, id, name, creation, registration_id, password_hash, password_salt, role)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (account_id, id) DO NOTHING
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device.id()))
+        .bind(device.name())
+        .bind(device.creation() as i64)
+        .bind(i64::from(*device.registration_id()))
+        .bind(device.password().hash())
+        .bind(device.password().salt())
+        .bind(device.role().to_string())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            return Err(ServerError::DeviceExists);
+        }
+        Ok(())
+    }
+
+    async fn rename_device(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        name: &str,
+    ) -> Result<(), ServerError> {
+        let renamed = sqlx::query(
+            r#"
+            UPDATE
+                Devices
+            SET
+                name = ?
+            WHERE
+                account_id = ? AND id = ?
+            "#,
+        )
+        .bind(name)
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if renamed == 0 {
+            return Err(ServerError::DeviceNotExist);
+        }
+        Ok(())
+    }
+
+    async fn set_device_role(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        role: DeviceRole,
+    ) -> Result<(), ServerError> {
+        let updated = sqlx::query(
+            r#"
+            UPDATE
+                Devices
+            SET
+                role = ?
+            WHERE
+                account_id = ? AND id = ?
+            "#,
+        )
+        .bind(role.to_string())
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if updated == 0 {
+            return Err(ServerError::DeviceNotExist);
+        }
+        Ok(())
+    }
+
+    async fn set_device_password(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        password: &Password,
+    ) -> Result<(), ServerError> {
+        let updated = sqlx::query(
+            r#"
+            UPDATE
+                Devices
+            SET
+                password_hash = ?, password_salt = ?
+            WHERE
+                account_id = ? AND id = ?
+            "#,
+        )
+        .bind(password.hash())
+        .bind(password.salt())
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if updated == 0 {
+            return Err(ServerError::DeviceNotExist);
+        }
+        Ok(())
+    }
+
+    async fn remove_device(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                Devices
+            WHERE
+                account_id = ? AND id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Err(ServerError::DeviceNotExist);
+        }
+        Ok(())
+    }
+
+    async fn add_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+        expires: u64,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            INSERT INTO LinkTokens (id, account_id, expires)
+            VALUES (?, ?, ?)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+        )
+        .bind(token_id)
+        .bind(*account_id.uuid())
+        .bind(expires as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn check_link_token(
+        &self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError> {
+        let usable = sqlx::query(
+            r#"
+            SELECT
+                id
+            FROM
+                LinkTokens
+            WHERE
+                id = ? AND account_id = ? AND NOT used AND NOT revoked
+            "#,
+        )
+        .bind(token_id)
+        .bind(*account_id.uuid())
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?;
+
+        if usable.is_none() {
+            return Err(self.link_token_error(account_id, token_id).await?);
+        }
+        Ok(())
+    }
+
+    async fn consume_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError> {
+        let consumed = sqlx::query(
+            r#"
+            UPDATE
+                LinkTokens
+            SET
+                used = TRUE
+            WHERE
+                id = ? AND account_id = ? AND NOT used AND NOT revoked
+            "#,
+        )
+        .bind(token_id)
+        .bind(*account_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if consumed == 0 {
+            return Err(self.link_token_error(account_id, token_id).await?);
+        }
+        Ok(())
+    }
+
+    async fn revoke_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError> {
+        let revoked = sqlx::query(
+            r#"
+            UPDATE
+                LinkTokens
+            SET
+                revoked = TRUE
+            WHERE
+                id = ? AND account_id = ? AND NOT used
+            "#,
+        )
+        .bind(token_id)
+        .bind(*account_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if revoked == 0 {
+            return Err(self.link_token_error(account_id, token_id).await?);
+        }
+        Ok(())
+    }
+
+    async fn remove_link_tokens(&mut self, account_id: AccountId) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            DELETE FROM
+                LinkTokens
+            WHERE
+                account_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_expired_link_tokens(
+        &mut self,
+        expired_before: u64,
+    ) -> Result<u64, ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                LinkTokens
+            WHERE
+                expires < ?
+            "#,
+        )
+        .bind(expired_before as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+        Ok(removed)
+    }
+}
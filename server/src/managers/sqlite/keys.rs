@@ -0,0 +1,422 @@
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::keys::{EcPreKey, Key, PqPreKey, SignedEcPreKey},
+};
+use sqlx::{sqlite::SqliteRow, Row as _, Sqlite};
+
+use crate::{
+    managers::{
+        database::Database,
+        traits::key_manager::{
+            LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
+        },
+    },
+    ServerError,
+};
+
+#[derive(Clone)]
+pub struct SqliteKeyManager {
+    database: Database<Sqlite>,
+}
+
+impl SqliteKeyManager {
+    pub fn new(database: Database<Sqlite>) -> Self {
+        Self { database }
+    }
+
+    pub(crate) fn with_database(&self, database: Database<Sqlite>) -> Self {
+        Self { database }
+    }
+}
+
+/// The one-time key tables are unique on the key id per device, so a
+/// concurrent upload of the same id surfaces as a unique violation
+fn key_conflict(err: sqlx::Error) -> ServerError {
+    match err.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => ServerError::KeyExists,
+        _ => err.into(),
+    }
+}
+
+fn ec_pre_key_from_row(row: SqliteRow) -> Result<EcPreKey, ServerError> {
+    Ok(EcPreKey {
+        key_id: row.try_get::<i64, _>("key_id")? as u32,
+        public_key: row.try_get::<Vec<u8>, _>("public_key")?.into(),
+    })
+}
+
+fn signed_ec_pre_key_from_row(row: SqliteRow) -> Result<SignedEcPreKey, ServerError> {
+    Ok(SignedEcPreKey {
+        key_id: row.try_get::<i64, _>("key_id")? as u32,
+        public_key: row.try_get::<Vec<u8>, _>("public_key")?.into(),
+        signature: row.try_get::<Vec<u8>, _>("signature")?.into(),
+    })
+}
+
+fn pq_pre_key_from_row(row: SqliteRow) -> Result<PqPreKey, ServerError> {
+    Ok(PqPreKey {
+        key_id: row.try_get::<i64, _>("key_id")? as u32,
+        public_key: row.try_get::<Vec<u8>, _>("public_key")?.into(),
+        signature: row.try_get::<Vec<u8>, _>("signature")?.into(),
+    })
+}
+
+fn key_ids_from_rows(rows: Vec<SqliteRow>) -> Result<Option<Vec<u32>>, ServerError> {
+    let ids = rows
+        .into_iter()
+        .map(|row| row.try_get::<i64, _>("key_id").map(|id| id as u32))
+        .collect::<Result<Vec<u32>, sqlx::Error>>()?;
+
+    Ok(if ids.is_empty() { None } else { Some(ids) })
+}
+
+#[async_trait::async_trait]
+impl PreKeyManager for SqliteKeyManager {
+    async fn get_pre_key(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<Option<EcPreKey>, ServerError> {
+        sqlx::query(
+            r#"
+            SELECT
+                key_id, public_key
+            FROM
+                PreKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            ORDER BY
+                id
+            LIMIT 1
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .map(ec_pre_key_from_row)
+        .transpose()
+    }
+
+    async fn get_pre_key_ids(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<Option<Vec<u32>>, ServerError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                key_id
+            FROM
+                PreKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            ORDER BY
+                id
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_all(&mut *self.database.connection().await?)
+        .await?;
+
+        key_ids_from_rows(rows)
+    }
+
+    async fn add_pre_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        key: EcPreKey,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            INSERT INTO PreKeys (account_id, device_id, key_id, public_key)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(i64::from(key.id()))
+        .bind(key.public_key())
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map(|_| ())
+        .map_err(key_conflict)
+    }
+
+    async fn remove_pre_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        id: u32,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            DELETE FROM
+                PreKeys
+            WHERE
+                account_id = ? AND device_id = ? AND key_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(i64::from(id))
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map(|_| ())
+        .map_err(ServerError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl SignedPreKeyManager for SqliteKeyManager {
+    async fn get_signed_pre_key(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<SignedEcPreKey, ServerError> {
+        sqlx::query(
+            r#"
+            SELECT
+                key_id, public_key, signature
+            FROM
+                SignedPreKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .ok_or(ServerError::AccountNotExist)
+        .and_then(signed_ec_pre_key_from_row)
+    }
+
+    async fn set_signed_pre_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        key: SignedEcPreKey,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            INSERT INTO SignedPreKeys (account_id, device_id, key_id, public_key, signature)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (account_id, device_id) DO UPDATE
+            SET key_id = excluded.key_id, public_key = excluded.public_key, signature = excluded.signature
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(i64::from(key.id()))
+        .bind(key.public_key())
+        .bind(key.signature())
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map(|_| ())
+        .map_err(ServerError::from)
+    }
+
+    async fn remove_signed_pre_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            DELETE FROM
+                SignedPreKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map(|_| ())
+        .map_err(ServerError::from)
+    }
+}
+
+#[async_trait::async_trait]
+impl PqPreKeyManager for SqliteKeyManager {
+    async fn get_pq_pre_key(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<Option<PqPreKey>, ServerError> {
+        sqlx::query(
+            r#"
+            SELECT
+                key_id, public_key, signature
+            FROM
+                PqPreKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            ORDER BY
+                id
+            LIMIT 1
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .map(pq_pre_key_from_row)
+        .transpose()
+    }
+
+    async fn get_pq_pre_key_ids(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<Option<Vec<u32>>, ServerError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                key_id
+            FROM
+                PqPreKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            ORDER BY
+                id
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_all(&mut *self.database.connection().await?)
+        .await?;
+
+        key_ids_from_rows(rows)
+    }
+
+    async fn add_pq_pre_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        key: PqPreKey,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            INSERT INTO PqPreKeys (account_id, device_id, key_id, public_key, signature)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(i64::from(key.id()))
+        .bind(key.public_key())
+        .bind(key.signature())
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map(|_| ())
+        .map_err(key_conflict)
+    }
+
+    async fn remove_pq_pre_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        id: u32,
+    ) -> Result<(), ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                PqPreKeys
+            WHERE
+                account_id = ? AND device_id = ? AND key_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(i64::from(id))
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Err(ServerError::KeyNotExist);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl LastResortKeyManager for SqliteKeyManager {
+    async fn get_last_resort_key(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<PqPreKey, ServerError> {
+        sqlx::query(
+            r#"
+            SELECT
+                key_id, public_key, signature
+            FROM
+                LastResortKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .ok_or(ServerError::KeyNotExist)
+        .and_then(pq_pre_key_from_row)
+    }
+
+    async fn set_last_resort_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        key: PqPreKey,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            INSERT INTO LastResortKeys (account_id, device_id, key_id, public_key, signature)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (account_id, device_id) DO UPDATE
+            SET key_id = excluded.key_id, public_key = excluded.public_key, signature = excluded.signature
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(i64::from(key.id()))
+        .bind(key.public_key())
+        .bind(key.signature())
+        .execute(&mut *self.database.connection().await?)
+        .await
+        .map(|_| ())
+        .map_err(ServerError::from)
+    }
+
+    async fn remove_last_resort_key(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                LastResortKeys
+            WHERE
+                account_id = ? AND device_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Err(ServerError::KeyNotExist);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+use std::{collections::HashMap, sync::Arc};
+
+use prost::Message as _;
+use sam_common::{
+    address::{AccountId, DeviceAddress, DeviceId},
+    sam_message::{Notification, ServerEnvelope},
+    time_now_millis,
+};
+use sqlx::{Row as _, Sqlite};
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    managers::{
+        database::Database,
+        traits::message_manager::{Dispatch, EnvelopeId, MessageManager},
+    },
+    ServerError,
+};
+
+#[derive(Clone)]
+pub struct SqliteMessageManager {
+    database: Database<Sqlite>,
+    subscribers: Arc<Mutex<HashMap<DeviceAddress, mpsc::Sender<Dispatch>>>>,
+    channel_buffer: usize,
+}
+
+impl SqliteMessageManager {
+    pub fn new(database: Database<Sqlite>, channel_buffer: usize) -> Self {
+        Self {
+            database,
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+            channel_buffer,
+        }
+    }
+
+    pub(crate) fn with_database(&self, database: Database<Sqlite>) -> Self {
+        Self {
+            database,
+            ..self.clone()
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageManager for SqliteMessageManager {
+    async fn insert_envelope(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        envelope_id: EnvelopeId,
+        mut envelope: ServerEnvelope,
+    ) -> Result<(), ServerError> {
+        let created = *envelope
+            .server_timestamp
+            .get_or_insert_with(|| time_now_millis() as u64);
+        // the index on seq alone turns MAX(seq) into a lookup of its last entry
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO Envelopes (account_id, device_id, id, envelope, created, seq)
+            VALUES (?, ?, ?, ?, ?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM Envelopes))
+            ON CONFLICT (account_id, device_id, id) DO NOTHING
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*envelope_id.uuid())
+        .bind(envelope.encode_to_vec())
+        .bind(created as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            return Err(ServerError::EnvelopeExists);
+        }
+
+        let key = DeviceAddress::new(account_id, device_id);
+        if let Some(sender) = self.subscribers.lock().await.get(&key) {
+            sender
+                .send(Dispatch::Envelope(envelope_id))
+                .await
+                .map_err(|_| ServerError::MessageSubscriberSendErorr)?;
+        }
+        Ok(())
+    }
+
+    async fn get_envelope(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        envelope_id: EnvelopeId,
+    ) -> Result<ServerEnvelope, ServerError> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                envelope
+            FROM
+                Envelopes
+            WHERE
+                account_id = ? AND device_id = ? AND id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*envelope_id.uuid())
+        .fetch_optional(&mut *self.database.connection().await?)
+        .await?
+        .ok_or(ServerError::EnvelopeNotExists)?;
+
+        ServerEnvelope::decode(row.try_get::<Vec<u8>, _>("envelope")?.as_slice())
+            .map_err(|_| ServerError::DatabaseDecodeError)
+    }
+
+    async fn remove_envelope(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        envelope_id: EnvelopeId,
+    ) -> Result<(), ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                Envelopes
+            WHERE
+                account_id = ? AND device_id = ? AND id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*envelope_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Err(ServerError::EnvelopeNotExists);
+        }
+        Ok(())
+    }
+
+    async fn get_envelope_ids(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Option<Vec<EnvelopeId>> {
+        let ids = sqlx::query(
+            r#"
+            SELECT
+                id
+            FROM
+                Envelopes
+            WHERE
+                account_id = ? AND device_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .fetch_all(&mut *self.database.connection().await.ok()?)
+        .await
+        .ok()?
+        .into_iter()
+        .map(|row| row.try_get::<Uuid, _>("id").map(EnvelopeId::from))
+        .collect::<Result<Vec<EnvelopeId>, sqlx::Error>>()
+        .ok()?;
+
+        if ids.is_empty() {
+            None
+        } else {
+            Some(ids)
+        }
+    }
+
+    async fn subscribe(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<mpsc::Receiver<Dispatch>, ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+        let (sender, receiver) = mpsc::channel(self.channel_buffer);
+
+        let mut subscribers = self.subscribers.lock().await;
+        if subscribers.contains_key(&key) {
+            return Err(ServerError::MessageSubscriberExists);
+        }
+        subscribers.insert(key, sender);
+        Ok(receiver)
+    }
+
+    async fn unsubscribe(&mut self, account_id: AccountId, device_id: DeviceId) {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        self.subscribers.lock().await.remove(&key);
+    }
+
+    async fn disconnect(&mut self, account_id: AccountId, device_id: DeviceId) {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        let sender = self.subscribers.lock().await.remove(&key);
+        if let Some(sender) = sender {
+            // the websocket may already be gone, then there is nothing to close
+            let _ = sender.send(Dispatch::Disconnect).await;
+        }
+    }
+
+    async fn notify(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        notification: Notification,
+    ) -> Result<(), ServerError> {
+        let key = DeviceAddress::new(account_id, device_id);
+
+        match self.subscribers.lock().await.get(&key) {
+            Some(sender) => sender
+                .send(Dispatch::Notification(notification))
+                .await
+                .map_err(|_| ServerError::MessageSubscriberSendErorr),
+            None => Ok(()),
+        }
+    }
+
+    async fn dispatch_envelopes(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError> {
+        let ids = match self.get_envelope_ids(account_id, device_id).await {
+            Some(ids) => ids,
+            None => return Ok(()),
+        };
+
+        let key = DeviceAddress::new(account_id, device_id);
+        match self.subscribers.lock().await.get(&key) {
+            Some(sender) => {
+                for id in ids {
+                    sender
+                        .send(Dispatch::Envelope(id))
+                        .await
+                        .map_err(|_| ServerError::MessageSubscriberSendErorr)?;
+                }
+                Ok(())
+            }
+            None => Err(ServerError::MessageSubscriberNotExists),
+        }
+    }
+
+    async fn add_pending_message(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        envelope_id: EnvelopeId,
+    ) -> Result<(), ServerError> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO PendingMessages (account_id, device_id, envelope_id)
+            VALUES (?, ?, ?)
+            ON CONFLICT (account_id, device_id, envelope_id) DO NOTHING
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*envelope_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            return Err(ServerError::MessageAlreadyPending);
+        }
+        Ok(())
+    }
+
+    async fn remove_pending_message(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        envelope_id: EnvelopeId,
+    ) -> Result<(), ServerError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                PendingMessages
+            WHERE
+                account_id = ? AND device_id = ? AND envelope_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*envelope_id.uuid())
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Err(ServerError::MessageNotPending);
+        }
+        Ok(())
+    }
+
+    async fn remove_pending_messages(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError> {
+        sqlx::query(
+            r#"
+            DELETE FROM
+                PendingMessages
+            WHERE
+                account_id = ? AND device_id = ?
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .execute(&mut *self.database.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_expired_envelopes(&mut self, created_before: u64) -> Result<u64, ServerError> {
+        sqlx::query(
+            r#"
+            DELETE FROM
+                PendingMessages
+            WHERE
+                EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        Envelopes
+                    WHERE
+                        Envelopes.account_id = PendingMessages.account_id
+                        AND Envelopes.device_id = PendingMessages.device_id
+                        AND Envelopes.id = PendingMessages.envelope_id
+                        AND Envelopes.created < ?
+                )
+            "#,
+        )
+        .bind(created_before as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?;
+
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                Envelopes
+            WHERE
+                created < ?
+            "#,
+        )
+        .bind(created_before as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+        Ok(removed)
+    }
+
+    async fn trim_envelopes(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        limit: usize,
+    ) -> Result<u64, ServerError> {
+        // everything but the last `limit` envelopes inserted for the device
+        sqlx::query(
+            r#"
+            DELETE FROM
+                PendingMessages
+            WHERE
+                account_id = ? AND device_id = ? AND envelope_id IN (
+                    SELECT
+                        id
+                    FROM
+                        Envelopes
+                    WHERE
+                        account_id = ? AND device_id = ?
+                    ORDER BY
+                        seq DESC
+                    LIMIT -1 OFFSET ?
+                )
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(limit as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?;
+
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM
+                Envelopes
+            WHERE
+                account_id = ? AND device_id = ? AND id IN (
+                    SELECT
+                        id
+                    FROM
+                        Envelopes
+                    WHERE
+                        account_id = ? AND device_id = ?
+                    ORDER BY
+                        seq DESC
+                    LIMIT -1 OFFSET ?
+                )
+            "#,
+        )
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(*account_id.uuid())
+        .bind(i64::from(*device_id))
+        .bind(limit as i64)
+        .execute(&mut *self.database.connection().await?)
+        .await?
+        .rows_affected();
+        Ok(removed)
+    }
+
+    async fn channel_buffer(&self) -> usize {
+        self.channel_buffer
+    }
+}
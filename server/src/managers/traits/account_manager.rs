@@ -5,6 +5,13 @@ use crate::{managers::entities::account::Account, ServerError};
 #[async_trait::async_trait]
 pub trait AccountManager: Send + Sync + Clone {
     async fn get_account(&self, id: AccountId) -> Result<Account, ServerError>;
+    async fn get_account_id(&self, username: &str) -> Result<AccountId, ServerError>;
+    /// Fails with [`ServerError::UsernameTaken`] if another account uses the username
     async fn add_account(&mut self, account: &Account) -> Result<(), ServerError>;
+    async fn set_username(
+        &mut self,
+        account_id: AccountId,
+        username: &str,
+    ) -> Result<(), ServerError>;
     async fn remove_account(&mut self, account_id: AccountId) -> Result<(), ServerError>;
 }
@@ -1,6 +1,9 @@
-use sam_common::address::{AccountId, DeviceId};
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::DeviceRole,
+};
 
-use crate::{managers::entities::device::Device, ServerError};
+use crate::{auth::password::Password, managers::entities::device::Device, ServerError};
 
 #[async_trait::async_trait]
 pub trait DeviceManager: Send + Sync + Clone {
@@ -14,9 +17,56 @@ pub trait DeviceManager: Send + Sync + Clone {
         account_id: AccountId,
         device: &Device,
     ) -> Result<(), ServerError>;
+    async fn rename_device(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        name: &str,
+    ) -> Result<(), ServerError>;
+    async fn set_device_role(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        role: DeviceRole,
+    ) -> Result<(), ServerError>;
+    async fn set_device_password(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        password: &Password,
+    ) -> Result<(), ServerError>;
     async fn remove_device(
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
     ) -> Result<(), ServerError>;
+    /// Stores an unused token, `expires` is unix time in milliseconds
+    async fn add_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+        expires: u64,
+    ) -> Result<(), ServerError>;
+    /// Fails like [`DeviceManager::consume_link_token`] without using the token
+    async fn check_link_token(
+        &self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError>;
+    /// Marks the token as used, fails if it was used or revoked before
+    async fn consume_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError>;
+    async fn revoke_link_token(
+        &mut self,
+        account_id: AccountId,
+        token_id: &str,
+    ) -> Result<(), ServerError>;
+    async fn remove_link_tokens(&mut self, account_id: AccountId) -> Result<(), ServerError>;
+    /// Removes tokens that expired before `expired_before` whatever their
+    /// state, returns how many were removed
+    async fn remove_expired_link_tokens(&mut self, expired_before: u64)
+        -> Result<u64, ServerError>;
 }
@@ -1,4 +1,3 @@
-use libsignal_protocol::IdentityKey;
 use sam_common::{
     address::{AccountId, DeviceId},
     api::keys::{EcPreKey, PqPreKey, SignedEcPreKey},
@@ -18,6 +17,7 @@ pub trait PreKeyManager: Send + Sync + Clone {
         account_id: AccountId,
         device_id: DeviceId,
     ) -> Result<Option<Vec<u32>>, ServerError>;
+    /// Fails with [`ServerError::KeyExists`] if the device already has a key with the id
     async fn add_pre_key(
         &mut self,
         account_id: AccountId,
@@ -43,7 +43,6 @@ pub trait SignedPreKeyManager {
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-        identity: &IdentityKey,
         key: SignedEcPreKey,
     ) -> Result<(), ServerError>;
     async fn remove_signed_pre_key(
@@ -65,11 +64,11 @@ pub trait PqPreKeyManager {
         account_id: AccountId,
         device_id: DeviceId,
     ) -> Result<Option<Vec<u32>>, ServerError>;
+    /// Fails with [`ServerError::KeyExists`] if the device already has a key with the id
     async fn add_pq_pre_key(
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-        identity: &IdentityKey,
         key: PqPreKey,
     ) -> Result<(), ServerError>;
     async fn remove_pq_pre_key(
@@ -91,7 +90,6 @@ pub trait LastResortKeyManager {
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-        identity: &IdentityKey,
         key: PqPreKey,
     ) -> Result<(), ServerError>;
     async fn remove_last_resort_key(
@@ -1,15 +1,27 @@
 use crate::ServerError;
 use sam_common::{
     address::{AccountId, DeviceId, MessageId},
-    sam_message::ServerEnvelope,
+    sam_message::{Notification, ServerEnvelope},
 };
 use tokio::sync::mpsc::Receiver;
 
 pub type EnvelopeId = MessageId;
 
+/// Pushed to the websocket of a subscribed device
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dispatch {
+    /// A stored envelope is ready to be delivered
+    Envelope(EnvelopeId),
+    Notification(Notification),
+    /// The device may no longer use the websocket, e.g. because it was unlinked
+    Disconnect,
+}
+
 #[async_trait::async_trait]
 pub trait MessageManager: Send + Sync + Clone {
     async fn channel_buffer(&self) -> usize;
+    /// Stores the envelope, its `server_timestamp` is set to the current time
+    /// unless it already has one
     async fn insert_envelope(
         &mut self,
         account_id: AccountId,
@@ -38,13 +50,23 @@ pub trait MessageManager: Send + Sync + Clone {
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
-    ) -> Result<Receiver<EnvelopeId>, ServerError>;
+    ) -> Result<Receiver<Dispatch>, ServerError>;
     async fn dispatch_envelopes(
         &mut self,
         account_id: AccountId,
         device_id: DeviceId,
     ) -> Result<(), ServerError>;
     async fn unsubscribe(&mut self, account_id: AccountId, device_id: DeviceId);
+    /// Removes the subscription of the device and tells its websocket to
+    /// close, does nothing if the device is not subscribed
+    async fn disconnect(&mut self, account_id: AccountId, device_id: DeviceId);
+    /// Pushes `notification` to the device if it is subscribed, otherwise it is dropped
+    async fn notify(
+        &self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        notification: Notification,
+    ) -> Result<(), ServerError>;
     async fn add_pending_message(
         &mut self,
         account_id: AccountId,
@@ -57,4 +79,22 @@ pub trait MessageManager: Send + Sync + Clone {
         device_id: DeviceId,
         envelope_id: EnvelopeId,
     ) -> Result<(), ServerError>;
+    /// Forgets every message that was sent to the device but not yet acknowledged
+    async fn remove_pending_messages(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+    ) -> Result<(), ServerError>;
+    /// Removes the envelopes of every device that were stored before
+    /// `created_before`, in unix milliseconds, together with their pending
+    /// entries. Returns the number of removed envelopes.
+    async fn remove_expired_envelopes(&mut self, created_before: u64) -> Result<u64, ServerError>;
+    /// Removes the first inserted envelopes of the device, and their pending
+    /// entries, until at most `limit` are left. Returns the number of removed envelopes.
+    async fn trim_envelopes(
+        &mut self,
+        account_id: AccountId,
+        device_id: DeviceId,
+        limit: usize,
+    ) -> Result<u64, ServerError>;
 }
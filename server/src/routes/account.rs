@@ -1,6 +1,8 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::State,
-    routing::{delete, post},
+    extract::{ConnectInfo, Path, State},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 
@@ -8,11 +10,13 @@ use axum_extra::{
     headers::{authorization::Basic, Authorization},
     TypedHeader,
 };
-use sam_common::api::account::{RegistrationRequest, RegistrationResponse};
+use sam_common::api::account::{
+    ChangeUsernameRequest, RegistrationRequest, RegistrationResponse, UsernameLookupResponse,
+};
 
 use crate::{
-    auth::authenticated_user::AuthenticatedUser,
-    logic::account::{create_account, delete_account},
+    auth::authenticated_user::{AuthenticatedUser, PasswordPrimaryUser},
+    logic::account::{change_username, create_account, delete_account, lookup_username},
     state::{state_type::StateType, ServerState},
     ServerError,
 };
@@ -36,38 +40,71 @@ async fn account_register_endpoint<T: StateType>(
 // Handle deletion of account
 async fn delete_account_endpoint<T: StateType>(
     State(mut state): State<ServerState<T>>,
-    auth_user: AuthenticatedUser,
+    PasswordPrimaryUser(auth_user): PasswordPrimaryUser,
 ) -> Result<(), ServerError> {
-    if *auth_user.device().id() != 1 {
-        return Err(ServerError::DeviceUnAuth);
-    }
     delete_account(&mut state, auth_user.account().id()).await
 }
 
+/// Handle resolving a username to the account that uses it
+async fn lookup_username_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(username): Path<String>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<UsernameLookupResponse>, ServerError> {
+    lookup_username(&state, auth_user.account().id(), peer.ip(), &username)
+        .await
+        .map(|account_id| Json(UsernameLookupResponse { account_id }))
+}
+
+/// Handle changing the username of the account
+async fn change_username_endpoint<T: StateType>(
+    State(mut state): State<ServerState<T>>,
+    PasswordPrimaryUser(auth_user): PasswordPrimaryUser,
+    Json(req): Json<ChangeUsernameRequest>,
+) -> Result<(), ServerError> {
+    change_username(&mut state, auth_user.account().id(), &req.username).await
+}
+
 pub fn account_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
     router
         .route("/api/v1/account", post(account_register_endpoint))
         .route("/api/v1/account", delete(delete_account_endpoint))
+        .route(
+            "/api/v1/account/username/{name}",
+            get(lookup_username_endpoint),
+        )
+        .route("/api/v1/account/username", put(change_username_endpoint))
 }
 
 #[cfg(test)]
 mod test {
-    use axum::http;
+    use std::time::Duration;
+
+    use axum::http::{self, StatusCode};
     use base64::{prelude::BASE64_STANDARD, Engine};
     use libsignal_protocol::IdentityKeyPair;
     use rand::rngs::OsRng;
-    use sam_common::api::{
-        device::DeviceActivationInfo, RegistrationRequest, RegistrationResponse,
+    use rstest::rstest;
+    use sam_common::{
+        address::AccountId,
+        api::{
+            device::DeviceActivationInfo, ChangeUsernameRequest, ErrorCode, ErrorResponse,
+            RegistrationRequest, RegistrationResponse, UsernameLookupResponse,
+        },
     };
 
     use crate::{
-        managers::traits::{
-            account_manager::AccountManager,
-            key_manager::{LastResortKeyManager, SignedPreKeyManager},
+        managers::{
+            rate_limit::RateLimit,
+            traits::{
+                account_manager::AccountManager,
+                key_manager::{LastResortKeyManager, SignedPreKeyManager},
+            },
         },
         routes::{
             account::account_routes,
-            test_utils::{create_user, test_server},
+            test_utils::{bearer, create_user, test_server},
         },
         state::ServerState,
         test_utils::{create_publish_pre_keys, pq_pre_key, signed_ec_pre_key},
@@ -98,6 +135,7 @@ mod test {
                 .try_into()
                 .expect("Can make RegistrationPreKeys"),
             },
+            unidentified_access_key: None,
         };
 
         let res = server
@@ -122,23 +160,13 @@ mod test {
 
         state
             .keys
-            .set_last_resort_key(
-                account_id,
-                device_id,
-                pair.identity_key(),
-                pq_pre_key(1, &pair),
-            )
+            .set_last_resort_key(account_id, device_id, pq_pre_key(1, &pair))
             .await
             .expect("Can set key");
 
         state
             .keys
-            .set_signed_pre_key(
-                account_id,
-                device_id,
-                pair.identity_key(),
-                signed_ec_pre_key(2, &pair, OsRng),
-            )
+            .set_signed_pre_key(account_id, device_id, signed_ec_pre_key(2, &pair, OsRng))
             .await
             .expect("Can set signed prekey");
 
@@ -154,4 +182,144 @@ mod test {
             .await;
         res.assert_status_ok();
     }
+
+    #[rstest]
+    #[case("password", StatusCode::OK, None)]
+    #[case("wrong", StatusCode::UNAUTHORIZED, Some(ErrorCode::Unauthorized))]
+    #[tokio::test]
+    async fn test_delete_api_v1_account_error_body(
+        #[case] password: &str,
+        #[case] expected_status: StatusCode,
+        #[case] expected_code: Option<ErrorCode>,
+    ) {
+        let mut state = ServerState::in_memory_test();
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+
+        let server = test_server(state.clone(), account_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.1:{password}"))
+        );
+
+        let res = server
+            .delete("/api/v1/account")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(expected_status);
+        if let Some(code) = expected_code {
+            assert_eq!(res.json::<ErrorResponse>().code, code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_api_v1_account_needs_password() {
+        let mut state = ServerState::in_memory_test();
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+
+        let server = test_server(state.clone(), account_routes);
+        let res = server
+            .delete("/api/v1/account")
+            .add_header(
+                http::header::AUTHORIZATION,
+                bearer(&state, account_id, 1.into()).await,
+            )
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+        assert!(state.accounts.get_account(account_id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_account_is_unauthorized() {
+        let state = ServerState::in_memory_test();
+        let server = test_server(state, account_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}.1:password", AccountId::generate()))
+        );
+
+        let res = server
+            .delete("/api/v1/account")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(res.json::<ErrorResponse>().code, ErrorCode::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn test_get_api_v1_account_username_name() {
+        let mut state = ServerState::in_memory_test()
+            .with_username_lookup_limit(RateLimit::new(1, Duration::from_secs(60)));
+        let (_, alice_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        let (_, bob_id, _) = create_user(&mut state, "bob", "laptop", "password", OsRng).await;
+
+        let server = test_server(state, account_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{bob_id}.1:password"))
+        );
+
+        let res = server
+            .get("/api/v1/account/username/alice")
+            .add_header(http::header::AUTHORIZATION, basic.clone())
+            .await;
+        res.assert_status_ok();
+        assert!(res.json::<UsernameLookupResponse>().account_id == alice_id);
+
+        let res = server
+            .get("/api/v1/account/username/alice")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.header(http::header::RETRY_AFTER) == "60");
+        assert_eq!(res.json::<ErrorResponse>().code, ErrorCode::RateLimited);
+
+        // the test server serves every request from the same peer
+        let res = server
+            .get("/api/v1/account/username/bob")
+            .add_header(
+                http::header::AUTHORIZATION,
+                format!(
+                    "Basic {}",
+                    BASE64_STANDARD.encode(format!("{alice_id}.1:password"))
+                ),
+            )
+            .await;
+        res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[rstest]
+    #[case("alicia", StatusCode::OK)]
+    #[case("bob", StatusCode::CONFLICT)]
+    #[case("", StatusCode::BAD_REQUEST)]
+    #[tokio::test]
+    async fn test_put_api_v1_account_username(
+        #[case] username: &str,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut state = ServerState::in_memory_test();
+        let (_, alice_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        create_user(&mut state, "bob", "laptop", "password", OsRng).await;
+
+        let server = test_server(state.clone(), account_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{alice_id}.1:password"))
+        );
+
+        let res = server
+            .put("/api/v1/account/username")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .json(&ChangeUsernameRequest {
+                username: username.to_string(),
+            })
+            .await;
+        res.assert_status(expected_status);
+
+        let account = state
+            .accounts
+            .get_account(alice_id)
+            .await
+            .expect("Alice still exists");
+        assert!((account.username() == username) == (expected_status == StatusCode::OK));
+    }
 }
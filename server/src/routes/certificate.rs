@@ -0,0 +1,83 @@
+use axum::{extract::State, routing::get, Json, Router};
+use sam_common::api::certificate::SenderCertificateResponse;
+
+use crate::{
+    auth::authenticated_user::AuthenticatedUser,
+    logic::certificate::create_sender_certificate,
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+/// Returns a sender certificate for sealed sender messages from the authenticated device
+async fn sender_certificate_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<SenderCertificateResponse>, ServerError> {
+    create_sender_certificate(&state, auth_user.account().id(), auth_user.device().id())
+        .await
+        .map(Json)
+}
+
+pub fn certificate_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
+    router.route(
+        "/api/v1/certificate/delivery",
+        get(sender_certificate_endpoint),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http;
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use libsignal_protocol::{SenderCertificate, Timestamp};
+    use rand::rngs::OsRng;
+    use sam_common::{api::certificate::SenderCertificateResponse, time_now_millis};
+
+    use crate::{
+        routes::{
+            certificate::certificate_routes,
+            test_utils::{create_user, test_server},
+        },
+        state::ServerState,
+    };
+
+    #[tokio::test]
+    async fn test_get_api_v1_certificate_delivery() {
+        let mut state = ServerState::in_memory_test();
+        let (pair, account_id, _) = create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+        let trust_root = *state.certificates.trust_root();
+
+        let server = test_server(state, certificate_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}.1:{}", account_id, "bob"))
+        );
+
+        let res = server
+            .get("/api/v1/certificate/delivery")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status_ok();
+
+        let response = res.json::<SenderCertificateResponse>();
+        let certificate = SenderCertificate::deserialize(&response.certificate)
+            .expect("Can decode sender certificate");
+        assert!(certificate
+            .validate(
+                &trust_root,
+                Timestamp::from_epoch_millis(time_now_millis() as u64)
+            )
+            .expect("Can validate certificate"));
+        assert!(certificate.sender_uuid().expect("Has sender") == account_id.to_string());
+        assert!(certificate.key().expect("Has key") == *pair.public_key());
+    }
+
+    #[tokio::test]
+    async fn test_get_api_v1_certificate_delivery_requires_auth() {
+        let state = ServerState::in_memory_test();
+        let server = test_server(state, certificate_routes);
+
+        let res = server.get("/api/v1/certificate/delivery").await;
+        res.assert_status_not_ok();
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
     extract::{Path, State},
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_extra::{
@@ -10,29 +10,41 @@ use axum_extra::{
 
 use sam_common::{
     address::DeviceId,
-    api::device::{LinkDeviceRequest, LinkDeviceResponse, LinkDeviceToken},
+    api::device::{
+        ChangePasswordRequest, DeviceInfoList, LinkDeviceRequest, LinkDeviceResponse,
+        LinkDeviceToken, RenameDeviceRequest,
+    },
 };
 
 use crate::{
-    auth::authenticated_user::AuthenticatedUser,
-    logic::device::{create_device_token, link_device, unlink_device},
+    auth::authenticated_user::{AuthenticatedUser, PasswordPrimaryUser, PasswordUser},
+    logic::device::{
+        change_device_password, create_device_token, link_device, list_devices, rename_device,
+        reset_device_password, revoke_device_token, transfer_primary, unlink_device,
+    },
     state::{state_type::StateType, ServerState},
     ServerError,
 };
 
 /// Handle device provisioning
 async fn device_provision_token_endpoint<T: StateType>(
-    State(state): State<ServerState<T>>,
-    auth_user: AuthenticatedUser,
+    State(mut state): State<ServerState<T>>,
+    PasswordPrimaryUser(auth_user): PasswordPrimaryUser,
 ) -> Result<Json<LinkDeviceToken>, ServerError> {
-    if auth_user.device().id() != 1.into() {
-        return Err(ServerError::DeviceProvisionUnAuth);
-    }
-    create_device_token(&state, auth_user.account().id())
+    create_device_token(&mut state, auth_user.account().id())
         .await
         .map(Json)
 }
 
+/// Handle revoking an unused provisioning token
+async fn revoke_device_token_endpoint<T: StateType>(
+    State(mut state): State<ServerState<T>>,
+    Path(token_id): Path<String>,
+    PasswordPrimaryUser(auth_user): PasswordPrimaryUser,
+) -> Result<(), ServerError> {
+    revoke_device_token(&mut state, auth_user.account().id(), &token_id).await
+}
+
 /// Handle device linking
 async fn link_device_endpoint<T: StateType>(
     State(mut state): State<ServerState<T>>,
@@ -44,26 +56,112 @@ async fn link_device_endpoint<T: StateType>(
         .map(Json)
 }
 
-/// Handle device linking
+/// Lists the devices linked to the account
+async fn list_devices_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<DeviceInfoList>, ServerError> {
+    list_devices(&state, auth_user.account().id())
+        .await
+        .map(Json)
+}
+
+/// Handle renaming a device, only the primary device may rename other devices
+async fn rename_device_endpoint<T: StateType>(
+    State(mut state): State<ServerState<T>>,
+    Path(device_id): Path<DeviceId>,
+    auth_user: AuthenticatedUser,
+    Json(req): Json<RenameDeviceRequest>,
+) -> Result<(), ServerError> {
+    if !auth_user.device().is_primary() && auth_user.device().id() != device_id {
+        return Err(ServerError::DeviceUnAuth);
+    }
+    rename_device(&mut state, auth_user.account().id(), device_id, req.name).await
+}
+
+/// Handle unlinking a device, linked devices may only unlink themselves
 async fn delete_device_endpoint<T: StateType>(
     State(mut state): State<ServerState<T>>,
     Path(device_id): Path<DeviceId>,
     auth_user: AuthenticatedUser,
 ) -> Result<(), ServerError> {
-    if *auth_user.device().id() == 1 && auth_user.device().id() != device_id {
+    if !auth_user.device().is_primary() && auth_user.device().id() != device_id {
         return Err(ServerError::DeviceUnAuth);
     }
     unlink_device(&mut state, auth_user.account().id(), device_id).await
 }
 
+/// Handle the primary device handing its role over to another device
+async fn transfer_primary_endpoint<T: StateType>(
+    State(mut state): State<ServerState<T>>,
+    Path(device_id): Path<DeviceId>,
+    PasswordPrimaryUser(auth_user): PasswordPrimaryUser,
+) -> Result<(), ServerError> {
+    transfer_primary(
+        &mut state,
+        auth_user.account().id(),
+        auth_user.device().id(),
+        device_id,
+    )
+    .await
+}
+
+/// Handle a device changing its own password, which takes the current
+/// password rather than a session token
+async fn change_password_endpoint<T: StateType>(
+    State(mut state): State<ServerState<T>>,
+    PasswordUser(auth_user): PasswordUser,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<(), ServerError> {
+    change_device_password(
+        &mut state,
+        auth_user.account().id(),
+        auth_user.device().id(),
+        req.password,
+    )
+    .await
+}
+
+/// Handle the primary device resetting the password of a linked device, its
+/// own password is changed through [`change_password_endpoint`] instead
+async fn reset_password_endpoint<T: StateType>(
+    State(mut state): State<ServerState<T>>,
+    Path(device_id): Path<DeviceId>,
+    PasswordPrimaryUser(auth_user): PasswordPrimaryUser,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<(), ServerError> {
+    if auth_user.device().id() == device_id {
+        return Err(ServerError::DeviceUnAuth);
+    }
+    reset_device_password(
+        &mut state,
+        auth_user.account().id(),
+        device_id,
+        req.password,
+    )
+    .await
+}
+
 pub fn device_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
     router
+        .route("/api/v1/devices", get(list_devices_endpoint))
         .route(
             "/api/v1/devices/provision",
             get(device_provision_token_endpoint),
         )
+        .route(
+            "/api/v1/devices/provision/{id}",
+            delete(revoke_device_token_endpoint),
+        )
         .route("/api/v1/devices/link", post(link_device_endpoint))
         .route("/api/v1/device/{id}", delete(delete_device_endpoint))
+        .route("/api/v1/device/password", put(change_password_endpoint))
+        .route("/api/v1/device/{id}/name", put(rename_device_endpoint))
+        .route(
+            "/api/v1/device/{id}/primary",
+            put(transfer_primary_endpoint),
+        )
+        .route("/api/v1/device/{id}/password", put(reset_password_endpoint))
 }
 
 #[cfg(test)]
@@ -74,7 +172,13 @@ mod test {
     use base64::{prelude::BASE64_STANDARD, Engine};
     use rand::rngs::OsRng;
     use rstest::rstest;
-    use sam_common::api::{device::DeviceActivationInfo, LinkDeviceRequest, LinkDeviceToken};
+    use sam_common::api::{
+        device::{
+            ChangePasswordRequest, DeviceActivationInfo, DeviceInfoList, DeviceRole,
+            RenameDeviceRequest,
+        },
+        ErrorCode, ErrorResponse, LinkDeviceRequest, LinkDeviceToken,
+    };
 
     use crate::{
         auth::password::Password,
@@ -84,7 +188,7 @@ mod test {
         },
         routes::{
             device::device_routes,
-            test_utils::{create_user, test_server},
+            test_utils::{bearer, create_user, test_server},
         },
         state::ServerState,
         test_utils::create_publish_pre_keys,
@@ -111,7 +215,7 @@ mod test {
 
     #[rstest]
     #[case(600, StatusCode::OK, true)]
-    #[case(0, StatusCode::FORBIDDEN, false)]
+    #[case(0, StatusCode::GONE, false)]
     #[tokio::test]
     async fn test_get_api_v1_devices_link(
         #[case] expire_time: u64,
@@ -165,6 +269,84 @@ mod test {
         assert!(state.devices.get_device(account_id, 2.into()).await.is_ok() == expects_ok_device);
     }
 
+    #[rstest]
+    #[case(1, StatusCode::OK, StatusCode::GONE)]
+    #[case(2, StatusCode::FORBIDDEN, StatusCode::OK)]
+    #[tokio::test]
+    async fn test_delete_api_v1_devices_provision_id(
+        #[case] revoking_device: u32,
+        #[case] expected_status: StatusCode,
+        #[case] expected_link_status: StatusCode,
+    ) {
+        let mut state = ServerState::in_memory_test();
+
+        let (pair, account_id, _) =
+            create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        state
+            .devices
+            .add_device(
+                account_id,
+                &Device::builder()
+                    .creation(0)
+                    .id(2.into())
+                    .registration_id(2.into())
+                    .name("microwave".to_string())
+                    .password(
+                        Password::generate("password".to_string())
+                            .expect("Password can be generated"),
+                    )
+                    .role(DeviceRole::Linked)
+                    .build(),
+            )
+            .await
+            .expect("Can Add Device");
+
+        let server = test_server(state.clone(), device_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.1:password"))
+        );
+        let token = server
+            .get("/api/v1/devices/provision")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await
+            .json::<LinkDeviceToken>();
+
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.{revoking_device}:password"))
+        );
+        let res = server
+            .delete(&format!("/api/v1/devices/provision/{}", token.id()))
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(expected_status);
+
+        let req = LinkDeviceRequest {
+            token,
+            device_activation: DeviceActivationInfo {
+                name: "car".to_string(),
+                registration_id: 3.into(),
+                key_bundle: create_publish_pre_keys(None, Some(66), None, Some(33), &pair, OsRng)
+                    .try_into()
+                    .expect("Can make RegistrationPreKeys"),
+            },
+        };
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}:otherpass"))
+        );
+        let res = server
+            .post("/api/v1/devices/link")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .json(&req)
+            .await;
+        res.assert_status(expected_link_status);
+        if expected_link_status == StatusCode::GONE {
+            assert!(res.json::<ErrorResponse>().code == ErrorCode::LinkTokenRevoked);
+        }
+    }
+
     #[tokio::test]
     async fn test_delete_api_v1_device_id() {
         let mut state = ServerState::in_memory_test();
@@ -183,6 +365,7 @@ mod test {
                         Password::generate("otherpass".to_string())
                             .expect("Password can be generated"),
                     )
+                    .role(DeviceRole::Linked)
                     .build(),
             )
             .await
@@ -200,4 +383,249 @@ mod test {
             .await;
         res.assert_status_ok();
     }
+
+    #[tokio::test]
+    async fn test_get_api_v1_devices() {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+
+        let server = test_server(state, device_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.1:password"))
+        );
+
+        let res = server
+            .get("/api/v1/devices")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status_ok();
+
+        let devices = res.json::<DeviceInfoList>().devices;
+        assert!(devices.len() == 1);
+        assert!(devices[0].id == 1.into());
+        assert!(devices[0].name == "phone");
+    }
+
+    #[rstest]
+    #[case(2, 2, StatusCode::OK)]
+    #[case(1, 2, StatusCode::OK)]
+    #[case(2, 1, StatusCode::FORBIDDEN)]
+    #[tokio::test]
+    async fn test_put_api_v1_device_id_name(
+        #[case] renaming_device: u32,
+        #[case] renamed_device: u32,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        state
+            .devices
+            .add_device(
+                account_id,
+                &Device::builder()
+                    .creation(0)
+                    .id(2.into())
+                    .registration_id(1.into())
+                    .name("microwave".to_string())
+                    .password(
+                        Password::generate("password".to_string())
+                            .expect("Password can be generated"),
+                    )
+                    .role(DeviceRole::Linked)
+                    .build(),
+            )
+            .await
+            .expect("Can Add Device");
+
+        let server = test_server(state.clone(), device_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.{renaming_device}:password"))
+        );
+
+        let res = server
+            .put(&format!("/api/v1/device/{renamed_device}/name"))
+            .add_header(http::header::AUTHORIZATION, basic)
+            .json(&RenameDeviceRequest {
+                name: "oven".to_string(),
+            })
+            .await;
+        res.assert_status(expected_status);
+
+        let renamed = state
+            .devices
+            .get_device(account_id, renamed_device.into())
+            .await
+            .expect("Device still exists");
+        assert!((renamed.name() == "oven") == (expected_status == StatusCode::OK));
+    }
+
+    #[rstest]
+    #[case(1, StatusCode::OK)]
+    #[case(2, StatusCode::FORBIDDEN)]
+    #[tokio::test]
+    async fn test_put_api_v1_device_id_primary(
+        #[case] transferring_device: u32,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        state
+            .devices
+            .add_device(
+                account_id,
+                &Device::builder()
+                    .creation(0)
+                    .id(2.into())
+                    .registration_id(2.into())
+                    .name("microwave".to_string())
+                    .password(
+                        Password::generate("password".to_string())
+                            .expect("Password can be generated"),
+                    )
+                    .role(DeviceRole::Linked)
+                    .build(),
+            )
+            .await
+            .expect("Can Add Device");
+
+        let server = test_server(state.clone(), device_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.{transferring_device}:password"))
+        );
+
+        let res = server
+            .put("/api/v1/device/2/primary")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(expected_status);
+
+        let microwave = state
+            .devices
+            .get_device(account_id, 2.into())
+            .await
+            .expect("Device still exists");
+        assert!(microwave.is_primary() == (expected_status == StatusCode::OK));
+    }
+
+    #[tokio::test]
+    async fn test_put_api_v1_device_password() {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+
+        let server = test_server(state.clone(), device_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.1:password"))
+        );
+
+        let res = server
+            .put("/api/v1/device/password")
+            .add_header(http::header::AUTHORIZATION, basic.clone())
+            .json(&ChangePasswordRequest {
+                password: "hunter2".to_string(),
+            })
+            .await;
+        res.assert_status_ok();
+
+        let res = server
+            .get("/api/v1/devices")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+
+        let phone = state
+            .devices
+            .get_device(account_id, 1.into())
+            .await
+            .expect("Device still exists");
+        phone
+            .password()
+            .verify("hunter2".to_string())
+            .expect("The new password is set");
+    }
+
+    #[rstest]
+    #[case(1, 2, StatusCode::OK)]
+    #[case(1, 1, StatusCode::FORBIDDEN)]
+    #[case(2, 1, StatusCode::FORBIDDEN)]
+    #[tokio::test]
+    async fn test_put_api_v1_device_id_password(
+        #[case] resetting_device: u32,
+        #[case] reset_device: u32,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        state
+            .devices
+            .add_device(
+                account_id,
+                &Device::builder()
+                    .creation(0)
+                    .id(2.into())
+                    .registration_id(2.into())
+                    .name("microwave".to_string())
+                    .password(
+                        Password::generate("password".to_string())
+                            .expect("Password can be generated"),
+                    )
+                    .role(DeviceRole::Linked)
+                    .build(),
+            )
+            .await
+            .expect("Can Add Device");
+
+        let server = test_server(state.clone(), device_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.{resetting_device}:password"))
+        );
+
+        let res = server
+            .put(&format!("/api/v1/device/{reset_device}/password"))
+            .add_header(http::header::AUTHORIZATION, basic)
+            .json(&ChangePasswordRequest {
+                password: "hunter2".to_string(),
+            })
+            .await;
+        res.assert_status(expected_status);
+
+        let device = state
+            .devices
+            .get_device(account_id, reset_device.into())
+            .await
+            .expect("Device still exists");
+        assert!(
+            device.password().verify("hunter2".to_string()).is_ok()
+                == (expected_status == StatusCode::OK)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_api_v1_device_id_password_needs_password() {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        let server = test_server(state.clone(), device_routes);
+
+        let res = server
+            .put("/api/v1/device/2/password")
+            .add_header(
+                http::header::AUTHORIZATION,
+                bearer(&state, account_id, 1.into()).await,
+            )
+            .json(&ChangePasswordRequest {
+                password: "hunter2".to_string(),
+            })
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+    }
 }
@@ -4,13 +4,13 @@ use axum::{
     Json, Router,
 };
 use sam_common::{
-    address::AccountId,
-    api::keys::{PreKeyBundles, PublishPreKeys},
+    address::{AccountId, DeviceId},
+    api::keys::{PreKeyBundles, PreKeyCount, PublishPreKeys},
 };
 
 use crate::{
     auth::authenticated_user::AuthenticatedUser,
-    logic::keys::{get_keybundles, publish_keybundle},
+    logic::keys::{get_device_keybundle, get_keybundles, get_pre_key_count, publish_keybundle},
     state::{state_type::StateType, ServerState},
     ServerError,
 };
@@ -24,6 +24,17 @@ pub async fn keys_bundles_endpoint<T: StateType>(
     get_keybundles(&mut state, account_id).await.map(Json)
 }
 
+/// Returns the key bundle of a single device of a user
+pub async fn device_keys_bundle_endpoint<T: StateType>(
+    Path((account_id, device_id)): Path<(AccountId, DeviceId)>,
+    _auth_user: AuthenticatedUser,
+    State(mut state): State<ServerState<T>>,
+) -> Result<Json<PreKeyBundles>, ServerError> {
+    get_device_keybundle(&mut state, account_id, device_id)
+        .await
+        .map(Json)
+}
+
 /// Handle publish of new key bundles
 async fn publish_keys_endpoint<T: StateType>(
     State(mut state): State<ServerState<T>>,
@@ -39,9 +50,24 @@ async fn publish_keys_endpoint<T: StateType>(
     .await
 }
 
+/// Returns how many one-time keys are left for the authenticated device
+async fn pre_key_count_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    auth_user: AuthenticatedUser,
+) -> Result<Json<PreKeyCount>, ServerError> {
+    get_pre_key_count(&state, auth_user.account().id(), auth_user.device().id())
+        .await
+        .map(Json)
+}
+
 pub fn key_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
     router
+        .route("/api/v1/keys/count", get(pre_key_count_endpoint))
         .route("/api/v1/keys/{account_id}", get(keys_bundles_endpoint))
+        .route(
+            "/api/v1/keys/{account_id}/{device_id}",
+            get(device_keys_bundle_endpoint),
+        )
         .route("/api/v1/keys", put(publish_keys_endpoint))
 }
 
@@ -59,7 +85,7 @@ mod test {
     use axum::http;
     use base64::{prelude::BASE64_STANDARD, Engine};
     use rand::rngs::OsRng;
-    use sam_common::api::{keys::PreKeyBundles, PreKeyBundle};
+    use sam_common::api::{keys::PreKeyBundles, PreKeyBundle, PreKeyCount};
 
     #[tokio::test]
     async fn test_post_api_v1_keys() {
@@ -136,4 +162,96 @@ mod test {
         res.assert_status_ok();
         res.assert_json(&expected);
     }
+
+    #[tokio::test]
+    async fn test_get_api_v1_keys_account_device() {
+        let mut state = ServerState::in_memory_test();
+        let (pair, account_id, device_id) =
+            create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+
+        let keys = create_publish_pre_keys(
+            Some(vec![1]),
+            Some(3),
+            Some(vec![4]),
+            Some(33),
+            &pair,
+            OsRng,
+        );
+        add_keybundle(
+            &mut state,
+            pair.identity_key(),
+            account_id,
+            device_id,
+            keys.clone(),
+        )
+        .await
+        .expect("Can add keys");
+
+        let server = test_server(state, key_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}.1:{}", account_id, "bob"))
+        );
+
+        let res = server
+            .get(&format!("/api/v1/keys/{account_id}/1"))
+            .add_header(http::header::AUTHORIZATION, basic.clone())
+            .await;
+
+        let expected = PreKeyBundles {
+            identity_key: *pair.identity_key(),
+            bundles: vec![PreKeyBundle {
+                device_id: 1,
+                registration_id: 1,
+                pre_key: keys.pre_keys.unwrap().first().cloned(),
+                pq_pre_key: keys.pq_pre_keys.unwrap().first().cloned().unwrap(),
+                signed_pre_key: keys.signed_pre_key.unwrap(),
+            }],
+        };
+
+        res.assert_status_ok();
+        res.assert_json(&expected);
+
+        let res = server
+            .get(&format!("/api/v1/keys/{account_id}/2"))
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+        res.assert_status(http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_api_v1_keys_count() {
+        let mut state = ServerState::in_memory_test();
+        let (pair, account_id, device_id) =
+            create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+
+        let keys = create_publish_pre_keys(
+            Some(vec![1, 2, 3]),
+            Some(3),
+            Some(vec![4]),
+            Some(33),
+            &pair,
+            OsRng,
+        );
+        add_keybundle(&mut state, pair.identity_key(), account_id, device_id, keys)
+            .await
+            .expect("Can add keys");
+
+        let server = test_server(state, key_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}.1:{}", account_id, "bob"))
+        );
+
+        let res = server
+            .get("/api/v1/keys/count")
+            .add_header(http::header::AUTHORIZATION, basic)
+            .await;
+
+        res.assert_status_ok();
+        res.assert_json(&PreKeyCount {
+            pre_keys: 3,
+            pq_pre_keys: 1,
+        });
+    }
 }
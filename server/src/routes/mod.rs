@@ -1,10 +1,13 @@
 mod account;
+mod certificate;
 mod device;
 mod keys;
+mod provisioning;
 mod router;
+mod session;
 mod websocket;
 
 #[cfg(test)]
-mod test_utils;
+pub(crate) mod test_utils;
 
 pub use router::router;
@@ -0,0 +1,130 @@
+use axum::{
+    extract::{Path, State, WebSocketUpgrade},
+    response::IntoResponse,
+    routing::{get, put},
+    Json, Router,
+};
+use sam_common::{address::ProvisioningId, api::ProvisionEnvelope};
+
+use crate::{
+    auth::authenticated_user::PasswordPrimaryUser,
+    logic::provisioning::{init_provisioning_websocket, send_provisioning_message},
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+/// A new device waits here for its provisioning message, it has no
+/// credentials yet
+async fn provisioning_websocket_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| init_provisioning_websocket(state, socket))
+}
+
+/// Handle the primary device sending a provisioning message to a new device
+async fn provisioning_message_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    Path(address): Path<ProvisioningId>,
+    _: PasswordPrimaryUser,
+    Json(envelope): Json<ProvisionEnvelope>,
+) -> Result<(), ServerError> {
+    send_provisioning_message(&state, address, envelope).await
+}
+
+pub fn provisioning_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
+    router
+        .route("/api/v1/provisioning", get(provisioning_websocket_endpoint))
+        .route(
+            "/api/v1/provisioning/{address}",
+            put(provisioning_message_endpoint),
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::http::{self, StatusCode};
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use futures_util::StreamExt;
+    use rand::rngs::OsRng;
+    use sam_common::api::{ProvisionEnvelope, ProvisioningAddress};
+    use tokio_tungstenite::{
+        connect_async,
+        tungstenite::{protocol::frame::coding::CloseCode, Message},
+    };
+
+    use crate::{
+        routes::{
+            provisioning::provisioning_routes,
+            test_utils::{create_user, start_websocket_server, test_server},
+        },
+        state::ServerState,
+    };
+
+    #[tokio::test]
+    async fn test_provisioning_message_is_relayed() {
+        let mut state = ServerState::in_memory_test();
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+
+        let address = "127.0.0.1:8007".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), provisioning_routes, address.clone());
+        started.await.expect("Server can start");
+
+        let (mut new_device, _) = connect_async(format!("ws://{address}/api/v1/provisioning"))
+            .await
+            .expect("New device can connect without credentials");
+        let provisioning_address = match new_device.next().await {
+            Some(Ok(Message::Text(text))) => {
+                serde_json::from_str::<ProvisioningAddress>(&text)
+                    .expect("Can decode provisioning address")
+                    .address
+            }
+            _ => panic!("New device did not receive its address"),
+        };
+
+        let envelope = ProvisionEnvelope {
+            public_key: vec![1; 33],
+            body: b"encrypted".to_vec(),
+        };
+        let server = test_server(state, provisioning_routes);
+        let basic = format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{account_id}.1:password"))
+        );
+        server
+            .put(&format!("/api/v1/provisioning/{provisioning_address}"))
+            .add_header(http::header::AUTHORIZATION, basic.clone())
+            .json(&envelope)
+            .await
+            .assert_status_ok();
+
+        let received = tokio::time::timeout(Duration::from_millis(300), new_device.next()).await;
+        let closed = tokio::time::timeout(Duration::from_millis(300), new_device.next()).await;
+
+        // every address is good for a single message
+        let res = server
+            .put(&format!("/api/v1/provisioning/{provisioning_address}"))
+            .add_header(http::header::AUTHORIZATION, basic)
+            .json(&envelope)
+            .await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        match received {
+            Ok(Some(Ok(Message::Text(text)))) => {
+                assert!(serde_json::from_str::<ProvisionEnvelope>(&text)
+                    .is_ok_and(|received| received == envelope))
+            }
+            _ => panic!("New device did not receive the envelope"),
+        }
+        assert!(matches!(
+            closed,
+            Ok(Some(Ok(Message::Close(Some(frame))))) if frame.code == CloseCode::Normal
+        ));
+        res.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -3,7 +3,9 @@ use axum::Router;
 use crate::state::{state_type::StateType, ServerState};
 
 use super::{
-    account::account_routes, device::device_routes, keys::key_routes, websocket::websocket_routes,
+    account::account_routes, certificate::certificate_routes, device::device_routes,
+    keys::key_routes, provisioning::provisioning_routes, session::session_routes,
+    websocket::websocket_routes,
 };
 
 type SAMRouter<T> = Router<ServerState<T>>;
@@ -35,6 +37,9 @@ pub fn router<T: StateType>() -> Router<ServerState<T>> {
         .add_routes(account_routes)
         .add_routes(key_routes)
         .add_routes(device_routes)
+        .add_routes(certificate_routes)
         .add_routes(websocket_routes)
+        .add_routes(provisioning_routes)
+        .add_routes(session_routes)
         .build()
 }
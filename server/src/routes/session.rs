@@ -0,0 +1,120 @@
+use axum::{extract::State, routing::post, Json, Router};
+use sam_common::api::SessionToken;
+
+use crate::{
+    auth::authenticated_user::PasswordUser,
+    logic::session::create_session,
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+/// Handle a device trading its password for a session token
+async fn create_session_endpoint<T: StateType>(
+    State(state): State<ServerState<T>>,
+    PasswordUser(auth_user): PasswordUser,
+) -> Result<Json<SessionToken>, ServerError> {
+    create_session(&state, auth_user.account().id(), auth_user.device())
+        .await
+        .map(Json)
+}
+
+pub fn session_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
+    router.route("/api/v1/session", post(create_session_endpoint))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        http::{self, StatusCode},
+        Router,
+    };
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use rand::rngs::OsRng;
+    use sam_common::api::{DeviceRole, SessionToken};
+
+    use crate::{
+        auth::password::Password,
+        managers::{entities::device::Device, traits::device_manager::DeviceManager},
+        routes::{
+            device::device_routes,
+            session::session_routes,
+            test_utils::{create_user, test_server},
+        },
+        state::{state_type::StateType, ServerState},
+    };
+
+    fn routes<T: StateType>(router: Router<ServerState<T>>) -> Router<ServerState<T>> {
+        device_routes(session_routes(router))
+    }
+
+    #[tokio::test]
+    async fn test_post_api_v1_session() {
+        let mut state = ServerState::in_memory_test();
+
+        let (_, account_id, _) = create_user(&mut state, "alice", "phone", "password", OsRng).await;
+        state
+            .devices
+            .add_device(
+                account_id,
+                &Device::builder()
+                    .creation(0)
+                    .id(2.into())
+                    .registration_id(2.into())
+                    .name("microwave".to_string())
+                    .password(
+                        Password::generate("password".to_string())
+                            .expect("Password can be generated"),
+                    )
+                    .role(DeviceRole::Linked)
+                    .build(),
+            )
+            .await
+            .expect("Can Add Device");
+
+        let server = test_server(state.clone(), routes);
+        let basic = |device: u32| {
+            format!(
+                "Basic {}",
+                BASE64_STANDARD.encode(format!("{account_id}.{device}:password"))
+            )
+        };
+
+        let res = server
+            .post("/api/v1/session")
+            .add_header(http::header::AUTHORIZATION, basic(2))
+            .await;
+        res.assert_status_ok();
+        let bearer = format!("Bearer {}", res.json::<SessionToken>().token);
+
+        let res = server
+            .get("/api/v1/devices")
+            .add_header(http::header::AUTHORIZATION, bearer.clone())
+            .await;
+        res.assert_status_ok();
+
+        // a session cannot be extended without the password
+        let res = server
+            .post("/api/v1/session")
+            .add_header(http::header::AUTHORIZATION, bearer.clone())
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+
+        let res = server
+            .get("/api/v1/devices")
+            .add_header(http::header::AUTHORIZATION, "Bearer forged")
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+
+        let res = server
+            .delete("/api/v1/device/2")
+            .add_header(http::header::AUTHORIZATION, basic(1))
+            .await;
+        res.assert_status_ok();
+
+        let res = server
+            .get("/api/v1/devices")
+            .add_header(http::header::AUTHORIZATION, bearer)
+            .await;
+        res.assert_status(StatusCode::UNAUTHORIZED);
+    }
+}
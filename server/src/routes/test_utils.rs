@@ -1,12 +1,23 @@
-use axum::Router;
+use std::{io::Error, net::SocketAddr};
+
+use axum::{extract::connect_info::MockConnectInfo, Router};
+use axum_server::Handle;
 use axum_test::TestServer;
 use libsignal_protocol::IdentityKeyPair;
 
 use rand::rngs::OsRng;
-use sam_common::address::{AccountId, DeviceId};
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::DeviceRole,
+};
+use tokio::{
+    sync::oneshot::{self, Receiver},
+    task::JoinHandle,
+};
 
 use crate::{
     auth::password::Password,
+    logic::session::create_session,
     managers::{
         entities::{account::Account, device::Device},
         traits::{account_manager::AccountManager, device_manager::DeviceManager},
@@ -14,12 +25,35 @@ use crate::{
     state::{state_type::StateType, ServerState},
 };
 
+/// Every request comes from the same peer address
 pub fn test_server<T: StateType>(
     state: ServerState<T>,
     routes: fn(Router<ServerState<T>>) -> Router<ServerState<T>>,
 ) -> TestServer {
-    TestServer::new(routes(Router::new()).with_state(state).into_make_service())
-        .expect("Can make test server")
+    let app = routes(Router::new())
+        .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))))
+        .with_state(state);
+    TestServer::new(app.into_make_service()).expect("Can make test server")
+}
+
+/// Serves `routes` on a real socket, for tests that need a websocket
+pub fn start_websocket_server<T: StateType>(
+    state: ServerState<T>,
+    routes: fn(Router<ServerState<T>>) -> Router<ServerState<T>>,
+    address: String,
+) -> (JoinHandle<Result<(), Error>>, Handle, Receiver<()>) {
+    let app = routes(Router::new()).with_state(state);
+    let (tx, started_rx) = oneshot::channel::<()>();
+    let axum = Handle::new();
+    let axum_handle = axum.clone();
+    let thread = tokio::spawn(async move {
+        let server = axum_server::bind(address.parse().expect("Can make socket addr from str"))
+            .handle(axum_handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        tx.send(()).expect("Can oneshot");
+        server.await
+    });
+    (thread, axum, started_rx)
 }
 
 pub async fn create_user<T: StateType>(
@@ -27,6 +61,17 @@ pub async fn create_user<T: StateType>(
     username: &str,
     device_name: &str,
     password: &str,
+    rng: OsRng,
+) -> (IdentityKeyPair, AccountId, DeviceId) {
+    create_user_with_access_key(state, username, device_name, password, None, rng).await
+}
+
+pub async fn create_user_with_access_key<T: StateType>(
+    state: &mut ServerState<T>,
+    username: &str,
+    device_name: &str,
+    password: &str,
+    unidentified_access_key: Option<&[u8]>,
     mut rng: OsRng,
 ) -> (IdentityKeyPair, AccountId, DeviceId) {
     let id_pair = IdentityKeyPair::generate(&mut rng);
@@ -34,6 +79,7 @@ pub async fn create_user<T: StateType>(
         .id(AccountId::generate())
         .identity(*id_pair.identity_key())
         .username(username.to_string())
+        .maybe_unidentified_access_key(unidentified_access_key.map(Box::from))
         .build();
     let device = Device::builder()
         .creation(0)
@@ -41,6 +87,7 @@ pub async fn create_user<T: StateType>(
         .registration_id(1.into())
         .name(device_name.to_string())
         .password(Password::generate(password.to_string()).expect("Password can be generated"))
+        .role(DeviceRole::Primary)
         .build();
     state
         .accounts
@@ -54,3 +101,20 @@ pub async fn create_user<T: StateType>(
         .expect("Device can be added");
     (id_pair, account.id(), device.id())
 }
+
+/// Authorization header with a session token of the device
+pub async fn bearer<T: StateType>(
+    state: &ServerState<T>,
+    account_id: AccountId,
+    device_id: DeviceId,
+) -> String {
+    let device = state
+        .devices
+        .get_device(account_id, device_id)
+        .await
+        .expect("Device exists");
+    let session = create_session(state, account_id, &device)
+        .await
+        .expect("Can create session");
+    format!("Bearer {}", session.token)
+}
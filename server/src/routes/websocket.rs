@@ -7,7 +7,7 @@ use axum::{
 
 use crate::{
     auth::authenticated_user::AuthenticatedUser,
-    logic::websocket::init_websocket,
+    logic::{keys::notify_if_pre_keys_low, websocket::init_websocket},
     managers::traits::message_manager::MessageManager,
     state::{state_type::StateType, ServerState},
     ServerError,
@@ -25,6 +25,7 @@ async fn websocket_endpoint<T: StateType>(
         .messages
         .dispatch_envelopes(account_id, device_id)
         .await?;
+    notify_if_pre_keys_low(&mut state, account_id, device_id).await?;
 
     Ok(ws.on_upgrade(move |socket| async move {
         init_websocket(state, auth_user, socket, dispatch).await
@@ -37,10 +38,8 @@ pub fn websocket_routes<T: StateType>(router: Router<ServerState<T>>) -> Router<
 
 #[cfg(test)]
 mod test {
-    use std::{io::Error, net::SocketAddr, time::Duration};
+    use std::time::Duration;
 
-    use axum::Router;
-    use axum_server::Handle;
     use base64::{prelude::BASE64_STANDARD, Engine};
     use futures_util::{SinkExt, StreamExt};
 
@@ -49,36 +48,34 @@ mod test {
     use rand::rngs::OsRng;
     use sam_common::{
         address::{AccountId, MessageId},
-        sam_message::{ClientEnvelope, ClientMessage, EnvelopeType, MessageType},
+        sam_message::{
+            ClientEnvelope, ClientMessage, EnvelopeType, ErrorType, MessageType, NotificationType,
+            ServerMessage,
+        },
     };
 
-    use tokio::{sync::oneshot, task::JoinHandle};
+    use tokio::time::error::Elapsed;
     use tokio_tungstenite::{
-        connect_async, tungstenite::client::IntoClientRequest, MaybeTlsStream, WebSocketStream,
+        connect_async,
+        tungstenite::{self, client::IntoClientRequest},
+        MaybeTlsStream, WebSocketStream,
     };
 
     use crate::{
-        routes::{test_utils::create_user, websocket::websocket_routes},
-        state::{state_type::StateType, ServerState},
+        managers::{
+            in_memory::InMemStateType, rate_limit::RateLimit,
+            traits::message_manager::MessageManager,
+        },
+        routes::{
+            test_utils::{create_user, create_user_with_access_key, start_websocket_server},
+            websocket::websocket_routes,
+        },
+        state::ServerState,
     };
-    use tokio::sync::oneshot::Receiver;
-
-    fn start_websocket_server<T: StateType>(
-        state: ServerState<T>,
-        address: String,
-    ) -> (JoinHandle<Result<(), Error>>, Handle, Receiver<()>) {
-        let app = websocket_routes(Router::new()).with_state(state);
-        let (tx, started_rx) = oneshot::channel::<()>();
-        let axum = Handle::new();
-        let axum_handle = axum.clone();
-        let thread = tokio::spawn(async move {
-            let server = axum_server::bind(address.parse().expect("Can make socket addr from str"))
-                .handle(axum_handle)
-                .serve(app.into_make_service_with_connect_info::<SocketAddr>());
-            tx.send(()).expect("Can oneshot");
-            server.await
-        });
-        (thread, axum, started_rx)
+
+    /// Test users publish no keys, which would be reported on every connect
+    fn test_state() -> ServerState<InMemStateType> {
+        ServerState::in_memory_test().with_pre_key_threshold(0)
     }
 
     async fn connect_user(
@@ -103,16 +100,52 @@ mod test {
         ws
     }
 
+    fn decode_server_message(
+        received: Result<
+            Option<Result<tokio_tungstenite::tungstenite::Message, tungstenite::Error>>,
+            Elapsed,
+        >,
+    ) -> ServerMessage {
+        match received {
+            Ok(Some(Ok(tokio_tungstenite::tungstenite::Message::Binary(b)))) => {
+                ServerMessage::decode(b).expect("Can decode server message")
+            }
+            _ => panic!("Did not receive a server message"),
+        }
+    }
+
+    async fn send_to_bob(
+        address: &str,
+        alice_id: AccountId,
+        envelope: ClientEnvelope,
+    ) -> ServerMessage {
+        let msg = ClientMessage::builder()
+            .id(MessageId::generate().into())
+            .message(envelope)
+            .r#type(MessageType::Message as i32)
+            .build();
+
+        let mut alice = connect_user(alice_id, "alice", "bob", address).await;
+        alice
+            .send(tokio_tungstenite::tungstenite::Message::Binary(
+                msg.encode_to_vec().into(),
+            ))
+            .await
+            .expect("Alice can send");
+        decode_server_message(tokio::time::timeout(Duration::from_millis(300), alice.next()).await)
+    }
+
     #[tokio::test]
     async fn test_websocket_alice_send_to_bob() {
-        let mut state = ServerState::in_memory_test();
+        let mut state = test_state();
         let (_, alice_id, alice_device) =
             create_user(&mut state, "alice", "phone", "bob", OsRng).await;
         let (_, bob_id, bob_device) =
             create_user(&mut state, "bob", "laptop", "cheeseburger", OsRng).await;
 
         let address = "127.0.0.1:8001".to_string();
-        let (thread, axum, started) = start_websocket_server(state.clone(), address.clone());
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
         started.await.expect("Server can start");
 
         let envelope = ClientEnvelope::builder()
@@ -121,6 +154,7 @@ mod test {
             .source_device_id(alice_device.into())
             .r#type(EnvelopeType::PlaintextContent as i32)
             .content(hashmap! {bob_device.into() => "hi bob<3".into()})
+            .registration_ids(hashmap! {bob_device.into() => 1})
             .build();
 
         let msg_id = MessageId::generate();
@@ -162,14 +196,15 @@ mod test {
 
     #[tokio::test]
     async fn test_websocket_alice_send_to_bob_offline() {
-        let mut state = ServerState::in_memory_test();
+        let mut state = test_state();
         let (_, alice_id, alice_device) =
             create_user(&mut state, "alice", "phone", "bob", OsRng).await;
         let (_, bob_id, bob_device) =
             create_user(&mut state, "bob", "laptop", "cheeseburger", OsRng).await;
 
         let address = "127.0.0.1:8002".to_string();
-        let (thread, axum, started) = start_websocket_server(state.clone(), address.clone());
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
         started.await.expect("Server can start");
 
         let envelope = ClientEnvelope::builder()
@@ -178,6 +213,7 @@ mod test {
             .source_device_id(alice_device.into())
             .r#type(EnvelopeType::PlaintextContent as i32)
             .content(hashmap! {bob_device.into() => "hi bob<3".into()})
+            .registration_ids(hashmap! {bob_device.into() => 1})
             .build();
 
         let msg_id = MessageId::generate();
@@ -215,4 +251,267 @@ mod test {
             "Bob could not received"
         )
     }
+
+    #[tokio::test]
+    async fn test_websocket_alice_cannot_spoof_bob() {
+        let mut state = test_state();
+        let (_, alice_id, _) = create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+        let (_, bob_id, bob_device) =
+            create_user(&mut state, "bob", "laptop", "cheeseburger", OsRng).await;
+
+        let address = "127.0.0.1:8003".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
+        started.await.expect("Server can start");
+
+        // alice pretends to be bob sending a note to himself
+        let envelope = ClientEnvelope::builder()
+            .destination_account_id(bob_id.into())
+            .source_account_id(bob_id.into())
+            .source_device_id(bob_device.into())
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .content(hashmap! {bob_device.into() => "i am bob".into()})
+            .registration_ids(hashmap! {bob_device.into() => 1})
+            .build();
+
+        let msg_id = MessageId::generate();
+        let msg = ClientMessage::builder()
+            .id(msg_id.into())
+            .message(envelope)
+            .r#type(MessageType::Message as i32)
+            .build();
+
+        let mut alice = connect_user(alice_id, "alice", "bob", &address).await;
+        alice
+            .send(tokio_tungstenite::tungstenite::Message::Binary(
+                msg.encode_to_vec().into(),
+            ))
+            .await
+            .expect("Alice can send");
+        let alice_received = tokio::time::timeout(Duration::from_millis(300), alice.next()).await;
+
+        let mut bob = connect_user(bob_id, "bob", "cheeseburger", &address).await;
+        let bob_received = tokio::time::timeout(Duration::from_millis(300), bob.next()).await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        let response = decode_server_message(alice_received);
+        assert!(response.r#type() == MessageType::Error);
+        assert!(response.error() == ErrorType::SourceMismatch);
+        assert!(MessageId::try_from(response.id).is_ok_and(|id| id == msg_id));
+        assert!(bob_received.is_err(), "Bob received a spoofed message");
+    }
+
+    #[tokio::test]
+    async fn test_websocket_mismatched_devices() {
+        let mut state = test_state();
+        let (_, alice_id, alice_device) =
+            create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+        let (_, bob_id, bob_device) =
+            create_user(&mut state, "bob", "laptop", "cheeseburger", OsRng).await;
+
+        let address = "127.0.0.1:8004".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
+        started.await.expect("Server can start");
+
+        // bob does not have a second device
+        let extra = ClientEnvelope::builder()
+            .destination_account_id(bob_id.into())
+            .source_account_id(alice_id.into())
+            .source_device_id(alice_device.into())
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .content(hashmap! {bob_device.into() => "hi bob<3".into(), 2 => "hi?".into()})
+            .registration_ids(hashmap! {bob_device.into() => 1, 2 => 1})
+            .build();
+        let extra_response = send_to_bob(&address, alice_id, extra).await;
+
+        let missing = ClientEnvelope::builder()
+            .destination_account_id(bob_id.into())
+            .source_account_id(alice_id.into())
+            .source_device_id(alice_device.into())
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .content(hashmap! {})
+            .registration_ids(hashmap! {})
+            .build();
+        let missing_response = send_to_bob(&address, alice_id, missing).await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        assert!(extra_response.error() == ErrorType::MismatchedDevices);
+        assert!(extra_response
+            .device_mismatch
+            .is_some_and(
+                |mismatch| mismatch.extra_devices == vec![2] && mismatch.missing_devices.is_empty()
+            ));
+
+        assert!(missing_response.error() == ErrorType::MismatchedDevices);
+        assert!(missing_response
+            .device_mismatch
+            .is_some_and(|mismatch| mismatch.missing_devices == vec![*bob_device]
+                && mismatch.extra_devices.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_stale_devices() {
+        let mut state = test_state();
+        let (_, alice_id, alice_device) =
+            create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+        let (_, bob_id, bob_device) =
+            create_user(&mut state, "bob", "laptop", "cheeseburger", OsRng).await;
+
+        let address = "127.0.0.1:8005".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
+        started.await.expect("Server can start");
+
+        // alice encrypted for an old installation of bob's laptop
+        let envelope = ClientEnvelope::builder()
+            .destination_account_id(bob_id.into())
+            .source_account_id(alice_id.into())
+            .source_device_id(alice_device.into())
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .content(hashmap! {bob_device.into() => "hi bob<3".into()})
+            .registration_ids(hashmap! {bob_device.into() => 1337})
+            .build();
+        let response = send_to_bob(&address, alice_id, envelope).await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        assert!(response.r#type() == MessageType::Error);
+        assert!(response.error() == ErrorType::StaleDevices);
+        assert!(response
+            .device_mismatch
+            .is_some_and(|mismatch| mismatch.stale_devices == vec![*bob_device]));
+        assert!(state
+            .messages
+            .get_envelope_ids(bob_id, bob_device)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_unidentified_delivery() {
+        let mut state = test_state();
+        let (_, alice_id, _) = create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+        let (_, bob_id, bob_device) = create_user_with_access_key(
+            &mut state,
+            "bob",
+            "laptop",
+            "cheeseburger",
+            Some(&[7; 16]),
+            OsRng,
+        )
+        .await;
+
+        let address = "127.0.0.1:8006".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
+        started.await.expect("Server can start");
+
+        let sealed = ClientEnvelope::unidentified(
+            bob_id,
+            hashmap! {bob_device => b"sealed".to_vec()},
+            hashmap! {bob_device => 1.into()},
+            vec![7; 16],
+        );
+        let accepted = send_to_bob(&address, alice_id, sealed).await;
+
+        let forged = ClientEnvelope::unidentified(
+            bob_id,
+            hashmap! {bob_device => b"forged".to_vec()},
+            hashmap! {bob_device => 1.into()},
+            vec![8; 16],
+        );
+        let rejected = send_to_bob(&address, alice_id, forged).await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        assert!(accepted.r#type() == MessageType::Ack);
+        assert!(rejected.r#type() == MessageType::Error);
+        assert!(rejected.error() == ErrorType::UnauthorizedDelivery);
+
+        let ids = state
+            .messages
+            .get_envelope_ids(bob_id, bob_device)
+            .await
+            .expect("Bob has a sealed envelope");
+        assert!(ids.len() == 1);
+        let envelope = state
+            .messages
+            .get_envelope(bob_id, bob_device, ids[0])
+            .await
+            .expect("Can get sealed envelope");
+        assert!(envelope.r#type() == EnvelopeType::UnidentifiedSender);
+        assert!(envelope.source_account_id.is_none());
+        assert!(envelope.source_device_id.is_none());
+        assert!(envelope.content == b"sealed".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_envelopes_are_rate_limited() {
+        let mut state =
+            test_state().with_envelope_limit(RateLimit::new(1, Duration::from_secs(60)));
+        let (_, alice_id, alice_device) =
+            create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+        let (_, bob_id, bob_device) =
+            create_user(&mut state, "bob", "laptop", "cheeseburger", OsRng).await;
+
+        let address = "127.0.0.1:8008".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
+        started.await.expect("Server can start");
+
+        let envelope = ClientEnvelope::builder()
+            .destination_account_id(bob_id.into())
+            .source_account_id(alice_id.into())
+            .source_device_id(alice_device.into())
+            .r#type(EnvelopeType::PlaintextContent as i32)
+            .content(hashmap! {bob_device.into() => "hi bob<3".into()})
+            .registration_ids(hashmap! {bob_device.into() => 1})
+            .build();
+        let accepted = send_to_bob(&address, alice_id, envelope.clone()).await;
+        let limited = send_to_bob(&address, alice_id, envelope).await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        assert!(accepted.r#type() == MessageType::Ack);
+        assert!(limited.r#type() == MessageType::Error);
+        assert!(limited.error() == ErrorType::RateLimited);
+        assert!(state
+            .messages
+            .get_envelope_ids(bob_id, bob_device)
+            .await
+            .is_some_and(|ids| ids.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_low_pre_keys_are_reported_on_connect() {
+        let mut state = ServerState::in_memory_test();
+        let (_, alice_id, _) = create_user(&mut state, "alice", "phone", "bob", OsRng).await;
+
+        let address = "127.0.0.1:8009".to_string();
+        let (thread, axum, started) =
+            start_websocket_server(state.clone(), websocket_routes, address.clone());
+        started.await.expect("Server can start");
+
+        let mut alice = connect_user(alice_id, "alice", "bob", &address).await;
+        let received = tokio::time::timeout(Duration::from_millis(300), alice.next()).await;
+
+        axum.shutdown();
+        let _ = thread.await;
+
+        let message = decode_server_message(received);
+        assert!(message.r#type() == MessageType::Notification);
+        let notification = message.notification.expect("Has notification");
+        assert!(notification.r#type() == NotificationType::PreKeysLow);
+        assert!(notification
+            .pre_key_count
+            .is_some_and(|count| count.pre_keys == 0 && count.pq_pre_keys == 0));
+    }
 }
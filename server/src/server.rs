@@ -1,9 +1,11 @@
+use crate::logic::retention::run_reaper;
 use crate::routes::router;
 use crate::state::state_type::StateType;
 use crate::state::ServerState;
-use axum::extract::Request;
-use axum::middleware::{from_fn, Next};
-use axum::response::IntoResponse;
+use crate::ServerError;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::{from_fn, from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
 use axum_server::tls_rustls::RustlsConfig;
 use log::info;
 use std::net::SocketAddr;
@@ -22,10 +24,30 @@ async fn log_request(req: Request, next: Next) -> impl IntoResponse {
     next.run(req).await
 }
 
+/// Rejects requests once the peer address has used up its limit, before any
+/// password is hashed. Clients behind a shared proxy share one limit.
+async fn limit_requests<T: StateType>(
+    State(state): State<ServerState<T>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ServerError> {
+    state.ip_requests.check(peer.ip()).await?;
+    Ok(next.run(req).await)
+}
+
+/// Must run before a [`RustlsConfig`] is created, installing it twice is harmless
+pub fn install_crypto_provider() {
+    // fails only if a provider is already installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+}
+
 pub async fn start_server<T: StateType>(config: ServerConfig<T>) -> Result<(), std::io::Error> {
     let state = config.state;
+    tokio::spawn(run_reaper(state.clone()));
 
     let app = router()
+        .layer(from_fn_with_state(state.clone(), limit_requests::<T>))
         .layer(from_fn(log_request))
         .with_state(state.clone());
 
@@ -35,9 +57,7 @@ pub async fn start_server<T: StateType>(config: ServerConfig<T>) -> Result<(), s
         config.addr
     );
     if let Some(tls_config) = config.tls {
-        rustls::crypto::ring::default_provider()
-            .install_default()
-            .expect("Failed to install rustls crypto provider");
+        install_crypto_provider();
         axum_server::bind_rustls(config.addr, tls_config)
             .serve(app.into_make_service_with_connect_info::<SocketAddr>())
             .await?;
@@ -49,3 +69,49 @@ pub async fn start_server<T: StateType>(config: ServerConfig<T>) -> Result<(), s
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use axum::{
+        extract::connect_info::MockConnectInfo, http::StatusCode, middleware::from_fn_with_state,
+        routing::get, Router,
+    };
+    use axum_test::TestServer;
+
+    use crate::{
+        managers::{in_memory::InMemStateType, rate_limit::RateLimit},
+        state::ServerState,
+    };
+
+    use super::limit_requests;
+
+    #[tokio::test]
+    async fn test_requests_are_limited_per_peer() {
+        let state = ServerState::in_memory_test()
+            .with_ip_request_limit(RateLimit::new(2, Duration::from_secs(60)));
+        let app = |peer: SocketAddr| {
+            Router::new()
+                .route("/", get(|| async {}))
+                .layer(from_fn_with_state(
+                    state.clone(),
+                    limit_requests::<InMemStateType>,
+                ))
+                .layer(MockConnectInfo(peer))
+                .with_state(state.clone())
+        };
+        let alice = TestServer::new(app(SocketAddr::from(([10, 0, 0, 1], 1234))))
+            .expect("Can start test server");
+        let bob = TestServer::new(app(SocketAddr::from(([10, 0, 0, 2], 1234))))
+            .expect("Can start test server");
+
+        alice.get("/").await.assert_status_ok();
+        alice.get("/").await.assert_status_ok();
+        let res = alice.get("/").await;
+        res.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key("retry-after"));
+
+        bob.get("/").await.assert_status_ok();
+    }
+}
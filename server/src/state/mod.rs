@@ -1,12 +1,90 @@
 pub mod state_type;
+use std::{net::IpAddr, time::Duration};
+
+use log::error;
+use rand::rngs::OsRng;
+use sam_common::address::{AccountId, DeviceAddress};
 use state_type::StateType;
 
+use crate::{
+    auth::certificate::CertificateSigner,
+    managers::{
+        provisioning::ProvisioningRelay,
+        rate_limit::{RateLimit, RateLimiter},
+    },
+    ServerError,
+};
+
+/// Devices are notified once fewer one-time keys than this are left
+pub const DEFAULT_PRE_KEY_THRESHOLD: u32 = 10;
+
+/// Undelivered envelopes are dropped after this long
+pub const DEFAULT_MESSAGE_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Envelopes kept per device, the oldest are dropped to make room for new ones
+pub const DEFAULT_MESSAGE_QUEUE_LIMIT: usize = 1000;
+
+/// Session tokens are short-lived, a stolen token is only useful for a while
+pub const DEFAULT_SESSION_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+/// Enough to find a few contacts, too few to enumerate the username directory
+pub const DEFAULT_USERNAME_LOOKUP_LIMIT: RateLimit = RateLimit {
+    burst: 10,
+    interval: Duration::from_secs(6),
+};
+
+/// Every request counts, including the ones that fail to authenticate
+pub const DEFAULT_IP_REQUEST_LIMIT: RateLimit = RateLimit {
+    burst: 100,
+    interval: Duration::from_millis(100),
+};
+
+/// Authenticated requests, fetching key bundles uses up one-time keys of others
+pub const DEFAULT_ACCOUNT_REQUEST_LIMIT: RateLimit = RateLimit {
+    burst: 50,
+    interval: Duration::from_millis(200),
+};
+
+/// Wrong passwords for an account from a single peer address, the owner
+/// elsewhere is not locked out
+pub const DEFAULT_FAILED_LOGIN_LIMIT: RateLimit = RateLimit {
+    burst: 10,
+    interval: Duration::from_secs(60),
+};
+
+/// Envelopes sent over the websocket of a single device
+pub const DEFAULT_ENVELOPE_LIMIT: RateLimit = RateLimit {
+    burst: 50,
+    interval: Duration::from_millis(100),
+};
+
 #[derive(Clone)]
 pub struct ServerState<T: StateType> {
     pub accounts: T::AccountManager,
     pub devices: T::DeviceManager,
     pub messages: T::MessageManager,
     pub keys: T::KeyManager,
+    pub certificates: CertificateSigner,
+    pub provisioning: ProvisioningRelay,
+    /// Requests per peer address
+    pub ip_requests: RateLimiter<IpAddr>,
+    /// Authenticated requests per account
+    pub account_requests: RateLimiter<AccountId>,
+    /// Wrong passwords per account and peer address
+    pub failed_logins: RateLimiter<(AccountId, IpAddr)>,
+    /// Envelopes per sending device
+    pub envelopes: RateLimiter<DeviceAddress>,
+    /// Username lookups per requesting account
+    pub username_lookups: RateLimiter<AccountId>,
+    /// Username lookups per peer address, so new accounts don't get a fresh limit
+    pub ip_username_lookups: RateLimiter<IpAddr>,
+    pub pre_key_threshold: u32,
+    /// How long a session token stays valid after it was issued
+    pub session_lifetime: Duration,
+    /// How long an envelope is kept if its device does not acknowledge it
+    pub message_retention: Duration,
+    /// Envelopes kept per device
+    pub message_queue_limit: usize,
 }
 
 impl<T: StateType> ServerState<T> {
@@ -21,6 +99,107 @@ impl<T: StateType> ServerState<T> {
             devices: device,
             messages: message,
             keys: key,
+            certificates: CertificateSigner::generate(&mut OsRng),
+            provisioning: ProvisioningRelay::default(),
+            ip_requests: RateLimiter::new(DEFAULT_IP_REQUEST_LIMIT),
+            account_requests: RateLimiter::new(DEFAULT_ACCOUNT_REQUEST_LIMIT),
+            failed_logins: RateLimiter::new(DEFAULT_FAILED_LOGIN_LIMIT),
+            envelopes: RateLimiter::new(DEFAULT_ENVELOPE_LIMIT),
+            username_lookups: RateLimiter::new(DEFAULT_USERNAME_LOOKUP_LIMIT),
+            ip_username_lookups: RateLimiter::new(DEFAULT_USERNAME_LOOKUP_LIMIT),
+            pre_key_threshold: DEFAULT_PRE_KEY_THRESHOLD,
+            session_lifetime: DEFAULT_SESSION_LIFETIME,
+            message_retention: DEFAULT_MESSAGE_RETENTION,
+            message_queue_limit: DEFAULT_MESSAGE_QUEUE_LIMIT,
+        }
+    }
+
+    /// Replaces the generated certificate signer, so sender certificates
+    /// stay valid across restarts
+    pub fn with_certificate_signer(mut self, certificates: CertificateSigner) -> Self {
+        self.certificates = certificates;
+        self
+    }
+
+    /// Sets the number of one-time keys below which a device is notified, zero
+    /// disables the notifications
+    pub fn with_pre_key_threshold(mut self, pre_key_threshold: u32) -> Self {
+        self.pre_key_threshold = pre_key_threshold;
+        self
+    }
+
+    pub fn with_session_lifetime(mut self, session_lifetime: Duration) -> Self {
+        self.session_lifetime = session_lifetime;
+        self
+    }
+
+    pub fn with_message_retention(mut self, message_retention: Duration) -> Self {
+        self.message_retention = message_retention;
+        self
+    }
+
+    pub fn with_message_queue_limit(mut self, message_queue_limit: usize) -> Self {
+        self.message_queue_limit = message_queue_limit;
+        self
+    }
+
+    /// Replaces the limit on requests per peer address
+    pub fn with_ip_request_limit(mut self, limit: RateLimit) -> Self {
+        self.ip_requests = RateLimiter::new(limit);
+        self
+    }
+
+    /// Replaces the limit on authenticated requests per account
+    pub fn with_account_request_limit(mut self, limit: RateLimit) -> Self {
+        self.account_requests = RateLimiter::new(limit);
+        self
+    }
+
+    /// Replaces the limit on wrong passwords per account and peer address
+    pub fn with_failed_login_limit(mut self, limit: RateLimit) -> Self {
+        self.failed_logins = RateLimiter::new(limit);
+        self
+    }
+
+    /// Replaces the limit on envelopes per device
+    pub fn with_envelope_limit(mut self, limit: RateLimit) -> Self {
+        self.envelopes = RateLimiter::new(limit);
+        self
+    }
+
+    /// Replaces the limit on username lookups, it applies both per account and
+    /// per peer address
+    pub fn with_username_lookup_limit(mut self, limit: RateLimit) -> Self {
+        self.username_lookups = RateLimiter::new(limit);
+        self.ip_username_lookups = RateLimiter::new(limit);
+        self
+    }
+
+    /// Starts a transaction, or joins the current one if `self` already is transactional
+    pub async fn begin(&self) -> Result<Self, ServerError> {
+        T::begin_transaction(self).await
+    }
+
+    pub async fn commit(self) -> Result<(), ServerError> {
+        T::commit_transaction(self).await
+    }
+
+    pub async fn rollback(self) -> Result<(), ServerError> {
+        T::rollback_transaction(self).await
+    }
+
+    /// Commits the transaction if `result` is ok, otherwise rolls it back.
+    /// Backends that cannot roll back keep the writes made before the error,
+    /// see [`StateType::begin_transaction`].
+    pub async fn finish<R>(self, result: Result<R, ServerError>) -> Result<R, ServerError> {
+        match result {
+            Ok(value) => self.commit().await.map(|_| value),
+            Err(err) => {
+                if let Err(rollback_err) = self.rollback().await {
+                    error!("Failed to roll back transaction '{}'", rollback_err);
+                }
+                Err(err)
+            }
         }
     }
 }
@@ -1,13 +1,29 @@
-use crate::managers::traits::{
-    account_manager::AccountManager,
-    device_manager::DeviceManager,
-    key_manager::{LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager},
-    message_manager::MessageManager,
+use crate::{
+    managers::traits::{
+        account_manager::AccountManager,
+        device_manager::DeviceManager,
+        key_manager::{LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager},
+        message_manager::MessageManager,
+    },
+    state::ServerState,
+    ServerError,
 };
 
-pub trait StateType: 'static + Clone {
+#[async_trait::async_trait]
+pub trait StateType: 'static + Clone + Send + Sync {
     type AccountManager: AccountManager;
     type DeviceManager: DeviceManager;
     type MessageManager: MessageManager;
     type KeyManager: PreKeyManager + SignedPreKeyManager + PqPreKeyManager + LastResortKeyManager;
+
+    /// Returns a state whose managers all operate inside the same transaction.
+    ///
+    /// Writes are only atomic on backends that can roll back. The in-memory
+    /// backend applies every write immediately and its rollback is a no-op, so
+    /// logic has to reject bad input before the first write instead of relying
+    /// on [`ServerState::finish`] to undo it.
+    async fn begin_transaction(state: &ServerState<Self>)
+        -> Result<ServerState<Self>, ServerError>;
+    async fn commit_transaction(state: ServerState<Self>) -> Result<(), ServerError>;
+    async fn rollback_transaction(state: ServerState<Self>) -> Result<(), ServerError>;
 }
@@ -0,0 +1,282 @@
+use super::{in_memory, key_pair, new_account, postgres, sqlite};
+use sam_common::address::AccountId;
+use sam_server::{
+    managers::{entities::account::Account, traits::account_manager::AccountManager},
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+macro_rules! test_account_manager {
+    ( [ $( $(#[$attr:meta])* ($name:ident, $factory:expr) ),* ] ) => {
+        $(
+            paste::paste! {
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _account_can_be_added_and_retrieved >]() {
+                    account_can_be_added_and_retrieved($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _account_cannot_be_added_twice >]() {
+                    account_cannot_be_added_twice($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _account_can_be_removed >]() {
+                    account_can_be_removed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _missing_account_does_not_exist >]() {
+                    missing_account_does_not_exist($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _unidentified_access_key_is_stored >]() {
+                    unidentified_access_key_is_stored($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _username_is_unique >]() {
+                    username_is_unique($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _username_can_be_changed >]() {
+                    username_can_be_changed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _username_ignores_case >]() {
+                    username_ignores_case($factory().await).await;
+                }
+            }
+        )*
+    };
+}
+
+async fn account_can_be_added_and_retrieved<T: StateType>(mut state: ServerState<T>) {
+    let pair = key_pair();
+    let account = new_account(&pair);
+
+    state
+        .accounts
+        .add_account(&account)
+        .await
+        .expect("Can add account");
+
+    let stored = state
+        .accounts
+        .get_account(account.id())
+        .await
+        .expect("Can get account");
+    assert!(stored.id() == account.id());
+    assert!(stored.username() == account.username());
+    assert!(*stored.identity() == *pair.identity_key());
+}
+
+async fn account_cannot_be_added_twice<T: StateType>(mut state: ServerState<T>) {
+    let account = new_account(&key_pair());
+
+    state
+        .accounts
+        .add_account(&account)
+        .await
+        .expect("Can add account");
+
+    assert!(matches!(
+        state.accounts.add_account(&account).await,
+        Err(ServerError::AccountExists)
+    ));
+}
+
+async fn account_can_be_removed<T: StateType>(mut state: ServerState<T>) {
+    let account = new_account(&key_pair());
+
+    state
+        .accounts
+        .add_account(&account)
+        .await
+        .expect("Can add account");
+    state
+        .accounts
+        .remove_account(account.id())
+        .await
+        .expect("Can remove account");
+
+    assert!(matches!(
+        state.accounts.get_account(account.id()).await,
+        Err(ServerError::AccountNotExist)
+    ));
+    assert!(matches!(
+        state.accounts.remove_account(account.id()).await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn missing_account_does_not_exist<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    assert!(matches!(
+        state.accounts.get_account(account_id).await,
+        Err(ServerError::AccountNotExist)
+    ));
+    assert!(matches!(
+        state.accounts.remove_account(account_id).await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn unidentified_access_key_is_stored<T: StateType>(mut state: ServerState<T>) {
+    let pair = key_pair();
+    let bob_id = AccountId::generate();
+    let account = Account::builder()
+        .id(bob_id)
+        .username(format!("bob-{bob_id}"))
+        .identity(*pair.identity_key())
+        .unidentified_access_key(Box::new([7; 16]))
+        .build();
+    let anonymous = new_account(&pair);
+
+    for account in [&account, &anonymous] {
+        state
+            .accounts
+            .add_account(account)
+            .await
+            .expect("Can add account");
+    }
+
+    assert!(state
+        .accounts
+        .get_account(account.id())
+        .await
+        .is_ok_and(|stored| stored.unidentified_access_key() == Some(&[7; 16][..])));
+    assert!(state
+        .accounts
+        .get_account(anonymous.id())
+        .await
+        .is_ok_and(|stored| stored.unidentified_access_key().is_none()));
+}
+
+async fn username_is_unique<T: StateType>(mut state: ServerState<T>) {
+    let pair = key_pair();
+    let alice = new_account(&pair);
+    let impostor = Account::builder()
+        .id(AccountId::generate())
+        .username(alice.username().to_string())
+        .identity(*pair.identity_key())
+        .build();
+
+    state
+        .accounts
+        .add_account(&alice)
+        .await
+        .expect("Can add account");
+    assert!(matches!(
+        state.accounts.add_account(&impostor).await,
+        Err(ServerError::UsernameTaken)
+    ));
+
+    assert!(state
+        .accounts
+        .get_account_id(alice.username())
+        .await
+        .is_ok_and(|id| id == alice.id()));
+    assert!(matches!(
+        state.accounts.get_account_id("Mallory").await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn username_can_be_changed<T: StateType>(mut state: ServerState<T>) {
+    let pair = key_pair();
+    let alice = new_account(&pair);
+    let bob_id = AccountId::generate();
+    let bob = Account::builder()
+        .id(bob_id)
+        .username(format!("bob-{bob_id}"))
+        .identity(*pair.identity_key())
+        .build();
+    for account in [&alice, &bob] {
+        state
+            .accounts
+            .add_account(account)
+            .await
+            .expect("Can add account");
+    }
+
+    let alicia = format!("alicia-{}", alice.id());
+    state
+        .accounts
+        .set_username(alice.id(), &alicia)
+        .await
+        .expect("Can change username");
+    assert!(state
+        .accounts
+        .get_account(alice.id())
+        .await
+        .is_ok_and(|account| account.username() == alicia));
+    assert!(state
+        .accounts
+        .get_account_id(&alicia)
+        .await
+        .is_ok_and(|id| id == alice.id()));
+    assert!(matches!(
+        state.accounts.get_account_id(alice.username()).await,
+        Err(ServerError::AccountNotExist)
+    ));
+
+    assert!(matches!(
+        state
+            .accounts
+            .set_username(alice.id(), bob.username())
+            .await,
+        Err(ServerError::UsernameTaken)
+    ));
+    assert!(matches!(
+        state
+            .accounts
+            .set_username(AccountId::generate(), "Charlie")
+            .await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn username_ignores_case<T: StateType>(mut state: ServerState<T>) {
+    let pair = key_pair();
+    let alice = new_account(&pair);
+    let impostor = Account::builder()
+        .id(AccountId::generate())
+        .username(alice.username().to_uppercase())
+        .identity(*pair.identity_key())
+        .build();
+
+    state
+        .accounts
+        .add_account(&alice)
+        .await
+        .expect("Can add account");
+    assert!(matches!(
+        state.accounts.add_account(&impostor).await,
+        Err(ServerError::UsernameTaken)
+    ));
+    assert!(state
+        .accounts
+        .get_account_id(&alice.username().to_uppercase())
+        .await
+        .is_ok_and(|id| id == alice.id()));
+}
+
+test_account_manager!([
+    (in_memory_account_manager, in_memory),
+    (sqlite_account_manager, sqlite),
+    #[ignore = "requires a local Postgres instance"]
+    (postgres_account_manager, postgres)
+]);
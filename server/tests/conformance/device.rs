@@ -0,0 +1,447 @@
+use super::{in_memory, new_device, postgres, sqlite};
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::DeviceRole,
+};
+use sam_server::{
+    auth::password::Password,
+    managers::traits::device_manager::DeviceManager,
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+macro_rules! test_device_manager {
+    ( [ $( $(#[$attr:meta])* ($name:ident, $factory:expr) ),* ] ) => {
+        $(
+            paste::paste! {
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_can_be_added_and_retrieved >]() {
+                    device_can_be_added_and_retrieved($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_cannot_be_added_twice >]() {
+                    device_cannot_be_added_twice($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _missing_device_does_not_exist >]() {
+                    missing_device_does_not_exist($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _devices_can_be_listed >]() {
+                    devices_can_be_listed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _next_device_id_fills_gaps >]() {
+                    next_device_id_fills_gaps($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_can_be_renamed >]() {
+                    device_can_be_renamed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_role_can_be_changed >]() {
+                    device_role_can_be_changed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_password_can_be_changed >]() {
+                    device_password_can_be_changed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_can_be_removed >]() {
+                    device_can_be_removed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _link_token_can_be_consumed_once >]() {
+                    link_token_can_be_consumed_once($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _link_token_can_be_revoked >]() {
+                    link_token_can_be_revoked($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _expired_link_tokens_are_removed >]() {
+                    expired_link_tokens_are_removed($factory().await).await;
+                }
+            }
+        )*
+    };
+}
+
+async fn device_can_be_added_and_retrieved<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let device = new_device(1.into());
+
+    state
+        .devices
+        .add_device(account_id, &device)
+        .await
+        .expect("Can add device");
+
+    let stored = state
+        .devices
+        .get_device(account_id, 1.into())
+        .await
+        .expect("Can get device");
+    assert!(stored == device);
+    stored
+        .password()
+        .verify("bob<3".to_string())
+        .expect("Alice loves bob");
+}
+
+async fn device_cannot_be_added_twice<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let device = new_device(1.into());
+
+    state
+        .devices
+        .add_device(account_id, &device)
+        .await
+        .expect("Can add device");
+
+    assert!(matches!(
+        state.devices.add_device(account_id, &device).await,
+        Err(ServerError::DeviceExists)
+    ));
+}
+
+async fn missing_device_does_not_exist<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    assert!(matches!(
+        state.devices.get_device(account_id, 1.into()).await,
+        Err(ServerError::DeviceNotExist)
+    ));
+    assert!(matches!(
+        state.devices.remove_device(account_id, 1.into()).await,
+        Err(ServerError::DeviceNotExist)
+    ));
+    assert!(matches!(
+        state.devices.get_devices(account_id).await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn devices_can_be_listed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    for id in [1u32, 2] {
+        state
+            .devices
+            .add_device(account_id, &new_device(id.into()))
+            .await
+            .expect("Can add device");
+    }
+
+    let mut devices = state
+        .devices
+        .get_devices(account_id)
+        .await
+        .expect("Can get devices");
+    devices.sort();
+    assert!(devices == vec![DeviceId::from(1), DeviceId::from(2)]);
+}
+
+async fn next_device_id_fills_gaps<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    for id in [1u32, 3] {
+        state
+            .devices
+            .add_device(account_id, &new_device(id.into()))
+            .await
+            .expect("Can add device");
+    }
+    assert!(state
+        .devices
+        .next_device_id(account_id)
+        .await
+        .is_ok_and(|id| id == 2.into()));
+
+    state
+        .devices
+        .add_device(account_id, &new_device(2.into()))
+        .await
+        .expect("Can add device");
+    assert!(state
+        .devices
+        .next_device_id(account_id)
+        .await
+        .is_ok_and(|id| id == 4.into()));
+}
+
+async fn device_can_be_renamed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    state
+        .devices
+        .add_device(account_id, &new_device(1.into()))
+        .await
+        .expect("Can add device");
+
+    state
+        .devices
+        .rename_device(account_id, 1.into(), "Alice Tablet")
+        .await
+        .expect("Can rename device");
+    assert!(state
+        .devices
+        .get_device(account_id, 1.into())
+        .await
+        .is_ok_and(|device| device.name() == "Alice Tablet"));
+
+    assert!(matches!(
+        state
+            .devices
+            .rename_device(account_id, 2.into(), "Alice Laptop")
+            .await,
+        Err(ServerError::DeviceNotExist)
+    ));
+}
+
+async fn device_role_can_be_changed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    state
+        .devices
+        .add_device(account_id, &new_device(1.into()))
+        .await
+        .expect("Can add device");
+
+    state
+        .devices
+        .set_device_role(account_id, 1.into(), DeviceRole::Primary)
+        .await
+        .expect("Can change role");
+    assert!(state
+        .devices
+        .get_device(account_id, 1.into())
+        .await
+        .is_ok_and(|device| device.role() == DeviceRole::Primary));
+
+    assert!(matches!(
+        state
+            .devices
+            .set_device_role(account_id, 2.into(), DeviceRole::Primary)
+            .await,
+        Err(ServerError::DeviceNotExist)
+    ));
+}
+
+async fn device_password_can_be_changed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    state
+        .devices
+        .add_device(account_id, &new_device(1.into()))
+        .await
+        .expect("Can add device");
+
+    let password = Password::generate("hunter2".to_string()).expect("Can generate password");
+    state
+        .devices
+        .set_device_password(account_id, 1.into(), &password)
+        .await
+        .expect("Can change password");
+    assert!(state
+        .devices
+        .get_device(account_id, 1.into())
+        .await
+        .is_ok_and(|device| device.password().verify("hunter2".to_string()).is_ok()));
+
+    assert!(matches!(
+        state
+            .devices
+            .set_device_password(account_id, 2.into(), &password)
+            .await,
+        Err(ServerError::DeviceNotExist)
+    ));
+}
+
+async fn device_can_be_removed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    for id in [1u32, 2] {
+        state
+            .devices
+            .add_device(account_id, &new_device(id.into()))
+            .await
+            .expect("Can add device");
+    }
+
+    state
+        .devices
+        .remove_device(account_id, 2.into())
+        .await
+        .expect("Can remove device");
+    assert!(matches!(
+        state.devices.get_device(account_id, 2.into()).await,
+        Err(ServerError::DeviceNotExist)
+    ));
+    assert!(state
+        .devices
+        .get_devices(account_id)
+        .await
+        .is_ok_and(|ids| ids == vec![1.into()]));
+
+    state
+        .devices
+        .remove_device(account_id, 1.into())
+        .await
+        .expect("Can remove device");
+    assert!(matches!(
+        state.devices.get_devices(account_id).await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn link_token_can_be_consumed_once<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    state
+        .devices
+        .add_link_token(account_id, "token", i64::MAX as u64)
+        .await
+        .expect("Can add link token");
+    assert!(matches!(
+        state
+            .devices
+            .consume_link_token(AccountId::generate(), "token")
+            .await,
+        Err(ServerError::DeviceTokenNotExist)
+    ));
+
+    state
+        .devices
+        .check_link_token(account_id, "token")
+        .await
+        .expect("Unused link token passes the check");
+    state
+        .devices
+        .consume_link_token(account_id, "token")
+        .await
+        .expect("Checking did not use the link token");
+    assert!(matches!(
+        state.devices.check_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenUsed)
+    ));
+    assert!(matches!(
+        state.devices.consume_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenUsed)
+    ));
+    assert!(matches!(
+        state.devices.revoke_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenUsed)
+    ));
+
+    state
+        .devices
+        .remove_link_tokens(account_id)
+        .await
+        .expect("Can remove link tokens");
+    assert!(matches!(
+        state.devices.consume_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenNotExist)
+    ));
+}
+
+async fn link_token_can_be_revoked<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    assert!(matches!(
+        state.devices.revoke_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenNotExist)
+    ));
+
+    state
+        .devices
+        .add_link_token(account_id, "token", i64::MAX as u64)
+        .await
+        .expect("Can add link token");
+    state
+        .devices
+        .revoke_link_token(account_id, "token")
+        .await
+        .expect("Can revoke link token");
+    state
+        .devices
+        .revoke_link_token(account_id, "token")
+        .await
+        .expect("Revoking twice is fine");
+    assert!(matches!(
+        state.devices.check_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenRevoked)
+    ));
+    assert!(matches!(
+        state.devices.consume_link_token(account_id, "token").await,
+        Err(ServerError::DeviceTokenRevoked)
+    ));
+}
+
+test_device_manager!([
+    (in_memory_device_manager, in_memory),
+    (sqlite_device_manager, sqlite),
+    #[ignore = "requires a local Postgres instance"]
+    (postgres_device_manager, postgres)
+]);
+
+async fn expired_link_tokens_are_removed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let [expired, used, fresh] =
+        ["expired", "used", "fresh"].map(|name| format!("{account_id}-{name}"));
+
+    for (token_id, expires) in [(&expired, 1_000), (&used, 1_500), (&fresh, 3_000)] {
+        state
+            .devices
+            .add_link_token(account_id, token_id, expires)
+            .await
+            .expect("Can add link token");
+    }
+    state
+        .devices
+        .consume_link_token(account_id, &used)
+        .await
+        .expect("Can consume link token");
+
+    assert!(
+        state
+            .devices
+            .remove_expired_link_tokens(2_000)
+            .await
+            .expect("Can remove expired link tokens")
+            >= 2
+    );
+    assert!(matches!(
+        state.devices.consume_link_token(account_id, &expired).await,
+        Err(ServerError::DeviceTokenNotExist)
+    ));
+    assert!(matches!(
+        state.devices.consume_link_token(account_id, &used).await,
+        Err(ServerError::DeviceTokenNotExist)
+    ));
+    state
+        .devices
+        .consume_link_token(account_id, &fresh)
+        .await
+        .expect("Unexpired token is kept");
+}
@@ -0,0 +1,273 @@
+use super::{ec_pre_key, in_memory, key_pair, postgres, pq_pre_key, signed_ec_pre_key, sqlite};
+use sam_common::{address::AccountId, api::Key};
+use sam_server::{
+    managers::traits::key_manager::{
+        LastResortKeyManager, PqPreKeyManager, PreKeyManager, SignedPreKeyManager,
+    },
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+macro_rules! test_key_manager {
+    ( [ $( $(#[$attr:meta])* ($name:ident, $factory:expr) ),* ] ) => {
+        $(
+            paste::paste! {
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _pre_keys_are_handed_out_in_order >]() {
+                    pre_keys_are_handed_out_in_order($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _signed_pre_key_can_be_set_and_replaced >]() {
+                    signed_pre_key_can_be_set_and_replaced($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _pq_pre_keys_are_handed_out_in_order >]() {
+                    pq_pre_keys_are_handed_out_in_order($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _last_resort_key_can_be_set_and_removed >]() {
+                    last_resort_key_can_be_set_and_removed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _key_ids_are_unique_per_device >]() {
+                    key_ids_are_unique_per_device($factory().await).await;
+                }
+            }
+        )*
+    };
+}
+
+async fn pre_keys_are_handed_out_in_order<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    assert!(state
+        .keys
+        .get_pre_key(account_id, 1.into())
+        .await
+        .is_ok_and(|key| key.is_none()));
+    assert!(state
+        .keys
+        .get_pre_key_ids(account_id, 1.into())
+        .await
+        .is_ok_and(|ids| ids.is_none()));
+
+    let first = ec_pre_key(0);
+    for key in [first.clone(), ec_pre_key(1)] {
+        state
+            .keys
+            .add_pre_key(account_id, 1.into(), key)
+            .await
+            .expect("Can add pre key");
+    }
+
+    assert!(state
+        .keys
+        .get_pre_key_ids(account_id, 1.into())
+        .await
+        .is_ok_and(|ids| ids == Some(vec![0, 1])));
+    assert!(state
+        .keys
+        .get_pre_key(account_id, 1.into())
+        .await
+        .is_ok_and(|key| key == Some(first)));
+
+    state
+        .keys
+        .remove_pre_key(account_id, 1.into(), 0)
+        .await
+        .expect("Can remove pre key");
+    assert!(state
+        .keys
+        .get_pre_key(account_id, 1.into())
+        .await
+        .is_ok_and(|key| key.is_some_and(|key| key.id() == 1)));
+
+    state
+        .keys
+        .remove_pre_key(account_id, 1.into(), 1)
+        .await
+        .expect("Can remove pre key");
+    assert!(state
+        .keys
+        .get_pre_key_ids(account_id, 1.into())
+        .await
+        .is_ok_and(|ids| ids.is_none()));
+}
+
+async fn signed_pre_key_can_be_set_and_replaced<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let pair = key_pair();
+
+    assert!(matches!(
+        state.keys.get_signed_pre_key(account_id, 1.into()).await,
+        Err(ServerError::AccountNotExist)
+    ));
+
+    state
+        .keys
+        .set_signed_pre_key(account_id, 1.into(), signed_ec_pre_key(1, &pair))
+        .await
+        .expect("Can set signed pre key");
+
+    let replacement = signed_ec_pre_key(2, &pair);
+    state
+        .keys
+        .set_signed_pre_key(account_id, 1.into(), replacement.clone())
+        .await
+        .expect("Can replace signed pre key");
+    assert!(state
+        .keys
+        .get_signed_pre_key(account_id, 1.into())
+        .await
+        .is_ok_and(|key| key == replacement));
+
+    state
+        .keys
+        .remove_signed_pre_key(account_id, 1.into())
+        .await
+        .expect("Can remove signed pre key");
+    assert!(matches!(
+        state.keys.get_signed_pre_key(account_id, 1.into()).await,
+        Err(ServerError::AccountNotExist)
+    ));
+}
+
+async fn pq_pre_keys_are_handed_out_in_order<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let pair = key_pair();
+
+    assert!(state
+        .keys
+        .get_pq_pre_key(account_id, 1.into())
+        .await
+        .is_ok_and(|key| key.is_none()));
+
+    let first = pq_pre_key(33, &pair);
+    for key in [first.clone(), pq_pre_key(34, &pair)] {
+        state
+            .keys
+            .add_pq_pre_key(account_id, 1.into(), key)
+            .await
+            .expect("Can add pq pre key");
+    }
+
+    assert!(state
+        .keys
+        .get_pq_pre_key_ids(account_id, 1.into())
+        .await
+        .is_ok_and(|ids| ids == Some(vec![33, 34])));
+    assert!(state
+        .keys
+        .get_pq_pre_key(account_id, 1.into())
+        .await
+        .is_ok_and(|key| key == Some(first)));
+
+    state
+        .keys
+        .remove_pq_pre_key(account_id, 1.into(), 33)
+        .await
+        .expect("Can remove pq pre key");
+    assert!(matches!(
+        state.keys.remove_pq_pre_key(account_id, 1.into(), 33).await,
+        Err(ServerError::KeyNotExist)
+    ));
+
+    state
+        .keys
+        .remove_pq_pre_key(account_id, 1.into(), 34)
+        .await
+        .expect("Can remove pq pre key");
+    assert!(state
+        .keys
+        .get_pq_pre_key_ids(account_id, 1.into())
+        .await
+        .is_ok_and(|ids| ids.is_none()));
+}
+
+async fn last_resort_key_can_be_set_and_removed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let pair = key_pair();
+
+    assert!(matches!(
+        state.keys.get_last_resort_key(account_id, 1.into()).await,
+        Err(ServerError::KeyNotExist)
+    ));
+
+    let key = pq_pre_key(2, &pair);
+    state
+        .keys
+        .set_last_resort_key(account_id, 1.into(), key.clone())
+        .await
+        .expect("Can set last resort key");
+    assert!(state
+        .keys
+        .get_last_resort_key(account_id, 1.into())
+        .await
+        .is_ok_and(|stored| stored == key));
+
+    state
+        .keys
+        .remove_last_resort_key(account_id, 1.into())
+        .await
+        .expect("Can remove last resort key");
+    assert!(matches!(
+        state
+            .keys
+            .remove_last_resort_key(account_id, 1.into())
+            .await,
+        Err(ServerError::KeyNotExist)
+    ));
+}
+
+async fn key_ids_are_unique_per_device<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let pair = key_pair();
+
+    state
+        .keys
+        .add_pre_key(account_id, 1.into(), ec_pre_key(0))
+        .await
+        .expect("Can add pre key");
+    assert!(matches!(
+        state
+            .keys
+            .add_pre_key(account_id, 1.into(), ec_pre_key(0))
+            .await,
+        Err(ServerError::KeyExists)
+    ));
+
+    state
+        .keys
+        .add_pq_pre_key(account_id, 1.into(), pq_pre_key(33, &pair))
+        .await
+        .expect("Can add pq pre key");
+    assert!(matches!(
+        state
+            .keys
+            .add_pq_pre_key(account_id, 1.into(), pq_pre_key(33, &pair))
+            .await,
+        Err(ServerError::KeyExists)
+    ));
+
+    state
+        .keys
+        .add_pre_key(account_id, 2.into(), ec_pre_key(0))
+        .await
+        .expect("Another device can use the same id");
+}
+
+test_key_manager!([
+    (in_memory_key_manager, in_memory),
+    (sqlite_key_manager, sqlite),
+    #[ignore = "requires a local Postgres instance"]
+    (postgres_key_manager, postgres)
+]);
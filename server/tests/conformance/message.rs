@@ -0,0 +1,512 @@
+use super::{in_memory, postgres, sqlite};
+use sam_common::{
+    address::{AccountId, MessageId},
+    sam_message::{EnvelopeType, Notification, NotificationType, PreKeyCount, ServerEnvelope},
+};
+use sam_server::{
+    managers::traits::message_manager::{Dispatch, MessageManager},
+    state::{state_type::StateType, ServerState},
+    ServerError,
+};
+
+macro_rules! test_message_manager {
+    ( [ $( $(#[$attr:meta])* ($name:ident, $factory:expr) ),* ] ) => {
+        $(
+            paste::paste! {
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _envelope_can_be_inserted_and_removed >]() {
+                    envelope_can_be_inserted_and_removed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _envelope_cannot_be_inserted_twice >]() {
+                    envelope_cannot_be_inserted_twice($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _missing_envelope_does_not_exist >]() {
+                    missing_envelope_does_not_exist($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _pending_messages_are_tracked >]() {
+                    pending_messages_are_tracked($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _pending_messages_can_be_removed_per_device >]() {
+                    pending_messages_can_be_removed_per_device($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _subscriber_receives_envelopes >]() {
+                    subscriber_receives_envelopes($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _device_can_only_subscribe_once >]() {
+                    device_can_only_subscribe_once($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _expired_envelopes_are_removed >]() {
+                    expired_envelopes_are_removed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _envelope_queue_is_trimmed >]() {
+                    envelope_queue_is_trimmed($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _envelope_queue_is_trimmed_in_insertion_order >]() {
+                    envelope_queue_is_trimmed_in_insertion_order($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _subscriber_receives_notifications >]() {
+                    subscriber_receives_notifications($factory().await).await;
+                }
+
+                #[tokio::test]
+                $(#[$attr])*
+                async fn [< $name _subscriber_can_be_disconnected >]() {
+                    subscriber_can_be_disconnected($factory().await).await;
+                }
+            }
+        )*
+    };
+}
+
+fn envelope(account_id: AccountId, envelope_id: MessageId) -> ServerEnvelope {
+    ServerEnvelope::builder()
+        .r#type(EnvelopeType::PlaintextContent as i32)
+        .destination_account_id(account_id.into())
+        .destination_device_id(1)
+        .source_account_id(AccountId::generate().into())
+        .source_device_id(1)
+        .content(b"hi".to_vec())
+        .id(envelope_id.into())
+        .build()
+}
+
+async fn envelope_can_be_inserted_and_removed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_id = MessageId::generate();
+    let envelope = envelope(account_id, envelope_id);
+
+    state
+        .messages
+        .insert_envelope(account_id, 1.into(), envelope_id, envelope.clone())
+        .await
+        .expect("Can insert envelope");
+    assert!(state
+        .messages
+        .get_envelope(account_id, 1.into(), envelope_id)
+        .await
+        .is_ok_and(|e| e.server_timestamp.is_some()
+            && ServerEnvelope {
+                server_timestamp: None,
+                ..e
+            } == envelope));
+    assert!(state
+        .messages
+        .get_envelope_ids(account_id, 1.into())
+        .await
+        .is_some_and(|ids| ids == vec![envelope_id]));
+
+    state
+        .messages
+        .remove_envelope(account_id, 1.into(), envelope_id)
+        .await
+        .expect("Can remove envelope");
+    assert!(state
+        .messages
+        .get_envelope_ids(account_id, 1.into())
+        .await
+        .is_none());
+}
+
+async fn envelope_cannot_be_inserted_twice<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_id = MessageId::generate();
+    let envelope = envelope(account_id, envelope_id);
+
+    state
+        .messages
+        .insert_envelope(account_id, 1.into(), envelope_id, envelope.clone())
+        .await
+        .expect("Can insert envelope");
+    assert!(matches!(
+        state
+            .messages
+            .insert_envelope(account_id, 1.into(), envelope_id, envelope)
+            .await,
+        Err(ServerError::EnvelopeExists)
+    ));
+}
+
+async fn missing_envelope_does_not_exist<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_id = MessageId::generate();
+
+    assert!(state
+        .messages
+        .get_envelope_ids(account_id, 1.into())
+        .await
+        .is_none());
+    assert!(matches!(
+        state
+            .messages
+            .get_envelope(account_id, 1.into(), envelope_id)
+            .await,
+        Err(ServerError::EnvelopeNotExists)
+    ));
+    assert!(matches!(
+        state
+            .messages
+            .remove_envelope(account_id, 1.into(), envelope_id)
+            .await,
+        Err(ServerError::EnvelopeNotExists)
+    ));
+}
+
+async fn pending_messages_are_tracked<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_id = MessageId::generate();
+
+    assert!(matches!(
+        state
+            .messages
+            .remove_pending_message(account_id, 1.into(), envelope_id)
+            .await,
+        Err(ServerError::MessageNotPending)
+    ));
+
+    state
+        .messages
+        .add_pending_message(account_id, 1.into(), envelope_id)
+        .await
+        .expect("Can add pending message");
+    assert!(matches!(
+        state
+            .messages
+            .add_pending_message(account_id, 1.into(), envelope_id)
+            .await,
+        Err(ServerError::MessageAlreadyPending)
+    ));
+
+    state
+        .messages
+        .remove_pending_message(account_id, 1.into(), envelope_id)
+        .await
+        .expect("Can remove pending message");
+    assert!(matches!(
+        state
+            .messages
+            .remove_pending_message(account_id, 1.into(), envelope_id)
+            .await,
+        Err(ServerError::MessageNotPending)
+    ));
+}
+
+async fn pending_messages_can_be_removed_per_device<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_ids = [MessageId::generate(), MessageId::generate()];
+
+    for device_id in [1u32, 2] {
+        for envelope_id in envelope_ids {
+            state
+                .messages
+                .add_pending_message(account_id, device_id.into(), envelope_id)
+                .await
+                .expect("Can add pending message");
+        }
+    }
+
+    state
+        .messages
+        .remove_pending_messages(account_id, 2.into())
+        .await
+        .expect("Can remove pending messages");
+
+    for envelope_id in envelope_ids {
+        assert!(matches!(
+            state
+                .messages
+                .remove_pending_message(account_id, 2.into(), envelope_id)
+                .await,
+            Err(ServerError::MessageNotPending)
+        ));
+        state
+            .messages
+            .remove_pending_message(account_id, 1.into(), envelope_id)
+            .await
+            .expect("Other devices keep their pending messages");
+    }
+}
+
+async fn insert_envelope_at<T: StateType>(
+    state: &mut ServerState<T>,
+    account_id: AccountId,
+    envelope_id: MessageId,
+    server_timestamp: u64,
+) {
+    let envelope = ServerEnvelope {
+        server_timestamp: Some(server_timestamp),
+        ..envelope(account_id, envelope_id)
+    };
+    state
+        .messages
+        .insert_envelope(account_id, 1.into(), envelope_id, envelope)
+        .await
+        .expect("Can insert envelope");
+    state
+        .messages
+        .add_pending_message(account_id, 1.into(), envelope_id)
+        .await
+        .expect("Can add pending message");
+}
+
+async fn expired_envelopes_are_removed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let expired_id = MessageId::generate();
+    let fresh_id = MessageId::generate();
+
+    insert_envelope_at(&mut state, account_id, expired_id, 1_000).await;
+    insert_envelope_at(&mut state, account_id, fresh_id, 3_000).await;
+
+    assert!(state
+        .messages
+        .remove_expired_envelopes(2_000)
+        .await
+        .is_ok_and(|removed| removed == 1));
+    assert!(state
+        .messages
+        .get_envelope_ids(account_id, 1.into())
+        .await
+        .is_some_and(|ids| ids == vec![fresh_id]));
+    assert!(matches!(
+        state
+            .messages
+            .remove_pending_message(account_id, 1.into(), expired_id)
+            .await,
+        Err(ServerError::MessageNotPending)
+    ));
+    state
+        .messages
+        .remove_pending_message(account_id, 1.into(), fresh_id)
+        .await
+        .expect("Fresh envelope stays pending");
+}
+
+async fn envelope_queue_is_trimmed<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_ids = [
+        MessageId::generate(),
+        MessageId::generate(),
+        MessageId::generate(),
+    ];
+
+    // timestamps are not the insertion order, the first inserted is dropped
+    for (envelope_id, server_timestamp) in envelope_ids.iter().zip([2_000, 1_000, 3_000]) {
+        insert_envelope_at(&mut state, account_id, *envelope_id, server_timestamp).await;
+    }
+
+    assert!(state
+        .messages
+        .trim_envelopes(account_id, 1.into(), 2)
+        .await
+        .is_ok_and(|removed| removed == 1));
+    assert!(matches!(
+        state
+            .messages
+            .get_envelope(account_id, 1.into(), envelope_ids[0])
+            .await,
+        Err(ServerError::EnvelopeNotExists)
+    ));
+    assert!(matches!(
+        state
+            .messages
+            .remove_pending_message(account_id, 1.into(), envelope_ids[0])
+            .await,
+        Err(ServerError::MessageNotPending)
+    ));
+    assert!(state
+        .messages
+        .get_envelope_ids(account_id, 1.into())
+        .await
+        .is_some_and(|ids| ids.len() == 2
+            && ids.contains(&envelope_ids[1])
+            && ids.contains(&envelope_ids[2])));
+
+    assert!(state
+        .messages
+        .trim_envelopes(account_id, 1.into(), 2)
+        .await
+        .is_ok_and(|removed| removed == 0));
+}
+
+async fn envelope_queue_is_trimmed_in_insertion_order<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let envelope_ids = [(); 5].map(|_| MessageId::generate());
+
+    // timestamps tie when envelopes arrive within the same millisecond
+    for envelope_id in envelope_ids {
+        insert_envelope_at(&mut state, account_id, envelope_id, 1_000).await;
+    }
+
+    assert!(state
+        .messages
+        .trim_envelopes(account_id, 1.into(), 2)
+        .await
+        .is_ok_and(|removed| removed == 3));
+    for envelope_id in &envelope_ids[..3] {
+        assert!(matches!(
+            state
+                .messages
+                .get_envelope(account_id, 1.into(), *envelope_id)
+                .await,
+            Err(ServerError::EnvelopeNotExists)
+        ));
+    }
+    assert!(state
+        .messages
+        .get_envelope_ids(account_id, 1.into())
+        .await
+        .is_some_and(|ids| ids.len() == 2
+            && ids.contains(&envelope_ids[3])
+            && ids.contains(&envelope_ids[4])));
+}
+
+async fn subscriber_receives_envelopes<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let stored_id = MessageId::generate();
+    let live_id = MessageId::generate();
+
+    state
+        .messages
+        .insert_envelope(
+            account_id,
+            1.into(),
+            stored_id,
+            envelope(account_id, stored_id),
+        )
+        .await
+        .expect("Can insert envelope");
+    assert!(matches!(
+        state
+            .messages
+            .dispatch_envelopes(account_id, 1.into())
+            .await,
+        Err(ServerError::MessageSubscriberNotExists)
+    ));
+
+    let mut receiver = state
+        .messages
+        .subscribe(account_id, 1.into())
+        .await
+        .expect("Can subscribe");
+    state
+        .messages
+        .dispatch_envelopes(account_id, 1.into())
+        .await
+        .expect("Can dispatch stored envelopes");
+    assert!(receiver.recv().await == Some(Dispatch::Envelope(stored_id)));
+
+    state
+        .messages
+        .insert_envelope(account_id, 1.into(), live_id, envelope(account_id, live_id))
+        .await
+        .expect("Can insert envelope");
+    assert!(receiver.recv().await == Some(Dispatch::Envelope(live_id)));
+}
+
+async fn device_can_only_subscribe_once<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    let _receiver = state
+        .messages
+        .subscribe(account_id, 1.into())
+        .await
+        .expect("Can subscribe");
+    assert!(matches!(
+        state.messages.subscribe(account_id, 1.into()).await,
+        Err(ServerError::MessageSubscriberExists)
+    ));
+
+    state.messages.unsubscribe(account_id, 1.into()).await;
+    state
+        .messages
+        .subscribe(account_id, 1.into())
+        .await
+        .expect("Can subscribe again after unsubscribing");
+}
+
+async fn subscriber_receives_notifications<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+    let notification = Notification::builder()
+        .r#type(NotificationType::PreKeysLow as i32)
+        .pre_key_count(PreKeyCount {
+            pre_keys: 1,
+            pq_pre_keys: 0,
+        })
+        .build();
+
+    state
+        .messages
+        .notify(account_id, 1.into(), notification.clone())
+        .await
+        .expect("Notifications for offline devices are dropped");
+
+    let mut receiver = state
+        .messages
+        .subscribe(account_id, 1.into())
+        .await
+        .expect("Can subscribe");
+    state
+        .messages
+        .notify(account_id, 1.into(), notification.clone())
+        .await
+        .expect("Can notify subscriber");
+    assert!(receiver.recv().await == Some(Dispatch::Notification(notification)));
+}
+
+async fn subscriber_can_be_disconnected<T: StateType>(mut state: ServerState<T>) {
+    let account_id = AccountId::generate();
+
+    state.messages.disconnect(account_id, 1.into()).await;
+
+    let mut receiver = state
+        .messages
+        .subscribe(account_id, 1.into())
+        .await
+        .expect("Can subscribe");
+    state.messages.disconnect(account_id, 1.into()).await;
+    assert!(receiver.recv().await == Some(Dispatch::Disconnect));
+    assert!(receiver.recv().await.is_none());
+
+    state
+        .messages
+        .subscribe(account_id, 1.into())
+        .await
+        .expect("Can subscribe again after being disconnected");
+}
+
+test_message_manager!([
+    (in_memory_message_manager, in_memory),
+    (sqlite_message_manager, sqlite),
+    #[ignore = "requires a local Postgres instance"]
+    (postgres_message_manager, postgres)
+]);
@@ -0,0 +1,103 @@
+use libsignal_protocol::{
+    kem, GenericSignedPreKey, IdentityKeyPair, KeyPair, KyberPreKeyId, KyberPreKeyRecord, PreKeyId,
+    PreKeyRecord, SignedPreKeyId, SignedPreKeyRecord, Timestamp,
+};
+use rand::rngs::OsRng;
+use sam_common::{
+    address::{AccountId, DeviceId},
+    api::{DeviceRole, EcPreKey, PqPreKey, SignedEcPreKey},
+    time_now_millis,
+};
+use sam_server::{
+    auth::password::Password,
+    managers::{
+        entities::{account::Account, device::Device},
+        in_memory::InMemStateType,
+        postgres::{connect_to_postgres, PostgresStateType},
+        sqlite::{connect_to_sqlite, SqliteStateType},
+    },
+    state::ServerState,
+};
+
+mod account;
+mod device;
+mod keys;
+mod message;
+
+const LINK_SECRET: &str = "test";
+
+async fn in_memory() -> ServerState<InMemStateType> {
+    ServerState::in_memory(LINK_SECRET.to_string(), 600, 10)
+}
+
+async fn sqlite() -> ServerState<SqliteStateType> {
+    let database = connect_to_sqlite("sqlite::memory:")
+        .await
+        .expect("Can open in-memory SQLite database");
+    ServerState::sqlite(database, LINK_SECRET.to_string(), 600, 10)
+}
+
+/// Requires `POSTGRES_DATABASE_URL` to point at a running Postgres instance
+async fn postgres() -> ServerState<PostgresStateType> {
+    let database_url = std::env::var("POSTGRES_DATABASE_URL")
+        .expect("POSTGRES_DATABASE_URL must be set to run Postgres tests");
+    let database = connect_to_postgres(&database_url)
+        .await
+        .expect("Can connect to Postgres");
+    ServerState::postgres(database, LINK_SECRET.to_string(), 600, 10)
+}
+
+pub fn key_pair() -> IdentityKeyPair {
+    IdentityKeyPair::generate(&mut OsRng)
+}
+
+/// Usernames are unique, so each account gets one derived from its id to let
+/// tests share a database
+pub fn new_account(pair: &IdentityKeyPair) -> Account {
+    let id = AccountId::generate();
+    Account::builder()
+        .id(id)
+        .username(format!("alice-{id}"))
+        .identity(*pair.identity_key())
+        .build()
+}
+
+pub fn new_device(id: DeviceId) -> Device {
+    Device::builder()
+        .id(id)
+        .registration_id(1.into())
+        .name("Alice Phone".to_string())
+        .creation(time_now_millis())
+        .password(Password::generate("bob<3".to_string()).expect("Can hash password"))
+        .role(DeviceRole::Linked)
+        .build()
+}
+
+pub fn ec_pre_key(id: u32) -> EcPreKey {
+    PreKeyRecord::new(PreKeyId::from(id), &KeyPair::generate(&mut OsRng)).into()
+}
+
+pub fn signed_ec_pre_key(id: u32, pair: &IdentityKeyPair) -> SignedEcPreKey {
+    let pre_key = KeyPair::generate(&mut OsRng);
+    let signature = pair
+        .private_key()
+        .calculate_signature(&pre_key.public_key.serialize(), &mut OsRng)
+        .expect("Signal Signature works");
+    SignedPreKeyRecord::new(
+        SignedPreKeyId::from(id),
+        Timestamp::from_epoch_millis(time_now_millis().try_into().expect("Time works")),
+        &pre_key,
+        &signature,
+    )
+    .into()
+}
+
+pub fn pq_pre_key(id: u32, pair: &IdentityKeyPair) -> PqPreKey {
+    KyberPreKeyRecord::generate(
+        kem::KeyType::Kyber1024,
+        KyberPreKeyId::from(id),
+        pair.private_key(),
+    )
+    .expect("Keys can be generated")
+    .into()
+}